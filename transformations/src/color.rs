@@ -0,0 +1,117 @@
+use crate::util::round_shift_clamp;
+use crate::Transformation;
+
+/// Fixed-point precision of the preset colour matrices.
+pub const COLOR_MATRIX_PRESET_SHIFT: u32 = 12;
+
+/// Bounds on the `ColorMatrix` parameters. They keep the per-channel
+/// accumulator well inside the 32-bit window the circuit decomposes.
+pub const MAX_COLOR_MATRIX_SHIFT: u32 = 16;
+pub const MAX_COLOR_MATRIX_COEFFICIENT: i32 = 1 << 16;
+pub const MAX_COLOR_MATRIX_OFFSET: i32 = 1 << 24;
+
+// Rec. 601 luma weights
+const LUMA: [f64; 3] = [0.299, 0.587, 0.114];
+
+impl Transformation {
+    pub fn color_matrix_from_floats(m: [[f64; 3]; 3]) -> Self {
+        let scale = (1 << COLOR_MATRIX_PRESET_SHIFT) as f64;
+        Transformation::ColorMatrix {
+            m: m.map(|row| row.map(|c| (c * scale).round() as i32)),
+            offset: [0; 3],
+            shift: COLOR_MATRIX_PRESET_SHIFT,
+        }
+    }
+
+    pub fn sepia() -> Self {
+        Self::color_matrix_from_floats([
+            [0.393, 0.769, 0.189],
+            [0.349, 0.686, 0.168],
+            [0.272, 0.534, 0.131],
+        ])
+    }
+
+    /// `scale` of 0 gives grayscale, 1 keeps the image intact.
+    pub fn saturation(scale: f64) -> Self {
+        let mut m = [[0f64; 3]; 3];
+        for (c, row) in m.iter_mut().enumerate() {
+            for (j, coefficient) in row.iter_mut().enumerate() {
+                *coefficient = (1.0 - scale) * LUMA[j] + if c == j { scale } else { 0.0 };
+            }
+        }
+        Self::color_matrix_from_floats(m)
+    }
+
+    pub fn hue_rotation(degrees: f64) -> Self {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        Self::color_matrix_from_floats([
+            [
+                0.213 + cos * 0.787 - sin * 0.213,
+                0.715 - cos * 0.715 - sin * 0.715,
+                0.072 - cos * 0.072 + sin * 0.928,
+            ],
+            [
+                0.213 - cos * 0.213 + sin * 0.143,
+                0.715 + cos * 0.285 + sin * 0.140,
+                0.072 - cos * 0.072 - sin * 0.283,
+            ],
+            [
+                0.213 - cos * 0.213 - sin * 0.787,
+                0.715 - cos * 0.715 + sin * 0.715,
+                0.072 + cos * 0.928 + sin * 0.072,
+            ],
+        ])
+    }
+}
+
+pub fn color_matrix_params_valid(m: &[[i32; 3]; 3], offset: &[i32; 3], shift: u32) -> bool {
+    shift <= MAX_COLOR_MATRIX_SHIFT
+        && m.iter()
+            .flatten()
+            .all(|c| c.abs() <= MAX_COLOR_MATRIX_COEFFICIENT)
+        && offset.iter().all(|o| o.abs() <= MAX_COLOR_MATRIX_OFFSET)
+}
+
+/// Applies the matrix to the RGB channels of an RGBA pixel, alpha is kept.
+pub fn apply_color_matrix(
+    m: &[[i32; 3]; 3],
+    offset: &[i32; 3],
+    shift: u32,
+    pixel: [u8; 4],
+) -> [u8; 4] {
+    let mut edited = pixel;
+    for c in 0..3 {
+        let acc = offset[c] as i64
+            + (0..3)
+                .map(|j| m[c][j] as i64 * pixel[j] as i64)
+                .sum::<i64>();
+        edited[c] = round_shift_clamp(acc, shift);
+    }
+    edited
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identity_matrix() {
+        let identity = [[1 << 8, 0, 0], [0, 1 << 8, 0], [0, 0, 1 << 8]];
+        let pixel = [12, 200, 77, 128];
+        assert_eq!(apply_color_matrix(&identity, &[0; 3], 8, pixel), pixel);
+    }
+
+    #[test]
+    fn test_sepia_clamps() {
+        match Transformation::sepia() {
+            Transformation::ColorMatrix { m, offset, shift } => {
+                assert!(color_matrix_params_valid(&m, &offset, shift));
+                assert_eq!(
+                    apply_color_matrix(&m, &offset, shift, [255; 4]),
+                    [255, 255, 239, 255]
+                );
+            }
+            _ => unreachable!(),
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use crate::util::round_shift_clamp;
+use crate::Region;
+
+/// Compare-exchange network selecting the median of 9 values into position 4
+/// (Paeth / Devillard `opt_med9`). After `(a, b)` the smaller value is at `a`.
+pub const MEDIAN9_NETWORK: [(usize, usize); 19] = [
+    (1, 2),
+    (4, 5),
+    (7, 8),
+    (0, 1),
+    (3, 4),
+    (6, 7),
+    (1, 2),
+    (4, 5),
+    (7, 8),
+    (0, 3),
+    (5, 8),
+    (4, 7),
+    (3, 6),
+    (1, 4),
+    (2, 5),
+    (4, 7),
+    (4, 2),
+    (6, 4),
+    (4, 2),
+];
+
+/// Fixed-point precision of the unsharp mask.
+pub const UNSHARP_SHIFT: u32 = 16;
+pub const MAX_UNSHARP_RADIUS: u32 = 3;
+/// Sharpening strength is given in percent.
+pub const MAX_UNSHARP_AMOUNT: u32 = 500;
+
+pub fn unsharp_params_valid(amount: u32, radius: u32) -> bool {
+    amount <= MAX_UNSHARP_AMOUNT && (1..=MAX_UNSHARP_RADIUS).contains(&radius)
+}
+
+/// Coordinates of the `(2 * radius + 1)^2` window around a pixel, with the
+/// image border replicated.
+pub fn clamped_window(
+    x: u32,
+    y: u32,
+    orig_w: u32,
+    orig_h: u32,
+    radius: u32,
+) -> impl Iterator<Item = (u32, u32)> {
+    let radius = radius as i64;
+    (-radius..=radius).flat_map(move |dy| {
+        (-radius..=radius).map(move |dx| {
+            (
+                (x as i64 + dx).clamp(0, orig_w as i64 - 1) as u32,
+                (y as i64 + dy).clamp(0, orig_h as i64 - 1) as u32,
+            )
+        })
+    })
+}
+
+/// Number of pixels preceding or following a pixel in raster order that its
+/// window can reach.
+pub fn window_reach(orig_w: u32, radius: u32) -> usize {
+    radius as usize * (orig_w as usize + 1)
+}
+
+pub fn window_size(radius: u32) -> i64 {
+    let side = 2 * radius as i64 + 1;
+    side * side
+}
+
+/// `amount / (100 * window_size)` in fixed point.
+pub fn unsharp_coefficient(amount: u32, radius: u32) -> i64 {
+    let divisor = 100 * window_size(radius);
+    (((amount as i64) << UNSHARP_SHIFT) + divisor / 2) / divisor
+}
+
+/// Sharpens a channel given the sum of its box-blur window:
+/// `channel + amount * (channel - blur)`, unless `|channel - blur| < threshold`.
+pub fn unsharp_channel(
+    amount: u32,
+    radius: u32,
+    threshold: u8,
+    channel: u8,
+    window_sum: i64,
+) -> u8 {
+    let n = window_size(radius);
+    let diff = n * channel as i64 - window_sum;
+    if diff.abs() < threshold as i64 * n {
+        return channel;
+    }
+    round_shift_clamp(
+        ((channel as i64) << UNSHARP_SHIFT) + unsharp_coefficient(amount, radius) * diff,
+        UNSHARP_SHIFT,
+    )
+}
+
+/// Applies the unsharp mask to a row-major RGBA image, alpha is kept.
+pub fn apply_unsharp(
+    pixels: &[[u8; 4]],
+    orig_w: u32,
+    orig_h: u32,
+    amount: u32,
+    radius: u32,
+    threshold: u8,
+) -> Vec<[u8; 4]> {
+    let mut edited = pixels.to_vec();
+    for (i, pixel) in edited.iter_mut().enumerate() {
+        let (x, y) = (i as u32 % orig_w, i as u32 / orig_w);
+        for c in 0..3 {
+            let window_sum = clamped_window(x, y, orig_w, orig_h, radius)
+                .map(|(nx, ny)| pixels[(ny * orig_w + nx) as usize][c] as i64)
+                .sum();
+            pixel[c] = unsharp_channel(amount, radius, threshold, pixel[c], window_sum);
+        }
+    }
+    edited
+}
+
+pub fn median9(mut values: [u8; 9]) -> u8 {
+    for (a, b) in MEDIAN9_NETWORK {
+        if values[a] > values[b] {
+            values.swap(a, b);
+        }
+    }
+    values[4]
+}
+
+/// Applies the 3x3 median to the pixels of a row-major RGBA image that lie in
+/// any of the regions, alpha is kept.
+pub fn apply_median3x3(
+    pixels: &[[u8; 4]],
+    orig_w: u32,
+    orig_h: u32,
+    regions: &[Region],
+) -> Vec<[u8; 4]> {
+    let mut edited = pixels.to_vec();
+    for (i, pixel) in edited.iter_mut().enumerate() {
+        let (x, y) = (i as u32 % orig_w, i as u32 / orig_w);
+        if !regions.iter().any(|region| region.contains(x, y)) {
+            continue;
+        }
+        for c in 0..3 {
+            let mut values = [0u8; 9];
+            for (value, (nx, ny)) in values
+                .iter_mut()
+                .zip(clamped_window(x, y, orig_w, orig_h, 1))
+            {
+                *value = pixels[(ny * orig_w + nx) as usize][c];
+            }
+            pixel[c] = median9(values);
+        }
+    }
+    edited
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_median_network() {
+        let mut values = [200, 3, 17, 255, 0, 99, 42, 42, 8];
+        let median = median9(values);
+        values.sort();
+        assert_eq!(median, values[4]);
+    }
+
+    #[test]
+    fn test_unsharp_keeps_flat_image() {
+        let pixels = vec![[90, 120, 200, 255]; 5 * 4];
+        assert_eq!(apply_unsharp(&pixels, 5, 4, 150, 1, 0), pixels);
+    }
+}
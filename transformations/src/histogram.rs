@@ -0,0 +1,59 @@
+pub const HISTOGRAM_BINS: usize = 256;
+
+/// Histograms of the R, G and B channels.
+pub fn channel_histograms(pixels: &[[u8; 4]]) -> [[u64; HISTOGRAM_BINS]; 3] {
+    let mut histograms = [[0u64; HISTOGRAM_BINS]; 3];
+    for pixel in pixels {
+        for (histogram, value) in histograms.iter_mut().zip(pixel) {
+            histogram[*value as usize] += 1;
+        }
+    }
+    histograms
+}
+
+/// `lut[k] = floor(cdf[k] * 255 / total)`, the plain CDF scaling without the
+/// `cdf_min` correction so the circuit only needs one division check per bin.
+pub fn equalization_lut(histogram: &[u64; HISTOGRAM_BINS], total: u64) -> [u8; HISTOGRAM_BINS] {
+    let mut lut = [0u8; HISTOGRAM_BINS];
+    let mut cdf = 0;
+    for (entry, count) in lut.iter_mut().zip(histogram) {
+        cdf += count;
+        *entry = (cdf * u8::MAX as u64 / total) as u8;
+    }
+    lut
+}
+
+/// Equalizes each RGB channel of the image independently, alpha is kept.
+pub fn apply_equalize(pixels: &[[u8; 4]]) -> Vec<[u8; 4]> {
+    let luts = channel_histograms(pixels)
+        .map(|histogram| equalization_lut(&histogram, pixels.len() as u64));
+    pixels
+        .iter()
+        .map(|pixel| {
+            let mut edited = *pixel;
+            for (channel, lut) in edited.iter_mut().zip(&luts) {
+                *channel = lut[*channel as usize];
+            }
+            edited
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_equalize_spreads_values() {
+        let pixels: Vec<[u8; 4]> = (0..4).map(|v| [v, 10 + v, 100, 255]).collect();
+        assert_eq!(
+            apply_equalize(&pixels),
+            vec![
+                [63, 63, 255, 255],
+                [127, 127, 255, 255],
+                [191, 191, 255, 255],
+                [255, 255, 255, 255],
+            ]
+        );
+    }
+}
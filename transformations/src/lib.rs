@@ -1,6 +1,75 @@
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+
 use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Serialize, Deserialize)]
+pub mod color;
+pub mod filter;
+pub mod histogram;
+pub mod tone;
+pub mod util;
+
+/// Axis-aligned rectangle in pixel coordinates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+}
+
+impl Region {
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        x >= self.x && x < self.x + self.w && y >= self.y && y < self.y + self.h
+    }
+
+    pub fn fits(&self, orig_w: u32, orig_h: u32) -> bool {
+        self.x + self.w <= orig_w && self.y + self.h <= orig_h
+    }
+
+    /// Whether any pixel of the region has a raster index in `pixels`.
+    pub fn overlaps_pixels(&self, orig_w: u32, pixels: Range<usize>) -> bool {
+        self.w > 0
+            && (self.y..self.y + self.h).any(|y| {
+                let row_start = (y * orig_w + self.x) as usize;
+                row_start < pixels.end && pixels.start < row_start + self.w as usize
+            })
+    }
+}
+
+/// Parses `x,y,w,h`.
+impl FromStr for Region {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s
+            .split(',')
+            .map(|v| v.trim().parse::<u32>().map_err(|e| e.to_string()))
+            .collect::<Result<Vec<_>, _>>()?;
+        match values[..] {
+            [x, y, w, h] => Ok(Region { x, y, w, h }),
+            _ => Err(format!("expected x,y,w,h, got {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{},{},{},{}", self.x, self.y, self.w, self.h)
+    }
+}
+
+/// Where a hidden crop was taken from. Only the prover knows it, it is never
+/// written to the metadata.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CropPosition {
+    pub orig_w: u32,
+    pub x: u32,
+    pub y: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Transformation {
     Crop {
         orig_w: u32,
@@ -10,4 +79,71 @@ pub enum Transformation {
         w: u32,
         h: u32,
     },
+    /// Per-pixel `rgb' = clamp((m * rgb + offset) >> shift)` in fixed point,
+    /// covers sepia, saturation and hue rotation presets.
+    ColorMatrix {
+        m: [[i32; 3]; 3],
+        offset: [i32; 3],
+        shift: u32,
+    },
+    /// Each RGB channel becomes 255 if it is at least `level` and 0 otherwise.
+    Threshold { level: u8 },
+    /// Each RGB channel is quantized to `levels` evenly spaced values.
+    Posterize { levels: u8 },
+    /// Box-blur based unsharp mask, `amount` is in percent. Channels whose
+    /// difference from the blur is below `threshold` are kept.
+    Unsharp {
+        orig_w: u32,
+        orig_h: u32,
+        amount: u32,
+        radius: u32,
+        threshold: u8,
+    },
+    /// Each RGB channel inside the regions is replaced with the median of its
+    /// 3x3 neighbourhood, pixels outside are kept.
+    Median3x3 {
+        orig_w: u32,
+        orig_h: u32,
+        regions: Vec<Region>,
+    },
+    /// Histogram equalization of each RGB channel with the lookup table
+    /// derived from the channel CDF of the whole original.
+    Equalize { orig_w: u32, orig_h: u32 },
+    /// Crop whose position and original width are private witnesses, only
+    /// the size of the crop is public. `position` is set by the prover.
+    HiddenCrop {
+        w: u32,
+        h: u32,
+        #[serde(skip)]
+        position: Option<CropPosition>,
+    },
+    /// Pixels outside the regions are kept, pixels inside may hold anything,
+    /// e.g. a caption bar or a sticker. The regions are public inputs of the
+    /// proof.
+    PreserveOutside {
+        orig_w: u32,
+        orig_h: u32,
+        regions: Vec<Region>,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_region_overlaps_pixels() {
+        let region = Region {
+            x: 2,
+            y: 1,
+            w: 2,
+            h: 2,
+        };
+        // rows 1 and 2 of a 5 pixel wide image cover 7..9 and 12..14
+        assert!(!region.overlaps_pixels(5, 0..7));
+        assert!(region.overlaps_pixels(5, 0..8));
+        assert!(!region.overlaps_pixels(5, 9..12));
+        assert!(region.overlaps_pixels(5, 13..20));
+        assert!(!region.overlaps_pixels(5, 14..20));
+    }
 }
@@ -0,0 +1,62 @@
+use crate::util::round_shift_clamp;
+
+/// Fixed-point precision of the posterize output scale.
+pub const POSTERIZE_SHIFT: u32 = 8;
+
+pub fn threshold_channel(level: u8, channel: u8) -> u8 {
+    if channel >= level {
+        u8::MAX
+    } else {
+        0
+    }
+}
+
+pub fn posterize_levels_valid(levels: u8) -> bool {
+    levels >= 2
+}
+
+/// `255 / (levels - 1)` in fixed point, maps a quantization bucket back to
+/// the byte range.
+pub fn posterize_multiplier(levels: u8) -> i64 {
+    let scale = (u8::MAX as i64) << POSTERIZE_SHIFT;
+    let divisor = levels as i64 - 1;
+    (scale + divisor / 2) / divisor
+}
+
+pub fn posterize_channel(levels: u8, channel: u8) -> u8 {
+    let bucket = (channel as i64 * levels as i64) >> 8;
+    round_shift_clamp(bucket * posterize_multiplier(levels), POSTERIZE_SHIFT)
+}
+
+/// Binarizes the RGB channels of an RGBA pixel, alpha is kept.
+pub fn apply_threshold(level: u8, pixel: [u8; 4]) -> [u8; 4] {
+    let mut edited = pixel;
+    for channel in edited.iter_mut().take(3) {
+        *channel = threshold_channel(level, *channel);
+    }
+    edited
+}
+
+/// Quantizes the RGB channels of an RGBA pixel to `levels` values, alpha is
+/// kept.
+pub fn apply_posterize(levels: u8, pixel: [u8; 4]) -> [u8; 4] {
+    let mut edited = pixel;
+    for channel in edited.iter_mut().take(3) {
+        *channel = posterize_channel(levels, *channel);
+    }
+    edited
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_posterize_keeps_extremes() {
+        for levels in 2..=u8::MAX {
+            assert_eq!(posterize_channel(levels, 0), 0);
+            assert_eq!(posterize_channel(levels, u8::MAX), u8::MAX);
+        }
+        assert_eq!(apply_posterize(2, [100, 128, 200, 7]), [0, 255, 255, 7]);
+    }
+}
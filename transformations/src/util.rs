@@ -0,0 +1,6 @@
+/// Fixed-point rounding used by the per-pixel kernels: computes
+/// `clamp((acc + 2^(shift-1)) >> shift, 0, 255)`.
+pub fn round_shift_clamp(acc: i64, shift: u32) -> u8 {
+    let rounding = if shift > 0 { 1 << (shift - 1) } else { 0 };
+    ((acc + rounding) >> shift).clamp(0, u8::MAX as i64) as u8
+}
@@ -0,0 +1,275 @@
+use anyhow::{anyhow, bail, Result};
+
+// The subset of CBOR (RFC 8949) C2PA manifests are written in. Lengths are
+// definite and heads as short as they go, so encoding is deterministic; map
+// entries keep their order.
+
+const MAX_DEPTH: usize = 64;
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Value {
+    Unsigned(u64),
+    /// `-1 - n`
+    Negative(u64),
+    Bytes(Vec<u8>),
+    Text(String),
+    Array(Vec<Value>),
+    Map(Vec<(Value, Value)>),
+    Tag(u64, Box<Value>),
+    Bool(bool),
+    Null,
+    Float(f64),
+}
+
+impl Value {
+    #[cfg(test)]
+    pub(crate) fn integer(value: i64) -> Self {
+        match value {
+            0.. => Value::Unsigned(value as u64),
+            _ => Value::Negative(!value as u64),
+        }
+    }
+
+    pub(crate) fn text(text: &str) -> Self {
+        Value::Text(text.to_string())
+    }
+
+    /// A map with text keys.
+    pub(crate) fn map<const N: usize>(entries: [(&str, Value); N]) -> Self {
+        Value::Map(
+            entries
+                .into_iter()
+                .map(|(key, value)| (Value::text(key), value))
+                .collect(),
+        )
+    }
+
+    /// The value of a text key of a map.
+    pub(crate) fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Map(entries) => entries
+                .iter()
+                .find(|(found, _)| matches!(found, Value::Text(found) if found == key))
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Value::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_text(&self) -> Option<&str> {
+        match self {
+            Value::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_u64(&self) -> Option<u64> {
+        match self {
+            Value::Unsigned(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.encode(&mut bytes);
+        bytes
+    }
+
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        match self {
+            Value::Unsigned(value) => write_head(bytes, 0, *value),
+            Value::Negative(value) => write_head(bytes, 1, *value),
+            Value::Bytes(value) => {
+                write_head(bytes, 2, value.len() as u64);
+                bytes.extend(value);
+            }
+            Value::Text(value) => {
+                write_head(bytes, 3, value.len() as u64);
+                bytes.extend(value.as_bytes());
+            }
+            Value::Array(values) => {
+                write_head(bytes, 4, values.len() as u64);
+                for value in values {
+                    value.encode(bytes);
+                }
+            }
+            Value::Map(entries) => {
+                write_head(bytes, 5, entries.len() as u64);
+                for (key, value) in entries {
+                    key.encode(bytes);
+                    value.encode(bytes);
+                }
+            }
+            Value::Tag(tag, value) => {
+                write_head(bytes, 6, *tag);
+                value.encode(bytes);
+            }
+            Value::Bool(value) => bytes.push(0xf4 + *value as u8),
+            Value::Null => bytes.push(0xf6),
+            Value::Float(value) => {
+                bytes.push(0xfb);
+                bytes.extend(value.to_be_bytes());
+            }
+        }
+    }
+
+    /// Decodes `bytes` holding exactly one item.
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut offset = 0;
+        let value = decode(bytes, &mut offset, 0)?;
+        if offset != bytes.len() {
+            bail!("trailing bytes after the CBOR item");
+        }
+        Ok(value)
+    }
+}
+
+fn write_head(bytes: &mut Vec<u8>, major: u8, value: u64) {
+    let major = major << 5;
+    match value {
+        0..=23 => bytes.push(major | value as u8),
+        24..=0xff => bytes.extend([major | 24, value as u8]),
+        0x100..=0xffff => {
+            bytes.push(major | 25);
+            bytes.extend((value as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            bytes.push(major | 26);
+            bytes.extend((value as u32).to_be_bytes());
+        }
+        _ => {
+            bytes.push(major | 27);
+            bytes.extend(value.to_be_bytes());
+        }
+    }
+}
+
+fn take<'a>(bytes: &'a [u8], offset: &mut usize, len: usize) -> Result<&'a [u8]> {
+    let taken = bytes
+        .get(
+            *offset
+                ..offset
+                    .checked_add(len)
+                    .ok_or_else(|| anyhow!("CBOR is truncated"))?,
+        )
+        .ok_or_else(|| anyhow!("CBOR is truncated"))?;
+    *offset += len;
+    Ok(taken)
+}
+
+/// Major type, the additional information and the argument it encodes.
+fn read_head(bytes: &[u8], offset: &mut usize) -> Result<(u8, u8, u64)> {
+    let initial = take(bytes, offset, 1)?[0];
+    let (major, info) = (initial >> 5, initial & 0x1f);
+    let argument = match info {
+        0..=23 => info as u64,
+        24..=27 => take(bytes, offset, 1 << (info - 24))?
+            .iter()
+            .fold(0, |value, byte| (value << 8) | *byte as u64),
+        _ => bail!("indefinite-length CBOR isn't supported"),
+    };
+    Ok((major, info, argument))
+}
+
+fn decode(bytes: &[u8], offset: &mut usize, depth: usize) -> Result<Value> {
+    if depth > MAX_DEPTH {
+        bail!("CBOR is nested too deeply");
+    }
+    let (major, info, argument) = read_head(bytes, offset)?;
+    // Every item takes at least a byte, longer counts can't be honest
+    let remaining = bytes.len() - *offset;
+    let count = || -> Result<usize> {
+        match usize::try_from(argument) {
+            Ok(count) if count <= remaining => Ok(count),
+            _ => bail!("CBOR is truncated"),
+        }
+    };
+    Ok(match major {
+        0 => Value::Unsigned(argument),
+        1 => Value::Negative(argument),
+        2 => Value::Bytes(take(bytes, offset, count()?)?.to_vec()),
+        3 => Value::Text(String::from_utf8(take(bytes, offset, count()?)?.to_vec())?),
+        4 => Value::Array(
+            (0..count()?)
+                .map(|_| decode(bytes, offset, depth + 1))
+                .collect::<Result<_>>()?,
+        ),
+        5 => Value::Map(
+            (0..count()?)
+                .map(|_| {
+                    Ok((
+                        decode(bytes, offset, depth + 1)?,
+                        decode(bytes, offset, depth + 1)?,
+                    ))
+                })
+                .collect::<Result<_>>()?,
+        ),
+        6 => Value::Tag(argument, Box::new(decode(bytes, offset, depth + 1)?)),
+        _ => match (info, argument) {
+            (20, _) => Value::Bool(false),
+            (21, _) => Value::Bool(true),
+            (22 | 23, _) => Value::Null,
+            (25, half) => Value::Float(half_to_f64(half as u16)),
+            (26, single) => Value::Float(f32::from_bits(single as u32) as f64),
+            (27, double) => Value::Float(f64::from_bits(double)),
+            _ => bail!("unknown CBOR simple value {}", argument),
+        },
+    })
+}
+
+fn half_to_f64(half: u16) -> f64 {
+    let (exponent, mantissa) = ((half >> 10) & 0x1f, (half & 0x3ff) as f64);
+    let magnitude = match exponent {
+        0 => mantissa * 2f64.powi(-24),
+        31 if mantissa == 0.0 => f64::INFINITY,
+        31 => f64::NAN,
+        _ => (mantissa + 1024.0) * 2f64.powi(exponent as i32 - 25),
+    };
+    if half & 0x8000 != 0 {
+        -magnitude
+    } else {
+        magnitude
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let value = Value::map([
+            ("alg", Value::text("sha256")),
+            ("pad", Value::Bytes(vec![0; 300])),
+            ("start", Value::Unsigned(70_000)),
+            ("offset", Value::integer(-7)),
+            (
+                "tagged",
+                Value::Tag(18, Box::new(Value::Array(vec![Value::Null]))),
+            ),
+        ]);
+        let bytes = value.to_bytes();
+        assert_eq!(&bytes[..5], &[0xa5, 0x63, b'a', b'l', b'g']);
+        assert_eq!(Value::from_bytes(&bytes).unwrap(), value);
+        assert_eq!(Value::integer(-7).to_bytes(), [0x26]);
+        // 1.5 as a half float
+        assert_eq!(
+            Value::from_bytes(&[0xf9, 0x3e, 0x00]).unwrap(),
+            Value::Float(1.5)
+        );
+    }
+}
@@ -0,0 +1,301 @@
+use anyhow::{anyhow, bail, Result};
+use sha2::{Digest, Sha256};
+use zkedit_zkp::signature::ecdsa::{
+    public_key_bytes, secret_key_from_hex, sign_digest, verify_digest,
+};
+use zkedit_zkp::signature::p256::P256;
+
+use super::cbor::Value;
+
+// C2PA claims are signed with a COSE_Sign1 (RFC 9052) over the detached claim,
+// with the signer's certificate chain in the protected header. zkedit signs
+// with ES256 under a local test key and a self-signed certificate made from
+// it, so viewers parse the credentials but don't trust the signer.
+
+const HEADER_ALG: u64 = 1;
+const HEADER_X5CHAIN: u64 = 33;
+/// ES256, COSE algorithm -7
+const ALG_ES256: u64 = 6;
+const COSE_SIGN1_TAG: u64 = 18;
+
+const OID_ECDSA_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+const OID_EC_PUBLIC_KEY: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+const OID_P256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+const OID_COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+const OID_KEY_USAGE: &[u8] = &[0x55, 0x1d, 0x0f];
+const OID_EXTENDED_KEY_USAGE: &[u8] = &[0x55, 0x1d, 0x25];
+const OID_EMAIL_PROTECTION: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x04];
+
+const DER_BOOLEAN: u8 = 0x01;
+const DER_INTEGER: u8 = 0x02;
+const DER_BIT_STRING: u8 = 0x03;
+const DER_OCTET_STRING: u8 = 0x04;
+const DER_OID: u8 = 0x06;
+const DER_UTF8_STRING: u8 = 0x0c;
+const DER_UTC_TIME: u8 = 0x17;
+const DER_SEQUENCE: u8 = 0x30;
+const DER_SET: u8 = 0x31;
+
+pub(crate) struct Signer {
+    /// Hex P-256 secret key
+    secret_key: String,
+    certificate: Vec<u8>,
+}
+
+impl Signer {
+    pub(crate) fn from_hex(secret_key_hex: &str) -> Result<Self> {
+        let certificate = self_signed_certificate(secret_key_hex)?;
+        Ok(Signer {
+            secret_key: secret_key_hex.to_string(),
+            certificate,
+        })
+    }
+
+    /// A tagged COSE_Sign1 over `claim`, which it leaves out.
+    pub(crate) fn sign(&self, claim: &[u8]) -> Result<Vec<u8>> {
+        let protected = Value::Map(vec![
+            (Value::Unsigned(HEADER_ALG), Value::Negative(ALG_ES256)),
+            (
+                Value::Unsigned(HEADER_X5CHAIN),
+                Value::Bytes(self.certificate.clone()),
+            ),
+        ])
+        .to_bytes();
+        let digest = Sha256::digest(sig_structure(&protected, claim));
+        let signature = sign_digest(
+            &digest.into(),
+            &secret_key_from_hex::<P256>(&self.secret_key)?,
+        );
+        Ok(Value::Tag(
+            COSE_SIGN1_TAG,
+            Box::new(Value::Array(vec![
+                Value::Bytes(protected),
+                Value::Map(Vec::new()),
+                Value::Null,
+                Value::Bytes(signature.to_vec()),
+            ])),
+        )
+        .to_bytes())
+    }
+}
+
+fn sig_structure(protected: &[u8], payload: &[u8]) -> Vec<u8> {
+    Value::Array(vec![
+        Value::text("Signature1"),
+        Value::Bytes(protected.to_vec()),
+        Value::Bytes(Vec::new()),
+        Value::Bytes(payload.to_vec()),
+    ])
+    .to_bytes()
+}
+
+/// Checks a COSE_Sign1 over `claim` against the leaf certificate of its
+/// chain. Errors say why the signature couldn't be checked.
+pub(crate) fn verify(cose: &[u8], claim: &[u8]) -> Result<bool> {
+    let cose = match Value::from_bytes(cose)? {
+        Value::Tag(COSE_SIGN1_TAG, cose) => *cose,
+        cose => cose,
+    };
+    let (protected, unprotected, signature) = match cose.as_array() {
+        Some([Value::Bytes(protected), unprotected, _, Value::Bytes(signature)]) => {
+            (protected, unprotected, signature)
+        }
+        _ => bail!("the signature isn't a COSE_Sign1"),
+    };
+    let protected_header = Value::from_bytes(protected)?;
+    let header = |label: u64| {
+        [&protected_header, unprotected]
+            .into_iter()
+            .find_map(|header| match header {
+                Value::Map(entries) => entries
+                    .iter()
+                    .find(|(key, _)| *key == Value::Unsigned(label))
+                    .map(|(_, value)| value),
+                _ => None,
+            })
+    };
+    if header(HEADER_ALG) != Some(&Value::Negative(ALG_ES256)) {
+        bail!("only ES256 signatures can be checked");
+    }
+    let certificate = match header(HEADER_X5CHAIN) {
+        Some(Value::Bytes(certificate)) => certificate,
+        Some(Value::Array(chain)) => chain
+            .first()
+            .and_then(Value::as_bytes)
+            .ok_or_else(|| anyhow!("the certificate chain is empty"))?,
+        _ => bail!("the signature has no certificate chain"),
+    };
+    let public_key = certificate_public_key(certificate)?;
+    let digest = Sha256::digest(sig_structure(protected, claim));
+    Ok(verify_digest::<P256>(
+        &digest.into(),
+        signature,
+        &public_key,
+    ))
+}
+
+fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![tag];
+    match content.len() {
+        len @ 0..=0x7f => bytes.push(len as u8),
+        len @ 0x80..=0xff => bytes.extend([0x81, len as u8]),
+        len => {
+            bytes.push(0x82);
+            bytes.extend((len as u16).to_be_bytes());
+        }
+    }
+    bytes.extend(content);
+    bytes
+}
+
+/// A positive DER integer of big-endian `bytes`.
+fn der_integer(bytes: &[u8]) -> Vec<u8> {
+    let first = bytes
+        .iter()
+        .position(|byte| *byte != 0)
+        .unwrap_or(bytes.len() - 1);
+    let mut content = Vec::new();
+    if bytes[first] & 0x80 != 0 {
+        content.push(0);
+    }
+    content.extend(&bytes[first..]);
+    der(DER_INTEGER, &content)
+}
+
+fn der_sequence(items: &[Vec<u8>]) -> Vec<u8> {
+    der(DER_SEQUENCE, &items.concat())
+}
+
+/// An X.509 v3 certificate of the key signed by itself, valid from 2025 to
+/// 2035 for signing documents.
+fn self_signed_certificate(secret_key_hex: &str) -> Result<Vec<u8>> {
+    let secret_key = secret_key_from_hex::<P256>(secret_key_hex)?;
+    let public_key = public_key_bytes(&secret_key);
+    let algorithm = der_sequence(&[der(DER_OID, OID_ECDSA_SHA256)]);
+    let name = der_sequence(&[der(
+        DER_SET,
+        &der_sequence(&[
+            der(DER_OID, OID_COMMON_NAME),
+            der(DER_UTF8_STRING, b"zkedit test signer"),
+        ]),
+    )]);
+    let mut key_bits = vec![0];
+    key_bits.extend(&public_key);
+    let critical = der(DER_BOOLEAN, &[0xff]);
+    let extensions = der_sequence(&[
+        der_sequence(&[
+            der(DER_OID, OID_KEY_USAGE),
+            critical,
+            // digitalSignature
+            der(DER_OCTET_STRING, &der(DER_BIT_STRING, &[0x07, 0x80])),
+        ]),
+        der_sequence(&[
+            der(DER_OID, OID_EXTENDED_KEY_USAGE),
+            der(
+                DER_OCTET_STRING,
+                &der_sequence(&[der(DER_OID, OID_EMAIL_PROTECTION)]),
+            ),
+        ]),
+    ]);
+
+    let tbs_certificate = der_sequence(&[
+        der(0xa0, &der_integer(&[2])),
+        der_integer(&Sha256::digest(&public_key)[..16]),
+        algorithm.clone(),
+        name.clone(),
+        der_sequence(&[
+            der(DER_UTC_TIME, b"250101000000Z"),
+            der(DER_UTC_TIME, b"350101000000Z"),
+        ]),
+        name,
+        der_sequence(&[
+            der_sequence(&[der(DER_OID, OID_EC_PUBLIC_KEY), der(DER_OID, OID_P256)]),
+            der(DER_BIT_STRING, &key_bits),
+        ]),
+        der(0xa3, &extensions),
+    ]);
+    let signature = sign_digest(&Sha256::digest(&tbs_certificate).into(), &secret_key);
+    let mut signature_bits = vec![0];
+    signature_bits.extend(der_sequence(&[
+        der_integer(&signature[..32]),
+        der_integer(&signature[32..]),
+    ]));
+    Ok(der_sequence(&[
+        tbs_certificate,
+        algorithm,
+        der(DER_BIT_STRING, &signature_bits),
+    ]))
+}
+
+/// Tag and content of each DER item in `bytes`.
+fn der_items(bytes: &[u8]) -> Result<Vec<(u8, &[u8])>> {
+    let mut items = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let truncated = || anyhow!("the certificate is truncated");
+        let tag = bytes[offset];
+        let first = *bytes.get(offset + 1).ok_or_else(truncated)?;
+        let (header_len, len) = match first {
+            0..=0x7f => (2, first as usize),
+            0x81..=0x84 => {
+                let len_bytes = (first & 0x7f) as usize;
+                let len = bytes
+                    .get(offset + 2..offset + 2 + len_bytes)
+                    .ok_or_else(truncated)?
+                    .iter()
+                    .fold(0, |len, byte| (len << 8) | *byte as usize);
+                (2 + len_bytes, len)
+            }
+            _ => bail!("the certificate isn't DER"),
+        };
+        let content = bytes
+            .get(offset + header_len..offset + header_len + len)
+            .ok_or_else(truncated)?;
+        items.push((tag, content));
+        offset += header_len + len;
+    }
+    Ok(items)
+}
+
+/// The P-256 public key of an X.509 certificate.
+fn certificate_public_key(certificate: &[u8]) -> Result<Vec<u8>> {
+    let not_x509 = || anyhow!("the certificate isn't X.509");
+    let certificate = match der_items(certificate)?.as_slice() {
+        [(DER_SEQUENCE, certificate)] => *certificate,
+        _ => return Err(not_x509()),
+    };
+    let tbs_certificate = match der_items(certificate)?.first() {
+        Some((DER_SEQUENCE, tbs_certificate)) => der_items(tbs_certificate)?,
+        _ => return Err(not_x509()),
+    };
+    // The version is optional, the subject public key info follows the
+    // serial number, algorithm, issuer, validity and subject
+    let versioned = tbs_certificate.first().is_some_and(|(tag, _)| *tag == 0xa0);
+    let key_info = match tbs_certificate.get(5 + versioned as usize) {
+        Some((DER_SEQUENCE, key_info)) => der_items(key_info)?,
+        _ => return Err(not_x509()),
+    };
+    match key_info.as_slice() {
+        [(DER_SEQUENCE, algorithm), (DER_BIT_STRING, [0, key @ ..])]
+            if der_items(algorithm)?
+                .iter()
+                .any(|item| *item == (DER_OID, OID_P256)) =>
+        {
+            Ok(key.to_vec())
+        }
+        _ => bail!("only P-256 certificates can be checked"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signature_verifies_with_certificate() {
+        let signer = Signer::from_hex("c0ffee").unwrap();
+        let cose = signer.sign(b"claim").unwrap();
+        assert!(verify(&cose, b"claim").unwrap());
+        assert!(!verify(&cose, b"other claim").unwrap());
+    }
+}
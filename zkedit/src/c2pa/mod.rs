@@ -0,0 +1,597 @@
+mod cbor;
+mod cose;
+
+use std::ops::Range;
+
+use anyhow::{anyhow, bail, Result};
+use sha2::{Digest, Sha256};
+use zkedit_transformations::Transformation;
+use zkedit_zkp::file::png;
+
+use self::cbor::Value;
+pub(crate) use self::cose::Signer;
+use crate::container::{jpeg_boxes, jpeg_segments, jpeg_with_box, Container};
+use crate::jumbf::{box_payload, content_type, make_box, parse_superbox, superbox};
+
+// A C2PA manifest store is a JUMBF superbox of manifests, the active one last.
+// A manifest holds its assertions, the claim listing them by hashed URI and
+// the COSE signature of the claim. zkedit writes one for the edited image
+// with the usual actions and ingredient assertions plus `org.zkedit.proof`,
+// which carries the proof bundle, and copies the manifests of the original
+// in so the ingredient can point at them. A `c2pa.hash.data` assertion binds
+// the manifest to every byte of the file outside the store.
+//
+// Stores go in a `caBX` chunk of PNG files and in APP11 segments of JPEG
+// files, the containers the C2PA specification and zkedit both cover.
+
+const STORE_CONTENT: [u8; 16] = content_type(b"c2pa");
+const MANIFEST_CONTENT: [u8; 16] = content_type(b"c2ma");
+const ASSERTION_STORE_CONTENT: [u8; 16] = content_type(b"c2as");
+const CLAIM_CONTENT: [u8; 16] = content_type(b"c2cl");
+const SIGNATURE_CONTENT: [u8; 16] = content_type(b"c2cs");
+const CBOR_CONTENT: [u8; 16] = content_type(b"cbor");
+
+const STORE_LABEL: &str = "c2pa";
+const ASSERTION_STORE_LABEL: &str = "c2pa.assertions";
+const CLAIM_LABEL: &str = "c2pa.claim";
+const SIGNATURE_LABEL: &str = "c2pa.signature";
+const ACTIONS_ASSERTION: &str = "c2pa.actions";
+const INGREDIENT_ASSERTION: &str = "c2pa.ingredient";
+const DATA_HASH_ASSERTION: &str = "c2pa.hash.data";
+const PROOF_ASSERTION: &str = "org.zkedit.proof";
+
+const PNG_CHUNK: &[u8; 4] = b"caBX";
+
+pub(crate) struct Assertion {
+    pub(crate) label: String,
+    /// The assertion superbox, whole
+    bytes: Vec<u8>,
+    content: Value,
+}
+
+pub(crate) struct Manifest {
+    pub(crate) label: String,
+    /// The manifest superbox, whole
+    bytes: Vec<u8>,
+    claim: Vec<u8>,
+    signature: Vec<u8>,
+    pub(crate) assertions: Vec<Assertion>,
+}
+
+pub(crate) struct ManifestStore {
+    pub(crate) manifests: Vec<Manifest>,
+}
+
+/// The manifest store embedded in `file`, if any.
+pub(crate) fn read_store(file: &[u8]) -> Result<Option<ManifestStore>> {
+    let store = match Container::detect(file)? {
+        Container::Png => png::find_chunk(file, PNG_CHUNK)?.map(|store| store.to_vec()),
+        Container::Jpeg => {
+            let (segments, _) = jpeg_segments(file)?;
+            jpeg_boxes(file, &segments)?
+                .into_values()
+                .map(|(jumb, _)| jumb)
+                .find(|jumb| {
+                    parse_superbox(jumb)
+                        .is_some_and(|superbox| superbox.label.as_deref() == Some(STORE_LABEL))
+                })
+        }
+        Container::WebP | Container::Isobmff => None,
+    };
+    store
+        .map(|store| ManifestStore::from_bytes(&store))
+        .transpose()
+}
+
+fn labelled<'a>(bytes: &'a [u8], content: &[u8; 16], label: Option<&str>) -> Result<Vec<&'a [u8]>> {
+    let superbox = parse_superbox(bytes).ok_or_else(|| anyhow!("a C2PA box isn't a superbox"))?;
+    if &superbox.content_type != content
+        || label.is_some_and(|label| superbox.label.as_deref() != Some(label))
+    {
+        bail!("unexpected C2PA box {}", superbox.label.unwrap_or_default());
+    }
+    Ok(superbox.children)
+}
+
+/// The CBOR in the `cbor` box of a superbox.
+fn cbor_content<'a>(children: &[&'a [u8]]) -> Result<&'a [u8]> {
+    match children.first().and_then(|child| box_payload(child)) {
+        Some((box_type, payload)) if &box_type == b"cbor" => Ok(payload),
+        _ => bail!("a C2PA box has no CBOR content"),
+    }
+}
+
+impl ManifestStore {
+    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let manifests = labelled(bytes, &STORE_CONTENT, Some(STORE_LABEL))?
+            .into_iter()
+            .map(Manifest::from_bytes)
+            .collect::<Result<_>>()?;
+        Ok(ManifestStore { manifests })
+    }
+
+    pub(crate) fn active(&self) -> Option<&Manifest> {
+        self.manifests.last()
+    }
+}
+
+impl Manifest {
+    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let label = parse_superbox(bytes)
+            .and_then(|superbox| superbox.label)
+            .ok_or_else(|| anyhow!("a C2PA manifest has no label"))?;
+        let (mut assertions, mut claim, mut signature) = (None, None, None);
+        for child in labelled(bytes, &MANIFEST_CONTENT, None)? {
+            match parse_superbox(child)
+                .and_then(|superbox| superbox.label)
+                .as_deref()
+            {
+                Some(ASSERTION_STORE_LABEL) => {
+                    assertions = Some(
+                        labelled(child, &ASSERTION_STORE_CONTENT, None)?
+                            .into_iter()
+                            .map(Assertion::from_bytes)
+                            .collect::<Result<Vec<_>>>()?,
+                    )
+                }
+                Some(CLAIM_LABEL) => {
+                    claim = Some(cbor_content(&labelled(child, &CLAIM_CONTENT, None)?)?.to_vec())
+                }
+                Some(SIGNATURE_LABEL) => {
+                    signature =
+                        Some(cbor_content(&labelled(child, &SIGNATURE_CONTENT, None)?)?.to_vec())
+                }
+                _ => {}
+            }
+        }
+        Ok(Manifest {
+            bytes: bytes.to_vec(),
+            claim: claim.ok_or_else(|| anyhow!("the manifest {} has no claim", label))?,
+            signature: signature.ok_or_else(|| anyhow!("the manifest {} isn't signed", label))?,
+            assertions: assertions.unwrap_or_default(),
+            label,
+        })
+    }
+
+    fn assertion(&self, label: &str) -> Option<&Value> {
+        self.assertions
+            .iter()
+            .find(|assertion| assertion.label == label)
+            .map(|assertion| &assertion.content)
+    }
+
+    pub(crate) fn claim_generator(&self) -> Option<String> {
+        Value::from_bytes(&self.claim)
+            .ok()?
+            .get("claim_generator")?
+            .as_text()
+            .map(str::to_string)
+    }
+
+    /// The proof bundle of the `org.zkedit.proof` assertion.
+    pub(crate) fn proof_bundle(&self) -> Option<&[u8]> {
+        self.assertion(PROOF_ASSERTION)?.get("bundle")?.as_bytes()
+    }
+
+    /// Checks the signature of the claim and the hashes of the assertions it
+    /// lists.
+    pub(crate) fn validate(&self) -> Result<()> {
+        if !cose::verify(&self.signature, &self.claim)? {
+            bail!("the claim signature is invalid");
+        }
+        let claim = Value::from_bytes(&self.claim)?;
+        let assertions = claim
+            .get("assertions")
+            .and_then(Value::as_array)
+            .ok_or_else(|| anyhow!("the claim lists no assertions"))?;
+        for uri in assertions {
+            let url = uri.get("url").and_then(Value::as_text).unwrap_or_default();
+            let label = url.rsplit('/').next().unwrap_or_default();
+            let assertion = self
+                .assertions
+                .iter()
+                .find(|assertion| assertion.label == label)
+                .ok_or_else(|| anyhow!("the assertion {} is missing", label))?;
+            if uri.get("hash").and_then(Value::as_bytes)
+                != Some(box_hash(&assertion.bytes).as_slice())
+            {
+                bail!("the assertion {} was changed after signing", label);
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the data hash covers `file` outside its exclusions.
+    pub(crate) fn validate_binding(&self, file: &[u8]) -> Result<()> {
+        let data_hash = self
+            .assertion(DATA_HASH_ASSERTION)
+            .ok_or_else(|| anyhow!("the manifest isn't bound to the file by a data hash"))?;
+        let exclusions = data_hash
+            .get("exclusions")
+            .and_then(Value::as_array)
+            .unwrap_or_default()
+            .iter()
+            .map(|exclusion| {
+                let start = exclusion.get("start").and_then(Value::as_u64);
+                let length = exclusion.get("length").and_then(Value::as_u64);
+                match (start, length) {
+                    (Some(start), Some(length)) => match start.checked_add(length) {
+                        Some(end) if end <= file.len() as u64 => Ok(start as usize..end as usize),
+                        _ => bail!("a data hash exclusion runs past the end of the file"),
+                    },
+                    _ => bail!("a data hash exclusion has no start or length"),
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if data_hash.get("hash").and_then(Value::as_bytes)
+            != Some(data_hash_of(file, &exclusions).as_slice())
+        {
+            bail!("the file was changed after signing");
+        }
+        Ok(())
+    }
+}
+
+impl Assertion {
+    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let superbox =
+            parse_superbox(bytes).ok_or_else(|| anyhow!("an assertion isn't a superbox"))?;
+        let label = superbox
+            .label
+            .ok_or_else(|| anyhow!("an assertion has no label"))?;
+        // Assertions of other serializations are kept for their hashes
+        let content = match superbox.content_type == CBOR_CONTENT {
+            true => Value::from_bytes(cbor_content(&superbox.children)?)?,
+            false => Value::Null,
+        };
+        Ok(Assertion {
+            label,
+            bytes: bytes.to_vec(),
+            content,
+        })
+    }
+}
+
+/// SHA-256 of a superbox without its header, as hashed URIs take it.
+fn box_hash(bytes: &[u8]) -> Vec<u8> {
+    Sha256::digest(&bytes[8..]).to_vec()
+}
+
+fn hashed_uri(url: String, hash: Vec<u8>) -> Value {
+    Value::map([
+        ("url", Value::Text(url)),
+        ("alg", Value::text("sha256")),
+        ("hash", Value::Bytes(hash)),
+    ])
+}
+
+fn data_hash_of(file: &[u8], exclusions: &[Range<usize>]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    let mut offset = 0;
+    for exclusion in exclusions {
+        hasher.update(&file[offset..exclusion.start.clamp(offset, file.len())]);
+        offset = offset.max(exclusion.end.min(file.len()));
+    }
+    hasher.update(&file[offset..]);
+    hasher.finalize().to_vec()
+}
+
+/// A `urn:uuid` of the first bytes of a SHA-256 digest, as a random UUID.
+fn uuid_of(bytes: &[u8]) -> String {
+    let mut uuid: [u8; 16] = Sha256::digest(bytes)[..16].try_into().unwrap();
+    uuid[6] = (uuid[6] & 0x0f) | 0x40;
+    uuid[8] = (uuid[8] & 0x3f) | 0x80;
+    let hex: String = uuid.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+fn mime_type(file: &[u8]) -> &'static str {
+    match Container::detect(file) {
+        Ok(Container::Png) => "image/png",
+        Ok(Container::Jpeg) => "image/jpeg",
+        Ok(Container::WebP) => "image/webp",
+        Ok(Container::Isobmff) if file.get(8..12) == Some(b"avif") => "image/avif",
+        Ok(Container::Isobmff) => "image/heif",
+        Err(_) => "application/octet-stream",
+    }
+}
+
+/// The C2PA action that describes the transformation.
+fn edit_action(transformation: &Transformation) -> &'static str {
+    match transformation {
+        Transformation::Crop { .. } | Transformation::HiddenCrop { .. } => "c2pa.cropped",
+        Transformation::ColorMatrix { .. }
+        | Transformation::Threshold { .. }
+        | Transformation::Posterize { .. }
+        | Transformation::Equalize { .. } => "c2pa.color_adjustments",
+        Transformation::Unsharp { .. } | Transformation::Median3x3 { .. } => "c2pa.filtered",
+        Transformation::PreserveOutside { .. } => "c2pa.edited",
+    }
+}
+
+/// The original an edit starts from.
+pub(crate) struct Ingredient<'a> {
+    pub(crate) title: &'a str,
+    pub(crate) file: &'a [u8],
+}
+
+/// `file`, the edited image, with a signed manifest store holding the
+/// manifests of the original and a new active manifest for the edit.
+pub(crate) fn sign_edited(
+    file: &[u8],
+    ingredient: Ingredient,
+    transformation: &Transformation,
+    bundle: &[u8],
+    signer: &Signer,
+) -> Result<Vec<u8>> {
+    let parent = read_store(ingredient.file)?;
+    let manifest_label = format!("urn:uuid:{}", uuid_of(&[file, bundle].concat()));
+
+    let mut ingredient_fields = vec![
+        (Value::text("dc:title"), Value::text(ingredient.title)),
+        (
+            Value::text("dc:format"),
+            Value::text(mime_type(ingredient.file)),
+        ),
+        (
+            Value::text("instanceID"),
+            Value::Text(format!("xmp:iid:{}", uuid_of(ingredient.file))),
+        ),
+        (Value::text("relationship"), Value::text("parentOf")),
+    ];
+    if let Some(parent) = parent.as_ref().and_then(ManifestStore::active) {
+        ingredient_fields.push((
+            Value::text("c2pa_manifest"),
+            hashed_uri(
+                format!("self#jumbf=/{}/{}", STORE_LABEL, parent.label),
+                box_hash(&parent.bytes),
+            ),
+        ));
+    }
+    let ingredient_assertion = assertion_box(INGREDIENT_ASSERTION, &Value::Map(ingredient_fields))?;
+    let actions = Value::map([(
+        "actions",
+        Value::Array(vec![
+            Value::map([
+                ("action", Value::text("c2pa.opened")),
+                (
+                    "parameters",
+                    Value::map([(
+                        "ingredient",
+                        hashed_uri(
+                            format!(
+                                "self#jumbf={}/{}",
+                                ASSERTION_STORE_LABEL, INGREDIENT_ASSERTION
+                            ),
+                            box_hash(&ingredient_assertion),
+                        ),
+                    )]),
+                ),
+            ]),
+            Value::map([
+                ("action", Value::text(edit_action(transformation))),
+                ("softwareAgent", Value::text("zkedit")),
+            ]),
+        ]),
+    )]);
+    let proof = Value::map([
+        (
+            "transformation",
+            Value::Text(serde_json::to_string(transformation)?),
+        ),
+        ("bundle", Value::Bytes(bundle.to_vec())),
+    ]);
+    let assertions = [
+        assertion_box(ACTIONS_ASSERTION, &actions)?,
+        ingredient_assertion,
+        assertion_box(PROOF_ASSERTION, &proof)?,
+    ];
+
+    let store = |exclusion: &Range<usize>, hash: Vec<u8>| -> Result<Vec<u8>> {
+        let data_hash = Value::map([
+            (
+                "exclusions",
+                Value::Array(vec![Value::map([
+                    ("start", Value::Unsigned(exclusion.start as u64)),
+                    ("length", Value::Unsigned(exclusion.len() as u64)),
+                ])]),
+            ),
+            ("name", Value::text("jumbf manifest")),
+            ("alg", Value::text("sha256")),
+            ("hash", Value::Bytes(hash)),
+            ("pad", Value::Bytes(Vec::new())),
+        ]);
+        let mut assertions = assertions.to_vec();
+        assertions.push(assertion_box(DATA_HASH_ASSERTION, &data_hash)?);
+        let manifest = manifest_box(&manifest_label, &assertions, file, signer)?;
+        let mut manifests: Vec<Vec<u8>> = parent
+            .iter()
+            .flat_map(|parent| &parent.manifests)
+            .map(|manifest| manifest.bytes.clone())
+            .collect();
+        manifests.push(manifest);
+        superbox(&STORE_CONTENT, STORE_LABEL, &manifests)
+    };
+
+    // The exclusion is written into the store it covers, the store is rebuilt
+    // until its size and so its place in the file settle. The digest has a
+    // fixed size, filling it in last doesn't move the store.
+    let mut exclusion = 0..0;
+    for _ in 0..8 {
+        let (signed, range) = with_store(file, &store(&exclusion, vec![0; 32])?)?;
+        if range == exclusion {
+            let hash = data_hash_of(&signed, &[range.clone()]);
+            return Ok(with_store(file, &store(&range, hash)?)?.0);
+        }
+        exclusion = range;
+    }
+    Err(anyhow!("the manifest store didn't settle in the file"))
+}
+
+fn assertion_box(label: &str, content: &Value) -> Result<Vec<u8>> {
+    superbox(
+        &CBOR_CONTENT,
+        label,
+        &[make_box(b"cbor", &content.to_bytes())?],
+    )
+}
+
+fn manifest_box(
+    label: &str,
+    assertions: &[Vec<u8>],
+    file: &[u8],
+    signer: &Signer,
+) -> Result<Vec<u8>> {
+    let assertion_uris = assertions
+        .iter()
+        .map(|assertion| {
+            let label = parse_superbox(assertion).unwrap().label.unwrap();
+            hashed_uri(
+                format!("self#jumbf={}/{}", ASSERTION_STORE_LABEL, label),
+                box_hash(assertion),
+            )
+        })
+        .collect();
+    let claim = Value::map([
+        (
+            "claim_generator",
+            Value::Text(format!("zkedit/{}", env!("CARGO_PKG_VERSION"))),
+        ),
+        (
+            "signature",
+            Value::Text(format!("self#jumbf={}", SIGNATURE_LABEL)),
+        ),
+        ("assertions", Value::Array(assertion_uris)),
+        ("dc:format", Value::text(mime_type(file))),
+        (
+            "instanceID",
+            Value::Text(format!("xmp:iid:{}", uuid_of(label.as_bytes()))),
+        ),
+        ("alg", Value::text("sha256")),
+    ])
+    .to_bytes();
+    let signature = signer.sign(&claim)?;
+    superbox(
+        &MANIFEST_CONTENT,
+        label,
+        &[
+            superbox(&ASSERTION_STORE_CONTENT, ASSERTION_STORE_LABEL, assertions)?,
+            superbox(&CLAIM_CONTENT, CLAIM_LABEL, &[make_box(b"cbor", &claim)?])?,
+            superbox(
+                &SIGNATURE_CONTENT,
+                SIGNATURE_LABEL,
+                &[make_box(b"cbor", &signature)?],
+            )?,
+        ],
+    )
+}
+
+/// `file` with `store` embedded in place of the store it carries, and the
+/// bytes the store takes up, headers included.
+fn with_store(file: &[u8], store: &[u8]) -> Result<(Vec<u8>, Range<usize>)> {
+    match Container::detect(file)? {
+        Container::Png => {
+            // The chunk goes right before the 12 bytes of IEND
+            let with_store = png::with_chunk(file, PNG_CHUNK, store)?;
+            let end = with_store.len() - 12;
+            Ok((with_store, end - store.len() - 12..end))
+        }
+        Container::Jpeg => jpeg_with_box(file, store, STORE_LABEL),
+        Container::WebP | Container::Isobmff => {
+            bail!("only PNG and JPEG images can carry a C2PA manifest")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::{embed_bundle, extract_bundle};
+
+    #[test]
+    fn test_data_hash_skips_exclusions() {
+        let file = b"before|store|after";
+        assert_eq!(
+            data_hash_of(file, &[7..13]),
+            Sha256::digest(b"before|after").to_vec()
+        );
+    }
+
+    fn png_file(pixels: &[u8]) -> Vec<u8> {
+        let mut file = Vec::new();
+        image::RgbImage::from_raw(2, 2, pixels.to_vec())
+            .unwrap()
+            .write_to(
+                &mut std::io::Cursor::new(&mut file),
+                image::ImageOutputFormat::Png,
+            )
+            .unwrap();
+        file
+    }
+
+    #[test]
+    fn test_signed_manifest_round_trip() {
+        let original = png_file(&[7; 12]);
+        let transformation = Transformation::Threshold { level: 128 };
+        let bundle = b"proof bundle".to_vec();
+        let edited = embed_bundle(
+            &png_file(&[0, 255, 0, 255, 0, 255, 0, 255, 0, 255, 0, 255]),
+            &bundle,
+        )
+        .unwrap();
+        let ingredient = Ingredient {
+            title: "original.png",
+            file: &original,
+        };
+        let signer = Signer::from_hex("c0ffee").unwrap();
+        let signed = sign_edited(&edited, ingredient, &transformation, &bundle, &signer).unwrap();
+
+        assert_eq!(extract_bundle(&signed).unwrap(), Some(bundle.clone()));
+        let store = read_store(&signed).unwrap().unwrap();
+        let manifest = store.active().unwrap();
+        assert_eq!(manifest.proof_bundle(), Some(bundle.as_slice()));
+        manifest.validate().unwrap();
+        manifest.validate_binding(&signed).unwrap();
+        assert_eq!(
+            image::load_from_memory(&signed)
+                .unwrap()
+                .to_rgb8()
+                .into_raw(),
+            image::load_from_memory(&edited)
+                .unwrap()
+                .to_rgb8()
+                .into_raw()
+        );
+    }
+
+    #[test]
+    fn test_binding_rejects_changed_and_truncated_files() {
+        let file = png_file(&[7; 12]);
+        let ingredient = Ingredient {
+            title: "original.png",
+            file: &file,
+        };
+        let signer = Signer::from_hex("c0ffee").unwrap();
+        let transformation = Transformation::Threshold { level: 128 };
+        let signed = sign_edited(&file, ingredient, &transformation, b"bundle", &signer).unwrap();
+        let store = read_store(&signed).unwrap().unwrap();
+        let manifest = store.active().unwrap();
+        manifest.validate_binding(&signed).unwrap();
+
+        let pixel_data = png::find_chunk(&signed, b"IDAT").unwrap().unwrap();
+        let mut tampered = signed.clone();
+        tampered[pixel_data.as_ptr() as usize - signed.as_ptr() as usize] ^= 1;
+        assert!(manifest.validate_binding(&tampered).is_err());
+        // Cut inside the store, its exclusion runs past the end
+        assert!(manifest
+            .validate_binding(&signed[..signed.len() - 20])
+            .is_err());
+    }
+}
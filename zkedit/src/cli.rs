@@ -1,4 +1,9 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
 use structopt::StructOpt;
+use zkedit_transformations::Region;
+use zkedit_zkp::signature::SignatureScheme;
 
 #[derive(Clone, StructOpt, Debug)]
 #[structopt(
@@ -11,6 +16,171 @@ pub enum Zkedit {
         #[structopt(short = "i")]
         orig_img_path: String,
 
+        /// A path to a hex device key, signs the original and keeps its hash
+        /// out of the proof
+        #[structopt(short = "k", long = "device-key")]
+        device_key_path: Option<String>,
+
+        /// Signature scheme of the device key, secp256k1, p256 or schnorr
+        #[structopt(short = "s", long = "signature-scheme", default_value = "secp256k1")]
+        signature_scheme: SignatureScheme,
+
+        /// Salts the original hash and writes the salt to original.salt, it
+        /// opens the hash together with the original
+        #[structopt(long = "salt")]
+        salted: bool,
+
+        /// Commits to the images with a Merkle tree over tiles, chunks the
+        /// edit doesn't touch are then left unproven
+        #[structopt(long = "merkle")]
+        merkle: bool,
+
+        /// Packs 7 bytes of pixels into each field element, fewer hash
+        /// permutations for more work splitting them in the circuits
+        #[structopt(long = "dense")]
+        dense: bool,
+
+        /// A path to a hex P-256 key, signs a C2PA manifest for the edited
+        /// image that carries the proof and refers to the original's manifest
+        #[structopt(long = "c2pa-key")]
+        c2pa_key_path: Option<String>,
+
+        #[structopt(subcommand)]
+        edit: Edit,
+    },
+
+    /// Proves several edits of one original in one run, hashing the original
+    /// once. Writes each edit to img_edit_<n>.png with its proof embedded and
+    /// the proof set proofs.zkes, which maps each file to its proof
+    ProveSet {
+        /// A path to original img file
+        #[structopt(short = "i")]
+        orig_img_path: String,
+
+        /// An edit as name=arguments, e.g. crop=55,30,446,361, saturation=1.5,
+        /// unsharp=100,1,0 or median=10,10,40,40;80,80,20,20, can be repeated
+        #[structopt(short = "t", long = "edit", required = true)]
+        edits: Vec<Edit>,
+
+        /// A path to a hex device key, signs the original and keeps its hash
+        /// out of the proofs
+        #[structopt(short = "k", long = "device-key")]
+        device_key_path: Option<String>,
+
+        /// Signature scheme of the device key, secp256k1, p256 or schnorr
+        #[structopt(short = "s", long = "signature-scheme", default_value = "secp256k1")]
+        signature_scheme: SignatureScheme,
+
+        /// Salts the original hash and writes the salt to original.salt
+        #[structopt(long = "salt")]
+        salted: bool,
+
+        /// Commits to the images with a Merkle tree over tiles
+        #[structopt(long = "merkle")]
+        merkle: bool,
+
+        /// Packs 7 bytes of pixels into each field element
+        #[structopt(long = "dense")]
+        dense: bool,
+    },
+
+    Verify {
+        /// A path to the original img file
+        #[structopt(short = "e")]
+        edited_image_path: String,
+
+        /// A path to the proof bundle or proof set, read from the edited image
+        /// or its C2PA manifest if left out
+        #[structopt(short = "m")]
+        metadata_path: Option<String>,
+    },
+
+    /// Checks that an original and its salt open the salted original hash
+    Open {
+        /// A path to the original img file
+        #[structopt(short = "i")]
+        orig_img_path: String,
+
+        /// A path to the proof bundle
+        #[structopt(short = "m")]
+        metadata_path: String,
+
+        /// A path to the salt file
+        #[structopt(short = "s")]
+        salt_path: String,
+    },
+
+    /// Embeds a proof bundle in a PNG, JPEG, WebP or ISOBMFF (AVIF, HEIF)
+    /// copy of the edited image, which has to decode to the proven pixels
+    Embed {
+        /// A path to the edited img file, the bundle is written into it
+        #[structopt(short = "e")]
+        edited_image_path: String,
+
+        /// A path to the proof bundle
+        #[structopt(short = "m")]
+        metadata_path: String,
+    },
+
+    /// Lists the C2PA manifests of an image and checks their signatures,
+    /// and that the active one is bound to the image
+    C2paInfo {
+        /// A path to the img file
+        #[structopt(short = "i")]
+        img_path: String,
+    },
+
+    /// Writes a proof bundle as proof.json, with hex hashes and a base64
+    /// proof
+    ExportJson {
+        /// A path to the proof bundle
+        #[structopt(short = "m")]
+        metadata_path: String,
+    },
+
+    /// Writes the proof bundle proof.zked back from a JSON export
+    ImportJson {
+        /// A path to the JSON export
+        #[structopt(short = "j")]
+        json_path: String,
+    },
+
+    /// Proves that an uncompressed BMP, PPM, PAM, TIFF or stored PNG file
+    /// holds the pixels of an original, writes file_link.json
+    LinkFile {
+        /// A path to the original img file
+        #[structopt(short = "i")]
+        orig_img_path: String,
+
+        /// A path to a hex device key, signs the file and keeps its hash out
+        /// of the proof
+        #[structopt(short = "k", long = "device-key")]
+        device_key_path: Option<String>,
+
+        /// Signature scheme of the device key, secp256k1, p256 or schnorr
+        #[structopt(short = "s", long = "signature-scheme", default_value = "secp256k1")]
+        signature_scheme: SignatureScheme,
+
+        /// Packs the pixels densely, for proofs made with --dense
+        #[structopt(long = "dense")]
+        dense: bool,
+    },
+
+    /// Checks a file link and that it is about the original of a proof
+    VerifyLink {
+        /// A path to the file link
+        #[structopt(short = "l")]
+        link_path: String,
+
+        /// A path to the proof bundle
+        #[structopt(short = "m")]
+        metadata_path: String,
+    },
+}
+
+#[derive(Clone, StructOpt, Debug)]
+pub enum Edit {
+    Crop {
         /// X coordinate of crop upper left pixel
         #[structopt(short = "x", default_value = "55")]
         crop_x: u32,
@@ -28,17 +198,162 @@ pub enum Zkedit {
         crop_h: u32,
     },
 
-    Verify {
-        /// A path to the original img file
+    Sepia,
+
+    Saturation {
+        /// Saturation multiplier, 0 gives grayscale
+        #[structopt(short = "s", default_value = "1.5")]
+        scale: f64,
+    },
+
+    HueRotate {
+        /// Rotation angle in degrees
+        #[structopt(short = "d", default_value = "90")]
+        degrees: f64,
+    },
+
+    Threshold {
+        /// Channels at or above the level become white, the rest black
+        #[structopt(short = "l", default_value = "128")]
+        level: u8,
+    },
+
+    Posterize {
+        /// Number of values left per channel
+        #[structopt(short = "l", default_value = "4")]
+        levels: u8,
+    },
+
+    Unsharp {
+        /// Sharpening strength in percent
+        #[structopt(short = "a", default_value = "100")]
+        amount: u32,
+
+        /// Radius of the blur window
+        #[structopt(short = "r", default_value = "1")]
+        radius: u32,
+
+        /// Minimal difference from the blur that gets sharpened
+        #[structopt(short = "t", default_value = "0")]
+        threshold: u8,
+    },
+
+    Median {
+        /// Region to denoise as x,y,w,h, can be repeated
+        #[structopt(short = "r", long = "region", required = true)]
+        regions: Vec<Region>,
+    },
+
+    Equalize,
+
+    /// Crop that keeps its position in the original secret
+    HiddenCrop {
+        /// X coordinate of crop upper left pixel
+        #[structopt(short = "x")]
+        crop_x: u32,
+
+        /// Y coordinate of crop upper left pixel
+        #[structopt(short = "y")]
+        crop_y: u32,
+
+        /// Width of the crop
+        #[structopt(short = "w")]
+        crop_w: u32,
+
+        /// Height of the crop
+        #[structopt(short = "h")]
+        crop_h: u32,
+    },
+
+    /// Proves that only the regions of an image edited elsewhere changed
+    PreserveOutside {
+        /// A path to the edited img file
         #[structopt(short = "e")]
-        edited_image_path: String,
+        edited_img_path: String,
 
-        /// A path to the metadata file
-        #[structopt(short = "m")]
-        metadata_path: String,
+        /// Region that may have changed as x,y,w,h, can be repeated
+        #[structopt(short = "r", long = "region", required = true)]
+        regions: Vec<Region>,
     },
 }
 
+fn edit_arguments<T: FromStr>(name: &str, arguments: &str, count: usize) -> Result<Vec<T>, String>
+where
+    T::Err: Display,
+{
+    let values = arguments
+        .split(',')
+        .map(|value| value.trim().parse::<T>().map_err(|err| err.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    if values.len() != count {
+        return Err(format!("{} takes {} arguments", name, count));
+    }
+    Ok(values)
+}
+
+impl FromStr for Edit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, arguments) = s.split_once('=').unwrap_or((s, ""));
+        let edit = match name {
+            "crop" | "hidden-crop" => {
+                let values = edit_arguments::<u32>(name, arguments, 4)?;
+                let (crop_x, crop_y, crop_w, crop_h) = (values[0], values[1], values[2], values[3]);
+                match name {
+                    "crop" => Edit::Crop {
+                        crop_x,
+                        crop_y,
+                        crop_w,
+                        crop_h,
+                    },
+                    _ => Edit::HiddenCrop {
+                        crop_x,
+                        crop_y,
+                        crop_w,
+                        crop_h,
+                    },
+                }
+            }
+            "sepia" => Edit::Sepia,
+            "equalize" => Edit::Equalize,
+            "saturation" => Edit::Saturation {
+                scale: edit_arguments(name, arguments, 1)?[0],
+            },
+            "hue-rotate" => Edit::HueRotate {
+                degrees: edit_arguments(name, arguments, 1)?[0],
+            },
+            "threshold" => Edit::Threshold {
+                level: edit_arguments(name, arguments, 1)?[0],
+            },
+            "posterize" => Edit::Posterize {
+                levels: edit_arguments(name, arguments, 1)?[0],
+            },
+            "unsharp" => {
+                let values = edit_arguments::<u32>(name, arguments, 3)?;
+                Edit::Unsharp {
+                    amount: values[0],
+                    radius: values[1],
+                    threshold: u8::try_from(values[2]).map_err(|err| err.to_string())?,
+                }
+            }
+            "median" => Edit::Median {
+                regions: arguments
+                    .split(';')
+                    .map(Region::from_str)
+                    .collect::<Result<_, _>>()?,
+            },
+            "preserve-outside" => {
+                return Err(
+                    "preserve-outside takes an image edited elsewhere, prove it alone".into(),
+                )
+            }
+            _ => return Err(format!("unknown edit {}", name)),
+        };
+        Ok(edit)
+    }
+}
+
 pub fn parse_options() -> Result<Zkedit, structopt::clap::Error> {
     Zkedit::from_args_safe()
 }
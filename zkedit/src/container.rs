@@ -0,0 +1,528 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use anyhow::{anyhow, bail, Result};
+use zkedit_zkp::file::png;
+
+use crate::jumbf::{box_payload, boxes, content_type, make_box, parse_superbox, superbox};
+
+// The proof bundle travels inside the edited image, in the place each
+// container keeps for application data, so decoders skip it and the pixels
+// don't change: a private PNG chunk, a JUMBF box split over APP11 segments in
+// JPEG, a RIFF chunk in WebP and a top-level `uuid` box in ISOBMFF files such
+// as AVIF and HEIF. Only the pixels are proven, the container around them
+// has to decode to exactly the edited pixels for `verify` to pass.
+
+/// PNG chunk the bundle is embedded in: ancillary, private and safe to copy,
+/// so decoders skip it and editors keep it.
+const PNG_CHUNK: &[u8; 4] = b"zkEd";
+const WEBP_CHUNK: &[u8; 4] = b"zkEd";
+/// Identifies the bundle in ISOBMFF `uuid` boxes and JUMBF content boxes
+const BUNDLE_UUID: [u8; 16] = [
+    0x23, 0x09, 0xbb, 0x2f, 0xb9, 0x75, 0x4b, 0xa5, 0x83, 0xde, 0xa4, 0xa8, 0x7c, 0x9e, 0xc7, 0xc1,
+];
+/// JUMBF content type of a box holding a `uuid` box
+const JUMBF_UUID_CONTENT: [u8; 16] = content_type(b"uuid");
+const JUMBF_LABEL: &str = "zkedit";
+
+const JPEG_SOS: u8 = 0xda;
+const JPEG_EOI: u8 = 0xd9;
+const JPEG_APP0: u8 = 0xe0;
+const JPEG_APP11: u8 = 0xeb;
+const JPEG_APP15: u8 = 0xef;
+/// Start of frame markers of lossless coding
+const JPEG_LOSSLESS_SOF: [u8; 4] = [0xc3, 0xc7, 0xcb, 0xcf];
+/// Box bytes per APP11 segment, what is left of 65535 bytes after the length,
+/// the `JP` tag, the box instance, the sequence number and the box header
+const APP11_BOX_BYTES: usize = 65535 - 2 - 2 - 2 - 4 - 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Container {
+    Png,
+    Jpeg,
+    WebP,
+    Isobmff,
+}
+
+impl Container {
+    pub(crate) fn detect(file: &[u8]) -> Result<Self> {
+        match file {
+            [0x89, b'P', b'N', b'G', ..] => Ok(Container::Png),
+            [0xff, 0xd8, ..] => Ok(Container::Jpeg),
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Ok(Container::WebP),
+            [_, _, _, _, b'f', b't', b'y', b'p', ..] => Ok(Container::Isobmff),
+            _ => Err(anyhow!(
+                "only PNG, JPEG, WebP and ISOBMFF (AVIF, HEIF) files can carry a proof"
+            )),
+        }
+    }
+}
+
+/// Why the encoding of `file` may not keep the pixels, `None` when it is
+/// lossless.
+pub(crate) fn lossy_encoding(file: &[u8]) -> Result<Option<&'static str>> {
+    Ok(match Container::detect(file)? {
+        Container::Png => None,
+        Container::Jpeg => {
+            let (segments, _) = jpeg_segments(file)?;
+            let lossless = segments
+                .iter()
+                .any(|(marker, _)| JPEG_LOSSLESS_SOF.contains(marker));
+            (!lossless).then_some("the JPEG is DCT-coded, which is lossy")
+        }
+        Container::WebP => {
+            let lossy = riff_chunks(file)?
+                .iter()
+                .any(|(fourcc, _)| fourcc == b"VP8 ");
+            lossy.then_some("the WebP is VP8-coded, which is lossy")
+        }
+        Container::Isobmff => Some("AVIF and HEIF images are usually lossy"),
+    })
+}
+
+/// `file` with `bundle` embedded, replacing the bundle it already carries.
+pub(crate) fn embed_bundle(file: &[u8], bundle: &[u8]) -> Result<Vec<u8>> {
+    match Container::detect(file)? {
+        Container::Png => png::with_chunk(file, PNG_CHUNK, bundle),
+        Container::Jpeg => Ok(jpeg_with_box(file, &jumbf_box(bundle)?, JUMBF_LABEL)?.0),
+        Container::WebP => webp_with_chunk(file, bundle),
+        Container::Isobmff => isobmff_with_box(file, bundle),
+    }
+}
+
+/// The bundle embedded in `file`, if any.
+pub(crate) fn extract_bundle(file: &[u8]) -> Result<Option<Vec<u8>>> {
+    Ok(match Container::detect(file)? {
+        Container::Png => png::find_chunk(file, PNG_CHUNK)?.map(|bundle| bundle.to_vec()),
+        Container::Jpeg => {
+            let (segments, _) = jpeg_segments(file)?;
+            jpeg_boxes(file, &segments)?
+                .values()
+                .find_map(|(jumb, _)| bundle_in_jumbf(jumb))
+        }
+        Container::WebP => riff_chunks(file)?
+            .into_iter()
+            .find(|(fourcc, _)| fourcc == WEBP_CHUNK)
+            .map(|(_, data)| file[data].to_vec()),
+        Container::Isobmff => boxes(file)?
+            .into_iter()
+            .find_map(|(box_type, data)| bundle_in_uuid_box(&box_type, &file[data]))
+            .map(|bundle| bundle.to_vec()),
+    })
+}
+
+/// `file` as the image decoder reads it: the WebP decoder rejects chunks it
+/// doesn't know, so the bundle chunk is dropped for it.
+pub(crate) fn decodable_file(file: &[u8]) -> Result<Cow<[u8]>> {
+    if Container::detect(file).ok() == Some(Container::WebP)
+        && riff_chunks(file)?
+            .iter()
+            .any(|(fourcc, _)| fourcc == WEBP_CHUNK)
+    {
+        return Ok(Cow::Owned(extended_webp_without_chunk(file)?));
+    }
+    Ok(Cow::Borrowed(file))
+}
+
+fn bundle_in_uuid_box<'a>(box_type: &[u8; 4], payload: &'a [u8]) -> Option<&'a [u8]> {
+    match (box_type, payload.strip_prefix(&BUNDLE_UUID)) {
+        (b"uuid", Some(bundle)) => Some(bundle),
+        _ => None,
+    }
+}
+
+/// A JUMBF superbox labelled `zkedit` holding the bundle in a `uuid` box.
+fn jumbf_box(bundle: &[u8]) -> Result<Vec<u8>> {
+    let mut content = BUNDLE_UUID.to_vec();
+    content.extend(bundle);
+    superbox(
+        &JUMBF_UUID_CONTENT,
+        JUMBF_LABEL,
+        &[make_box(b"uuid", &content)?],
+    )
+}
+
+fn bundle_in_jumbf(jumb: &[u8]) -> Option<Vec<u8>> {
+    let superbox = parse_superbox(jumb)?;
+    if superbox.content_type != JUMBF_UUID_CONTENT || superbox.label.as_deref() != Some(JUMBF_LABEL)
+    {
+        return None;
+    }
+    let (box_type, payload) = box_payload(superbox.children.first()?)?;
+    bundle_in_uuid_box(&box_type, payload).map(|bundle| bundle.to_vec())
+}
+
+/// Marker and range of each marker segment before the scan, and where the
+/// scan starts.
+pub(crate) fn jpeg_segments(file: &[u8]) -> Result<(Vec<(u8, Range<usize>)>, usize)> {
+    let mut segments = Vec::new();
+    let mut offset = 2;
+    loop {
+        match file.get(offset..offset + 2) {
+            // Fill byte
+            Some([0xff, 0xff]) => offset += 1,
+            Some([0xff, JPEG_SOS | JPEG_EOI]) => return Ok((segments, offset)),
+            Some(&[0xff, marker]) => {
+                let len = file
+                    .get(offset + 2..offset + 4)
+                    .ok_or_else(|| anyhow!("the JPEG is truncated"))?;
+                let end = offset + 2 + u16::from_be_bytes([len[0], len[1]]) as usize;
+                if end > file.len() || end < offset + 4 {
+                    bail!("the JPEG is truncated");
+                }
+                segments.push((marker, offset..end));
+                offset = end;
+            }
+            _ => bail!("the JPEG has no scan"),
+        }
+    }
+}
+
+/// The boxes carried by APP11 segments by box instance, with the segments
+/// each is split over.
+pub(crate) fn jpeg_boxes(
+    file: &[u8],
+    segments: &[(u8, Range<usize>)],
+) -> Result<BTreeMap<u16, (Vec<u8>, Vec<usize>)>> {
+    let mut pieces: BTreeMap<u16, Vec<(u32, usize)>> = BTreeMap::new();
+    for (index, (marker, range)) in segments.iter().enumerate() {
+        let data = &file[range.start + 4..range.end];
+        if *marker == JPEG_APP11 && data.len() >= 16 && data.starts_with(b"JP") {
+            let instance = u16::from_be_bytes([data[2], data[3]]);
+            let sequence = u32::from_be_bytes(data[4..8].try_into().unwrap());
+            pieces.entry(instance).or_default().push((sequence, index));
+        }
+    }
+    pieces
+        .into_iter()
+        .map(|(instance, mut pieces)| {
+            pieces.sort();
+            let mut jumb = Vec::new();
+            for (i, (_, index)) in pieces.iter().enumerate() {
+                let data = &file[segments[*index].1.start + 12..segments[*index].1.end];
+                // Every segment repeats the box header
+                let header_len = match data[..4] {
+                    [0, 0, 0, 1] => 16,
+                    _ => 8,
+                };
+                if data.len() < header_len {
+                    bail!("an APP11 segment is truncated");
+                }
+                jumb.extend(&data[if i == 0 { 0 } else { header_len }..]);
+            }
+            let indexes = pieces.into_iter().map(|(_, index)| index).collect();
+            Ok((instance, (jumb, indexes)))
+        })
+        .collect()
+}
+
+/// `file` with `jumb` in APP11 segments after the leading APP segments, in
+/// place of the superbox labelled `label` it carries. Also returns where the
+/// segments are.
+pub(crate) fn jpeg_with_box(
+    file: &[u8],
+    jumb: &[u8],
+    label: &str,
+) -> Result<(Vec<u8>, Range<usize>)> {
+    let (segments, scan) = jpeg_segments(file)?;
+    let jpeg_boxes = jpeg_boxes(file, &segments)?;
+    let mut dropped = Vec::new();
+    for (jumb, indexes) in jpeg_boxes.values() {
+        if parse_superbox(jumb).is_some_and(|superbox| superbox.label.as_deref() == Some(label)) {
+            dropped.extend(indexes);
+        }
+    }
+    let instance = (1..=u16::MAX)
+        .find(|instance| {
+            jpeg_boxes.get(instance).map_or(true, |(_, indexes)| {
+                indexes.iter().all(|i| dropped.contains(i))
+            })
+        })
+        .ok_or_else(|| anyhow!("the JPEG has no free APP11 box instance"))?;
+
+    let mut app11 = Vec::new();
+    for (sequence, piece) in jumb[8..].chunks(APP11_BOX_BYTES).enumerate() {
+        app11.extend([0xff, JPEG_APP11]);
+        app11.extend(((piece.len() + 18) as u16).to_be_bytes());
+        app11.extend(b"JP");
+        app11.extend(instance.to_be_bytes());
+        app11.extend((sequence as u32 + 1).to_be_bytes());
+        app11.extend(&jumb[..8]);
+        app11.extend(piece);
+    }
+
+    let leading_apps = segments
+        .iter()
+        .take_while(|(marker, _)| (JPEG_APP0..=JPEG_APP15).contains(marker))
+        .count();
+    let mut with_box = file[..2].to_vec();
+    let mut inserted = 0..0;
+    for (index, (_, range)) in segments.iter().enumerate() {
+        if index == leading_apps {
+            inserted = with_box.len()..with_box.len() + app11.len();
+            with_box.extend(&app11);
+        }
+        if !dropped.contains(&index) {
+            with_box.extend(&file[range.clone()]);
+        }
+    }
+    if leading_apps == segments.len() {
+        inserted = with_box.len()..with_box.len() + app11.len();
+        with_box.extend(&app11);
+    }
+    with_box.extend(&file[scan..]);
+    Ok((with_box, inserted))
+}
+
+/// FourCC and payload range of each chunk of a RIFF file.
+fn riff_chunks(file: &[u8]) -> Result<Vec<([u8; 4], Range<usize>)>> {
+    let mut chunks = Vec::new();
+    let mut offset = 12;
+    while offset < file.len() {
+        let header = file
+            .get(offset..offset + 8)
+            .ok_or_else(|| anyhow!("a RIFF chunk header is truncated"))?;
+        let len = u32::from_le_bytes(header[4..].try_into().unwrap()) as usize;
+        if offset + 8 + len > file.len() {
+            bail!("a RIFF chunk runs past the end of the file");
+        }
+        chunks.push((
+            header[..4].try_into().unwrap(),
+            offset + 8..offset + 8 + len,
+        ));
+        // Chunks are padded to an even length
+        offset += 8 + len + len % 2;
+    }
+    Ok(chunks)
+}
+
+/// The VP8X chunk of a simple WebP, only extended files may hold other
+/// chunks.
+fn webp_extended_header(file: &[u8], chunks: &[([u8; 4], Range<usize>)]) -> Result<Vec<u8>> {
+    let (fourcc, data) = chunks
+        .first()
+        .ok_or_else(|| anyhow!("the WebP has no image"))?;
+    let data = &file[data.clone()];
+    let (width, height, alpha) = match (fourcc, data) {
+        (b"VP8 ", [_, _, _, 0x9d, 0x01, 0x2a, w0, w1, h0, h1, ..]) => (
+            u16::from_le_bytes([*w0, *w1]) as u32 & 0x3fff,
+            u16::from_le_bytes([*h0, *h1]) as u32 & 0x3fff,
+            false,
+        ),
+        (b"VP8L", [0x2f, b0, b1, b2, b3, ..]) => {
+            let bits = u32::from_le_bytes([*b0, *b1, *b2, *b3]);
+            (
+                (bits & 0x3fff) + 1,
+                ((bits >> 14) & 0x3fff) + 1,
+                // The lossless decoder always yields alpha, an unflagged
+                // VP8L in an extended file would decode to RGB
+                true,
+            )
+        }
+        _ => bail!("the WebP image data is corrupt"),
+    };
+    let mut header = b"VP8X".to_vec();
+    header.extend(10u32.to_le_bytes());
+    header.push(if alpha { 0x10 } else { 0 });
+    header.extend([0; 3]);
+    header.extend(&(width - 1).to_le_bytes()[..3]);
+    header.extend(&(height - 1).to_le_bytes()[..3]);
+    Ok(header)
+}
+
+/// `file` as an extended WebP without the bundle chunk.
+fn extended_webp_without_chunk(file: &[u8]) -> Result<Vec<u8>> {
+    let chunks = riff_chunks(file)?;
+    let mut without_chunk = file[..12].to_vec();
+    if !chunks.iter().any(|(fourcc, _)| fourcc == b"VP8X") {
+        without_chunk.extend(webp_extended_header(file, &chunks)?);
+    }
+    for (fourcc, data) in chunks {
+        if &fourcc != WEBP_CHUNK {
+            without_chunk.extend(&file[data.start - 8..data.end]);
+            if data.len() % 2 == 1 {
+                without_chunk.push(0);
+            }
+        }
+    }
+    set_riff_len(&mut without_chunk)?;
+    Ok(without_chunk)
+}
+
+fn set_riff_len(file: &mut [u8]) -> Result<()> {
+    let riff_len = u32::try_from(file.len() - 8).map_err(|_| anyhow!("the file is too large"))?;
+    file[4..8].copy_from_slice(&riff_len.to_le_bytes());
+    Ok(())
+}
+
+/// `file` with the bundle in a chunk at its end, where extended WebPs may
+/// hold unknown chunks.
+fn webp_with_chunk(file: &[u8], bundle: &[u8]) -> Result<Vec<u8>> {
+    let mut with_chunk = extended_webp_without_chunk(file)?;
+    let len = u32::try_from(bundle.len()).map_err(|_| anyhow!("the proof is too large"))?;
+    with_chunk.extend(WEBP_CHUNK);
+    with_chunk.extend(len.to_le_bytes());
+    with_chunk.extend(bundle);
+    if bundle.len() % 2 == 1 {
+        with_chunk.push(0);
+    }
+    set_riff_len(&mut with_chunk)?;
+    Ok(with_chunk)
+}
+
+/// `file` with the bundle in a `uuid` box at its end. Boxes before it keep
+/// their offsets, which the item locations of AVIF and HEIF refer to.
+fn isobmff_with_box(file: &[u8], bundle: &[u8]) -> Result<Vec<u8>> {
+    let boxes = boxes(file)?;
+    let mut with_box = file.to_vec();
+    if let Some((box_type, data)) = boxes.last() {
+        let start = boxes.iter().rev().nth(1).map_or(0, |(_, data)| data.end);
+        if bundle_in_uuid_box(box_type, &file[data.clone()]).is_some() {
+            with_box.truncate(start);
+        } else if file[start..start + 4] == [0, 0, 0, 0] {
+            // The last box runs to the end of the file, its size has to be
+            // spelled out before a box follows
+            let len =
+                u32::try_from(data.end - start).map_err(|_| anyhow!("the file is too large"))?;
+            with_box[start..start + 4].copy_from_slice(&len.to_be_bytes());
+        }
+    }
+    if boxes[..boxes.len().saturating_sub(1)]
+        .iter()
+        .any(|(box_type, data)| bundle_in_uuid_box(box_type, &file[data.clone()]).is_some())
+    {
+        bail!("the proof box isn't the last box of the file, removing it would move the media");
+    }
+    let mut payload = BUNDLE_UUID.to_vec();
+    payload.extend(bundle);
+    with_box.extend(make_box(b"uuid", &payload)?);
+    Ok(with_box)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_jpeg_bundle_round_trip() {
+        // SOI, an APP0 and a DQT segment, the scan and EOI
+        let mut file = vec![0xff, 0xd8, 0xff, JPEG_APP0, 0, 4, 1, 2];
+        file.extend([0xff, 0xdb, 0, 3, 9]);
+        file.extend([0xff, JPEG_SOS, 0, 2, 7, 7, 0xff, JPEG_EOI]);
+        let bundle: Vec<u8> = (0..150_000).map(|i| i as u8).collect();
+
+        let embedded = embed_bundle(&file, b"old").unwrap();
+        let embedded = embed_bundle(&embedded, &bundle).unwrap();
+        assert_eq!(extract_bundle(&embedded).unwrap(), Some(bundle));
+        let (segments, _) = jpeg_segments(&embedded).unwrap();
+        let markers: Vec<u8> = segments.iter().map(|(marker, _)| *marker).collect();
+        assert_eq!(
+            markers,
+            [JPEG_APP0, JPEG_APP11, JPEG_APP11, JPEG_APP11, 0xdb]
+        );
+        assert!(embedded.ends_with(&file[13..]));
+    }
+
+    /// A 2×2 lossless WebP, each pixel picks one of two greens and reds.
+    fn lossless_webp() -> Vec<u8> {
+        let mut bits = Vec::new();
+        let mut push = |value: u32, len: usize| bits.extend((0..len).map(|i| (value >> i) & 1));
+        // Width and height less one, no alpha hint, version 0, no transform,
+        // no color cache and no meta prefix codes
+        push(1, 14);
+        push(1, 14);
+        push(0, 4);
+        push(0, 3);
+        // Simple prefix codes: two 8-bit symbols for green and red, one
+        // for blue, alpha and the distance
+        for symbols in [[0x40, 0xc0], [0x10, 0x90]] {
+            push(0b111, 3);
+            push(symbols[0], 8);
+            push(symbols[1], 8);
+        }
+        for symbol in [0x33, 0xff] {
+            push(0b101, 3);
+            push(symbol, 8);
+        }
+        push(0b001, 4);
+        // Green and red of each pixel
+        push(0b10_01_11_00, 8);
+
+        let mut data = vec![0x2f];
+        data.extend(bits.chunks(8).map(|byte| {
+            byte.iter()
+                .enumerate()
+                .fold(0, |acc, (i, bit)| acc | (*bit as u8) << i)
+        }));
+        let mut file = b"RIFF\0\0\0\0WEBPVP8L".to_vec();
+        file.extend((data.len() as u32).to_le_bytes());
+        file.extend(&data);
+        if data.len() % 2 == 1 {
+            file.push(0);
+        }
+        set_riff_len(&mut file).unwrap();
+        file
+    }
+
+    fn decoded_pixels(file: &[u8]) -> Vec<u8> {
+        image::load_from_memory(&decodable_file(file).unwrap())
+            .unwrap()
+            .to_rgba8()
+            .into_raw()
+    }
+
+    #[test]
+    fn test_webp_bundle_round_trip() {
+        let file = lossless_webp();
+        let pixels = decoded_pixels(&file);
+        assert_eq!(
+            &pixels[..8],
+            [0x10, 0x40, 0x33, 0xff, 0x90, 0xc0, 0x33, 0xff]
+        );
+        let bundle: Vec<u8> = (0..301).map(|i| i as u8).collect();
+
+        let embedded = embed_bundle(&file, b"old").unwrap();
+        let embedded = embed_bundle(&embedded, &bundle).unwrap();
+        assert_eq!(extract_bundle(&embedded).unwrap(), Some(bundle));
+        let len = u32::from_le_bytes(embedded[4..8].try_into().unwrap());
+        assert_eq!(len as usize, embedded.len() - 8);
+        // The simple file is upgraded to an extended one, whose VP8X header
+        // has the canvas size and keeps the lossless alpha
+        let chunks = riff_chunks(&embedded).unwrap();
+        let fourccs: Vec<&[u8; 4]> = chunks.iter().map(|(fourcc, _)| fourcc).collect();
+        assert_eq!(fourccs, [b"VP8X", b"VP8L", WEBP_CHUNK]);
+        assert_eq!(
+            embedded[chunks[0].1.clone()],
+            [0x10, 0, 0, 0, 1, 0, 0, 1, 0, 0]
+        );
+        assert_eq!(embedded[chunks[1].1.clone()], file[20..file.len() - 1]);
+        assert_eq!(decoded_pixels(&embedded), pixels);
+    }
+
+    #[test]
+    fn test_isobmff_bundle_round_trip() {
+        // An ftyp box and a media box running to the end of the file
+        let mut file = make_box(b"ftyp", b"avifmif1").unwrap();
+        let media = file.len();
+        file.extend([0, 0, 0, 0]);
+        file.extend(b"mdat");
+        file.extend(1..=9);
+        let bundle: Vec<u8> = (0..70_000).map(|i| i as u8).collect();
+
+        let embedded = embed_bundle(&file, b"old").unwrap();
+        let embedded = embed_bundle(&embedded, &bundle).unwrap();
+        assert_eq!(extract_bundle(&embedded).unwrap(), Some(bundle));
+        let box_types: Vec<[u8; 4]> = boxes(&embedded)
+            .unwrap()
+            .into_iter()
+            .map(|(box_type, _)| box_type)
+            .collect();
+        assert_eq!(box_types, [*b"ftyp", *b"mdat", *b"uuid"]);
+        // The decoder reads the coded pixels at their item locations, the
+        // media keeps its bytes and offset and only its size is spelled out
+        assert_eq!(embedded[..media], file[..media]);
+        assert_eq!(embedded[media..media + 4], 17u32.to_be_bytes());
+        assert_eq!(embedded[media + 4..file.len()], file[media + 4..]);
+    }
+}
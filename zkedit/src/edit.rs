@@ -0,0 +1,192 @@
+use anyhow::{bail, Result};
+use image::{ColorType, DynamicImage, GrayImage, Luma, Rgb, RgbImage, RgbaImage};
+
+use zkedit_transformations::color::apply_color_matrix;
+use zkedit_transformations::filter::{apply_median3x3, apply_unsharp};
+use zkedit_transformations::histogram::apply_equalize;
+use zkedit_transformations::tone::{apply_posterize, apply_threshold};
+use zkedit_transformations::Transformation;
+use zkedit_zkp::pixel::PixelFormat;
+
+/// The format that keeps every sample of `img`. Gray with alpha is committed
+/// as RGBA, floating point samples have no format.
+pub(crate) fn pixel_format(img: &DynamicImage) -> Result<PixelFormat> {
+    Ok(match img.color() {
+        ColorType::L8 => PixelFormat::Luma8,
+        ColorType::L16 => PixelFormat::Luma16,
+        ColorType::Rgb8 => PixelFormat::Rgb8,
+        ColorType::Rgb16 => PixelFormat::Rgb16,
+        ColorType::La8 | ColorType::Rgba8 => PixelFormat::Rgba8,
+        ColorType::La16 | ColorType::Rgba16 => PixelFormat::Rgba16,
+        color => bail!("{:?} images aren't supported", color),
+    })
+}
+
+fn samples_to_bytes(samples: &[u16]) -> Vec<u8> {
+    samples
+        .iter()
+        .flat_map(|sample| sample.to_le_bytes())
+        .collect()
+}
+
+/// The pixel stream of `img` in `format`, samples are little-endian.
+pub(crate) fn pixel_stream(img: &DynamicImage, format: PixelFormat) -> Vec<u8> {
+    match format {
+        PixelFormat::Luma8 => img.to_luma8().into_raw(),
+        PixelFormat::Rgb8 => img.to_rgb8().into_raw(),
+        PixelFormat::Rgba8 => img.to_rgba8().into_raw(),
+        PixelFormat::Luma16 => samples_to_bytes(&img.to_luma16()),
+        PixelFormat::Rgb16 => samples_to_bytes(&img.to_rgb16()),
+        PixelFormat::Rgba16 => samples_to_bytes(&img.to_rgba16()),
+    }
+}
+
+fn align_crop_edit(
+    crop_bytes: &[u8],
+    bytes_per_pixel: usize,
+    orig_width: u32,
+    orig_height: u32,
+    crop_x: u32,
+    crop_y: u32,
+    crop_width: u32,
+) -> Vec<u8> {
+    let row_len = crop_width as usize * bytes_per_pixel;
+    let mut aligned = vec![0; orig_width as usize * orig_height as usize * bytes_per_pixel];
+    for (row, crop_row) in crop_bytes.chunks_exact(row_len).enumerate() {
+        let start =
+            ((crop_y as usize + row) * orig_width as usize + crop_x as usize) * bytes_per_pixel;
+        aligned[start..start + row_len].copy_from_slice(crop_row);
+    }
+    aligned
+}
+
+fn map_pixels(img: &RgbaImage, f: impl Fn([u8; 4]) -> [u8; 4]) -> RgbaImage {
+    let mut edited = img.clone();
+    for pixel in edited.pixels_mut() {
+        pixel.0 = f(pixel.0);
+    }
+    edited
+}
+
+/// Narrows a channel edit back to the 8-bit `format`, the way `pack_pixel`
+/// drops the channels the format doesn't store.
+fn from_rgba8(edited: RgbaImage, format: PixelFormat) -> DynamicImage {
+    let (width, height) = edited.dimensions();
+    match format {
+        PixelFormat::Luma8 => {
+            DynamicImage::ImageLuma8(GrayImage::from_fn(width, height, |x, y| {
+                Luma([edited.get_pixel(x, y)[0]])
+            }))
+        }
+        PixelFormat::Rgb8 => DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+            let [r, g, b, _] = edited.get_pixel(x, y).0;
+            Rgb([r, g, b])
+        })),
+        _ => DynamicImage::ImageRgba8(edited),
+    }
+}
+
+/// Natively performs the edit described by the transformation on an original
+/// of `format`.
+pub(crate) fn apply_transformation(
+    img: &DynamicImage,
+    format: PixelFormat,
+    transformation: &Transformation,
+) -> DynamicImage {
+    match transformation {
+        Transformation::Crop { x, y, w, h, .. } => img.crop_imm(*x, *y, *w, *h),
+        Transformation::HiddenCrop { w, h, position } => {
+            let position = position.expect("hidden crop position is needed to edit");
+            img.crop_imm(position.x, position.y, *w, *h)
+        }
+        // The regions are drawn over outside of zkedit
+        Transformation::PreserveOutside { .. } => img.clone(),
+        _ => from_rgba8(apply_channel_edit(&img.to_rgba8(), transformation), format),
+    }
+}
+
+/// Channel edits work on RGBA8 pixels, like their circuits see them.
+fn apply_channel_edit(img: &RgbaImage, transformation: &Transformation) -> RgbaImage {
+    match transformation {
+        Transformation::ColorMatrix { m, offset, shift } => {
+            map_pixels(img, |pixel| apply_color_matrix(m, offset, *shift, pixel))
+        }
+        Transformation::Threshold { level } => {
+            map_pixels(img, |pixel| apply_threshold(*level, pixel))
+        }
+        Transformation::Posterize { levels } => {
+            map_pixels(img, |pixel| apply_posterize(*levels, pixel))
+        }
+        Transformation::Unsharp {
+            orig_w,
+            orig_h,
+            amount,
+            radius,
+            threshold,
+        } => {
+            let pixels: Vec<[u8; 4]> = img.pixels().map(|pixel| pixel.0).collect();
+            let edited = apply_unsharp(&pixels, *orig_w, *orig_h, *amount, *radius, *threshold);
+            RgbaImage::from_raw(*orig_w, *orig_h, edited.concat()).unwrap()
+        }
+        Transformation::Median3x3 {
+            orig_w,
+            orig_h,
+            regions,
+        } => {
+            let pixels: Vec<[u8; 4]> = img.pixels().map(|pixel| pixel.0).collect();
+            let edited = apply_median3x3(&pixels, *orig_w, *orig_h, regions);
+            RgbaImage::from_raw(*orig_w, *orig_h, edited.concat()).unwrap()
+        }
+        Transformation::Equalize { orig_w, orig_h } => {
+            let pixels: Vec<[u8; 4]> = img.pixels().map(|pixel| pixel.0).collect();
+            let edited = apply_equalize(&pixels);
+            RgbaImage::from_raw(*orig_w, *orig_h, edited.concat()).unwrap()
+        }
+        Transformation::Crop { .. }
+        | Transformation::HiddenCrop { .. }
+        | Transformation::PreserveOutside { .. } => unreachable!("not a channel edit"),
+    }
+}
+
+/// Lays the edited image out as the pixel stream of `format` the circuit
+/// hashes, which always has the length of the original one.
+pub(crate) fn align_edit(
+    edited: &DynamicImage,
+    format: PixelFormat,
+    transformation: &Transformation,
+    original_length: usize,
+) -> Vec<u8> {
+    let bytes = pixel_stream(edited, format);
+    match transformation {
+        Transformation::Crop {
+            orig_w,
+            orig_h,
+            x,
+            y,
+            w,
+            h: _,
+        } => align_crop_edit(
+            &bytes,
+            format.bytes_per_pixel(),
+            *orig_w,
+            *orig_h,
+            *x,
+            *y,
+            *w,
+        ),
+        Transformation::ColorMatrix { .. }
+        | Transformation::Threshold { .. }
+        | Transformation::Posterize { .. }
+        | Transformation::Unsharp { .. }
+        | Transformation::Median3x3 { .. }
+        | Transformation::Equalize { .. }
+        | Transformation::PreserveOutside { .. } => bytes,
+        // the crop itself followed by zeros, an aligned crop would reveal its
+        // position
+        Transformation::HiddenCrop { .. } => {
+            let mut bytes = bytes;
+            bytes.resize(original_length, 0);
+            bytes
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use std::ops::Range;
+
+use anyhow::{anyhow, bail, Result};
+
+// JUMBF (ISO 19566-5) is the ISOBMFF box syntax plus `jumb` superboxes, which
+// open with a `jumd` box describing their content by a type UUID and a label.
+// The zkedit box of JPEG files and C2PA manifest stores are made of them.
+
+/// The content type UUID JUMBF derives from a four-letter type.
+pub(crate) const fn content_type(tag: &[u8; 4]) -> [u8; 16] {
+    [
+        tag[0], tag[1], tag[2], tag[3], 0x00, 0x11, 0x00, 0x10, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38,
+        0x9b, 0x71,
+    ]
+}
+
+pub(crate) fn make_box(box_type: &[u8; 4], payload: &[u8]) -> Result<Vec<u8>> {
+    let len = u32::try_from(payload.len() + 8).map_err(|_| anyhow!("a box is too large"))?;
+    let mut bytes = len.to_be_bytes().to_vec();
+    bytes.extend(box_type);
+    bytes.extend(payload);
+    Ok(bytes)
+}
+
+/// Type and payload range of each box in `bytes`.
+pub(crate) fn boxes(bytes: &[u8]) -> Result<Vec<([u8; 4], Range<usize>)>> {
+    let mut boxes = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let header = bytes
+            .get(offset..offset + 8)
+            .ok_or_else(|| anyhow!("a box header is truncated"))?;
+        let box_type: [u8; 4] = header[4..].try_into().unwrap();
+        let (header_len, len) = match u32::from_be_bytes(header[..4].try_into().unwrap()) {
+            0 => (8, bytes.len() - offset),
+            1 => {
+                let len = bytes
+                    .get(offset + 8..offset + 16)
+                    .ok_or_else(|| anyhow!("a box header is truncated"))?;
+                let len = u64::from_be_bytes(len.try_into().unwrap());
+                // A length past the address space runs past the end as well
+                (16, usize::try_from(len).unwrap_or(usize::MAX))
+            }
+            len => (8, len as usize),
+        };
+        let end = match offset.checked_add(len) {
+            Some(end) if len >= header_len && end <= bytes.len() => end,
+            _ => bail!("a box runs past the end of the file"),
+        };
+        boxes.push((box_type, offset + header_len..end));
+        offset = end;
+    }
+    Ok(boxes)
+}
+
+/// A `jumb` superbox with the content type and label of its description box.
+pub(crate) fn superbox(
+    content_type: &[u8; 16],
+    label: &str,
+    children: &[Vec<u8>],
+) -> Result<Vec<u8>> {
+    let mut description = content_type.to_vec();
+    // Requestable, with a label
+    description.push(0x03);
+    description.extend(label.as_bytes());
+    description.push(0);
+
+    let mut payload = make_box(b"jumd", &description)?;
+    for child in children {
+        payload.extend(child);
+    }
+    make_box(b"jumb", &payload)
+}
+
+pub(crate) struct Superbox<'a> {
+    pub(crate) content_type: [u8; 16],
+    pub(crate) label: Option<String>,
+    /// The boxes after the description box, whole
+    pub(crate) children: Vec<&'a [u8]>,
+}
+
+/// Reads `bytes` as a single `jumb` superbox.
+pub(crate) fn parse_superbox(bytes: &[u8]) -> Option<Superbox<'_>> {
+    let (box_type, payload) = boxes(bytes).ok()?.into_iter().next()?;
+    if &box_type != b"jumb" || payload.end != bytes.len() {
+        return None;
+    }
+    let payload = &bytes[payload];
+    let children = boxes(payload).ok()?;
+    let (description_type, description) = children.first()?;
+    let description = &payload[description.clone()];
+    if description_type != b"jumd" || description.len() < 17 {
+        return None;
+    }
+    let label = match description[16] & 0x02 {
+        0 => None,
+        _ => {
+            let label = &description[17..];
+            let end = label.iter().position(|byte| *byte == 0)?;
+            Some(String::from_utf8(label[..end].to_vec()).ok()?)
+        }
+    };
+    Some(Superbox {
+        content_type: description[..16].try_into().unwrap(),
+        label,
+        // Boxes follow each other, each ends where the next starts
+        children: children
+            .windows(2)
+            .map(|pair| &payload[pair[0].1.end..pair[1].1.end])
+            .collect(),
+    })
+}
+
+/// Type and payload of a single box.
+pub(crate) fn box_payload(bytes: &[u8]) -> Option<([u8; 4], &[u8])> {
+    let (box_type, payload) = boxes(bytes).ok()?.into_iter().next()?;
+    Some((box_type, &bytes[payload]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_boxes_reject_oversized_largesize() {
+        let mut bytes = make_box(b"free", b"data").unwrap();
+        // A largesize box whose end overflows the offset
+        bytes.extend([0, 0, 0, 1]);
+        bytes.extend(b"uuid");
+        bytes.extend(u64::MAX.to_be_bytes());
+        assert!(boxes(&bytes).is_err());
+
+        let boxes = boxes(&make_box(b"free", b"data").unwrap()).unwrap();
+        assert_eq!(boxes, vec![(*b"free", 8..12)]);
+    }
+}
@@ -1,73 +1,139 @@
+pub mod c2pa;
 pub mod cli;
+pub mod container;
+pub mod edit;
+pub mod jumbf;
 pub mod metadata;
 
 use std::fs;
+use std::io::Cursor;
+use std::path::Path;
 use std::time::Instant;
 
-use anyhow::Result;
-use cli::{parse_options, Zkedit};
-use image::imageops::crop;
-use image::ImageBuffer;
+use anyhow::{bail, Result};
+use cli::{parse_options, Edit, Zkedit};
 use log::LevelFilter;
 
 use image::io::Reader as ImageReader;
-use image::{buffer::Pixels, Rgba};
+use image::{DynamicImage, GenericImageView};
 
-use zkedit_transformations::Transformation;
+use zkedit_transformations::{CropPosition, Transformation};
 use zkedit_zkp::builder::TransformationCircuitBuilder;
-use zkedit_zkp::util::calculate_poseidon;
-
-use crate::metadata::ProofMetadata;
-
-fn pixels_to_bytes(pixels: Pixels<Rgba<u8>>) -> Vec<u8> {
-    let mut pixel_bytes = vec![];
-    for pixel in pixels {
-        pixel_bytes.extend_from_slice(&pixel.0)
-    }
-    pixel_bytes
-}
-
-fn align_crop_edit(
-    crop_img: ImageBuffer<Rgba<u8>, Vec<u8>>,
-    orig_width: u32,
-    orig_height: u32,
-    crop_x: u32,
-    crop_y: u32,
-    crop_width: u32,
-    crop_height: u32,
-) -> Vec<u8> {
-    let lx_bound = crop_x;
-    let rx_bound = crop_x + crop_width;
-    let uy_bound = crop_y;
-    let dy_bound = crop_y + crop_height;
-
-    let mut aligned_image = ImageBuffer::new(orig_width, orig_height);
-    for (x, y, pixel) in aligned_image.enumerate_pixels_mut() {
-        if x >= lx_bound && x < rx_bound && y >= uy_bound && y < dy_bound {
-            *pixel = crop_img.get_pixel(x - crop_x, y - crop_y).clone();
-        }
-    }
+use zkedit_zkp::circuit::OriginalWitness;
+use zkedit_zkp::commitment::{bind_dimensions, CommitmentScheme};
+use zkedit_zkp::file::link::FileLinkBuilder;
+use zkedit_zkp::file::FileLayout;
+use zkedit_zkp::pixel::{Packing, PixelLayout};
+use zkedit_zkp::proof::ProofEncoding;
+use zkedit_zkp::salt::{salted_original_hash, Salt};
+use zkedit_zkp::signature::{sign_file, sign_original, OriginalSignature, SignatureScheme};
+use zkedit_zkp::transformations::region::unauthenticated_regions;
+
+use crate::c2pa::{read_store, sign_edited, Ingredient, Signer};
+use crate::container::{decodable_file, embed_bundle, extract_bundle, lossy_encoding};
+use crate::edit::{align_edit, apply_transformation, pixel_format, pixel_stream};
+use crate::metadata::{to_hex, FileLinkMetadata, ProofMetadata, ProofSet, ProofSetMember};
 
-    pixels_to_bytes(aligned_image.pixels())
+const L: usize = 12 * 85 * 256;
+
+fn edit_to_transformation(edit: Edit, width: u32, height: u32) -> Transformation {
+    match edit {
+        Edit::Crop {
+            crop_x,
+            crop_y,
+            crop_w,
+            crop_h,
+        } => Transformation::Crop {
+            orig_w: width,
+            orig_h: height,
+            x: crop_x,
+            y: crop_y,
+            w: crop_w,
+            h: crop_h,
+        },
+        Edit::Sepia => Transformation::sepia(),
+        Edit::Saturation { scale } => Transformation::saturation(scale),
+        Edit::HueRotate { degrees } => Transformation::hue_rotation(degrees),
+        Edit::Threshold { level } => Transformation::Threshold { level },
+        Edit::Posterize { levels } => Transformation::Posterize { levels },
+        Edit::Unsharp {
+            amount,
+            radius,
+            threshold,
+        } => Transformation::Unsharp {
+            orig_w: width,
+            orig_h: height,
+            amount,
+            radius,
+            threshold,
+        },
+        Edit::Median { regions } => Transformation::Median3x3 {
+            orig_w: width,
+            orig_h: height,
+            regions,
+        },
+        Edit::Equalize => Transformation::Equalize {
+            orig_w: width,
+            orig_h: height,
+        },
+        Edit::HiddenCrop {
+            crop_x,
+            crop_y,
+            crop_w,
+            crop_h,
+        } => Transformation::HiddenCrop {
+            w: crop_w,
+            h: crop_h,
+            position: Some(CropPosition {
+                orig_w: width,
+                x: crop_x,
+                y: crop_y,
+            }),
+        },
+        Edit::PreserveOutside { regions, .. } => Transformation::PreserveOutside {
+            orig_w: width,
+            orig_h: height,
+            regions,
+        },
+    }
 }
 
-const L: usize = 12 * 85 * 256;
+/// The original and what every proof of an edit of it shares.
+struct Original {
+    img: DynamicImage,
+    layout: PixelLayout,
+    pixel_bytes: Vec<u8>,
+    witness: OriginalWitness<L>,
+    signature: Option<OriginalSignature>,
+    commitment: CommitmentScheme,
+    salted: bool,
+}
 
-fn prove_crop(
-    orig_img_path: String,
-    crop_x: u32,
-    crop_y: u32,
-    crop_w: u32,
-    crop_h: u32,
-) -> Result<()> {
-    let mut img = ImageReader::open(orig_img_path)?.decode()?.into_rgba8();
-    let pixels = img.pixels();
-    let width = img.width();
-    let height = img.height();
-    let bytes_length = width * height * 4;
+fn read_original(
+    orig_img_path: &str,
+    device_key_path: Option<String>,
+    signature_scheme: SignatureScheme,
+    salted: bool,
+    merkle: bool,
+    dense: bool,
+) -> Result<Original> {
+    let img = ImageReader::open(orig_img_path)?.decode()?;
+    let format = pixel_format(&img)?;
+    let layout = PixelLayout {
+        format,
+        packing: if dense {
+            Packing::Dense
+        } else {
+            Packing::Pixels
+        },
+    };
+    let (width, height) = img.dimensions();
+    let pixel_bytes = pixel_stream(&img, format);
+    let bytes_length = pixel_bytes.len();
 
     println!(
-        "Read image {}x{} pixels. {}B, {}kB, {}mB",
+        "Read {:?} image {}x{} pixels. {}B, {}kB, {}mB",
+        format,
         width,
         height,
         bytes_length,
@@ -75,46 +141,103 @@ fn prove_crop(
         bytes_length / (1024 * 1024)
     );
 
-    let pixel_bytes = pixels_to_bytes(pixels);
-    let pixels_hash = calculate_poseidon(&pixel_bytes);
+    let commitment = if merkle {
+        CommitmentScheme::MerkleTiles { width, height }
+    } else {
+        CommitmentScheme::Sponge
+    };
 
-    let crop_img = crop(&mut img, crop_x, crop_y, crop_w, crop_h).to_image();
+    // Stands in for the signature an attested camera produces at capture
+    let signature = match device_key_path {
+        Some(path) => {
+            let secret_key = fs::read_to_string(path)?;
+            Some(sign_original::<L>(
+                &pixel_bytes,
+                layout,
+                commitment,
+                signature_scheme,
+                &secret_key,
+            )?)
+        }
+        None => None,
+    };
 
-    println!(
-        "Crop image {}x{} pixels",
-        crop_img.width(),
-        crop_img.height()
-    );
+    let salt = if salted {
+        let salt = Salt::random();
+        fs::write("original.salt", salt.to_hex())?;
+        Some(salt)
+    } else {
+        None
+    };
 
-    let mut crop_bytes = pixels_to_bytes(crop_img.pixels());
-    crop_bytes.extend(std::iter::repeat(0).take(pixel_bytes.len() - crop_bytes.len()));
+    let witness = OriginalWitness::new(&pixel_bytes, layout, commitment, salt.as_ref());
+    Ok(Original {
+        img,
+        layout,
+        pixel_bytes,
+        witness,
+        signature,
+        commitment,
+        salted,
+    })
+}
 
-    crop_img.save("img_crop.png");
+/// Proves the edit, writes the edited image to `edited_img_path` with the
+/// proof bundle embedded and returns the bundle.
+fn prove_edit(
+    original: &Original,
+    transformation: Transformation,
+    edited_img: DynamicImage,
+    edited_img_path: &str,
+) -> Result<ProofMetadata> {
+    if let Transformation::HiddenCrop { .. } = transformation {
+        // The proof binds the private original dimensions to the commitment
+        if original.signature.is_some() || original.commitment != CommitmentScheme::Sponge {
+            bail!("a hidden crop needs an unsigned original without Merkle tiles");
+        }
+    }
+    println!(
+        "Edited image {}x{} pixels",
+        edited_img.width(),
+        edited_img.height()
+    );
 
-    let alidned_crop_bytes =
-        align_crop_edit(crop_img, width, height, crop_x, crop_y, crop_w, crop_h);
+    edited_img.save(edited_img_path)?;
+    let edited_dimensions = edited_img.dimensions();
+    let pixel_bytes = &original.pixel_bytes;
 
-    let crop_transformation = Transformation::Crop {
-        orig_w: width,
-        orig_h: height,
-        x: crop_x,
-        y: crop_y,
-        w: crop_w,
-        h: crop_h,
-    };
+    let aligned_edit_bytes = align_edit(
+        &edited_img,
+        original.layout.format,
+        &transformation,
+        pixel_bytes.len(),
+    );
 
     println!("Building curcuit");
     let start = Instant::now();
-    let builder = TransformationCircuitBuilder::<L>::new(
-        pixel_bytes.len(),
-        Box::new(crop_transformation.clone()),
-    );
+    let mut builder =
+        TransformationCircuitBuilder::<L>::new(pixel_bytes.len(), Box::new(transformation.clone()))
+            .with_commitment(original.commitment)
+            .with_pixel_format(original.layout.format);
+    if original.layout.packing == Packing::Dense {
+        builder = builder.with_dense_packing();
+    }
+    if let Some(signature) = &original.signature {
+        builder = builder.with_signed_original(signature.scheme());
+    }
+    if original.salted {
+        builder = builder.with_salted_original();
+    }
     let mut circuit = builder.build_curcuit();
     let duration = start.elapsed();
     println!("Built curcuit in {:?}s", duration);
 
     let proof = circuit
-        .prove(&pixel_bytes, &alidned_crop_bytes)
+        .prove_with_original(
+            &original.witness,
+            &aligned_edit_bytes,
+            original.signature.as_ref(),
+        )
         .expect("Error while trying to prove...");
 
     let proof_bytes = proof.to_bytes();
@@ -124,54 +247,274 @@ fn prove_crop(
         proof_bytes.len() / 1024
     );
 
+    let original_dimensions = match &transformation {
+        Transformation::HiddenCrop { .. } => None,
+        _ => Some(original.img.dimensions()),
+    };
     let metadata = ProofMetadata {
-        proof,
+        circuit_digest: circuit.circuit_digest(),
+        commitment: original.commitment,
+        pixel_format: original.layout.format,
+        packing: original.layout.packing,
+        signature: proof.signature_scheme(),
+        salted: proof.is_salted(),
+        original_hash: proof.original_hash(),
+        signer_public_key: proof.signer_public_key(),
+        edited_hash: proof.edited_hash(),
+        transformation,
         original_length: pixel_bytes.len(),
-        edited_length: alidned_crop_bytes.len(),
-        transformation: crop_transformation,
+        edited_length: aligned_edit_bytes.len(),
+        original_dimensions,
+        edited_dimensions,
+        proof: proof_bytes,
+    };
+
+    let edited_file = embed_bundle(&fs::read(edited_img_path)?, &metadata.to_bytes()?)?;
+    fs::write(edited_img_path, edited_file)?;
+    Ok(metadata)
+}
+
+fn prove(
+    orig_img_path: String,
+    edit: Edit,
+    device_key_path: Option<String>,
+    signature_scheme: SignatureScheme,
+    salted: bool,
+    merkle: bool,
+    dense: bool,
+    c2pa_key_path: Option<String>,
+) -> Result<()> {
+    let original = read_original(
+        &orig_img_path,
+        device_key_path,
+        signature_scheme,
+        salted,
+        merkle,
+        dense,
+    )?;
+    let (width, height) = original.img.dimensions();
+
+    let edited_img_path = match &edit {
+        Edit::PreserveOutside {
+            edited_img_path, ..
+        } => Some(edited_img_path.clone()),
+        _ => None,
+    };
+    let transformation = edit_to_transformation(edit, width, height);
+    original
+        .layout
+        .format
+        .check_transformation(&transformation)?;
+    let edited_img = match edited_img_path {
+        Some(path) => {
+            let edited_img = ImageReader::open(path)?.decode()?;
+            if edited_img.dimensions() != (width, height) {
+                bail!("the edited image must have the size of the original");
+            }
+            edited_img
+        }
+        None => apply_transformation(&original.img, original.layout.format, &transformation),
     };
 
-    fs::write("metadata.json", rmp_serde::to_vec(&metadata)?).expect("Unable to write file");
+    let metadata = prove_edit(&original, transformation, edited_img, "img_edit.png")?;
+    let bundle = metadata.to_bytes()?;
+    fs::write("proof.zked", &bundle).expect("Unable to write file");
+    // Signed last, the manifest binds every other byte of the file
+    if let Some(path) = c2pa_key_path {
+        let signer = Signer::from_hex(fs::read_to_string(path)?.trim())?;
+        let original_file = fs::read(&orig_img_path)?;
+        let ingredient = Ingredient {
+            title: Path::new(&orig_img_path)
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or(&orig_img_path),
+            file: &original_file,
+        };
+        let edited_file = sign_edited(
+            &fs::read("img_edit.png")?,
+            ingredient,
+            &metadata.transformation,
+            &bundle,
+            &signer,
+        )?;
+        fs::write("img_edit.png", edited_file)?;
+    }
     Ok(())
 }
 
-fn verify(edited_image_path: String, metadata_path: String) -> Result<()> {
-    let metadata: ProofMetadata = rmp_serde::from_slice(&fs::read(metadata_path)?)?;
-    println!(
-        "Original length: {}, edited length: {}",
-        metadata.original_length, metadata.edited_length
+fn prove_set(
+    orig_img_path: String,
+    edits: Vec<Edit>,
+    device_key_path: Option<String>,
+    signature_scheme: SignatureScheme,
+    salted: bool,
+    merkle: bool,
+    dense: bool,
+) -> Result<()> {
+    let original = read_original(
+        &orig_img_path,
+        device_key_path,
+        signature_scheme,
+        salted,
+        merkle,
+        dense,
+    )?;
+    let (width, height) = original.img.dimensions();
+
+    let transformations: Vec<Transformation> = edits
+        .into_iter()
+        .map(|edit| edit_to_transformation(edit, width, height))
+        .collect();
+    for transformation in &transformations {
+        original
+            .layout
+            .format
+            .check_transformation(transformation)?;
+        // Its original hash also commits to the private dimensions
+        if let Transformation::HiddenCrop { .. } = transformation {
+            bail!("a hidden crop proves another original hash, prove it on its own");
+        }
+    }
+
+    let mut members = Vec::new();
+    for (index, transformation) in transformations.into_iter().enumerate() {
+        let edited_img =
+            apply_transformation(&original.img, original.layout.format, &transformation);
+        let edited_file = format!("img_edit_{}.png", index + 1);
+        let bundle = prove_edit(&original, transformation, edited_img, &edited_file)?;
+        members.push(ProofSetMember {
+            edited_file,
+            bundle,
+        });
+    }
+    fs::write("proofs.zkes", ProofSet::from_members(members)?.to_bytes()?)?;
+    Ok(())
+}
+
+/// Checks that the edited image decodes to the pixels the proof commits to.
+fn check_edited_pixels(edited_image_path: &str, metadata: &ProofMetadata) -> Result<()> {
+    let file = fs::read(edited_image_path)?;
+    let img = ImageReader::new(Cursor::new(decodable_file(&file)?))
+        .with_guessed_format()?
+        .decode()?;
+    if img.dimensions() != metadata.edited_dimensions {
+        bail!("the edited image doesn't have the size the proof is for");
+    }
+    let pixel_bytes = align_edit(
+        &img,
+        metadata.pixel_format,
+        &metadata.transformation,
+        metadata.original_length,
     );
-    let original_hash = metadata.proof.original_hash();
-    let edited_hash = metadata.proof.edited_hash();
-
-    let mut img = ImageReader::open(edited_image_path)?.decode()?.into_rgba8();
-
-    match metadata.transformation {
-        Transformation::Crop {
-            orig_w,
-            x,
-            y,
-            w,
-            h,
-            orig_h,
-        } => {
-            let pixel_bytes = align_crop_edit(img, orig_w, orig_h, x, y, w, h);
-            let pixels_hash = calculate_poseidon(&pixel_bytes);
-            assert_eq!(edited_hash, pixels_hash);
+    if metadata
+        .commitment
+        .commit::<L>(&pixel_bytes, metadata.layout())
+        != metadata.edited_hash
+    {
+        match lossy_encoding(&file) {
+            Ok(Some(reason)) => bail!(
+                "the edited image doesn't decode to the proven pixels, {}",
+                reason
+            ),
+            _ => bail!("the edited image doesn't decode to the proven pixels"),
+        }
+    }
+    Ok(())
+}
+
+/// The bundle of the set member the edited image decodes to, a single
+/// bundle has to match.
+fn select_member(edited_image_path: &str, bundles: Vec<ProofMetadata>) -> Result<ProofMetadata> {
+    if bundles.len() == 1 {
+        let metadata = bundles.into_iter().next().unwrap();
+        check_edited_pixels(edited_image_path, &metadata)?;
+        return Ok(metadata);
+    }
+    for metadata in bundles {
+        if check_edited_pixels(edited_image_path, &metadata).is_ok() {
+            return Ok(metadata);
         }
     }
+    bail!("the edited image doesn't decode to any member of the proof set")
+}
+
+fn verify(edited_image_path: String, metadata_path: Option<String>) -> Result<()> {
+    let bundles = match metadata_path {
+        Some(path) => {
+            let bytes = fs::read(path)?;
+            match ProofSet::is_set(&bytes) {
+                true => ProofSet::from_bytes(&bytes)?
+                    .members
+                    .into_iter()
+                    .map(|member| member.bundle)
+                    .collect(),
+                false => vec![ProofMetadata::from_bytes(&bytes)?],
+            }
+        }
+        None => {
+            let file = fs::read(&edited_image_path)?;
+            let c2pa_bundle = || {
+                let store = read_store(&file).ok()??;
+                Some(store.active()?.proof_bundle()?.to_vec())
+            };
+            match extract_bundle(&file).ok().flatten().or_else(c2pa_bundle) {
+                Some(bundle) => vec![ProofMetadata::from_bytes(&bundle)?],
+                None => bail!("the edited image doesn't carry a proof, pass one with -m"),
+            }
+        }
+    };
+    let metadata = select_member(&edited_image_path, bundles)?;
+    println!(
+        "Original length: {}, edited length: {}, pixel format: {:?}, packing: {:?}",
+        metadata.original_length, metadata.edited_length, metadata.pixel_format, metadata.packing
+    );
+    match (&metadata.signer_public_key, &metadata.original_hash) {
+        (Some(public_key), _) => println!("Original signed by: {}", to_hex(public_key)),
+        (None, Some(original_hash)) => println!(
+            "Original hash{}: {}",
+            if metadata.salted { " (salted)" } else { "" },
+            to_hex(original_hash)
+        ),
+        (None, None) => bail!("the proof bundle holds neither an original hash nor a signer"),
+    }
 
     println!("Building curcuit");
     let start = Instant::now();
-    let builder = TransformationCircuitBuilder::<L>::new(
+    let mut builder = TransformationCircuitBuilder::<L>::new(
         metadata.original_length,
-        Box::new(metadata.transformation),
-    );
+        Box::new(metadata.transformation.clone()),
+    )
+    .with_commitment(metadata.commitment)
+    .with_pixel_format(metadata.pixel_format);
+    if metadata.packing == Packing::Dense {
+        builder = builder.with_dense_packing();
+    }
+    if let Some(scheme) = metadata.signature {
+        builder = builder.with_signed_original(scheme);
+    }
+    if metadata.salted {
+        builder = builder.with_salted_original();
+    }
     let circuit = builder.build_curcuit();
     println!("Built curcuit in {:?}s", start.elapsed());
+    metadata.check_circuit_digest(&circuit.circuit_digest())?;
+
+    // A bundle holds the raw bytes, importing JSON already decoded the base64
+    let proof = circuit.proof_from_bytes(metadata.proof, ProofEncoding::Bytes)?;
+    if proof.original_hash() != metadata.original_hash
+        || proof.signer_public_key() != metadata.signer_public_key
+        || proof.edited_hash() != metadata.edited_hash
+    {
+        bail!("the proof bundle doesn't match the public inputs of its proof");
+    }
+    if let Transformation::PreserveOutside { .. } = metadata.transformation {
+        for region in unauthenticated_regions(&proof.transformation_public_inputs()) {
+            println!("Unauthenticated region: {}", region);
+        }
+    }
 
     let verify_start = Instant::now();
-    match metadata.proof.verify(circuit.circuit) {
+    match proof.verify(circuit.circuit) {
         Ok(_) => println!("Proof is valid!"),
         Err(_) => println!("Proof is invalid!"),
     }
@@ -179,6 +522,168 @@ fn verify(edited_image_path: String, metadata_path: String) -> Result<()> {
     Ok(())
 }
 
+fn open(orig_img_path: String, metadata_path: String, salt_path: String) -> Result<()> {
+    let metadata = ProofMetadata::from_bytes(&fs::read(metadata_path)?)?;
+    let salt = Salt::from_hex(&fs::read_to_string(salt_path)?)?;
+
+    let img = ImageReader::open(orig_img_path)?.decode()?;
+    let pixel_bytes = pixel_stream(&img, metadata.pixel_format);
+    let mut salted_hash = salted_original_hash::<L>(&pixel_bytes, metadata.layout(), &salt);
+    if let Transformation::HiddenCrop { .. } = metadata.transformation {
+        let (width, height) = img.dimensions();
+        salted_hash = bind_dimensions(&salted_hash, width, height, metadata.layout());
+    }
+
+    if metadata.salted && metadata.original_hash == Some(salted_hash) {
+        println!("The original opens the salted hash!");
+    } else {
+        println!("The original doesn't open the salted hash!");
+    }
+    Ok(())
+}
+
+fn embed(edited_image_path: String, metadata_path: String) -> Result<()> {
+    let metadata = ProofMetadata::from_bytes(&fs::read(metadata_path)?)?;
+    check_edited_pixels(&edited_image_path, &metadata)?;
+    let edited_file = embed_bundle(&fs::read(&edited_image_path)?, &metadata.to_bytes()?)?;
+    fs::write(edited_image_path, edited_file)?;
+    Ok(())
+}
+
+fn c2pa_info(img_path: String) -> Result<()> {
+    let file = fs::read(img_path)?;
+    let store = match read_store(&file)? {
+        Some(store) => store,
+        None => bail!("the image carries no C2PA manifest"),
+    };
+    for (index, manifest) in store.manifests.iter().enumerate() {
+        let active = index + 1 == store.manifests.len();
+        println!(
+            "Manifest {}{}",
+            manifest.label,
+            if active { " (active)" } else { "" }
+        );
+        if let Some(claim_generator) = manifest.claim_generator() {
+            println!("  Claim generator: {}", claim_generator);
+        }
+        for assertion in &manifest.assertions {
+            println!("  Assertion: {}", assertion.label);
+        }
+        let validation = match active {
+            true => manifest
+                .validate()
+                .and_then(|_| manifest.validate_binding(&file)),
+            false => manifest.validate(),
+        };
+        match validation {
+            Ok(_) => println!("  Manifest is valid!"),
+            Err(err) => println!("  Manifest is invalid: {}", err),
+        }
+    }
+    Ok(())
+}
+
+fn export_json(metadata_path: String) -> Result<()> {
+    let metadata = ProofMetadata::from_bytes(&fs::read(metadata_path)?)?;
+    fs::write("proof.json", metadata.to_json()?)?;
+    Ok(())
+}
+
+fn import_json(json_path: String) -> Result<()> {
+    let metadata = ProofMetadata::from_json(&fs::read_to_string(json_path)?)?;
+    fs::write("proof.zked", metadata.to_bytes()?)?;
+    Ok(())
+}
+
+fn link_file(
+    orig_img_path: String,
+    device_key_path: Option<String>,
+    signature_scheme: SignatureScheme,
+    dense: bool,
+) -> Result<()> {
+    let file = fs::read(&orig_img_path)?;
+    let layout = FileLayout::parse(&file)?;
+    println!("Read file of {}x{} pixels", layout.width, layout.height);
+    let skeleton = layout.skeleton(&file);
+    // The pixel hash is the original hash of proofs of the decoded file
+    let pixel_layout = PixelLayout {
+        format: pixel_format(&ImageReader::open(&orig_img_path)?.decode()?)?,
+        packing: if dense {
+            Packing::Dense
+        } else {
+            Packing::Pixels
+        },
+    };
+
+    // Stands in for the signature a camera makes over the file it writes
+    let signature = match device_key_path {
+        Some(path) => Some(sign_file(
+            &file,
+            signature_scheme,
+            &fs::read_to_string(path)?,
+        )?),
+        None => None,
+    };
+
+    println!("Building curcuit");
+    let start = Instant::now();
+    let mut builder = FileLinkBuilder::<L>::new(&skeleton, pixel_layout)?;
+    if let Some(signature) = &signature {
+        builder = builder.with_signed_file(signature.scheme());
+    }
+    let circuit = builder.build_circuit();
+    println!("Built curcuit in {:?}s", start.elapsed());
+
+    let proof = circuit.prove(&file, signature.as_ref())?;
+    let metadata = FileLinkMetadata {
+        proof,
+        skeleton,
+        pixel_format: pixel_layout.format,
+        packing: pixel_layout.packing,
+    };
+    fs::write("file_link.json", rmp_serde::to_vec(&metadata)?)?;
+    Ok(())
+}
+
+fn verify_link(link_path: String, metadata_path: String) -> Result<()> {
+    let link: FileLinkMetadata = rmp_serde::from_slice(&fs::read(link_path)?)?;
+    let metadata = ProofMetadata::from_bytes(&fs::read(metadata_path)?)?;
+    if metadata.commitment != CommitmentScheme::Sponge || metadata.salted {
+        bail!("only unsalted sponge commitments can be linked to a file");
+    }
+    if let Transformation::HiddenCrop { .. } = metadata.transformation {
+        bail!("the original of a hidden crop commits to its private dimensions as well");
+    }
+    if link.layout() != metadata.layout() {
+        bail!(
+            "the file was linked as {:?} pixels with {:?} packing, the proof takes {:?} with {:?}",
+            link.pixel_format,
+            link.packing,
+            metadata.pixel_format,
+            metadata.packing
+        );
+    }
+    match metadata.original_hash {
+        Some(original_hash) if original_hash == link.proof.pixel_hash() => {}
+        Some(_) => bail!("the file holds another original"),
+        None => bail!("the original of a signed proof can't be linked to a file"),
+    }
+    match link.proof.signer_public_key() {
+        Some(public_key) => println!("File signed by: {}", to_hex(&public_key)),
+        None => println!("File hash: {}", to_hex(&link.proof.file_hash().unwrap())),
+    }
+
+    let mut builder = FileLinkBuilder::<L>::new(&link.skeleton, link.layout())?;
+    if let Some(scheme) = link.proof.signature_scheme() {
+        builder = builder.with_signed_file(scheme);
+    }
+    match link.proof.verify(builder.build_circuit().circuit) {
+        Ok(_) => println!("File link is valid!"),
+        Err(_) => println!("File link is invalid!"),
+    }
+    Ok(())
+}
+
 fn main() -> Result<()> {
     let options = parse_options()?;
 
@@ -192,14 +697,65 @@ fn main() -> Result<()> {
     match options {
         Zkedit::Prove {
             orig_img_path,
-            crop_x,
-            crop_y,
-            crop_w,
-            crop_h,
-        } => prove_crop(orig_img_path, crop_x, crop_y, crop_w, crop_h),
+            edit,
+            device_key_path,
+            signature_scheme,
+            salted,
+            merkle,
+            dense,
+            c2pa_key_path,
+        } => prove(
+            orig_img_path,
+            edit,
+            device_key_path,
+            signature_scheme,
+            salted,
+            merkle,
+            dense,
+            c2pa_key_path,
+        ),
+        Zkedit::ProveSet {
+            orig_img_path,
+            edits,
+            device_key_path,
+            signature_scheme,
+            salted,
+            merkle,
+            dense,
+        } => prove_set(
+            orig_img_path,
+            edits,
+            device_key_path,
+            signature_scheme,
+            salted,
+            merkle,
+            dense,
+        ),
         Zkedit::Verify {
             edited_image_path,
             metadata_path,
         } => verify(edited_image_path, metadata_path),
+        Zkedit::Open {
+            orig_img_path,
+            metadata_path,
+            salt_path,
+        } => open(orig_img_path, metadata_path, salt_path),
+        Zkedit::Embed {
+            edited_image_path,
+            metadata_path,
+        } => embed(edited_image_path, metadata_path),
+        Zkedit::C2paInfo { img_path } => c2pa_info(img_path),
+        Zkedit::ExportJson { metadata_path } => export_json(metadata_path),
+        Zkedit::ImportJson { json_path } => import_json(json_path),
+        Zkedit::LinkFile {
+            orig_img_path,
+            device_key_path,
+            signature_scheme,
+            dense,
+        } => link_file(orig_img_path, device_key_path, signature_scheme, dense),
+        Zkedit::VerifyLink {
+            link_path,
+            metadata_path,
+        } => verify_link(link_path, metadata_path),
     }
 }
@@ -1,11 +1,498 @@
-use serde::{Deserialize, Serialize};
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use zkedit_transformations::Transformation;
-use zkedit_zkp::proof::TransformationProof;
+use zkedit_zkp::commitment::CommitmentScheme;
+use zkedit_zkp::file::link::FileLinkProof;
+use zkedit_zkp::pixel::{Packing, PixelFormat, PixelLayout};
+use zkedit_zkp::signature::SignatureScheme;
+
+// A proof bundle is the magic bytes, the format version as a little-endian
+// u16 and a MessagePack map of the fields of `ProofMetadata` by name. Loaders
+// skip fields they don't know and optional fields default when missing, so
+// fields can be added within a version. Anything else bumps the version.
+//
+// The bundle describes the proof without the circuit: the hashes are copied
+// out of the public inputs, which verification checks, and the circuit digest
+// tells a proof of an older circuit apart from an invalid one.
+//
+// The `metadata.json` of earlier versions was a MessagePack array of the
+// proof, the lengths and the transformation, without magic bytes. Its circuit
+// no longer exists, so it is only recognized to tell the user to re-prove.
+//
+// A proof set holds the bundles of several renditions of one original, made
+// in one run, each with the name of the file it was written to and proving
+// another transformation. It names the original hash or signer key all of
+// them prove. It is laid out like a bundle under its own magic bytes and
+// shares the format version.
+//
+// The same fields also export to JSON, an object with a `format_version` key
+// besides them. In JSON the hashes are hex and the proof is base64, MessagePack
+// keeps them as bytes.
+
+const MAGIC: &[u8; 4] = b"ZKED";
+const SET_MAGIC: &[u8; 4] = b"ZKES";
+const FORMAT_VERSION: u16 = 1;
+/// MessagePack header of the array of four fields of a legacy bundle
+const LEGACY_HEADER: u8 = 0x94;
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub(crate) fn from_hex(hex: &str) -> Result<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        bail!("odd number of hex digits");
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            let digits = hex
+                .get(i..i + 2)
+                .ok_or_else(|| anyhow!("not hex: {}", hex))?;
+            Ok(u8::from_str_radix(digits, 16)?)
+        })
+        .collect()
+}
+
+/// Bytes as hex in human-readable formats
+mod hex_bytes {
+    use serde::de::Error;
+
+    use super::*;
+
+    pub fn serialize<S: Serializer>(bytes: &Vec<u8>, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&to_hex(bytes))
+        } else {
+            bytes.serialize(serializer)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        if deserializer.is_human_readable() {
+            from_hex(&String::deserialize(deserializer)?).map_err(D::Error::custom)
+        } else {
+            Vec::deserialize(deserializer)
+        }
+    }
+}
+
+mod hex_option {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        bytes: &Option<Vec<u8>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match bytes {
+            Some(bytes) => serializer.serialize_some(&Hex(bytes.clone())),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Vec<u8>>, D::Error> {
+        Ok(Option::<Hex>::deserialize(deserializer)?.map(|hex| hex.0))
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Hex(#[serde(with = "hex_bytes")] Vec<u8>);
+}
+
+/// Bytes as base64 in human-readable formats
+mod base64_bytes {
+    use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+    use serde::de::Error;
+
+    use super::*;
+
+    pub fn serialize<S: Serializer>(bytes: &Vec<u8>, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&BASE64.encode(bytes))
+        } else {
+            bytes.serialize(serializer)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        if deserializer.is_human_readable() {
+            BASE64
+                .decode(String::deserialize(deserializer)?)
+                .map_err(D::Error::custom)
+        } else {
+            Vec::deserialize(deserializer)
+        }
+    }
+}
 
 #[derive(Serialize, Deserialize)]
 pub struct ProofMetadata {
-    pub(crate) proof: TransformationProof,
+    #[serde(with = "hex_bytes")]
+    pub(crate) circuit_digest: Vec<u8>,
+    pub(crate) commitment: CommitmentScheme,
+    /// Bundles from before pixel formats hold RGBA8 pixels
+    #[serde(default)]
+    pub(crate) pixel_format: PixelFormat,
+    #[serde(default)]
+    pub(crate) packing: Packing,
+    #[serde(default)]
+    pub(crate) signature: Option<SignatureScheme>,
+    #[serde(default)]
+    pub(crate) salted: bool,
+    /// `None` for a signed original
+    #[serde(default, with = "hex_option")]
+    pub(crate) original_hash: Option<Vec<u8>>,
+    #[serde(default, with = "hex_option")]
+    pub(crate) signer_public_key: Option<Vec<u8>>,
+    #[serde(with = "hex_bytes")]
+    pub(crate) edited_hash: Vec<u8>,
+    pub(crate) transformation: Transformation,
     pub(crate) original_length: usize,
     pub(crate) edited_length: usize,
-    pub(crate) transformation: Transformation,
+    /// Left out when the transformation keeps the size of the original secret
+    #[serde(default)]
+    pub(crate) original_dimensions: Option<(u32, u32)>,
+    pub(crate) edited_dimensions: (u32, u32),
+    /// `TransformationProof::to_bytes`
+    #[serde(with = "base64_bytes")]
+    pub(crate) proof: Vec<u8>,
+}
+
+impl ProofMetadata {
+    pub(crate) fn layout(&self) -> PixelLayout {
+        PixelLayout {
+            format: self.pixel_format,
+            packing: self.packing,
+        }
+    }
+
+    /// Checks that the proof was made by the circuit with `digest`.
+    pub(crate) fn check_circuit_digest(&self, digest: &[u8]) -> Result<()> {
+        if self.circuit_digest != digest {
+            bail!("the proof was made by another version of the circuit");
+        }
+        Ok(())
+    }
+
+    pub(crate) fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(FORMAT_VERSION.to_le_bytes());
+        bytes.extend(rmp_serde::to_vec_named(self)?);
+        Ok(bytes)
+    }
+
+    pub(crate) fn to_json(&self) -> Result<String> {
+        let mut json = serde_json::to_value(self)?;
+        json.as_object_mut()
+            .unwrap()
+            .insert("format_version".into(), FORMAT_VERSION.into());
+        Ok(serde_json::to_string_pretty(&json)?)
+    }
+
+    pub(crate) fn from_json(json: &str) -> Result<Self> {
+        let mut json: serde_json::Value = serde_json::from_str(json)?;
+        let version = json
+            .as_object_mut()
+            .and_then(|fields| fields.remove("format_version"))
+            .and_then(|version| version.as_u64())
+            .ok_or_else(|| anyhow!("the proof JSON has no format_version"))?;
+        if version != FORMAT_VERSION as u64 {
+            bail!(
+                "unsupported proof bundle version {}, this build reads version {}",
+                version,
+                FORMAT_VERSION
+            );
+        }
+        Ok(serde_json::from_value(json)?)
+    }
+
+    /// Reads a bundle or its JSON export.
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.iter().find(|byte| !byte.is_ascii_whitespace()) == Some(&b'{') {
+            return Self::from_json(std::str::from_utf8(bytes)?);
+        }
+        if bytes.first() == Some(&LEGACY_HEADER) {
+            bail!("a legacy proof bundle from before the versioned format, prove the edit again");
+        }
+        let fields = versioned_fields(bytes, MAGIC)
+            .ok_or_else(|| anyhow!("not a zkedit proof bundle"))??;
+        Ok(rmp_serde::from_slice(fields)?)
+    }
+}
+
+/// The MessagePack after `magic` and a version this build reads, `None` for
+/// other magic bytes.
+fn versioned_fields<'a>(bytes: &'a [u8], magic: &[u8; 4]) -> Option<Result<&'a [u8]>> {
+    if bytes.len() < magic.len() + 2 || &bytes[..magic.len()] != magic {
+        return None;
+    }
+    let version = u16::from_le_bytes([bytes[magic.len()], bytes[magic.len() + 1]]);
+    if version != FORMAT_VERSION {
+        return Some(Err(anyhow!(
+            "unsupported proof bundle version {}, this build reads version {}",
+            version,
+            FORMAT_VERSION
+        )));
+    }
+    Some(Ok(&bytes[magic.len() + 2..]))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ProofSetMember {
+    /// The file the rendition was written to
+    pub(crate) edited_file: String,
+    pub(crate) bundle: ProofMetadata,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ProofSet {
+    /// The original every member proves, `None` for a signed original
+    #[serde(default, with = "hex_option")]
+    pub(crate) original_hash: Option<Vec<u8>>,
+    #[serde(default, with = "hex_option")]
+    pub(crate) signer_public_key: Option<Vec<u8>>,
+    pub(crate) members: Vec<ProofSetMember>,
+}
+
+impl ProofSet {
+    /// The set of `members`, which have to prove the same original.
+    pub(crate) fn from_members(members: Vec<ProofSetMember>) -> Result<Self> {
+        let first = &members
+            .first()
+            .ok_or_else(|| anyhow!("a proof set needs a member"))?
+            .bundle;
+        let mut set = ProofSet {
+            original_hash: first.original_hash.clone(),
+            signer_public_key: first.signer_public_key.clone(),
+            members: Vec::new(),
+        };
+        for member in members {
+            set.check_member(&member)?;
+            if set.member(&member.bundle.transformation).is_some() {
+                bail!(
+                    "{} proves an edit the set already holds",
+                    member.edited_file
+                );
+            }
+            set.members.push(member);
+        }
+        Ok(set)
+    }
+
+    /// The member proving `transformation`.
+    pub(crate) fn member(&self, transformation: &Transformation) -> Option<&ProofSetMember> {
+        self.members
+            .iter()
+            .find(|member| &member.bundle.transformation == transformation)
+    }
+
+    fn check_member(&self, member: &ProofSetMember) -> Result<()> {
+        if member.bundle.original_hash != self.original_hash
+            || member.bundle.signer_public_key != self.signer_public_key
+        {
+            bail!(
+                "{} proves another original than the set",
+                member.edited_file
+            );
+        }
+        Ok(())
+    }
+
+    pub(crate) fn is_set(bytes: &[u8]) -> bool {
+        bytes.starts_with(SET_MAGIC)
+    }
+
+    pub(crate) fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = SET_MAGIC.to_vec();
+        bytes.extend(FORMAT_VERSION.to_le_bytes());
+        bytes.extend(rmp_serde::to_vec_named(self)?);
+        Ok(bytes)
+    }
+
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let fields = versioned_fields(bytes, SET_MAGIC)
+            .ok_or_else(|| anyhow!("not a zkedit proof set"))??;
+        let set: ProofSet = rmp_serde::from_slice(fields)?;
+        for member in &set.members {
+            set.check_member(member)?;
+        }
+        Ok(set)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FileLinkMetadata {
+    pub(crate) proof: FileLinkProof,
+    /// The file with its pixel bytes zeroed
+    pub(crate) skeleton: Vec<u8>,
+    /// Links from before pixel formats hash RGBA8 pixels
+    #[serde(default)]
+    pub(crate) pixel_format: PixelFormat,
+    #[serde(default)]
+    pub(crate) packing: Packing,
+}
+
+impl FileLinkMetadata {
+    pub(crate) fn layout(&self) -> PixelLayout {
+        PixelLayout {
+            format: self.pixel_format,
+            packing: self.packing,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bundle() -> ProofMetadata {
+        ProofMetadata {
+            circuit_digest: vec![0xc1; 32],
+            commitment: CommitmentScheme::Sponge,
+            pixel_format: PixelFormat::Rgb8,
+            packing: Packing::Dense,
+            signature: None,
+            salted: true,
+            original_hash: Some(vec![0x0a; 32]),
+            signer_public_key: None,
+            edited_hash: vec![0xed; 32],
+            transformation: Transformation::Threshold { level: 128 },
+            original_length: 96,
+            edited_length: 96,
+            original_dimensions: Some((8, 4)),
+            edited_dimensions: (8, 4),
+            proof: (0..=255).collect(),
+        }
+    }
+
+    #[test]
+    fn test_bundle_round_trip() {
+        let bytes = bundle().to_bytes().unwrap();
+        assert!(bytes.starts_with(MAGIC));
+        let decoded = ProofMetadata::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.layout(), bundle().layout());
+        assert_eq!(decoded.original_hash, bundle().original_hash);
+        assert_eq!(decoded.proof, bundle().proof);
+        assert_eq!(decoded.to_bytes().unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let json = bundle().to_json().unwrap();
+        let fields: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(fields["format_version"], FORMAT_VERSION);
+        assert_eq!(fields["edited_hash"], "ed".repeat(32));
+        assert!(fields["proof"].as_str().unwrap().starts_with("AAECAwQF"));
+
+        let decoded = ProofMetadata::from_bytes(json.as_bytes()).unwrap();
+        assert_eq!(decoded.to_bytes().unwrap(), bundle().to_bytes().unwrap());
+    }
+
+    #[test]
+    fn test_bundles_skip_unknown_and_default_missing_fields() {
+        let mut fields: serde_json::Value =
+            serde_json::from_str(&bundle().to_json().unwrap()).unwrap();
+        let object = fields.as_object_mut().unwrap();
+        object.insert("added_later".into(), 1.into());
+        object.remove("packing");
+        let decoded = ProofMetadata::from_json(&fields.to_string()).unwrap();
+        assert_eq!(decoded.packing, Packing::Pixels);
+        assert_eq!(decoded.proof, bundle().proof);
+    }
+
+    #[test]
+    fn test_bundles_reject_other_versions() {
+        let mut bytes = bundle().to_bytes().unwrap();
+        bytes[MAGIC.len()..MAGIC.len() + 2].copy_from_slice(&2u16.to_le_bytes());
+        let error = ProofMetadata::from_bytes(&bytes).err().unwrap();
+        assert!(error.to_string().contains("version 2"));
+
+        let mut fields: serde_json::Value =
+            serde_json::from_str(&bundle().to_json().unwrap()).unwrap();
+        fields["format_version"] = 2.into();
+        assert!(ProofMetadata::from_json(&fields.to_string()).is_err());
+        fields.as_object_mut().unwrap().remove("format_version");
+        assert!(ProofMetadata::from_json(&fields.to_string()).is_err());
+    }
+
+    #[test]
+    fn test_bundles_reject_legacy_and_foreign_bytes() {
+        let legacy = rmp_serde::to_vec(&(
+            vec![0u8; 16],
+            96usize,
+            96usize,
+            Transformation::Threshold { level: 128 },
+        ))
+        .unwrap();
+        let error = ProofMetadata::from_bytes(&legacy).err().unwrap();
+        assert!(error.to_string().contains("legacy"));
+
+        let error = ProofMetadata::from_bytes(b"\x89PNG\r\n").err().unwrap();
+        assert!(error.to_string().contains("not a zkedit proof bundle"));
+    }
+
+    fn set_member(index: usize, transformation: Transformation) -> ProofSetMember {
+        ProofSetMember {
+            edited_file: format!("img_edit_{}.png", index),
+            bundle: ProofMetadata {
+                transformation,
+                ..bundle()
+            },
+        }
+    }
+
+    #[test]
+    fn test_proof_set_picks_member_by_transformation() {
+        let posterize = Transformation::Posterize { levels: 4 };
+        let set = ProofSet::from_members(vec![
+            set_member(1, Transformation::Threshold { level: 128 }),
+            set_member(2, posterize.clone()),
+        ])
+        .unwrap();
+        let set = ProofSet::from_bytes(&set.to_bytes().unwrap()).unwrap();
+        assert_eq!(set.original_hash, bundle().original_hash);
+        assert_eq!(
+            set.member(&posterize).unwrap().edited_file,
+            "img_edit_2.png"
+        );
+        assert!(set
+            .member(&Transformation::Posterize { levels: 8 })
+            .is_none());
+
+        // Two renditions of the same edit
+        assert!(ProofSet::from_members(vec![
+            set_member(1, posterize.clone()),
+            set_member(2, posterize),
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_proof_set_rejects_member_of_other_original() {
+        let mut other = set_member(2, Transformation::Posterize { levels: 4 });
+        other.bundle.original_hash = Some(vec![0x0b; 32]);
+        let error = ProofSet::from_members(vec![
+            set_member(1, Transformation::Threshold { level: 128 }),
+            other,
+        ])
+        .err()
+        .unwrap();
+        assert!(error.to_string().contains("img_edit_2.png"));
+
+        let mut set = ProofSet::from_members(vec![
+            set_member(1, Transformation::Threshold { level: 128 }),
+            set_member(2, Transformation::Posterize { levels: 4 }),
+        ])
+        .unwrap();
+        set.members[1].bundle.original_hash = Some(vec![0x0b; 32]);
+        assert!(ProofSet::from_bytes(&set.to_bytes().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_bundle_checks_circuit_digest() {
+        let metadata = bundle();
+        assert!(metadata.check_circuit_digest(&[0xc1; 32]).is_ok());
+        assert!(metadata.check_circuit_digest(&[0xc2; 32]).is_err());
+    }
 }
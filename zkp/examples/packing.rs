@@ -0,0 +1,80 @@
+//! Proves a crop of a random RGBA8 image with pixel or dense packing and
+//! reports the prover time, the proof size and the peak memory of the
+//! process. Run it once
+//! per packing, the peak covers the whole process:
+//!
+//! cargo run --release --example packing -- <width> <height> [dense]
+
+use std::env;
+use std::fs;
+use std::time::Instant;
+
+use anyhow::{anyhow, Result};
+use rand::Rng;
+use zkedit_transformations::Transformation;
+use zkedit_zkp::builder::TransformationCircuitBuilder;
+
+const L: usize = 12 * 85 * 256;
+
+/// Peak resident set size of the process in kB, Linux only.
+fn peak_rss_kb() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    line.split_whitespace().nth(1)?.parse().ok()
+}
+
+fn main() -> Result<()> {
+    let args: Vec<String> = env::args().collect();
+    let usage = || anyhow!("usage: packing <width> <height> [dense]");
+    let width: u32 = args.get(1).ok_or_else(usage)?.parse()?;
+    let height: u32 = args.get(2).ok_or_else(usage)?.parse()?;
+    let dense = args.get(3).map(String::as_str) == Some("dense");
+
+    let mut rng = rand::thread_rng();
+    let original: Vec<u8> = (0..width * height * 4).map(|_| rng.gen()).collect();
+    let (x, y, w, h) = (width / 4, height / 4, width / 2, height / 2);
+    // The aligned crop, zero outside of it
+    let mut edited = vec![0; original.len()];
+    for row in y..y + h {
+        let start = ((row * width + x) * 4) as usize;
+        let end = start + (w * 4) as usize;
+        edited[start..end].copy_from_slice(&original[start..end]);
+    }
+    let transformation = Transformation::Crop {
+        orig_w: width,
+        orig_h: height,
+        x,
+        y,
+        w,
+        h,
+    };
+
+    let start = Instant::now();
+    let mut builder =
+        TransformationCircuitBuilder::<L>::new(original.len(), Box::new(transformation));
+    if dense {
+        builder = builder.with_dense_packing();
+    }
+    let mut circuit = builder.build_curcuit();
+    let build_time = start.elapsed();
+
+    let start = Instant::now();
+    let proof = circuit.prove(&original, &edited, None, None)?;
+    let prove_time = start.elapsed();
+    let proof_size = proof.to_bytes().len();
+
+    let start = Instant::now();
+    proof.verify(circuit.circuit)?;
+    println!(
+        "{}x{} {} packing: built in {:?}, proven in {:?}, verified in {:?}, proof of {} kB, peak RSS {} MB",
+        width,
+        height,
+        if dense { "dense" } else { "pixel" },
+        build_time,
+        prove_time,
+        start.elapsed(),
+        proof_size / 1024,
+        peak_rss_kb().map_or("unknown".to_string(), |kb| (kb / 1024).to_string())
+    );
+    Ok(())
+}
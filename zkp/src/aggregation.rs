@@ -0,0 +1,136 @@
+use std::cmp::min;
+use std::ops::Range;
+
+use anyhow::Result;
+use log::Level;
+use plonky2::{
+    iop::{target::Target, witness::PartialWitness},
+    plonk::{
+        circuit_builder::CircuitBuilder,
+        circuit_data::{CircuitConfig, CircuitData},
+        proof::{
+            CompressedProofWithPublicInputs, ProofWithPublicInputs, ProofWithPublicInputsTarget,
+        },
+        prover::prove,
+    },
+    util::timing::TimingTree,
+};
+
+use crate::pixel::PixelLayout;
+use crate::{C, D, F};
+
+// The transformation proofs, the region reveal and the links prove a pixel
+// stream chunk by chunk and verify the chunk proofs in one circuit, which
+// chains the states the chunks hand over.
+
+/// Config of the chunk circuits and the circuits aggregating them. The proofs
+/// are published, so they have to hide the pixels, salts and positions in
+/// their witnesses.
+pub(crate) fn zk_recursion_config() -> CircuitConfig {
+    CircuitConfig {
+        zero_knowledge: true,
+        ..CircuitConfig::standard_recursion_config()
+    }
+}
+
+/// How a pixel stream of `len` bytes splits into chunks of `L` elements.
+#[derive(Clone, Copy)]
+pub(crate) struct Chunking<const L: usize> {
+    pub(crate) len: usize,
+    pub(crate) layout: PixelLayout,
+}
+
+impl<const L: usize> Chunking<L> {
+    pub(crate) fn new(len: usize, layout: PixelLayout) -> Self {
+        Self { len, layout }
+    }
+
+    /// Number of pixels, see `PixelFormat::bytes_per_unit`.
+    pub(crate) fn pixels(&self) -> usize {
+        self.len / self.layout.format.bytes_per_unit()
+    }
+
+    /// Number of elements the pixel stream packs into.
+    pub(crate) fn elements(&self) -> usize {
+        let per_element = self.layout.bytes_per_element();
+        (self.len + per_element - 1) / per_element
+    }
+
+    pub(crate) fn total_chunks(&self) -> usize {
+        (self.elements() + L - 1) / L
+    }
+
+    /// Number of elements of `chunk`, the last chunk takes the rest.
+    pub(crate) fn chunk_len(&self, chunk: usize) -> usize {
+        if chunk == self.total_chunks() - 1 {
+            self.elements() - chunk * L
+        } else {
+            L
+        }
+    }
+
+    /// Numbers of the pixels packed into the elements of `chunk`.
+    pub(crate) fn chunk_pixels(&self, chunk: usize) -> Range<usize> {
+        let per_chunk = self.layout.pixels_in(L);
+        chunk * per_chunk..min((chunk + 1) * per_chunk, self.pixels())
+    }
+
+    /// Whether every chunk holds whole pixels.
+    pub(crate) fn has_whole_pixels(&self) -> bool {
+        L * self.layout.bytes_per_element() % self.layout.format.bytes_per_unit() == 0
+    }
+}
+
+/// Verifies a proof of `chunk_circuit` and returns its target. The verifier
+/// data is a constant of the circuit, so only proofs of this very chunk
+/// circuit verify.
+pub(crate) fn verify_chunk_proof(
+    builder: &mut CircuitBuilder<F, D>,
+    chunk_circuit: &CircuitData<F, C, D>,
+) -> ProofWithPublicInputsTarget<D> {
+    let pt = builder.add_virtual_proof_with_pis::<C>(&chunk_circuit.common);
+    let inner_data = builder.constant_verifier_data(&chunk_circuit.verifier_only);
+    builder.verify_proof::<C>(&pt, &inner_data, &chunk_circuit.common);
+    pt
+}
+
+/// Connects `last`, the state the previous chunk handed over, to the public
+/// inputs `init` of a chunk proof and moves it on to the public inputs `fin`.
+pub(crate) fn chain_state(
+    builder: &mut CircuitBuilder<F, D>,
+    last: &mut [Target],
+    public_inputs: &[Target],
+    init: (usize, usize),
+    fin: (usize, usize),
+) {
+    for (left, right) in last.iter().zip(&public_inputs[init.0..init.1]) {
+        builder.connect(*left, *right);
+    }
+    last.copy_from_slice(&public_inputs[fin.0..fin.1]);
+}
+
+pub(crate) fn prove_chunk(
+    chunk_circuit: &CircuitData<F, C, D>,
+    inputs: PartialWitness<F>,
+) -> Result<ProofWithPublicInputs<F, C, D>> {
+    let mut timing = TimingTree::new("prove_chunk", Level::Info);
+    let proof = prove(
+        &chunk_circuit.prover_only,
+        &chunk_circuit.common,
+        inputs,
+        &mut timing,
+    )?;
+    timing.print();
+    Ok(proof)
+}
+
+/// Proves the aggregating circuit, compressed as the proofs get published.
+pub(crate) fn prove_compressed(
+    circuit: &CircuitData<F, C, D>,
+    pw: PartialWitness<F>,
+) -> Result<CompressedProofWithPublicInputs<F, C, D>> {
+    let mut timing = TimingTree::new("prove", Level::Debug);
+    let proof = prove(&circuit.prover_only, &circuit.common, pw, &mut timing)?;
+    timing.print();
+    proof.compress(&circuit.verifier_only.circuit_digest, &circuit.common)
+}
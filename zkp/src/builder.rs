@@ -1,27 +1,108 @@
-use plonky2::hash::hashing::SPONGE_WIDTH;
+use std::cmp::min;
+
+use plonky2::hash::hash_types::HashOutTarget;
+use plonky2::iop::target::Target;
 use plonky2::plonk::{circuit_builder::CircuitBuilder, circuit_data::CircuitConfig};
-use plonky2_field::types::Field;
 
+use crate::aggregation::{chain_state, verify_chunk_proof, zk_recursion_config, Chunking};
 use crate::circuit::{TransformationChunkCircuit, TransformationCircuit};
+use crate::commitment::{
+    build_tile_circuit, dimensions_hash_circuit, merkle_root_circuit,
+    private_dimensions_hash_circuit, ChunkCommitmentTargets, CommitmentScheme,
+};
+use crate::halo::{build_halo_circuit, HaloTargets};
+use crate::pixel::{Packing, PixelFormat, PixelLayout};
 use crate::proof::ChunkProof;
+use crate::signature::{build_signature_circuit, SignatureScheme};
+use crate::transformations::util::{split_halo, split_pixels};
 use crate::{hash::build_hash_chunk_circuit, transformations::TransformationLogic};
 use crate::{C, D, F};
 
 pub struct TransformationCircuitBuilder<const L: usize> {
     transformation: Box<dyn TransformationLogic<L>>,
+    /// In bytes, the number of elements depends on the pixel layout
     original_len: usize,
-    total_chunks: usize,
+    layout: PixelLayout,
+    signature: Option<SignatureScheme>,
+    salted: bool,
+    commitment: CommitmentScheme,
 }
 
 impl<const L: usize> TransformationCircuitBuilder<L> {
     pub fn new(original_len: usize, transformation: Box<dyn TransformationLogic<L>>) -> Self {
         Self {
             transformation,
-            original_len: original_len / 4,
-            total_chunks: ((original_len / 4) + L - 1) / L,
+            original_len,
+            layout: PixelFormat::Rgba8.into(),
+            signature: None,
+            salted: false,
+            commitment: CommitmentScheme::Sponge,
         }
     }
 
+    /// Keeps the original hash private, the proof verifies a signature over it
+    /// and exposes the public key of the signer instead.
+    pub fn with_signed_original(mut self, scheme: SignatureScheme) -> Self {
+        self.signature = Some(scheme);
+        self
+    }
+
+    /// Salts the original hash so it can't be matched against candidate images,
+    /// the owner opens it by revealing the original and the salt.
+    pub fn with_salted_original(mut self) -> Self {
+        self.salted = true;
+        self
+    }
+
+    /// Commits to the images with `scheme` instead of the default sponge.
+    pub fn with_commitment(mut self, scheme: CommitmentScheme) -> Self {
+        self.commitment = scheme;
+        self
+    }
+
+    /// Commits to pixel streams of `format` instead of RGBA8.
+    pub fn with_pixel_format(mut self, format: PixelFormat) -> Self {
+        self.layout.format = format;
+        self
+    }
+
+    /// Packs 7 bytes of the pixel streams into each element, see
+    /// `Packing::Dense`.
+    pub fn with_dense_packing(mut self) -> Self {
+        self.layout.packing = Packing::Dense;
+        self
+    }
+
+    /// How the pixel stream of the original splits into chunks.
+    fn original(&self) -> Chunking<L> {
+        Chunking::new(self.original_len, self.layout)
+    }
+
+    /// Chunks that need a chunk proof. With a halo the neighbours of a touched
+    /// chunk are proven as well, they vouch for the halo it consumes.
+    fn proven_chunks(&self) -> Vec<bool> {
+        let total_chunks = self.original().total_chunks();
+        if self.commitment == CommitmentScheme::Sponge {
+            return vec![true; total_chunks];
+        }
+        let touched: Vec<bool> = (0..total_chunks)
+            .map(|chunk| {
+                self.transformation
+                    .touches_chunk(self.original().chunk_pixels(chunk), self.layout.format)
+            })
+            .collect();
+        if self.transformation.halo() == 0 {
+            return touched;
+        }
+        (0..total_chunks)
+            .map(|chunk| {
+                touched[chunk.saturating_sub(1)..min(chunk + 2, total_chunks)]
+                    .iter()
+                    .any(|touched| *touched)
+            })
+            .collect()
+    }
+
     fn build_chunk_circuit(
         &self,
         config: &CircuitConfig,
@@ -29,95 +110,272 @@ impl<const L: usize> TransformationCircuitBuilder<L> {
     ) -> TransformationChunkCircuit {
         let mut builder = CircuitBuilder::<F, D>::new(config.clone());
 
-        let chunk_len = if chunk == self.total_chunks - 1 {
-            self.original_len % L
+        let chunk_len = self.original().chunk_len(chunk);
+        let (original_commitment, edited_commitment) = match self.commitment {
+            CommitmentScheme::Sponge => (
+                ChunkCommitmentTargets::Sponge(build_hash_chunk_circuit::<F, D, L>(
+                    &mut builder,
+                    chunk_len,
+                    self.salted && chunk == 0,
+                )),
+                ChunkCommitmentTargets::Sponge(build_hash_chunk_circuit::<F, D, L>(
+                    &mut builder,
+                    chunk_len,
+                    false,
+                )),
+            ),
+            CommitmentScheme::MerkleTiles { .. } => (
+                ChunkCommitmentTargets::Tile(build_tile_circuit(&mut builder, chunk_len)),
+                ChunkCommitmentTargets::Tile(build_tile_circuit(&mut builder, chunk_len)),
+            ),
+        };
+        let layout = self.layout;
+        let pixels = self.original().chunk_pixels(chunk);
+        let original_chunk = original_commitment.elements();
+        let original_pixels = split_pixels(&mut builder, original_chunk, layout, pixels.len());
+        let edited_pixels = split_pixels(
+            &mut builder,
+            edited_commitment.elements(),
+            layout,
+            pixels.len(),
+        );
+
+        let halo_pis_start = builder.num_public_inputs();
+        // The transformation asks for pixels, the chunks exchange whole elements
+        let halo_bytes = self.transformation.halo() * layout.format.bytes_per_unit();
+        let per_element = layout.bytes_per_element();
+        let halo = (halo_bytes + per_element - 1) / per_element;
+        let halo_targets = if halo > 0 {
+            assert!(halo <= L, "The halo can't be wider than a chunk");
+            let chunk_start = chunk * L;
+            build_halo_circuit(
+                &mut builder,
+                original_chunk,
+                halo,
+                min(halo, chunk_start),
+                min(halo, self.original().elements() - chunk_start - chunk_len),
+            )
         } else {
-            L
+            HaloTargets::empty()
         };
-        let orig_hasher_targets = build_hash_chunk_circuit::<F, D, L>(&mut builder, chunk_len);
-        let edit_hasher_targets = build_hash_chunk_circuit::<F, D, L>(&mut builder, chunk_len);
+        let halo_pixels = split_halo(
+            &mut builder,
+            &halo_targets,
+            layout,
+            self.original().pixels() - pixels.end,
+        );
 
-        self.transformation.build_chunk_circuit(
+        let transformation_pis_start = builder.num_public_inputs();
+        let transformation_inputs = self.transformation.build_chunk_circuit(
             &mut builder,
-            &orig_hasher_targets.input
-                [..orig_hasher_targets.input.len() - orig_hasher_targets.padding_len],
-            &edit_hasher_targets.input
-                [..edit_hasher_targets.input.len() - edit_hasher_targets.padding_len],
-            chunk,
+            &original_pixels,
+            &edited_pixels,
+            &halo_pixels,
+            pixels.start,
+            layout.format,
         );
 
         let circuit = builder.build::<C>();
         TransformationChunkCircuit {
-            original_chunk: orig_hasher_targets,
-            edited_chunk: edit_hasher_targets,
+            chunk,
+            original_chunk: original_commitment,
+            edited_chunk: edited_commitment,
+            halo: halo_targets,
+            transformation_inputs,
+            halo_pis_start,
+            transformation_pis_start,
             circuit,
         }
     }
 
-    pub fn build_curcuit(&self) -> TransformationCircuit<L> {
-        let config = CircuitConfig::standard_recursion_config();
+    pub fn build_curcuit(self) -> TransformationCircuit<L> {
+        // A signature is over the plain hash devices compute
+        assert!(
+            !(self.salted && self.signature.is_some()),
+            "A signed original can't be salted"
+        );
+        assert!(
+            !(self.salted && self.commitment != CommitmentScheme::Sponge),
+            "Only the sponge commitment can be salted"
+        );
+        // A signature over the plain hash doesn't cover the dimensions and the
+        // tiles fix them in the circuit
+        assert!(
+            !(self.transformation.hides_dimensions()
+                && (self.signature.is_some() || self.commitment != CommitmentScheme::Sponge)),
+            "Private original dimensions need an unsigned sponge commitment"
+        );
+        assert!(
+            self.transformation.supports_format(self.layout.format),
+            "The transformation doesn't support {:?} originals",
+            self.layout.format
+        );
+        assert!(
+            self.original().has_whole_pixels(),
+            "A chunk must hold whole pixels"
+        );
+        let config = zk_recursion_config();
         let mut chunk_circuits = Vec::new();
         let mut builder = CircuitBuilder::<F, D>::new(config.clone());
 
-        let mut last_original_final_state_target = builder.constants(&[F::ZERO; SPONGE_WIDTH]);
-        let mut last_edited_final_state_target = builder.constants(&[F::ZERO; SPONGE_WIDTH]);
+        let initial_state = self.layout.sponge_initial_state::<F>();
+        let mut last_original_final_state_target = builder.constants(&initial_state);
+        let mut last_edited_final_state_target = builder.constants(&initial_state);
+        let mut original_leaves = Vec::new();
+        let mut edited_leaves = Vec::new();
+        let mut skipped_leaves = Vec::new();
+
+        let halo = self.transformation.halo();
+        let mut last_halo_public_inputs: Option<Vec<Target>> = None;
+        let mut chunk_public_inputs = Vec::new();
 
         let mut pts = Vec::new();
-        let mut ids = Vec::new();
-        for chunk in 0..self.total_chunks {
-            let chunk_circuit = self.build_chunk_circuit(&config, chunk);
-            let pt = builder.add_virtual_proof_with_pis::<C>(&chunk_circuit.circuit.common);
-
-            let original_init_state_target = &pt.public_inputs
-                [ChunkProof::ORIGINAL_INIT_STATE_PI_INDEXES.0
-                    ..ChunkProof::ORIGINAL_INIT_STATE_PI_INDEXES.1];
-            for (left, right) in last_original_final_state_target
-                .iter()
-                .zip(original_init_state_target.iter())
-            {
-                builder.connect(*left, *right);
+        for (chunk, proven) in self.proven_chunks().into_iter().enumerate() {
+            if !proven {
+                // An untouched tile has the same leaf in both trees
+                let leaf = builder.add_virtual_hash();
+                original_leaves.push(leaf);
+                edited_leaves.push(leaf);
+                skipped_leaves.push((chunk, leaf));
+                last_halo_public_inputs = None;
+                continue;
             }
-            last_original_final_state_target.copy_from_slice(
-                &pt.public_inputs[ChunkProof::ORIGINAL_FINAL_STATE_PI_INDEXES.0
-                    ..ChunkProof::ORIGINAL_FINAL_STATE_PI_INDEXES.1],
-            );
-
-            let edited_init_state_target = &pt.public_inputs
-                [ChunkProof::EDITED_INIT_STATE_PI_INDEXES.0
-                    ..ChunkProof::EDITED_INIT_STATE_PI_INDEXES.1];
-            for (left, right) in last_edited_final_state_target
-                .iter()
-                .zip(edited_init_state_target.iter())
-            {
-                builder.connect(*left, *right);
-            }
-            last_edited_final_state_target.copy_from_slice(
-                &pt.public_inputs[ChunkProof::EDITED_FINAL_STATE_PI_INDEXES.0
-                    ..ChunkProof::EDITED_FINAL_STATE_PI_INDEXES.1],
-            );
 
-            let inner_data = builder.add_virtual_verifier_data(
-                chunk_circuit.circuit.common.config.fri_config.cap_height,
-            );
+            let chunk_circuit = self.build_chunk_circuit(&config, chunk);
+            let pt = verify_chunk_proof(&mut builder, &chunk_circuit.circuit);
 
-            builder.verify_proof::<C>(&pt, &inner_data, &chunk_circuit.circuit.common);
+            match self.commitment {
+                CommitmentScheme::Sponge => {
+                    chain_state(
+                        &mut builder,
+                        &mut last_original_final_state_target,
+                        &pt.public_inputs,
+                        ChunkProof::ORIGINAL_INIT_STATE_PI_INDEXES,
+                        ChunkProof::ORIGINAL_FINAL_STATE_PI_INDEXES,
+                    );
+                    chain_state(
+                        &mut builder,
+                        &mut last_edited_final_state_target,
+                        &pt.public_inputs,
+                        ChunkProof::EDITED_INIT_STATE_PI_INDEXES,
+                        ChunkProof::EDITED_FINAL_STATE_PI_INDEXES,
+                    );
+                }
+                CommitmentScheme::MerkleTiles { .. } => {
+                    for (leaves, indexes) in [
+                        (&mut original_leaves, ChunkProof::ORIGINAL_LEAF_PI_INDEXES),
+                        (&mut edited_leaves, ChunkProof::EDITED_LEAF_PI_INDEXES),
+                    ] {
+                        leaves.push(HashOutTarget::from_vec(
+                            pt.public_inputs[indexes.0..indexes.1].to_vec(),
+                        ));
+                    }
+                }
+            }
 
+            // Every chunk has to consume the same halo its neighbours hashed
+            if halo > 0 {
+                let halo_public_inputs = &pt.public_inputs[chunk_circuit.halo_pis_start..];
+                if let Some(last_halo_public_inputs) = &last_halo_public_inputs {
+                    for (left, right) in [
+                        (
+                            ChunkProof::HALO_TAIL_HASH_PI_INDEXES,
+                            ChunkProof::HALO_BEFORE_HASH_PI_INDEXES,
+                        ),
+                        (
+                            ChunkProof::HALO_AFTER_HASH_PI_INDEXES,
+                            ChunkProof::HALO_HEAD_HASH_PI_INDEXES,
+                        ),
+                    ] {
+                        for (l, r) in last_halo_public_inputs[left.0..left.1]
+                            .iter()
+                            .zip(&halo_public_inputs[right.0..right.1])
+                        {
+                            builder.connect(*l, *r);
+                        }
+                    }
+                }
+                last_halo_public_inputs = Some(halo_public_inputs.to_vec());
+            }
+
+            chunk_public_inputs
+                .push(pt.public_inputs[chunk_circuit.transformation_pis_start..].to_vec());
             chunk_circuits.push(chunk_circuit);
             pts.push(pt);
-            ids.push(inner_data);
         }
 
-        builder.register_public_inputs(&last_original_final_state_target[..4]);
-        builder.register_public_inputs(&last_edited_final_state_target[..4]);
+        let (original_hash, edited_hash) = match self.commitment {
+            CommitmentScheme::Sponge => (
+                last_original_final_state_target[..4].to_vec(),
+                last_edited_final_state_target[..4].to_vec(),
+            ),
+            CommitmentScheme::MerkleTiles { width, height } => {
+                let mut commit = |leaves| {
+                    let root = merkle_root_circuit(&mut builder, leaves);
+                    dimensions_hash_circuit(&mut builder, root, width, height, self.layout)
+                        .elements
+                        .to_vec()
+                };
+                (commit(original_leaves), commit(edited_leaves))
+            }
+        };
+
+        // Dimensions the transformation keeps private are absorbed into the
+        // commitment to the original, so its pixels can't be taken for an
+        // image of other dimensions
+        let original_dimensions = match self.transformation.hides_dimensions() {
+            true => builder.add_virtual_targets(2),
+            false => Vec::new(),
+        };
+        let original_commitment = match original_dimensions[..] {
+            [width, height] => private_dimensions_hash_circuit(
+                &mut builder,
+                HashOutTarget::from_vec(original_hash.clone()),
+                width,
+                height,
+                self.layout,
+            )
+            .elements
+            .to_vec(),
+            _ => original_hash.clone(),
+        };
+
+        let signature = match self.signature {
+            Some(scheme) => Some(build_signature_circuit(
+                &mut builder,
+                scheme,
+                &original_commitment,
+            )),
+            None => {
+                builder.register_public_inputs(&original_commitment);
+                None
+            }
+        };
+        builder.register_public_inputs(&edited_hash);
+        let mut aggregation_inputs = original_dimensions.clone();
+        aggregation_inputs.extend(self.transformation.build_aggregation_circuit(
+            &mut builder,
+            &original_hash,
+            &edited_hash,
+            &original_dimensions,
+            &chunk_public_inputs,
+            self.layout.format,
+        ));
         builder.print_gate_counts(0);
 
         let circuit = builder.build::<C>();
 
         TransformationCircuit {
             circuit,
+            transformation: self.transformation,
+            aggregation_inputs,
+            signature,
+            salted: self.salted,
+            commitment: self.commitment,
+            layout: self.layout,
+            skipped_leaves,
             chunk_circuits,
             pts,
-            ids,
         }
     }
 }
@@ -1,103 +1,282 @@
+use std::cmp::min;
 use std::time::Instant;
 
-use anyhow::Result;
-use itertools::izip;
-use log::Level;
+use anyhow::{anyhow, Result};
 use plonky2::{
-    iop::witness::{PartialWitness, WitnessWrite},
+    hash::hash_types::{HashOut, HashOutTarget},
+    iop::{
+        target::Target,
+        witness::{PartialWitness, WitnessWrite},
+    },
     plonk::{
-        circuit_data::{CircuitData, VerifierCircuitTarget},
-        proof::ProofWithPublicInputsTarget,
-        prover::prove,
+        circuit_data::CircuitData, config::GenericHashOut, proof::ProofWithPublicInputsTarget,
     },
-    util::timing::TimingTree,
 };
 
 use crate::{
-    hash::{ChunkHashTargets, ChunkHasher},
-    proof::{ChunkProof, TransformationProof},
-    util::bytes_to_field64,
+    aggregation::{prove_chunk, prove_compressed},
+    commitment::{tile_leaf, ChunkCommitmentTargets, CommitmentScheme},
+    halo::HaloTargets,
+    hash::ChunkHasher,
+    pixel::PixelLayout,
+    proof::{ChunkProof, ProofEncoding, TransformationProof},
+    salt::Salt,
+    signature::{OriginalSignature, SignatureTargets},
+    transformations::{ImageWitness, TransformationLogic},
+    util::set_multiple_targets,
     C, D, F,
 };
 
 pub(crate) struct TransformationChunkCircuit {
+    pub(crate) chunk: usize,
     pub(crate) circuit: CircuitData<F, C, D>,
-    pub(crate) original_chunk: ChunkHashTargets,
-    pub(crate) edited_chunk: ChunkHashTargets,
+    pub(crate) original_chunk: ChunkCommitmentTargets,
+    pub(crate) edited_chunk: ChunkCommitmentTargets,
+    pub(crate) halo: HaloTargets,
+    pub(crate) transformation_inputs: Vec<Target>,
+    /// Public inputs from this index on are the halo ones, if any
+    pub(crate) halo_pis_start: usize,
+    /// Public inputs from this index on are the transformation specific ones
+    pub(crate) transformation_pis_start: usize,
 }
 
 pub struct TransformationCircuit<const L: usize> {
     pub circuit: CircuitData<F, C, D>,
+    pub(crate) transformation: Box<dyn TransformationLogic<L>>,
+    pub(crate) aggregation_inputs: Vec<Target>,
+    pub(crate) signature: Option<SignatureTargets>,
+    pub(crate) salted: bool,
+    pub(crate) commitment: CommitmentScheme,
+    pub(crate) layout: PixelLayout,
+    /// Tiles without a chunk proof and their leaf under a Merkle commitment
+    pub(crate) skipped_leaves: Vec<(usize, HashOutTarget)>,
     pub(crate) chunk_circuits: Vec<TransformationChunkCircuit>,
     pub(crate) pts: Vec<ProofWithPublicInputsTarget<D>>,
-    pub(crate) ids: Vec<VerifierCircuitTarget>,
+}
+
+/// The original as the chunk proofs take it, its pixels, the sponge states
+/// between its chunks and its commitment. Made once, it serves the proofs of
+/// several transformations of the same original.
+pub struct OriginalWitness<const L: usize> {
+    elements: Vec<F>,
+    pixels: usize,
+    hasher: ChunkHasher<F, D, L>,
+    hash: HashOut<F>,
+    commitment: CommitmentScheme,
+    layout: PixelLayout,
+    salted: bool,
+}
+
+impl<const L: usize> OriginalWitness<L> {
+    /// `original` is a pixel stream packed as `layout`.
+    pub fn new(
+        original: &[u8],
+        layout: PixelLayout,
+        commitment: CommitmentScheme,
+        salt: Option<&Salt>,
+    ) -> Self {
+        let elements = layout.pack::<F>(original);
+        let hasher = match salt {
+            Some(salt) => ChunkHasher::<F, D, L>::new_salted(&elements, salt.0),
+            None => ChunkHasher::<F, D, L>::new(&elements),
+        }
+        .with_layout(layout)
+        .absorbed();
+        let hash = match (salt, commitment) {
+            (None, CommitmentScheme::MerkleTiles { .. }) => {
+                commitment.commit_elements::<L>(&elements, layout)
+            }
+            _ => hasher.output(),
+        };
+        OriginalWitness {
+            elements,
+            pixels: original.len() / layout.format.bytes_per_unit(),
+            hasher,
+            hash,
+            commitment,
+            layout,
+            salted: salt.is_some(),
+        }
+    }
+}
+
+fn populate_commitment_inputs<const L: usize>(
+    targets: &ChunkCommitmentTargets,
+    hasher: &mut ChunkHasher<F, D, L>,
+    image: &[F],
+    chunk: usize,
+    inputs: &mut PartialWitness<F>,
+) {
+    match targets {
+        ChunkCommitmentTargets::Sponge(targets) => hasher.populate_chunk_inputs(targets, inputs),
+        ChunkCommitmentTargets::Tile(targets) => {
+            set_multiple_targets(inputs, targets, &image[chunk * L..])
+        }
+    }
 }
 
 impl<const L: usize> TransformationCircuit<L> {
+    /// Identifies the circuit, proofs only verify against the circuit they
+    /// were made with.
+    pub fn circuit_digest(&self) -> Vec<u8> {
+        self.circuit.verifier_only.circuit_digest.to_bytes()
+    }
+
+    /// Decodes a proof this circuit made, see `TransformationProof::from_bytes`.
+    pub fn proof_from_bytes(
+        &self,
+        bytes: Vec<u8>,
+        encoding: ProofEncoding,
+    ) -> Result<TransformationProof> {
+        TransformationProof::from_bytes(
+            bytes,
+            encoding,
+            &self.circuit.common,
+            self.signature.as_ref().map(|signature| signature.scheme()),
+            self.salted,
+            self.commitment,
+        )
+    }
+
     fn prove_chunk(
         &self,
         orig_hasher: &mut ChunkHasher<F, D, L>,
         edit_hasher: &mut ChunkHasher<F, D, L>,
         chunk_curcuit: &TransformationChunkCircuit,
+        images: &ImageWitness,
     ) -> Result<ChunkProof> {
+        let chunk = chunk_curcuit.chunk;
         let mut inputs = PartialWitness::<F>::new();
-        orig_hasher.populate_chunk_inputs(&chunk_curcuit.original_chunk, &mut inputs);
-        edit_hasher.populate_chunk_inputs(&chunk_curcuit.edited_chunk, &mut inputs);
-
-        let mut timing = TimingTree::new("prove_chunk", Level::Info);
-        let proof = prove(
-            &chunk_curcuit.circuit.prover_only,
-            &chunk_curcuit.circuit.common,
-            inputs,
-            &mut timing,
-        )?;
-        timing.print();
+        populate_commitment_inputs(
+            &chunk_curcuit.original_chunk,
+            orig_hasher,
+            images.original,
+            chunk,
+            &mut inputs,
+        );
+        populate_commitment_inputs(
+            &chunk_curcuit.edited_chunk,
+            edit_hasher,
+            images.edited,
+            chunk,
+            &mut inputs,
+        );
+        chunk_curcuit.halo.populate_halo_inputs(
+            &mut inputs,
+            images.original,
+            chunk * L,
+            chunk_curcuit.original_chunk.elements().len(),
+        );
+        set_multiple_targets(
+            &mut inputs,
+            &chunk_curcuit.transformation_inputs,
+            &self
+                .transformation
+                .chunk_witness(images, images.layout.pixels_in(chunk * L)),
+        );
 
+        let proof = prove_chunk(&chunk_curcuit.circuit, inputs)?;
         chunk_curcuit.circuit.verify(proof.clone())?;
 
         Ok(ChunkProof { proof })
     }
 
-    pub fn prove(&mut self, original: &[u8], edited: &[u8]) -> Result<TransformationProof> {
-        let original_elements = bytes_to_field64::<F>(original);
-        let edited_elements = bytes_to_field64::<F>(edited);
+    /// `original` and `edited` are pixel streams of the circuit's layout.
+    /// `signature` is required exactly when the circuit was built with a signed
+    /// original, and `salt` when it was built with a salted one.
+    pub fn prove(
+        &mut self,
+        original: &[u8],
+        edited: &[u8],
+        signature: Option<&OriginalSignature>,
+        salt: Option<&Salt>,
+    ) -> Result<TransformationProof> {
+        let original = OriginalWitness::new(original, self.layout, self.commitment, salt);
+        self.prove_with_original(&original, edited, signature)
+    }
+
+    /// Like `prove`, for an original whose witness is shared with the proofs
+    /// of other transformations.
+    pub fn prove_with_original(
+        &mut self,
+        original: &OriginalWitness<L>,
+        edited: &[u8],
+        signature: Option<&OriginalSignature>,
+    ) -> Result<TransformationProof> {
+        if self.salted != original.salted {
+            return Err(anyhow!("the salt doesn't match the circuit"));
+        }
+        if self.commitment != original.commitment {
+            return Err(anyhow!("the original commitment doesn't match the circuit"));
+        }
+        if self.layout != original.layout {
+            return Err(anyhow!(
+                "the original pixel layout doesn't match the circuit"
+            ));
+        }
+        let original_elements = &original.elements;
+        let edited_elements = self.layout.pack::<F>(edited);
+        if edited_elements.len() != original_elements.len() {
+            return Err(anyhow!(
+                "the edited image doesn't have the size of the original"
+            ));
+        }
 
+        let original_bytes = original_elements.len() * self.layout.bytes_per_element();
         println!(
             "Going to proof the hash of {} bytes. {} kB",
-            original.len(),
-            original.len() / 1024
+            original_bytes,
+            original_bytes / 1024
         );
         let start = Instant::now();
 
-        let mut orig_hasher = ChunkHasher::<F, D, L>::new(&original_elements);
-        let mut edit_hasher = ChunkHasher::<F, D, L>::new(&edited_elements);
+        let mut orig_hasher = original.hasher.clone();
+        let mut edit_hasher =
+            ChunkHasher::<F, D, L>::new(&edited_elements).with_layout(self.layout);
+        let images = ImageWitness {
+            original: original_elements,
+            edited: &edited_elements,
+            original_hash: original.hash,
+            edited_hash: self
+                .commitment
+                .commit_elements::<L>(&edited_elements, self.layout),
+            layout: self.layout,
+            pixels: original.pixels,
+        };
 
         let mut pw = PartialWitness::new();
-        for (chunk_circuit, pt, inner_data) in izip!(&self.chunk_circuits, &self.pts, &self.ids) {
+        set_multiple_targets(
+            &mut pw,
+            &self.aggregation_inputs,
+            &self.transformation.aggregation_witness(&images),
+        );
+        match (&self.signature, signature) {
+            (Some(targets), Some(signature)) if targets.scheme() == signature.scheme() => {
+                targets.set_witness(&mut pw, signature)
+            }
+            (None, None) => {}
+            _ => return Err(anyhow!("the original signature doesn't match the circuit")),
+        }
+        for (chunk, leaf) in &self.skipped_leaves {
+            let tile = &original_elements[chunk * L..min((chunk + 1) * L, original_elements.len())];
+            pw.set_hash_target(*leaf, tile_leaf(tile));
+        }
+        for (chunk_circuit, pt) in self.chunk_circuits.iter().zip(&self.pts) {
             println!("Proving chunk...");
             let chunk_proof =
-                self.prove_chunk(&mut orig_hasher, &mut edit_hasher, chunk_circuit)?;
+                self.prove_chunk(&mut orig_hasher, &mut edit_hasher, chunk_circuit, &images)?;
             pw.set_proof_with_pis_target(&pt, &chunk_proof.proof);
-            pw.set_verifier_data_target(&inner_data, &chunk_circuit.circuit.verifier_only);
         }
-
-        let mut timing = TimingTree::new("prove", Level::Debug);
-        let proof = prove(
-            &self.circuit.prover_only,
-            &self.circuit.common,
-            pw,
-            &mut timing,
-        )?;
-        timing.print();
+        let proof = prove_compressed(&self.circuit, pw)?;
 
         let duration = start.elapsed();
         println!("Total time for prove is: {:?}", duration);
 
         Ok(TransformationProof {
-            proof: proof.compress(
-                &self.circuit.verifier_only.circuit_digest,
-                &self.circuit.common,
-            )?,
+            proof,
+            signature: signature.map(|signature| signature.scheme()),
+            salted: self.salted,
+            commitment: self.commitment,
         })
     }
 }
@@ -0,0 +1,231 @@
+use plonky2::hash::hash_types::{HashOut, HashOutTarget};
+use plonky2::hash::poseidon::PoseidonHash;
+use plonky2::iop::target::Target;
+use plonky2::plonk::circuit_builder::CircuitBuilder;
+use plonky2::plonk::config::{GenericHashOut, Hasher};
+use plonky2_field::types::Field;
+use serde::{Deserialize, Serialize};
+
+use crate::hash::{ChunkHashTargets, ChunkHasher};
+use crate::pixel::PixelLayout;
+use crate::{D, F};
+
+/// How the original and the edited image are committed to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CommitmentScheme {
+    /// One Poseidon sponge over all pixels, the chunk proofs chain its state.
+    Sponge,
+    /// Poseidon Merkle tree over tiles of `L` pixels whose root is hashed
+    /// together with the dimensions. Chunks are independent, so tiles a
+    /// transformation doesn't touch need no chunk proof. The dimensions are
+    /// part of the commitment, which doesn't suit a hidden crop.
+    MerkleTiles { width: u32, height: u32 },
+}
+
+impl CommitmentScheme {
+    /// Natively computes the commitment to `image`, a pixel stream packed as
+    /// `layout`, encoded like `TransformationProof::edited_hash`.
+    pub fn commit<const L: usize>(&self, image: &[u8], layout: PixelLayout) -> Vec<u8> {
+        self.commit_elements::<L>(&layout.pack::<F>(image), layout)
+            .to_bytes()
+    }
+
+    pub(crate) fn commit_elements<const L: usize>(
+        &self,
+        data: &[F],
+        layout: PixelLayout,
+    ) -> HashOut<F> {
+        match self {
+            CommitmentScheme::Sponge => ChunkHasher::<F, D, L>::new(data)
+                .with_layout(layout)
+                .absorbed()
+                .output(),
+            CommitmentScheme::MerkleTiles { width, height } => {
+                let root = merkle_root(data.chunks(L).map(tile_leaf).collect());
+                dimensions_hash(root, *width, *height, layout)
+            }
+        }
+    }
+}
+
+/// Hash commitment targets of a chunk, either the chained sponge or the tile.
+pub(crate) enum ChunkCommitmentTargets {
+    Sponge(ChunkHashTargets),
+    Tile(Vec<Target>),
+}
+
+impl ChunkCommitmentTargets {
+    /// Elements of the chunk without the sponge padding.
+    pub(crate) fn elements(&self) -> &[Target] {
+        match self {
+            ChunkCommitmentTargets::Sponge(targets) => {
+                &targets.input[..targets.input.len() - targets.padding_len]
+            }
+            ChunkCommitmentTargets::Tile(input) => input,
+        }
+    }
+}
+
+pub(crate) fn tile_leaf(tile: &[F]) -> HashOut<F> {
+    PoseidonHash::hash_or_noop(tile)
+}
+
+/// Adds the pixels of a tile as private inputs and registers its leaf hash.
+pub(crate) fn build_tile_circuit(
+    builder: &mut CircuitBuilder<F, D>,
+    tile_len: usize,
+) -> Vec<Target> {
+    let input = builder.add_virtual_targets(tile_len);
+    let leaf = builder.hash_or_noop::<PoseidonHash>(input.clone());
+    builder.register_public_inputs(&leaf.elements);
+    input
+}
+
+// The tree is padded with zero leaves to a power of two, inner nodes hash the
+// concatenation of their children.
+
+fn merkle_root(mut layer: Vec<HashOut<F>>) -> HashOut<F> {
+    layer.resize(layer.len().next_power_of_two(), HashOut::ZERO);
+    while layer.len() > 1 {
+        layer = layer
+            .chunks(2)
+            .map(|pair| PoseidonHash::hash_no_pad(&[pair[0].elements, pair[1].elements].concat()))
+            .collect();
+    }
+    layer[0]
+}
+
+pub(crate) fn merkle_root_circuit(
+    builder: &mut CircuitBuilder<F, D>,
+    mut layer: Vec<HashOutTarget>,
+) -> HashOutTarget {
+    let zero = builder.constant_hash(HashOut::ZERO);
+    layer.resize(layer.len().next_power_of_two(), zero);
+    while layer.len() > 1 {
+        layer = layer
+            .chunks(2)
+            .map(|pair| {
+                builder.hash_n_to_hash_no_pad::<PoseidonHash>(
+                    [pair[0].elements, pair[1].elements].concat(),
+                )
+            })
+            .collect();
+    }
+    layer[0]
+}
+
+// The layout tag follows the dimensions. Absorbing a trailing zero leaves the
+// sponge as it was, so RGBA8 commitments are the same as without the tag.
+
+fn dimensions_hash(root: HashOut<F>, width: u32, height: u32, layout: PixelLayout) -> HashOut<F> {
+    let mut inputs = root.elements.to_vec();
+    inputs.extend([
+        F::from_canonical_u32(width),
+        F::from_canonical_u32(height),
+        F::from_canonical_u64(layout.tag()),
+    ]);
+    PoseidonHash::hash_no_pad(&inputs)
+}
+
+pub(crate) fn dimensions_hash_circuit(
+    builder: &mut CircuitBuilder<F, D>,
+    root: HashOutTarget,
+    width: u32,
+    height: u32,
+    layout: PixelLayout,
+) -> HashOutTarget {
+    let width = builder.constant(F::from_canonical_u32(width));
+    let height = builder.constant(F::from_canonical_u32(height));
+    private_dimensions_hash_circuit(builder, root, width, height, layout)
+}
+
+/// `dimensions_hash_circuit` over dimensions that are private inputs.
+pub(crate) fn private_dimensions_hash_circuit(
+    builder: &mut CircuitBuilder<F, D>,
+    root: HashOutTarget,
+    width: Target,
+    height: Target,
+    layout: PixelLayout,
+) -> HashOutTarget {
+    let mut inputs = root.elements.to_vec();
+    inputs.extend([width, height]);
+    inputs.push(builder.constant(F::from_canonical_u64(layout.tag())));
+    builder.hash_n_to_hash_no_pad::<PoseidonHash>(inputs)
+}
+
+/// Commitment to an original whose dimensions the proof keeps private, see
+/// `TransformationLogic::hides_dimensions`. `hash` is its sponge commitment,
+/// encoded like `TransformationProof::original_hash`, the dimensions are
+/// absorbed as for Merkle tiles.
+pub fn bind_dimensions(hash: &[u8], width: u32, height: u32, layout: PixelLayout) -> Vec<u8> {
+    dimensions_hash(HashOut::from_bytes(hash), width, height, layout).to_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use zkedit_transformations::{Region, Transformation};
+
+    use crate::builder::TransformationCircuitBuilder;
+    use crate::pixel::PixelFormat;
+    use crate::testing::{random_pixels, HEIGHT, L, WIDTH};
+    use crate::util::calculate_poseidon;
+
+    use super::*;
+
+    #[test]
+    fn test_sponge_commitment_is_poseidon() {
+        let image: Vec<u8> = (0..160).map(|i| i as u8).collect();
+        assert_eq!(
+            CommitmentScheme::Sponge.commit::<24>(&image, PixelFormat::Rgba8.into()),
+            calculate_poseidon(&image)
+        );
+    }
+
+    #[test]
+    fn test_merkle_tiles_skip_untouched_tiles() {
+        let scheme = CommitmentScheme::MerkleTiles {
+            width: WIDTH,
+            height: HEIGHT,
+        };
+        // Inside the first of the two tiles
+        let region = Region {
+            x: 1,
+            y: 0,
+            w: 2,
+            h: 2,
+        };
+        let preserve_outside = Transformation::PreserveOutside {
+            orig_w: WIDTH,
+            orig_h: HEIGHT,
+            regions: vec![region],
+        };
+        let original = random_pixels((WIDTH * HEIGHT) as usize);
+        let mut edited = original.clone();
+        edited[1] = [0, 0, 0, 255];
+        let (original, edited) = (original.concat(), edited.concat());
+
+        let mut circuit =
+            TransformationCircuitBuilder::<L>::new(original.len(), Box::new(preserve_outside))
+                .with_commitment(scheme)
+                .build_curcuit();
+        assert_eq!(circuit.chunk_circuits.len(), 1);
+        let layout = PixelFormat::Rgba8.into();
+        let proof = circuit.prove(&original, &edited, None, None).unwrap();
+        assert_eq!(
+            proof.original_hash(),
+            Some(scheme.commit::<L>(&original, layout))
+        );
+        assert_eq!(proof.edited_hash(), scheme.commit::<L>(&edited, layout));
+
+        // The skipped tile is the original one in both trees, an edit there
+        // doesn't end up in the edited hash
+        let mut tampered = edited.clone();
+        tampered[original.len() - 1] ^= 1;
+        let tampered_proof = circuit.prove(&original, &tampered, None, None).unwrap();
+        assert_ne!(
+            tampered_proof.edited_hash(),
+            scheme.commit::<L>(&tampered, layout)
+        );
+        proof.verify(circuit.circuit).unwrap();
+    }
+}
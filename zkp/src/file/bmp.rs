@@ -0,0 +1,40 @@
+use anyhow::{anyhow, Result};
+
+use super::{read_u16, read_u32, FileLayout};
+
+const BI_RGB: u32 = 0;
+
+/// Layout of an uncompressed 24-bit BMP. Rows are padded to four bytes and
+/// stored bottom-up unless the height is negative, pixels are BGR.
+pub fn parse_layout(file: &[u8]) -> Result<FileLayout> {
+    let pixel_offset = read_u32(file, 10, false)? as usize;
+    let width = read_u32(file, 18, false)? as i32;
+    let height = read_u32(file, 22, false)? as i32;
+    let bits_per_pixel = read_u16(file, 28, false)?;
+    let compression = read_u32(file, 30, false)?;
+    if bits_per_pixel != 24 || compression != BI_RGB {
+        return Err(anyhow!("only uncompressed 24-bit BMPs are supported"));
+    }
+    if width <= 0 || height == 0 {
+        return Err(anyhow!("the image is empty"));
+    }
+
+    let (width, top_down) = (width as u32, height < 0);
+    let height = height.unsigned_abs();
+    let stride = (width as usize * 3 + 3) / 4 * 4;
+    let rows = (0..height as usize)
+        .map(|y| {
+            let stored_row = if top_down { y } else { height as usize - 1 - y };
+            pixel_offset + stored_row * stride
+        })
+        .collect();
+
+    FileLayout::unfiltered(
+        file.len(),
+        width,
+        height,
+        rows,
+        3,
+        [Some(2), Some(1), Some(0), None],
+    )
+}
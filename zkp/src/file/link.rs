@@ -0,0 +1,922 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use anyhow::{anyhow, Result};
+use plonky2::{
+    hash::{
+        hashing::{SPONGE_CAPACITY, SPONGE_RATE, SPONGE_WIDTH},
+        poseidon::PoseidonHash,
+    },
+    iop::{
+        target::{BoolTarget, Target},
+        witness::{PartialWitness, WitnessWrite},
+    },
+    plonk::{
+        circuit_builder::CircuitBuilder,
+        circuit_data::{CircuitConfig, CircuitData, CommonCircuitData},
+        proof::{CompressedProofWithPublicInputs, ProofWithPublicInputsTarget},
+    },
+    util::serialization::Write,
+};
+use plonky2_field::types::Field;
+use serde::{Deserialize, Serialize};
+
+use super::png::{
+    build_adler32_reduction, build_crc32_update, build_prediction, connect_filtered, crc32_update,
+    ADLER32_MODULUS,
+};
+use super::{Checksum, FileLayout};
+use crate::{
+    aggregation::{
+        chain_state, prove_chunk, prove_compressed, verify_chunk_proof, zk_recursion_config,
+    },
+    hash::{absorb_segment, build_hash_segment_circuit, ChunkHashTargets},
+    pixel::{PixelFormat, PixelLayout},
+    signature::{build_signature_circuit, OriginalSignature, SignatureScheme, SignatureTargets},
+    transformations::util::{split_pixels, unpack_pixel},
+    util::{bytes_to_field64, pack_le_bytes, set_multiple_targets},
+    C, D, F,
+};
+
+// Each chunk circuit takes a group of consecutive pixel rows. It absorbs the
+// group's pixels, packed in the layout of the proofs the file links to, into
+// the pixel sponge and the bytes of the file the rows are stored in into the
+// file sponge, then connects every channel byte to its offset in the file and
+// every other byte to the public skeleton. Groups are chained in pixel order
+// for the pixel hash and in file order for the file hash, so bottom-up BMPs
+// and shuffled TIFF strips prove alike.
+//
+// A group's file bytes rarely start on a sponge block. Its segment starts at
+// the block boundary below, the head bytes up to the group are published and
+// checked by the group before it, which holds them as its tail.
+//
+// PNG checksums are chained through the groups in file order like the file
+// sponge, and the last row of a group is linked by its hash to the next group,
+// which needs it as the row above to reverse the filters.
+
+const PIXEL_INIT_STATE_PI_INDEXES: (usize, usize) = (0, SPONGE_WIDTH);
+const PIXEL_FINAL_STATE_PI_INDEXES: (usize, usize) = (SPONGE_WIDTH, 2 * SPONGE_WIDTH);
+const FILE_INIT_STATE_PI_INDEXES: (usize, usize) = (2 * SPONGE_WIDTH, 3 * SPONGE_WIDTH);
+const FILE_FINAL_STATE_PI_INDEXES: (usize, usize) = (3 * SPONGE_WIDTH, 4 * SPONGE_WIDTH);
+/// The CRC-32 state and the unreduced Adler-32 sums
+const CHECKSUM_INIT_STATE_PI_INDEXES: (usize, usize) = (4 * SPONGE_WIDTH, 4 * SPONGE_WIDTH + 3);
+const CHECKSUM_FINAL_STATE_PI_INDEXES: (usize, usize) =
+    (4 * SPONGE_WIDTH + 3, 4 * SPONGE_WIDTH + 6);
+const ABOVE_ROW_HASH_PI_INDEXES: (usize, usize) = (4 * SPONGE_WIDTH + 6, 4 * SPONGE_WIDTH + 10);
+const LAST_ROW_HASH_PI_INDEXES: (usize, usize) = (4 * SPONGE_WIDTH + 10, 4 * SPONGE_WIDTH + 14);
+/// The head bytes follow, then the tail bytes.
+const HEAD_BYTES_PI_START: usize = 4 * SPONGE_WIDTH + 14;
+
+/// File bytes in a sponge block.
+const BLOCK_BYTES: usize = 4 * SPONGE_RATE;
+
+struct RowGroup {
+    rows: Range<usize>,
+    /// Elements of the pixel stream the rows are packed into
+    elements: Range<usize>,
+    /// File bytes the group constrains
+    bytes: Range<usize>,
+    /// File bytes its segment absorbs, from the block boundary below `bytes`
+    segment: Range<usize>,
+    last_in_file: bool,
+}
+
+impl RowGroup {
+    fn head(&self) -> Range<usize> {
+        self.segment.start..self.bytes.start
+    }
+
+    /// Bytes past the segment, absorbed by the next group in the file.
+    fn tail(&self) -> Range<usize> {
+        self.segment.end.min(self.bytes.end)..self.bytes.end
+    }
+}
+
+const CRC32_START: u8 = 1;
+const CRC32_COVERED: u8 = 2;
+const ADLER32_COVERED: u8 = 4;
+
+#[derive(Clone, Copy)]
+enum ChecksumByte {
+    Crc32(usize),
+    Adler32(usize),
+}
+
+/// Which checksums each byte of the file goes into or is part of.
+struct Checksums {
+    /// Empty for files without checksums
+    flags: Vec<u8>,
+    outputs: HashMap<usize, ChecksumByte>,
+}
+
+impl Checksums {
+    fn new(layout: &FileLayout) -> Self {
+        let mut flags = Vec::new();
+        let mut outputs = HashMap::new();
+        if !layout.checksums.is_empty() {
+            flags.resize(layout.file_len, 0);
+        }
+        for checksum in &layout.checksums {
+            match checksum {
+                Checksum::Crc32 { over, at } => {
+                    for offset in over.clone() {
+                        flags[offset] |= CRC32_COVERED;
+                    }
+                    flags[over.start] |= CRC32_START;
+                    for (i, offset) in at.iter().enumerate() {
+                        outputs.insert(*offset, ChecksumByte::Crc32(i));
+                    }
+                }
+                Checksum::Adler32 { at } => {
+                    for (_, run) in &layout.stream {
+                        for offset in run.clone() {
+                            flags[offset] |= ADLER32_COVERED;
+                        }
+                    }
+                    for (i, offset) in at.iter().enumerate() {
+                        outputs.insert(*offset, ChecksumByte::Adler32(i));
+                    }
+                }
+            }
+        }
+        Self { flags, outputs }
+    }
+
+    fn flags(&self, offset: usize) -> u8 {
+        self.flags.get(offset).copied().unwrap_or(0)
+    }
+}
+
+/// Native checksum state between two groups.
+#[derive(Clone, Copy)]
+struct ChecksumState {
+    crc32: u32,
+    adler32: [u64; 2],
+}
+
+impl ChecksumState {
+    const INITIAL: Self = ChecksumState {
+        crc32: 0,
+        adler32: [1, 0],
+    };
+
+    fn absorb(&mut self, flags: u8, byte: u8) {
+        if flags & CRC32_START != 0 {
+            self.crc32 = u32::MAX;
+        }
+        if flags & CRC32_COVERED != 0 {
+            self.crc32 = crc32_update(self.crc32, byte);
+        }
+        if flags & ADLER32_COVERED != 0 {
+            self.adler32[0] += byte as u64;
+            self.adler32[1] += self.adler32[0];
+        }
+    }
+
+    fn to_field(self) -> [F; 3] {
+        [
+            F::from_canonical_u32(self.crc32),
+            F::from_canonical_u64(self.adler32[0]),
+            F::from_canonical_u64(self.adler32[1]),
+        ]
+    }
+}
+
+struct FileLinkChunkCircuit {
+    circuit: CircuitData<F, C, D>,
+    pixels: ChunkHashTargets,
+    file: ChunkHashTargets,
+    tail: Vec<Target>,
+    checksum_init: [Target; 3],
+    /// Pixels of the row above the group, for the filters
+    above: Vec<Target>,
+    /// Quotients and remainders of the Adler-32 sums, if the group holds
+    /// bytes of the checksum
+    adler32_reduction: Option<[[Target; 2]; 2]>,
+}
+
+pub struct FileLinkBuilder<const L: usize> {
+    layout: FileLayout,
+    /// How the pixel hash packs the pixels, the layout of the proofs it links
+    pixel_layout: PixelLayout,
+    skeleton: Vec<u8>,
+    /// In pixel order
+    groups: Vec<RowGroup>,
+    file_order: Vec<usize>,
+    checksums: Checksums,
+    signature: Option<SignatureScheme>,
+}
+
+impl<const L: usize> FileLinkBuilder<L> {
+    /// The circuit is determined by the skeleton, the file with its pixel
+    /// bytes zeroed, and the layout of the pixel hash, which has to be the
+    /// layout of the proofs the file is linked to.
+    pub fn new(skeleton: &[u8], pixel_layout: PixelLayout) -> Result<Self> {
+        if !matches!(pixel_layout.format, PixelFormat::Rgb8 | PixelFormat::Rgba8) {
+            return Err(anyhow!(
+                "only RGB8 and RGBA8 pixels can be linked to a file"
+            ));
+        }
+        let layout = FileLayout::parse(skeleton)?;
+        let (width, height) = (layout.width as usize, layout.height as usize);
+        // Non-last pixel segments have to be whole sponge blocks, so groups
+        // take whole blocks of rows
+        let per_element = pixel_layout.bytes_per_element();
+        let row_bytes = width * pixel_layout.format.bytes_per_pixel();
+        let rows_per_block = (1..)
+            .map(|blocks| blocks * SPONGE_RATE)
+            .find(|rows| rows * row_bytes % (SPONGE_RATE * per_element) == 0)
+            .unwrap();
+        let rows_per_group =
+            (L * per_element / row_bytes / rows_per_block * rows_per_block).max(rows_per_block);
+        let elements = |rows: usize| (rows * row_bytes + per_element - 1) / per_element;
+        let mut groups: Vec<RowGroup> = (0..height)
+            .step_by(rows_per_group)
+            .map(|y| RowGroup {
+                rows: y..(y + rows_per_group).min(height),
+                elements: elements(y)..elements((y + rows_per_group).min(height)),
+                bytes: 0..0,
+                segment: 0..0,
+                last_in_file: false,
+            })
+            .collect();
+
+        let first_byte = |group: &RowGroup| {
+            group
+                .rows
+                .clone()
+                .map(|y| layout.file_offset(layout.rows[y]))
+                .min()
+                .unwrap()
+        };
+        let mut file_order: Vec<usize> = (0..groups.len()).collect();
+        file_order.sort_by_key(|group| first_byte(&groups[*group]));
+
+        for (position, group) in file_order.iter().enumerate() {
+            let last_in_file = position == file_order.len() - 1;
+            let start = if position == 0 {
+                0
+            } else {
+                first_byte(&groups[*group])
+            };
+            let end = if last_in_file {
+                layout.file_len
+            } else {
+                first_byte(&groups[file_order[position + 1]])
+            };
+            if groups[*group]
+                .rows
+                .clone()
+                .any(|y| layout.file_offset(layout.rows[y] + layout.row_len() - 1) >= end)
+            {
+                return Err(anyhow!("rows of different groups interleave in the file"));
+            }
+
+            let segment_start = start / BLOCK_BYTES * BLOCK_BYTES;
+            let segment_end = if last_in_file {
+                (end + 3) / 4 * 4
+            } else {
+                end / BLOCK_BYTES * BLOCK_BYTES
+            };
+            if segment_end <= segment_start {
+                return Err(anyhow!("the rows are too short to link"));
+            }
+
+            let group = &mut groups[*group];
+            group.bytes = start..end;
+            group.segment = segment_start..segment_end;
+            group.last_in_file = last_in_file;
+        }
+
+        Ok(Self {
+            checksums: Checksums::new(&layout),
+            layout,
+            pixel_layout,
+            skeleton: skeleton.to_vec(),
+            groups,
+            file_order,
+            signature: None,
+        })
+    }
+
+    /// Keeps the file hash private, the proof verifies a signature over it and
+    /// exposes the public key of the signer instead.
+    pub fn with_signed_file(mut self, scheme: SignatureScheme) -> Self {
+        self.signature = Some(scheme);
+        self
+    }
+
+    fn build_chunk_circuit(
+        &self,
+        config: &CircuitConfig,
+        group: &RowGroup,
+        last_in_pixels: bool,
+    ) -> FileLinkChunkCircuit {
+        let mut builder = CircuitBuilder::<F, D>::new(config.clone());
+
+        let width = self.layout.width as usize;
+        let format = self.pixel_layout.format;
+        let pixel_count = group.rows.len() * width;
+        let element_count = group.segment.len() / 4;
+        let pixels = build_hash_segment_circuit(&mut builder, group.elements.len(), last_in_pixels);
+        let pixel_targets = split_pixels(
+            &mut builder,
+            &pixels.input[..group.elements.len()],
+            self.pixel_layout,
+            pixel_count,
+        );
+        let file = build_hash_segment_circuit(&mut builder, element_count, group.last_in_file);
+        let checksum_init = builder.add_virtual_target_arr::<3>();
+        builder.register_public_inputs(&checksum_init);
+
+        // window[width + i] are the channels of pixel i of the group, the
+        // window starts with the row above
+        let zero = builder.zero();
+        let filtered = !self.layout.filters.is_empty();
+        let above = if filtered && group.rows.start > 0 {
+            builder.add_virtual_targets(width)
+        } else {
+            Vec::new()
+        };
+        let mut window: Vec<[Target; 4]> = if above.is_empty() {
+            vec![[zero; 4]; width]
+        } else {
+            above
+                .iter()
+                .map(|pixel| unpack_pixel(&mut builder, *pixel, format))
+                .collect()
+        };
+        for pixel in &pixel_targets {
+            window.push(unpack_pixel(&mut builder, *pixel, format));
+        }
+
+        // bytes[i] is the file byte at segment.start + i, with its bits
+        let mut bytes: Vec<(Target, Vec<BoolTarget>)> = Vec::new();
+        for element in &file.input[..element_count] {
+            let bits = builder.split_le(*element, 32);
+            for byte_bits in bits.chunks(8) {
+                bytes.push((builder.le_sum(byte_bits.iter()), byte_bits.to_vec()));
+            }
+        }
+        let tail = builder.add_virtual_targets(group.tail().len());
+        for byte in &tail {
+            let bits = builder.split_le(*byte, 8);
+            bytes.push((*byte, bits));
+        }
+
+        let opaque = builder.constant(F::from_canonical_u8(u8::MAX));
+        let mut channel_bytes = HashMap::new();
+        for (i, channels) in window[width..].iter().enumerate() {
+            for (channel, byte) in channels.iter().enumerate() {
+                match self
+                    .layout
+                    .channel_offset(group.rows.start * width + i, channel)
+                {
+                    Some(offset) => {
+                        channel_bytes.insert(offset, (i, channel));
+                    }
+                    None => builder.connect(*byte, opaque),
+                }
+            }
+        }
+
+        let has_checksums = !self.layout.checksums.is_empty();
+        let mut crc32: [BoolTarget; 32] = if has_checksums {
+            builder.split_le(checksum_init[0], 32).try_into().unwrap()
+        } else {
+            [builder._false(); 32]
+        };
+        let mut adler32 = [checksum_init[1], checksum_init[2]];
+        let mut adler32_reduction = None;
+        for offset in group.bytes.clone() {
+            let (byte, bits) = &bytes[offset - group.segment.start];
+            match (
+                channel_bytes.get(&offset),
+                self.checksums.outputs.get(&offset),
+            ) {
+                (Some(&(i, channel)), _) => {
+                    let pixel = window[width + i][channel];
+                    match self.layout.filters.get(group.rows.start + i / width) {
+                        Some(filter) => {
+                            let first = i % width == 0;
+                            let left = if first {
+                                zero
+                            } else {
+                                window[width + i - 1][channel]
+                            };
+                            let up = window[i][channel];
+                            let up_left = if first { zero } else { window[i - 1][channel] };
+                            let prediction =
+                                build_prediction(&mut builder, *filter, left, up, up_left);
+                            connect_filtered(&mut builder, *byte, pixel, prediction);
+                        }
+                        None => builder.connect(*byte, pixel),
+                    }
+                }
+                (None, Some(&ChecksumByte::Crc32(i))) => {
+                    // Stored big-endian and inverted
+                    let inverted: Vec<BoolTarget> = crc32[8 * (3 - i)..8 * (4 - i)]
+                        .iter()
+                        .map(|bit| builder.not(*bit))
+                        .collect();
+                    let expected = builder.le_sum(inverted.iter());
+                    builder.connect(*byte, expected);
+                }
+                (None, Some(&ChecksumByte::Adler32(i))) => {
+                    // The stream is over, the sums are final
+                    let (_, [a_bits, b_bits]) = adler32_reduction.get_or_insert_with(|| {
+                        let (a, a_bits) = build_adler32_reduction(&mut builder, adler32[0]);
+                        let (b, b_bits) = build_adler32_reduction(&mut builder, adler32[1]);
+                        ([a, b], [a_bits, b_bits])
+                    });
+                    // b then a, big-endian
+                    let expected_bits = match i {
+                        0 => &b_bits[8..],
+                        1 => &b_bits[..8],
+                        2 => &a_bits[8..],
+                        _ => &a_bits[..8],
+                    };
+                    let expected = builder.le_sum(expected_bits.iter());
+                    builder.connect(*byte, expected);
+                }
+                (None, None) => {
+                    let expected = builder.constant(F::from_canonical_u8(self.skeleton[offset]));
+                    builder.connect(*byte, expected);
+                }
+            }
+
+            let flags = self.checksums.flags(offset);
+            if flags & CRC32_START != 0 {
+                crc32 = [builder._true(); 32];
+            }
+            if flags & CRC32_COVERED != 0 {
+                crc32 = build_crc32_update(&mut builder, &crc32, bits);
+            }
+            if flags & ADLER32_COVERED != 0 {
+                adler32[0] = builder.add(adler32[0], *byte);
+                adler32[1] = builder.add(adler32[1], adler32[0]);
+            }
+        }
+        // The last element is zero-extended past the end of the file
+        if group.last_in_file {
+            for (byte, _) in &bytes[group.bytes.end - group.segment.start..] {
+                builder.assert_zero(*byte);
+            }
+        }
+
+        let crc32_final = if has_checksums {
+            builder.le_sum(crc32.iter())
+        } else {
+            checksum_init[0]
+        };
+        builder.register_public_input(crc32_final);
+        builder.register_public_inputs(&adler32);
+
+        if filtered {
+            let above_hash = if above.is_empty() {
+                vec![zero; 4]
+            } else {
+                builder
+                    .hash_n_to_hash_no_pad::<PoseidonHash>(above.clone())
+                    .elements
+                    .to_vec()
+            };
+            let last_row = pixel_targets[pixel_count - width..].to_vec();
+            let last_row_hash = builder.hash_n_to_hash_no_pad::<PoseidonHash>(last_row);
+            builder.register_public_inputs(&above_hash);
+            builder.register_public_inputs(&last_row_hash.elements);
+        } else {
+            builder.register_public_inputs(&[zero; 8]);
+        }
+
+        let head: Vec<Target> = bytes[..group.head().len()]
+            .iter()
+            .map(|(byte, _)| *byte)
+            .collect();
+        builder.register_public_inputs(&head);
+        builder.register_public_inputs(&tail);
+
+        FileLinkChunkCircuit {
+            circuit: builder.build::<C>(),
+            pixels,
+            file,
+            tail,
+            checksum_init,
+            above,
+            adler32_reduction: adler32_reduction.map(|(targets, _)| targets),
+        }
+    }
+
+    pub fn build_circuit(self) -> FileLinkCircuit {
+        let config = zk_recursion_config();
+        let mut builder = CircuitBuilder::<F, D>::new(config.clone());
+
+        let mut chunk_circuits = Vec::new();
+        let mut pts = Vec::new();
+        for (i, group) in self.groups.iter().enumerate() {
+            let chunk_circuit =
+                self.build_chunk_circuit(&config, group, i == self.groups.len() - 1);
+            let pt = verify_chunk_proof(&mut builder, &chunk_circuit.circuit);
+
+            chunk_circuits.push(chunk_circuit);
+            pts.push(pt);
+        }
+
+        let chain = |builder: &mut CircuitBuilder<F, D>,
+                     order: &[usize],
+                     initial_state: &[F],
+                     init: (usize, usize),
+                     fin: (usize, usize)| {
+            let mut last_final_state_target = builder.constants(initial_state);
+            for group in order {
+                chain_state(
+                    builder,
+                    &mut last_final_state_target,
+                    &pts[*group].public_inputs,
+                    init,
+                    fin,
+                );
+            }
+            last_final_state_target
+        };
+        let pixel_order: Vec<usize> = (0..self.groups.len()).collect();
+        let pixel_hash = chain(
+            &mut builder,
+            &pixel_order,
+            &self.pixel_layout.sponge_initial_state(),
+            PIXEL_INIT_STATE_PI_INDEXES,
+            PIXEL_FINAL_STATE_PI_INDEXES,
+        );
+        let file_hash = chain(
+            &mut builder,
+            &self.file_order,
+            &[F::ZERO; SPONGE_WIDTH],
+            FILE_INIT_STATE_PI_INDEXES,
+            FILE_FINAL_STATE_PI_INDEXES,
+        );
+        // Each group checks the checksums it holds bytes of
+        chain(
+            &mut builder,
+            &self.file_order,
+            &ChecksumState::INITIAL.to_field(),
+            CHECKSUM_INIT_STATE_PI_INDEXES,
+            CHECKSUM_FINAL_STATE_PI_INDEXES,
+        );
+
+        if !self.layout.filters.is_empty() {
+            for pair in pts.windows(2) {
+                let (above_start, above_end) = ABOVE_ROW_HASH_PI_INDEXES;
+                let (last_start, last_end) = LAST_ROW_HASH_PI_INDEXES;
+                for (left, right) in pair[0].public_inputs[last_start..last_end]
+                    .iter()
+                    .zip(&pair[1].public_inputs[above_start..above_end])
+                {
+                    builder.connect(*left, *right);
+                }
+            }
+        }
+
+        // The tail a group checks is the head the next group in the file absorbs
+        for pair in self.file_order.windows(2) {
+            let (previous, next) = (&self.groups[pair[0]], &self.groups[pair[1]]);
+            let tail_start = HEAD_BYTES_PI_START + previous.head().len();
+            let tail = &pts[pair[0]].public_inputs[tail_start..];
+            let head = &pts[pair[1]].public_inputs
+                [HEAD_BYTES_PI_START..HEAD_BYTES_PI_START + next.head().len()];
+            assert_eq!(tail.len(), head.len());
+            for (left, right) in tail.iter().zip(head) {
+                builder.connect(*left, *right);
+            }
+        }
+
+        builder.register_public_inputs(&pixel_hash[..SPONGE_CAPACITY]);
+        let signature = match self.signature {
+            Some(scheme) => Some(build_signature_circuit(
+                &mut builder,
+                scheme,
+                &file_hash[..SPONGE_CAPACITY],
+            )),
+            None => {
+                builder.register_public_inputs(&file_hash[..SPONGE_CAPACITY]);
+                None
+            }
+        };
+
+        FileLinkCircuit {
+            circuit: builder.build::<C>(),
+            layout: self.layout,
+            pixel_layout: self.pixel_layout,
+            groups: self.groups,
+            file_order: self.file_order,
+            checksums: self.checksums,
+            chunk_circuits,
+            pts,
+            signature,
+        }
+    }
+}
+
+pub struct FileLinkCircuit {
+    pub circuit: CircuitData<F, C, D>,
+    layout: FileLayout,
+    pixel_layout: PixelLayout,
+    groups: Vec<RowGroup>,
+    file_order: Vec<usize>,
+    checksums: Checksums,
+    chunk_circuits: Vec<FileLinkChunkCircuit>,
+    pts: Vec<ProofWithPublicInputsTarget<D>>,
+    signature: Option<SignatureTargets>,
+}
+
+impl FileLinkCircuit {
+    /// `signature` is required exactly when the circuit was built with a
+    /// signed file.
+    pub fn prove(
+        &self,
+        file: &[u8],
+        signature: Option<&OriginalSignature>,
+    ) -> Result<FileLinkProof> {
+        if FileLayout::parse(file)? != self.layout {
+            return Err(anyhow!("the file doesn't match the skeleton"));
+        }
+        // The decoded RGBA pixels, with the channels of the format
+        let format = self.pixel_layout.format;
+        let stream: Vec<u8> = self
+            .layout
+            .pixels(file)
+            .chunks(4)
+            .flat_map(|pixel| pixel[..format.channels()].to_vec())
+            .collect();
+        let pixels = self.pixel_layout.pack::<F>(&stream);
+        let pixel_units = pack_le_bytes::<F>(&stream, format.bytes_per_pixel());
+        // The last segment ends on the zero-extended last element
+        let segment_elements = |segment: &Range<usize>| {
+            bytes_to_field64::<F>(&file[segment.start..segment.end.min(file.len())])
+        };
+
+        let width = self.layout.width as usize;
+        let mut pixel_states = Vec::new();
+        let mut state = self.pixel_layout.sponge_initial_state();
+        for (i, group) in self.groups.iter().enumerate() {
+            pixel_states.push(state);
+            state = absorb_segment(
+                state,
+                &pixels[group.elements.clone()],
+                i == self.groups.len() - 1,
+            );
+        }
+        let mut file_states = vec![[F::ZERO; SPONGE_WIDTH]; self.groups.len()];
+        let mut state = [F::ZERO; SPONGE_WIDTH];
+        for group in &self.file_order {
+            let segment = &self.groups[*group].segment;
+            file_states[*group] = state;
+            state = absorb_segment(
+                state,
+                &segment_elements(segment),
+                self.groups[*group].last_in_file,
+            );
+        }
+
+        let mut checksum_states = vec![ChecksumState::INITIAL; self.groups.len()];
+        let mut state = ChecksumState::INITIAL;
+        for group in &self.file_order {
+            checksum_states[*group] = state;
+            for offset in self.groups[*group].bytes.clone() {
+                state.absorb(self.checksums.flags(offset), file[offset]);
+            }
+        }
+        // Nothing follows the Adler-32 checksum in the stream
+        let adler32 = state.adler32;
+
+        let mut pw = PartialWitness::new();
+        for (i, group) in self.groups.iter().enumerate() {
+            let chunk_circuit = &self.chunk_circuits[i];
+            let mut inputs = PartialWitness::<F>::new();
+            chunk_circuit.pixels.populate_segment_inputs(
+                &mut inputs,
+                &pixel_states[i],
+                &pixels[group.elements.clone()],
+                i == self.groups.len() - 1,
+            );
+            chunk_circuit.file.populate_segment_inputs(
+                &mut inputs,
+                &file_states[i],
+                &segment_elements(&group.segment),
+                group.last_in_file,
+            );
+            for (target, byte) in chunk_circuit.tail.iter().zip(&file[group.tail()]) {
+                inputs.set_target(*target, F::from_canonical_u8(*byte));
+            }
+            set_multiple_targets(
+                &mut inputs,
+                &chunk_circuit.checksum_init,
+                &checksum_states[i].to_field(),
+            );
+            if !chunk_circuit.above.is_empty() {
+                set_multiple_targets(
+                    &mut inputs,
+                    &chunk_circuit.above,
+                    &pixel_units[(group.rows.start - 1) * width..group.rows.start * width],
+                );
+            }
+            if let Some(reduction) = &chunk_circuit.adler32_reduction {
+                for (targets, sum) in reduction.iter().zip(adler32) {
+                    set_multiple_targets(
+                        &mut inputs,
+                        targets,
+                        &[
+                            F::from_canonical_u64(sum / ADLER32_MODULUS),
+                            F::from_canonical_u64(sum % ADLER32_MODULUS),
+                        ],
+                    );
+                }
+            }
+
+            let proof = prove_chunk(&chunk_circuit.circuit, inputs)?;
+            pw.set_proof_with_pis_target(&self.pts[i], &proof);
+        }
+
+        match (&self.signature, signature) {
+            (Some(targets), Some(signature)) if targets.scheme() == signature.scheme() => {
+                targets.set_witness(&mut pw, signature)
+            }
+            (None, None) => {}
+            _ => return Err(anyhow!("the file signature doesn't match the circuit")),
+        }
+
+        Ok(FileLinkProof {
+            proof: prove_compressed(&self.circuit, pw)?,
+            signature: signature.map(|signature| signature.scheme()),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FileLinkProof {
+    pub(crate) proof: CompressedProofWithPublicInputs<F, C, D>,
+    /// Set when the file hash is replaced by the public key of its signer
+    pub(crate) signature: Option<SignatureScheme>,
+}
+
+impl FileLinkProof {
+    /// Comparable with the original hash of an unsalted sponge
+    /// `TransformationProof`.
+    pub fn pixel_hash(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes
+            .write_field_vec(&self.proof.public_inputs[..SPONGE_CAPACITY])
+            .unwrap();
+        bytes
+    }
+
+    /// `None` if the file is only attested by a signature.
+    pub fn file_hash(&self) -> Option<Vec<u8>> {
+        if self.signature.is_some() {
+            return None;
+        }
+        let mut bytes = Vec::new();
+        bytes
+            .write_field_vec(&self.proof.public_inputs[SPONGE_CAPACITY..2 * SPONGE_CAPACITY])
+            .unwrap();
+        Some(bytes)
+    }
+
+    /// Public key of the device that signed the file, if the proof was built
+    /// with a signed file.
+    pub fn signer_public_key(&self) -> Option<Vec<u8>> {
+        self.signature.map(|scheme| {
+            scheme.encode_public_key(
+                &self.proof.public_inputs
+                    [SPONGE_CAPACITY..SPONGE_CAPACITY + scheme.public_key_len()],
+            )
+        })
+    }
+
+    pub fn signature_scheme(&self) -> Option<SignatureScheme> {
+        self.signature
+    }
+
+    pub fn verify(&self, circuit: CircuitData<F, C, D>) -> Result<()> {
+        circuit.verify_compressed(self.proof.clone())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.proof.to_bytes()
+    }
+
+    pub fn from_bytes(
+        bytes: Vec<u8>,
+        common_data: &CommonCircuitData<F, D>,
+        signature: Option<SignatureScheme>,
+    ) -> Result<Self> {
+        Ok(FileLinkProof {
+            proof: CompressedProofWithPublicInputs::from_bytes(bytes, common_data)?,
+            signature,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use plonky2::plonk::config::GenericHashOut;
+    use zkedit_transformations::Transformation;
+
+    use super::*;
+    use crate::builder::TransformationCircuitBuilder;
+    use crate::commitment::CommitmentScheme;
+    use crate::file::file_hash;
+    use crate::pixel::Packing;
+    use crate::testing::{proves, random_bytes, L};
+
+    const PIXEL_BYTES: usize = 8 * 16 * 3;
+
+    /// A PPM of two row groups.
+    fn ppm() -> Vec<u8> {
+        let mut file = b"P6\n# from a scanner\n8 16\n255\n".to_vec();
+        file.extend(random_bytes(PIXEL_BYTES));
+        file
+    }
+
+    fn link(file: &[u8], pixel_layout: PixelLayout) -> (FileLinkCircuit, FileLinkProof) {
+        let skeleton = FileLayout::parse(file).unwrap().skeleton(file);
+        let circuit = FileLinkBuilder::<L>::new(&skeleton, pixel_layout)
+            .unwrap()
+            .build_circuit();
+        let proof = circuit.prove(file, None).unwrap();
+        (circuit, proof)
+    }
+
+    #[test]
+    fn test_link_proves_file_and_pixel_hash() {
+        let file = ppm();
+        let rgb8 = PixelFormat::Rgb8.into();
+        let (circuit, proof) = link(&file, rgb8);
+        assert_eq!(circuit.chunk_circuits.len(), 2);
+        assert_eq!(
+            proof.pixel_hash(),
+            CommitmentScheme::Sponge.commit::<L>(&file[file.len() - PIXEL_BYTES..], rgb8)
+        );
+        assert_eq!(proof.file_hash(), Some(file_hash(&file).to_bytes()));
+        proof.verify(circuit.circuit).unwrap();
+    }
+
+    #[test]
+    fn test_link_matches_proof_of_original() {
+        let file = ppm();
+        let original = &file[file.len() - PIXEL_BYTES..];
+        let mut edited = vec![0; original.len()];
+        for y in 2..11 {
+            let row = y * 8 * 3;
+            edited[row + 3..row + 18].copy_from_slice(&original[row + 3..row + 18]);
+        }
+        let crop = Transformation::Crop {
+            orig_w: 8,
+            orig_h: 16,
+            x: 1,
+            y: 2,
+            w: 5,
+            h: 9,
+        };
+        for packing in [Packing::Pixels, Packing::Dense] {
+            let pixel_layout = PixelLayout {
+                format: PixelFormat::Rgb8,
+                packing,
+            };
+            let (circuit, link_proof) = link(&file, pixel_layout);
+            link_proof.verify(circuit.circuit).unwrap();
+
+            // Chunks of 48 elements hold whole densely packed pixels
+            let mut builder =
+                TransformationCircuitBuilder::<48>::new(original.len(), Box::new(crop.clone()))
+                    .with_pixel_format(PixelFormat::Rgb8);
+            if packing == Packing::Dense {
+                builder = builder.with_dense_packing();
+            }
+            let proof = builder
+                .build_curcuit()
+                .prove(original, &edited, None, None)
+                .unwrap();
+            assert_eq!(proof.original_hash(), Some(link_proof.pixel_hash()));
+        }
+
+        // The RGBA8 hash of the same file is another commitment
+        let (_, rgba_proof) = link(&file, PixelFormat::Rgba8.into());
+        assert_ne!(
+            rgba_proof.pixel_hash(),
+            CommitmentScheme::Sponge.commit::<L>(original, PixelFormat::Rgb8.into())
+        );
+    }
+
+    #[test]
+    fn test_link_rejects_file_off_the_skeleton() {
+        let file = ppm();
+        let skeleton = FileLayout::parse(&file).unwrap().skeleton(&file);
+        let circuit = FileLinkBuilder::<L>::new(&skeleton, PixelFormat::Rgb8.into())
+            .unwrap()
+            .build_circuit();
+        // Same layout, another comment
+        let mut other = file.clone();
+        other[5] = b'F';
+        assert!(!proves(|| circuit.prove(&other, None).map(|_| ())));
+    }
+}
@@ -0,0 +1,210 @@
+use std::ops::Range;
+
+use anyhow::{anyhow, Result};
+use plonky2::hash::hash_types::HashOut;
+use plonky2::hash::poseidon::PoseidonHash;
+use plonky2::plonk::config::Hasher;
+
+use crate::util::bytes_to_field64;
+use crate::F;
+
+pub mod bmp;
+pub mod link;
+pub mod netpbm;
+pub mod png;
+pub mod tiff;
+
+// Cameras sign the file rather than the decoded pixel stream. For uncompressed
+// containers every channel byte sits at a fixed offset of the file, which the
+// parsers below derive from the header. Everything else in the file, headers
+// and row padding, is published as the skeleton: the file with its pixel bytes
+// zeroed, from which the verifier rebuilds the same layout. PNGs with stored
+// deflate blocks fit the same mould once the rows are unfiltered and the
+// checksums over pixel bytes are recomputed.
+
+/// Where the pixels of an uncompressed file are.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileLayout {
+    pub width: u32,
+    pub height: u32,
+    pub file_len: usize,
+    /// Runs of the file that make up the raster stream, each with its offset
+    /// in the stream. The whole file for uncompressed formats.
+    pub stream: Vec<(usize, Range<usize>)>,
+    /// Stream offset of the first byte of each row, top to bottom
+    pub rows: Vec<usize>,
+    /// Bytes per pixel in the file
+    pub pixel_stride: usize,
+    /// Offsets of the red, green, blue and alpha bytes within a pixel. Without
+    /// an alpha byte the alpha is 255, the way the decoder fills it in.
+    pub channels: [Option<usize>; 4],
+    /// PNG filter type of each row, empty when the rows are stored as is
+    pub filters: Vec<u8>,
+    pub checksums: Vec<Checksum>,
+}
+
+/// A checksum the file stores over bytes that depend on the pixels.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Checksum {
+    /// Big-endian CRC-32 of a PNG chunk's type and data
+    Crc32 { over: Range<usize>, at: [usize; 4] },
+    /// Big-endian Adler-32 of the raster stream, ending the zlib stream. Its
+    /// bytes can straddle two chunks.
+    Adler32 { at: [usize; 4] },
+}
+
+impl FileLayout {
+    /// Parses the header of a BMP, PPM, PAM, TIFF or PNG file.
+    pub fn parse(file: &[u8]) -> Result<Self> {
+        match file {
+            [b'B', b'M', ..] => bmp::parse_layout(file),
+            [b'P', b'6', ..] | [b'P', b'7', ..] => netpbm::parse_layout(file),
+            [b'I', b'I', 42, 0, ..] | [b'M', b'M', 0, 42, ..] => tiff::parse_layout(file),
+            [0x89, b'P', b'N', b'G', ..] => png::parse_layout(file),
+            _ => Err(anyhow!(
+                "not an uncompressed BMP, PPM, PAM, TIFF or PNG file"
+            )),
+        }
+    }
+
+    /// The layout of a format that stores its rows directly in the file.
+    pub(crate) fn unfiltered(
+        file_len: usize,
+        width: u32,
+        height: u32,
+        rows: Vec<usize>,
+        pixel_stride: usize,
+        channels: [Option<usize>; 4],
+    ) -> Result<Self> {
+        let layout = FileLayout {
+            width,
+            height,
+            file_len,
+            stream: vec![(0, 0..file_len)],
+            rows,
+            pixel_stride,
+            channels,
+            filters: Vec::new(),
+            checksums: Vec::new(),
+        };
+        layout.validate()?;
+        Ok(layout)
+    }
+
+    fn stream_len(&self) -> usize {
+        self.stream
+            .last()
+            .map_or(0, |(start, run)| start + run.len())
+    }
+
+    /// File offset of a byte of the raster stream.
+    pub(crate) fn file_offset(&self, stream_offset: usize) -> usize {
+        let run = self
+            .stream
+            .partition_point(|(start, _)| *start <= stream_offset)
+            - 1;
+        let (start, run) = &self.stream[run];
+        run.start + stream_offset - start
+    }
+
+    pub(crate) fn row_len(&self) -> usize {
+        self.width as usize * self.pixel_stride
+    }
+
+    /// File offset of channel `channel` of pixel `pixel`, `None` for an alpha
+    /// the file doesn't store.
+    pub(crate) fn channel_offset(&self, pixel: usize, channel: usize) -> Option<usize> {
+        let (x, y) = (pixel % self.width as usize, pixel / self.width as usize);
+        self.channels[channel]
+            .map(|offset| self.file_offset(self.rows[y] + x * self.pixel_stride + offset))
+    }
+
+    /// Decodes the RGBA pixel stream the pixel commitment is over.
+    pub fn pixels(&self, file: &[u8]) -> Vec<u8> {
+        let mut pixels = Vec::new();
+        let mut above = vec![0u8; self.row_len()];
+        for (y, row) in self.rows.iter().enumerate() {
+            let mut row: Vec<u8> = (*row..*row + self.row_len())
+                .map(|offset| file[self.file_offset(offset)])
+                .collect();
+            if let Some(filter) = self.filters.get(y) {
+                png::unfilter(*filter, &mut row, &above, self.pixel_stride);
+            }
+            for pixel in row.chunks(self.pixel_stride) {
+                pixels.extend(
+                    self.channels
+                        .map(|channel| channel.map_or(u8::MAX, |offset| pixel[offset])),
+                );
+            }
+            above = row;
+        }
+        pixels
+    }
+
+    /// The file with all pixel bytes and the checksums over them zeroed.
+    pub fn skeleton(&self, file: &[u8]) -> Vec<u8> {
+        let mut skeleton = file.to_vec();
+        for row in &self.rows {
+            for offset in *row..*row + self.row_len() {
+                skeleton[self.file_offset(offset)] = 0;
+            }
+        }
+        for checksum in &self.checksums {
+            let (Checksum::Crc32 { at, .. } | Checksum::Adler32 { at }) = checksum;
+            for offset in at {
+                skeleton[*offset] = 0;
+            }
+        }
+        skeleton
+    }
+
+    /// Checks the layout fits the file and pixel rows don't overlap.
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.width == 0 || self.height == 0 || self.rows.len() != self.height as usize {
+            return Err(anyhow!("the image is empty"));
+        }
+        let mut rows = self.rows.clone();
+        rows.sort();
+        for pair in rows.windows(2) {
+            if pair[0] + self.row_len() > pair[1] {
+                return Err(anyhow!("pixel rows overlap"));
+            }
+        }
+        if rows.last().unwrap() + self.row_len() > self.stream_len() {
+            return Err(anyhow!("pixel rows run past the end of the file"));
+        }
+        Ok(())
+    }
+}
+
+/// The hash a device signs over the file: the same sponge as the pixel
+/// commitment, run over the file bytes.
+pub fn file_hash(file: &[u8]) -> HashOut<F> {
+    PoseidonHash::hash_pad(&bytes_to_field64::<F>(file))
+}
+
+pub(crate) fn read_u16(bytes: &[u8], offset: usize, big_endian: bool) -> Result<u16> {
+    let value: [u8; 2] = bytes
+        .get(offset..offset + 2)
+        .ok_or_else(|| anyhow!("the header is truncated"))?
+        .try_into()
+        .unwrap();
+    Ok(if big_endian {
+        u16::from_be_bytes(value)
+    } else {
+        u16::from_le_bytes(value)
+    })
+}
+
+pub(crate) fn read_u32(bytes: &[u8], offset: usize, big_endian: bool) -> Result<u32> {
+    let value: [u8; 4] = bytes
+        .get(offset..offset + 4)
+        .ok_or_else(|| anyhow!("the header is truncated"))?
+        .try_into()
+        .unwrap();
+    Ok(if big_endian {
+        u32::from_be_bytes(value)
+    } else {
+        u32::from_le_bytes(value)
+    })
+}
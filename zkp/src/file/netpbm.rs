@@ -0,0 +1,116 @@
+use anyhow::{anyhow, Result};
+
+use super::FileLayout;
+
+/// Reads whitespace separated header tokens, skipping `#` comments.
+struct Header<'a> {
+    file: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Header<'a> {
+    fn next_token(&mut self) -> Result<String> {
+        loop {
+            match self.file.get(self.position) {
+                Some(b'#') => {
+                    while self
+                        .file
+                        .get(self.position)
+                        .is_some_and(|byte| *byte != b'\n')
+                    {
+                        self.position += 1;
+                    }
+                }
+                Some(byte) if byte.is_ascii_whitespace() => self.position += 1,
+                Some(_) => break,
+                None => return Err(anyhow!("the header is truncated")),
+            }
+        }
+        let start = self.position;
+        while self
+            .file
+            .get(self.position)
+            .is_some_and(|byte| !byte.is_ascii_whitespace())
+        {
+            self.position += 1;
+        }
+        Ok(String::from_utf8_lossy(&self.file[start..self.position]).into_owned())
+    }
+
+    fn next_number(&mut self) -> Result<u32> {
+        let token = self.next_token()?;
+        token
+            .parse()
+            .map_err(|_| anyhow!("{} is not a number", token))
+    }
+
+    /// A single whitespace byte separates the last token from the raster.
+    fn raster_offset(&self) -> usize {
+        self.position + 1
+    }
+}
+
+/// Layout of a binary PPM (P6) or a PAM (P7) with 8-bit RGB or RGB_ALPHA
+/// tuples. Rows are stored top-down without padding.
+pub fn parse_layout(file: &[u8]) -> Result<FileLayout> {
+    let mut header = Header { file, position: 2 };
+    let (width, height, depth) = if file.starts_with(b"P6") {
+        let (width, height) = (header.next_number()?, header.next_number()?);
+        if header.next_number()? != 255 {
+            return Err(anyhow!("only 8-bit PPMs are supported"));
+        }
+        (width, height, 3)
+    } else {
+        let (mut width, mut height, mut depth, mut maxval) = (0, 0, 0, 0);
+        loop {
+            match header.next_token()?.as_str() {
+                "ENDHDR" => break,
+                "WIDTH" => width = header.next_number()?,
+                "HEIGHT" => height = header.next_number()?,
+                "DEPTH" => depth = header.next_number()? as usize,
+                "MAXVAL" => maxval = header.next_number()?,
+                // TUPLTYPE is implied by the depth
+                _ => {
+                    header.next_token()?;
+                }
+            }
+        }
+        if maxval != 255 || !(depth == 3 || depth == 4) {
+            return Err(anyhow!("only 8-bit RGB and RGB_ALPHA PAMs are supported"));
+        }
+        (width, height, depth)
+    };
+
+    let raster_offset = header.raster_offset();
+    let row_len = width as usize * depth;
+    let rows = (0..height as usize)
+        .map(|y| raster_offset + y * row_len)
+        .collect();
+    FileLayout::unfiltered(
+        file.len(),
+        width,
+        height,
+        rows,
+        depth,
+        [Some(0), Some(1), Some(2), (depth == 4).then_some(3)],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ppm_with_comment() {
+        let mut file = b"P6\n# from a scanner\n2 3\n255\n".to_vec();
+        let raster_offset = file.len();
+        file.extend([7u8; 2 * 3 * 3]);
+        let layout = parse_layout(&file).unwrap();
+        assert_eq!((layout.width, layout.height), (2, 3));
+        assert_eq!(
+            layout.rows,
+            vec![raster_offset, raster_offset + 6, raster_offset + 12]
+        );
+        assert_eq!(layout.channel_offset(3, 2), Some(raster_offset + 6 + 3 + 2));
+    }
+}
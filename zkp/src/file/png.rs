@@ -0,0 +1,468 @@
+use std::ops::Range;
+
+use anyhow::{anyhow, Result};
+use plonky2::{
+    iop::target::{BoolTarget, Target},
+    plonk::circuit_builder::CircuitBuilder,
+};
+use plonky2_field::types::Field;
+
+use super::{read_u32, Checksum, FileLayout};
+use crate::{sha256::xor, transformations::util::is_less_than, D, F};
+
+// Only stored deflate blocks keep the filtered rows verbatim in the file, any
+// compressed block would need an inflater in-circuit. The zlib stream is split
+// across the IDAT chunks at arbitrary offsets, so the raster stream is a list
+// of runs of the file. The CRC-32s of the IDAT chunks and the Adler-32 of the
+// stream depend on the pixels and are recomputed by the link circuit.
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+const CRC32_POLYNOMIAL: u32 = 0xedb8_8320;
+pub(crate) const ADLER32_MODULUS: u64 = 65521;
+/// Bits of the Adler-32 sum quotients, the unreduced sums of files up to
+/// about 128MB stay below `ADLER32_MODULUS << QUOTIENT_BITS`.
+const QUOTIENT_BITS: usize = 47;
+
+const FILTER_NONE: u8 = 0;
+const FILTER_SUB: u8 = 1;
+const FILTER_UP: u8 = 2;
+const FILTER_AVERAGE: u8 = 3;
+const FILTER_PAETH: u8 = 4;
+
+/// Splits `range` of a stream made of `runs` into the file ranges it covers.
+fn file_ranges(runs: &[(usize, Range<usize>)], range: Range<usize>) -> Result<Vec<Range<usize>>> {
+    let mut ranges = Vec::new();
+    for (start, run) in runs {
+        let (from, to) = (range.start.max(*start), range.end.min(start + run.len()));
+        if from < to {
+            ranges.push(run.start + from - start..run.start + to - start);
+        }
+    }
+    if ranges.iter().map(|range| range.len()).sum::<usize>() != range.len() {
+        return Err(anyhow!("the zlib stream is truncated"));
+    }
+    Ok(ranges)
+}
+
+/// Layout of a non-interlaced 8-bit RGB or RGBA PNG whose zlib stream only
+/// has stored blocks.
+pub fn parse_layout(file: &[u8]) -> Result<FileLayout> {
+    let mut header = None;
+    let mut zlib_runs = Vec::new();
+    let mut zlib_len = 0;
+    let mut checksums = Vec::new();
+    for (chunk_type, data) in chunks(file)? {
+        match &chunk_type {
+            b"IHDR" => header = Some(data.start),
+            b"IDAT" => {
+                checksums.push(Checksum::Crc32 {
+                    over: data.start - 4..data.end,
+                    at: std::array::from_fn(|i| data.end + i),
+                });
+                let len = data.len();
+                zlib_runs.push((zlib_len, data));
+                zlib_len += len;
+            }
+            _ => {}
+        }
+    }
+
+    let header = header.ok_or_else(|| anyhow!("the PNG has no header"))?;
+    let width = read_u32(file, header, true)?;
+    let height = read_u32(file, header + 4, true)?;
+    let pixel_stride = match file[header + 8..header + 13] {
+        [8, 2, 0, 0, 0] => 3,
+        [8, 6, 0, 0, 0] => 4,
+        _ => {
+            return Err(anyhow!(
+                "only non-interlaced 8-bit RGB and RGBA PNGs are supported"
+            ))
+        }
+    };
+
+    let zlib_byte = |offset: usize| -> Result<u8> {
+        Ok(file[file_ranges(&zlib_runs, offset..offset + 1)?[0].start])
+    };
+    let (method, flags) = (zlib_byte(0)?, zlib_byte(1)?);
+    if method & 0x0f != 8 || flags & 0x20 != 0 || (method as u16 * 256 + flags as u16) % 31 != 0 {
+        return Err(anyhow!("the image data isn't a zlib stream"));
+    }
+    let mut stream = Vec::new();
+    let mut stream_len = 0;
+    let mut offset = 2;
+    loop {
+        let block_header = zlib_byte(offset)?;
+        if (block_header >> 1) & 3 != 0 {
+            return Err(anyhow!("only stored deflate blocks are supported"));
+        }
+        let len = zlib_byte(offset + 1)? as usize | ((zlib_byte(offset + 2)? as usize) << 8);
+        let nlen = zlib_byte(offset + 3)? as usize | ((zlib_byte(offset + 4)? as usize) << 8);
+        if len ^ nlen != 0xffff {
+            return Err(anyhow!("a stored block has a corrupt length"));
+        }
+        for run in file_ranges(&zlib_runs, offset + 5..offset + 5 + len)? {
+            let run_len = run.len();
+            stream.push((stream_len, run));
+            stream_len += run_len;
+        }
+        offset += 5 + len;
+        if block_header & 1 == 1 {
+            break;
+        }
+    }
+    let adler = file_ranges(&zlib_runs, offset..offset + 4)?;
+    let mut at = adler.into_iter().flatten();
+    checksums.push(Checksum::Adler32 {
+        at: std::array::from_fn(|_| at.next().unwrap()),
+    });
+
+    // Each row starts with its filter type
+    let row_len = width as usize * pixel_stride;
+    if stream_len != height as usize * (row_len + 1) {
+        return Err(anyhow!("the image data doesn't match the dimensions"));
+    }
+    let mut layout = FileLayout {
+        width,
+        height,
+        file_len: file.len(),
+        stream,
+        rows: (0..height as usize)
+            .map(|y| y * (row_len + 1) + 1)
+            .collect(),
+        pixel_stride,
+        channels: [Some(0), Some(1), Some(2), (pixel_stride == 4).then_some(3)],
+        filters: Vec::new(),
+        checksums,
+    };
+    layout.filters = layout
+        .rows
+        .iter()
+        .map(|row| file[layout.file_offset(row - 1)])
+        .collect();
+    if layout.filters.iter().any(|filter| *filter > FILTER_PAETH) {
+        return Err(anyhow!("unknown filter type"));
+    }
+    layout.validate()?;
+    Ok(layout)
+}
+
+/// The type and data range of each chunk up to IEND.
+fn chunks(file: &[u8]) -> Result<Vec<([u8; 4], Range<usize>)>> {
+    if !file.starts_with(&SIGNATURE) {
+        return Err(anyhow!("not a PNG file"));
+    }
+    let mut chunks = Vec::new();
+    let mut offset = SIGNATURE.len();
+    loop {
+        let len = read_u32(file, offset, true)? as usize;
+        let data = offset + 8..offset + 8 + len;
+        if data.end + 4 > file.len() {
+            return Err(anyhow!("the PNG is truncated"));
+        }
+        let chunk_type: [u8; 4] = file[offset + 4..offset + 8].try_into().unwrap();
+        offset = data.end + 4;
+        chunks.push((chunk_type, data));
+        if &chunk_type == b"IEND" {
+            return Ok(chunks);
+        }
+    }
+}
+
+/// Data of the first chunk of type `chunk_type`.
+pub fn find_chunk<'a>(file: &'a [u8], chunk_type: &[u8; 4]) -> Result<Option<&'a [u8]>> {
+    Ok(chunks(file)?
+        .into_iter()
+        .find(|(found, _)| found == chunk_type)
+        .map(|(_, data)| &file[data]))
+}
+
+/// The PNG with its chunks of type `chunk_type` replaced by one holding
+/// `data`, placed before IEND. The pixels don't change as long as the chunk
+/// is ancillary.
+pub fn with_chunk(file: &[u8], chunk_type: &[u8; 4], data: &[u8]) -> Result<Vec<u8>> {
+    if data.len() > i32::MAX as usize {
+        return Err(anyhow!("a PNG chunk holds at most 2^31 - 1 bytes"));
+    }
+    let mut with_chunk = SIGNATURE.to_vec();
+    for (found, range) in chunks(file)? {
+        if &found == b"IEND" {
+            with_chunk.extend((data.len() as u32).to_be_bytes());
+            let start = with_chunk.len();
+            with_chunk.extend(chunk_type);
+            with_chunk.extend(data);
+            with_chunk.extend(crc32(&with_chunk[start..]).to_be_bytes());
+        }
+        if &found != chunk_type {
+            with_chunk.extend(&file[range.start - 8..range.end + 4]);
+        }
+    }
+    Ok(with_chunk)
+}
+
+fn paeth(left: u8, up: u8, up_left: u8) -> u8 {
+    let (a, b, c) = (left as i16, up as i16, up_left as i16);
+    let (pa, pb, pc) = ((b - c).abs(), (a - c).abs(), (a + b - 2 * c).abs());
+    if pa <= pb && pa <= pc {
+        left
+    } else if pb <= pc {
+        up
+    } else {
+        up_left
+    }
+}
+
+/// Reverses the filter of a row in place, `above` is the unfiltered row
+/// above, zeros for the first row.
+pub(crate) fn unfilter(filter: u8, row: &mut [u8], above: &[u8], bytes_per_pixel: usize) {
+    for i in 0..row.len() {
+        let (left, up_left) = if i >= bytes_per_pixel {
+            (row[i - bytes_per_pixel], above[i - bytes_per_pixel])
+        } else {
+            (0, 0)
+        };
+        let prediction = match filter {
+            FILTER_NONE => 0,
+            FILTER_SUB => left,
+            FILTER_UP => above[i],
+            FILTER_AVERAGE => ((left as u16 + above[i] as u16) / 2) as u8,
+            _ => paeth(left, above[i], up_left),
+        };
+        row[i] = row[i].wrapping_add(prediction);
+    }
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes
+        .iter()
+        .fold(u32::MAX, |state, byte| crc32_update(state, *byte))
+}
+
+pub(crate) fn crc32_update(state: u32, byte: u8) -> u32 {
+    let mut state = state ^ byte as u32;
+    for _ in 0..8 {
+        state = if state & 1 == 1 {
+            (state >> 1) ^ CRC32_POLYNOMIAL
+        } else {
+            state >> 1
+        };
+    }
+    state
+}
+
+fn absolute_difference(
+    builder: &mut CircuitBuilder<F, D>,
+    a: Target,
+    b: Target,
+    num_bits: usize,
+) -> Target {
+    let less = is_less_than(builder, a, b, num_bits);
+    let a_minus_b = builder.sub(a, b);
+    let b_minus_a = builder.sub(b, a);
+    builder.select(less, b_minus_a, a_minus_b)
+}
+
+/// In-circuit prediction of a byte from its left, upper and upper left
+/// neighbours under a filter type.
+pub(crate) fn build_prediction(
+    builder: &mut CircuitBuilder<F, D>,
+    filter: u8,
+    left: Target,
+    up: Target,
+    up_left: Target,
+) -> Target {
+    match filter {
+        FILTER_NONE => builder.zero(),
+        FILTER_SUB => left,
+        FILTER_UP => up,
+        FILTER_AVERAGE => {
+            let sum = builder.add(left, up);
+            let bits = builder.split_le(sum, 9);
+            builder.le_sum(bits[1..].iter())
+        }
+        _ => {
+            let pa = absolute_difference(builder, up, up_left, 8);
+            let pb = absolute_difference(builder, left, up_left, 8);
+            let sum = builder.add(left, up);
+            let double = builder.add(up_left, up_left);
+            let pc = absolute_difference(builder, sum, double, 9);
+
+            let pb_below_pa = is_less_than(builder, pb, pa, 9);
+            let pc_below_pa = is_less_than(builder, pc, pa, 9);
+            let pc_below_pb = is_less_than(builder, pc, pb, 9);
+            let pa_not_lowest = builder.or(pb_below_pa, pc_below_pa);
+            let up_or_up_left = builder.select(pc_below_pb, up_left, up);
+            builder.select(pa_not_lowest, up_or_up_left, left)
+        }
+    }
+}
+
+/// Constrains `raw` to be `byte - prediction` modulo 256, the way the filter
+/// stores it. All three are bytes.
+pub(crate) fn connect_filtered(
+    builder: &mut CircuitBuilder<F, D>,
+    raw: Target,
+    byte: Target,
+    prediction: Target,
+) {
+    // raw + prediction - byte is either 0 or 256
+    let sum = builder.add(raw, prediction);
+    let wrap = builder.sub(sum, byte);
+    let wrap_less_256 = builder.add_const(wrap, -F::from_canonical_u16(256));
+    let product = builder.mul(wrap, wrap_less_256);
+    builder.assert_zero(product);
+}
+
+/// In-circuit counterpart of `crc32_update`, on the little-endian bits of
+/// the state and the byte.
+pub(crate) fn build_crc32_update(
+    builder: &mut CircuitBuilder<F, D>,
+    state: &[BoolTarget; 32],
+    byte: &[BoolTarget],
+) -> [BoolTarget; 32] {
+    let mut state = *state;
+    for bit in byte {
+        let feedback = xor(builder, state[0], *bit);
+        let zero = builder._false();
+        state = std::array::from_fn(|i| {
+            let shifted = if i < 31 { state[i + 1] } else { zero };
+            match (i < 31, (CRC32_POLYNOMIAL >> i) & 1 == 1) {
+                (true, true) => xor(builder, shifted, feedback),
+                (false, true) => feedback,
+                _ => shifted,
+            }
+        });
+    }
+    state
+}
+
+/// Reduces an unreduced Adler-32 sum modulo 65521 and returns the bits of the
+/// remainder. The quotient and the remainder are witnesses.
+pub(crate) fn build_adler32_reduction(
+    builder: &mut CircuitBuilder<F, D>,
+    sum: Target,
+) -> ([Target; 2], Vec<BoolTarget>) {
+    let quotient = builder.add_virtual_target();
+    let remainder = builder.add_virtual_target();
+    builder.range_check(quotient, QUOTIENT_BITS);
+    let bits = builder.split_le(remainder, 16);
+    let modulus = builder.constant(F::from_canonical_u64(ADLER32_MODULUS));
+    let reduced = is_less_than(builder, remainder, modulus, 16);
+    builder.assert_one(reduced.target);
+
+    let recombined =
+        builder.mul_const_add(F::from_canonical_u64(ADLER32_MODULUS), quotient, remainder);
+    builder.connect(sum, recombined);
+    ([quotient, remainder], bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use plonky2::plonk::config::GenericHashOut;
+    use rand::Rng;
+
+    use super::*;
+    use crate::commitment::CommitmentScheme;
+    use crate::file::file_hash;
+    use crate::file::link::FileLinkBuilder;
+    use crate::pixel::PixelFormat;
+    use crate::testing::{proves, random_bytes, L};
+
+    fn png_chunk(chunk_type: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+        chunk.extend(chunk_type);
+        chunk.extend(data);
+        chunk.extend(crc32(&chunk[4..]).to_be_bytes());
+        chunk
+    }
+
+    /// A random RGB PNG with random row filters, its zlib stream is one
+    /// stored block split across two IDAT chunks.
+    fn stored_png(width: u32, height: u32) -> Vec<u8> {
+        let mut rng = rand::thread_rng();
+        let mut raw = Vec::new();
+        for _ in 0..height {
+            raw.push(rng.gen_range(FILTER_NONE..=FILTER_PAETH));
+            raw.extend(random_bytes(width as usize * 3));
+        }
+        let mut zlib = vec![0x78, 0x01, 0x01];
+        zlib.extend((raw.len() as u16).to_le_bytes());
+        zlib.extend((!(raw.len() as u16)).to_le_bytes());
+        zlib.extend(&raw);
+        let (a, b) = raw.iter().fold((1u32, 0u32), |(a, b), byte| {
+            let a = (a + *byte as u32) % ADLER32_MODULUS as u32;
+            (a, (b + a) % ADLER32_MODULUS as u32)
+        });
+        zlib.extend((b << 16 | a).to_be_bytes());
+
+        let mut header = width.to_be_bytes().to_vec();
+        header.extend(height.to_be_bytes());
+        header.extend([8, 2, 0, 0, 0]);
+        let mut file = SIGNATURE.to_vec();
+        file.extend(png_chunk(b"IHDR", &header));
+        let (first, second) = zlib.split_at(zlib.len() / 2);
+        file.extend(png_chunk(b"IDAT", first));
+        file.extend(png_chunk(b"IDAT", second));
+        file.extend(png_chunk(b"IEND", &[]));
+        file
+    }
+
+    #[test]
+    fn test_link_proves_stored_png() {
+        let file = stored_png(8, 16);
+        let layout = parse_layout(&file).unwrap();
+        let rgb8 = PixelFormat::Rgb8.into();
+        let circuit = FileLinkBuilder::<L>::new(&layout.skeleton(&file), rgb8)
+            .unwrap()
+            .build_circuit();
+        let proof = circuit.prove(&file, None).unwrap();
+        let pixels: Vec<u8> = layout
+            .pixels(&file)
+            .chunks(4)
+            .flat_map(|pixel| pixel[..3].to_vec())
+            .collect();
+        assert_eq!(
+            proof.pixel_hash(),
+            CommitmentScheme::Sponge.commit::<L>(&pixels, rgb8)
+        );
+        assert_eq!(proof.file_hash(), Some(file_hash(&file).to_bytes()));
+        proof.verify(circuit.circuit).unwrap();
+
+        // A pixel byte changed without updating the checksums over it
+        let mut corrupt = file.clone();
+        let first_row = layout.file_offset(layout.rows[0]);
+        corrupt[first_row] ^= 1;
+        assert!(!proves(|| circuit.prove(&corrupt, None).map(|_| ())));
+    }
+
+    #[test]
+    fn test_unfilter_paeth() {
+        let above: [u8; 6] = [10, 20, 30, 200, 100, 50];
+        let original: [u8; 6] = [12, 25, 31, 190, 90, 60];
+        // Filter the row the way an encoder does, then reverse it
+        let mut row = original;
+        for i in (0..row.len()).rev() {
+            let (left, up_left) = if i >= 3 {
+                (original[i - 3], above[i - 3])
+            } else {
+                (0, 0)
+            };
+            row[i] = original[i].wrapping_sub(paeth(left, above[i], up_left));
+        }
+        unfilter(FILTER_PAETH, &mut row, &above, 3);
+        assert_eq!(row, original);
+    }
+
+    #[test]
+    fn test_with_chunk_replaces_chunk() {
+        let mut file = SIGNATURE.to_vec();
+        file.extend([0, 0, 0, 0]);
+        file.extend(b"IEND");
+        file.extend([0xae, 0x42, 0x60, 0x82]);
+
+        let file = with_chunk(&file, b"zkEd", b"first").unwrap();
+        let file = with_chunk(&file, b"zkEd", b"second").unwrap();
+        assert_eq!(find_chunk(&file, b"zkEd").unwrap(), Some(&b"second"[..]));
+        assert_eq!(chunks(&file).unwrap().len(), 2);
+        assert_eq!(crc32(b"IEND"), 0xae42_6082);
+    }
+}
@@ -0,0 +1,120 @@
+use anyhow::{anyhow, Result};
+
+use super::{read_u16, read_u32, FileLayout};
+
+const IMAGE_WIDTH: u16 = 256;
+const IMAGE_LENGTH: u16 = 257;
+const BITS_PER_SAMPLE: u16 = 258;
+const COMPRESSION: u16 = 259;
+const PHOTOMETRIC_INTERPRETATION: u16 = 262;
+const STRIP_OFFSETS: u16 = 273;
+const SAMPLES_PER_PIXEL: u16 = 277;
+const ROWS_PER_STRIP: u16 = 278;
+const PLANAR_CONFIGURATION: u16 = 284;
+
+const TYPE_SHORT: u16 = 3;
+const TYPE_LONG: u16 = 4;
+
+/// An entry of the first image file directory.
+struct Entry {
+    tag: u16,
+    field_type: u16,
+    count: usize,
+    /// Offset of the value, or of the value itself when it fits the entry
+    value_offset: usize,
+}
+
+impl Entry {
+    fn value(&self, file: &[u8], index: usize, big_endian: bool) -> Result<u32> {
+        if index >= self.count {
+            return Err(anyhow!("tag {} has only {} values", self.tag, self.count));
+        }
+        match self.field_type {
+            TYPE_SHORT => Ok(read_u16(file, self.value_offset + 2 * index, big_endian)? as u32),
+            TYPE_LONG => read_u32(file, self.value_offset + 4 * index, big_endian),
+            _ => Err(anyhow!("tag {} is not an integer", self.tag)),
+        }
+    }
+}
+
+fn read_entries(file: &[u8], big_endian: bool) -> Result<Vec<Entry>> {
+    let ifd_offset = read_u32(file, 4, big_endian)? as usize;
+    let entry_count = read_u16(file, ifd_offset, big_endian)? as usize;
+    (0..entry_count)
+        .map(|i| {
+            let offset = ifd_offset + 2 + 12 * i;
+            let field_type = read_u16(file, offset + 2, big_endian)?;
+            let count = read_u32(file, offset + 4, big_endian)? as usize;
+            let size = match field_type {
+                TYPE_SHORT => 2,
+                TYPE_LONG => 4,
+                _ => 1,
+            };
+            let value_offset = if size * count <= 4 {
+                offset + 8
+            } else {
+                read_u32(file, offset + 8, big_endian)? as usize
+            };
+            Ok(Entry {
+                tag: read_u16(file, offset, big_endian)?,
+                field_type,
+                count,
+                value_offset,
+            })
+        })
+        .collect()
+}
+
+/// Layout of an uncompressed, chunky 8-bit RGB or RGBA TIFF. Rows are read
+/// from the strips of the first image, top-down.
+pub fn parse_layout(file: &[u8]) -> Result<FileLayout> {
+    let big_endian = file.starts_with(b"MM");
+    let entries = read_entries(file, big_endian)?;
+    let find = |tag: u16| entries.iter().find(|entry| entry.tag == tag);
+    let value = |tag: u16, default: Option<u32>| match (find(tag), default) {
+        (Some(entry), _) => entry.value(file, 0, big_endian),
+        (None, Some(default)) => Ok(default),
+        (None, None) => Err(anyhow!("tag {} is missing", tag)),
+    };
+
+    let width = value(IMAGE_WIDTH, None)?;
+    let height = value(IMAGE_LENGTH, None)?;
+    let samples_per_pixel = value(SAMPLES_PER_PIXEL, Some(1))? as usize;
+    if value(COMPRESSION, Some(1))? != 1
+        || value(PHOTOMETRIC_INTERPRETATION, None)? != 2
+        || value(PLANAR_CONFIGURATION, Some(1))? != 1
+        || !(samples_per_pixel == 3 || samples_per_pixel == 4)
+    {
+        return Err(anyhow!("only uncompressed chunky RGB TIFFs are supported"));
+    }
+    let bits_per_sample = find(BITS_PER_SAMPLE).ok_or_else(|| anyhow!("tag 258 is missing"))?;
+    for sample in 0..samples_per_pixel {
+        if bits_per_sample.value(file, sample, big_endian)? != 8 {
+            return Err(anyhow!("only 8-bit TIFFs are supported"));
+        }
+    }
+
+    let strip_offsets = find(STRIP_OFFSETS).ok_or_else(|| anyhow!("tag 273 is missing"))?;
+    let rows_per_strip = value(ROWS_PER_STRIP, Some(height))?.min(height).max(1) as usize;
+    let row_len = width as usize * samples_per_pixel;
+    let rows = (0..height as usize)
+        .map(|y| {
+            let strip_offset = strip_offsets.value(file, y / rows_per_strip, big_endian)?;
+            Ok(strip_offset as usize + (y % rows_per_strip) * row_len)
+        })
+        .collect::<Result<_>>()?;
+
+    FileLayout::unfiltered(
+        file.len(),
+        width,
+        height,
+        rows,
+        samples_per_pixel,
+        [
+            Some(0),
+            Some(1),
+            Some(2),
+            (samples_per_pixel == 4).then_some(3),
+        ],
+    )
+}
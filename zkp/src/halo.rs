@@ -0,0 +1,73 @@
+use std::cmp::min;
+
+use plonky2::hash::hash_types::RichField;
+use plonky2::hash::poseidon::PoseidonHash;
+use plonky2::iop::target::Target;
+use plonky2::iop::witness::PartialWitness;
+use plonky2::plonk::circuit_builder::CircuitBuilder;
+use plonky2_field::extension::Extendable;
+
+use crate::util::set_multiple_targets;
+
+/// Original pixels right before and after a chunk, for transformations whose
+/// output depends on a neighbourhood of the pixel (e.g. the rows above and
+/// below it).
+pub struct HaloTargets {
+    pub before: Vec<Target>,
+    pub after: Vec<Target>,
+}
+
+impl HaloTargets {
+    pub fn empty() -> Self {
+        Self {
+            before: Vec::new(),
+            after: Vec::new(),
+        }
+    }
+
+    pub fn populate_halo_inputs<F: RichField>(
+        &self,
+        inputs: &mut PartialWitness<F>,
+        original: &[F],
+        chunk_start: usize,
+        chunk_len: usize,
+    ) {
+        let chunk_end = chunk_start + chunk_len;
+        set_multiple_targets(
+            inputs,
+            &self.before,
+            &original[chunk_start - self.before.len()..chunk_start],
+        );
+        set_multiple_targets(
+            inputs,
+            &self.after,
+            &original[chunk_end..chunk_end + self.after.len()],
+        );
+    }
+}
+
+/// Adds the halo of a chunk as private inputs. The halo can't be tied to the
+/// original sponge directly, so the chunk exposes the hashes of its own first
+/// and last `halo` pixels along with the hashes of the halo it consumed, and
+/// the aggregation circuit connects them between neighbouring chunks.
+pub fn build_halo_circuit<F: RichField + Extendable<D>, const D: usize>(
+    builder: &mut CircuitBuilder<F, D>,
+    original_chunk: &[Target],
+    halo: usize,
+    before_len: usize,
+    after_len: usize,
+) -> HaloTargets {
+    let before = builder.add_virtual_targets(before_len);
+    let after = builder.add_virtual_targets(after_len);
+
+    let edge_len = min(halo, original_chunk.len());
+    let head = original_chunk[..edge_len].to_vec();
+    let tail = original_chunk[original_chunk.len() - edge_len..].to_vec();
+
+    for pixels in [head, tail, before.clone(), after.clone()] {
+        let hash = builder.hash_n_to_hash_no_pad::<PoseidonHash>(pixels);
+        builder.register_public_inputs(&hash.elements);
+    }
+
+    HaloTargets { before, after }
+}
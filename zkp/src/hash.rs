@@ -1,24 +1,33 @@
 use plonky2::hash::hashing::{PlonkyPermutation, SPONGE_RATE, SPONGE_WIDTH};
 use plonky2::iop::target::Target;
 
-use plonky2::hash::hash_types::RichField;
+use plonky2::hash::hash_types::{HashOut, RichField, NUM_HASH_OUT_ELTS};
 use plonky2::hash::poseidon::{PoseidonHash, PoseidonPermutation};
 use plonky2::iop::witness::PartialWitness;
 use plonky2::plonk::circuit_builder::CircuitBuilder;
 use plonky2_field::extension::Extendable;
 
+use crate::pixel::PixelLayout;
 use crate::util::set_multiple_targets;
 
+/// Number of elements of a commitment salt.
+pub const SALT_LEN: usize = NUM_HASH_OUT_ELTS;
+
 pub struct ChunkHashTargets {
     init_state: [Target; SPONGE_WIDTH],
     final_state: [Target; SPONGE_WIDTH],
+    /// Empty unless the salt is absorbed in this chunk
+    salt: Vec<Target>,
     pub input: Vec<Target>,
     pub padding_len: usize,
 }
 
+/// With `salted` a private salt is absorbed before the chunk, which only the
+/// first chunk of a salted commitment does.
 pub fn build_hash_chunk_circuit<F: RichField + Extendable<D>, const D: usize, const L: usize>(
     builder: &mut CircuitBuilder<F, D>,
     chunk_len: usize,
+    salted: bool,
 ) -> ChunkHashTargets {
     let (input_len, padding_len) = if chunk_len < L {
         (chunk_len, get_padding_length(chunk_len))
@@ -28,6 +37,49 @@ pub fn build_hash_chunk_circuit<F: RichField + Extendable<D>, const D: usize, co
 
     let input_targets = builder.add_virtual_targets(input_len + padding_len);
     let init_state_targets = builder.add_virtual_target_arr::<SPONGE_WIDTH>();
+    let mut state = init_state_targets;
+    let salt_targets = if salted {
+        let salt_targets = builder.add_virtual_targets(SALT_LEN);
+        state[..SALT_LEN].copy_from_slice(&salt_targets);
+        state = builder.permute::<PoseidonHash>(state);
+        salt_targets
+    } else {
+        Vec::new()
+    };
+    let final_state_targets = builder.permute_many::<PoseidonHash>(state, &input_targets);
+
+    builder.register_public_inputs(&init_state_targets);
+    builder.register_public_inputs(&final_state_targets);
+
+    ChunkHashTargets {
+        init_state: init_state_targets,
+        final_state: final_state_targets,
+        salt: salt_targets,
+        input: input_targets,
+        padding_len: padding_len,
+    }
+}
+
+/// Sponge over a segment of whole rate blocks, or over the padded rest of the
+/// input when `last`. Unlike chunks, consecutive segments can have any length.
+pub(crate) fn build_hash_segment_circuit<F: RichField + Extendable<D>, const D: usize>(
+    builder: &mut CircuitBuilder<F, D>,
+    segment_len: usize,
+    last: bool,
+) -> ChunkHashTargets {
+    let padding_len = if last {
+        get_padding_length(segment_len)
+    } else {
+        assert_eq!(
+            segment_len % SPONGE_RATE,
+            0,
+            "segments must be whole blocks"
+        );
+        0
+    };
+
+    let input_targets = builder.add_virtual_targets(segment_len + padding_len);
+    let init_state_targets = builder.add_virtual_target_arr::<SPONGE_WIDTH>();
     let final_state_targets =
         builder.permute_many::<PoseidonHash>(init_state_targets, &input_targets);
 
@@ -37,19 +89,66 @@ pub fn build_hash_chunk_circuit<F: RichField + Extendable<D>, const D: usize, co
     ChunkHashTargets {
         init_state: init_state_targets,
         final_state: final_state_targets,
+        salt: Vec::new(),
         input: input_targets,
-        padding_len: padding_len,
+        padding_len,
+    }
+}
+
+/// Natively absorbs a segment, the counterpart of `build_hash_segment_circuit`.
+pub(crate) fn absorb_segment<F: RichField>(
+    state: [F; SPONGE_WIDTH],
+    segment: &[F],
+    last: bool,
+) -> [F; SPONGE_WIDTH] {
+    let mut input = segment.to_vec();
+    if last {
+        pad(&mut input);
+    }
+    let mut state = state;
+    for block in input.chunks(SPONGE_RATE) {
+        state[..block.len()].copy_from_slice(block);
+        state = PoseidonPermutation::permute(state);
+    }
+    state
+}
+
+impl ChunkHashTargets {
+    /// Fills in a segment from `absorb_segment`'s input and states.
+    pub(crate) fn populate_segment_inputs<F: RichField>(
+        &self,
+        inputs: &mut PartialWitness<F>,
+        init_state: &[F; SPONGE_WIDTH],
+        segment: &[F],
+        last: bool,
+    ) {
+        let mut input = segment.to_vec();
+        if last {
+            pad(&mut input);
+        }
+        set_multiple_targets(inputs, &self.init_state, init_state);
+        set_multiple_targets(inputs, &self.input, &input);
     }
 }
 
+fn pad<F: RichField>(input: &mut Vec<F>) {
+    input.push(F::ONE);
+    while (input.len() + 1) % SPONGE_WIDTH != 0 {
+        input.push(F::ZERO);
+    }
+    input.push(F::ONE);
+}
+
 fn get_padding_length(len: usize) -> usize {
     let padded_length = len + 2;
     ((padded_length + SPONGE_WIDTH - 1) / SPONGE_WIDTH) * SPONGE_WIDTH - len
 }
 
+#[derive(Clone)]
 pub struct ChunkHasher<F: RichField + Extendable<D>, const D: usize, const L: usize> {
     data: Vec<F>,
     states: Vec<[F; SPONGE_WIDTH]>,
+    salt: Option<[F; SALT_LEN]>,
     current_chunk: usize,
     total_chunks: usize,
 }
@@ -63,21 +162,62 @@ impl<'a, F: RichField + Extendable<D>, const D: usize, const L: usize> ChunkHash
         Self {
             data: data.to_vec(),
             states: states,
+            salt: None,
             current_chunk: 0,
             total_chunks: (data.len() + L - 1) / L,
         }
     }
 
+    /// Absorbs `salt` before the data, so the hash can't be matched against
+    /// guessed candidates without knowing the salt.
+    pub fn new_salted(data: &[F], salt: [F; SALT_LEN]) -> Self {
+        Self {
+            salt: Some(salt),
+            ..Self::new(data)
+        }
+    }
+
+    /// Starts from the initial state of `layout`, see `PixelLayout::tag`.
+    pub fn with_layout(mut self, layout: PixelLayout) -> Self {
+        self.states[0] = layout.sponge_initial_state();
+        self
+    }
+
     pub fn total_chunks(&self) -> usize {
         self.total_chunks
     }
 
-    fn pad_chunk(chunk: &mut Vec<F>) {
-        chunk.push(F::ONE);
-        while (chunk.len() + 1) % SPONGE_WIDTH != 0 {
-            chunk.push(F::ZERO);
+    /// Natively computes the hash the chained chunk circuits output.
+    pub fn hash(data: &[F]) -> HashOut<F> {
+        Self::new(data).finish()
+    }
+
+    pub fn salted_hash(data: &[F], salt: [F; SALT_LEN]) -> HashOut<F> {
+        Self::new_salted(data, salt).finish()
+    }
+
+    fn finish(self) -> HashOut<F> {
+        self.absorbed().output()
+    }
+
+    /// Absorbs every chunk and rewinds to the first one. The chunk states are
+    /// kept, so the hasher can populate the chunk inputs of several proofs
+    /// without hashing the data again.
+    pub(crate) fn absorbed(mut self) -> Self {
+        while self.current_chunk < self.total_chunks {
+            let (_, _, final_state) = self.prepare_chunk_prove_data();
+            self.current_chunk += 1;
+            self.states.push(final_state);
         }
-        chunk.push(F::ONE);
+        self.current_chunk = 0;
+        self
+    }
+
+    /// The hash of an absorbed hasher.
+    pub(crate) fn output(&self) -> HashOut<F> {
+        let mut elements = [F::ZERO; NUM_HASH_OUT_ELTS];
+        elements.copy_from_slice(&self.states.last().unwrap()[..NUM_HASH_OUT_ELTS]);
+        HashOut { elements }
     }
 
     pub fn will_pad(&self) -> Option<usize> {
@@ -95,14 +235,23 @@ impl<'a, F: RichField + Extendable<D>, const D: usize, const L: usize> ChunkHash
     }
 
     fn prepare_chunk_prove_data(&self) -> ([F; SPONGE_WIDTH], Vec<F>, [F; SPONGE_WIDTH]) {
-        let initial_state = *self.states.last().unwrap();
-        let mut state = initial_state.clone();
-
+        let initial_state = self.states[self.current_chunk];
         let mut chunk = self.get_current_chunk().to_vec();
 
         // Apply padding for the last chunk
         if self.current_chunk == self.total_chunks - 1 {
-            Self::pad_chunk(&mut chunk);
+            pad(&mut chunk);
+        }
+
+        // Already absorbed
+        if let Some(final_state) = self.states.get(self.current_chunk + 1) {
+            return (initial_state, chunk, *final_state);
+        }
+
+        let mut state = initial_state.clone();
+        if let (0, Some(salt)) = (self.current_chunk, self.salt) {
+            state[..SALT_LEN].copy_from_slice(&salt);
+            state = PoseidonPermutation::permute(state);
         }
 
         // Calculate next state
@@ -120,11 +269,111 @@ impl<'a, F: RichField + Extendable<D>, const D: usize, const L: usize> ChunkHash
         inputs: &mut PartialWitness<F>,
     ) {
         let (initial_state, chunk, final_state) = self.prepare_chunk_prove_data();
+        if self.states.len() == self.current_chunk + 1 {
+            self.states.push(final_state);
+        }
         self.current_chunk += 1;
-        self.states.push(final_state);
 
         set_multiple_targets(inputs, &targets.init_state, &initial_state);
+        if let Some(salt) = self.salt {
+            set_multiple_targets(inputs, &targets.salt, &salt);
+        }
         set_multiple_targets(inputs, &targets.input, &chunk);
         set_multiple_targets(inputs, &targets.final_state, &final_state);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use plonky2_field::types::Field;
+    use zkedit_transformations::tone::apply_posterize;
+    use zkedit_transformations::Transformation;
+
+    use super::*;
+    use crate::builder::TransformationCircuitBuilder;
+    use crate::circuit::OriginalWitness;
+    use crate::commitment::CommitmentScheme;
+    use crate::pixel::{Packing, PixelFormat};
+    use crate::testing::{random_bytes, threshold, HEIGHT, L, WIDTH};
+    use crate::{D, F};
+
+    #[test]
+    fn test_absorbed_hasher_replays_chunks() {
+        let data: Vec<F> = (0..50).map(F::from_canonical_u32).collect();
+        let salt = [F::ONE; SALT_LEN];
+        let mut fresh = ChunkHasher::<F, D, 16>::new_salted(&data, salt);
+        let mut absorbed = ChunkHasher::<F, D, 16>::new_salted(&data, salt).absorbed();
+        assert_eq!(
+            absorbed.output(),
+            ChunkHasher::<F, D, 16>::salted_hash(&data, salt)
+        );
+        for _ in 0..fresh.total_chunks() {
+            let expected = fresh.prepare_chunk_prove_data();
+            assert_eq!(absorbed.prepare_chunk_prove_data(), expected);
+            fresh.states.push(expected.2);
+            fresh.current_chunk += 1;
+            absorbed.current_chunk += 1;
+        }
+    }
+
+    #[test]
+    fn test_shared_original_proves_renditions() {
+        let original = random_bytes((WIDTH * HEIGHT * 4) as usize);
+        let layout = PixelFormat::Rgba8.into();
+        let witness = OriginalWitness::<L>::new(&original, layout, CommitmentScheme::Sponge, None);
+
+        let (threshold, thresholded) = threshold(&original);
+        let posterized: Vec<u8> = original
+            .chunks(4)
+            .flat_map(|pixel| apply_posterize(4, pixel.try_into().unwrap()))
+            .collect();
+        let mut hashes = Vec::new();
+        for (transformation, edited) in [
+            (threshold, thresholded),
+            (Transformation::Posterize { levels: 4 }, posterized),
+        ] {
+            let mut circuit =
+                TransformationCircuitBuilder::<L>::new(original.len(), Box::new(transformation))
+                    .build_curcuit();
+            let proof = circuit
+                .prove_with_original(&witness, &edited, None)
+                .unwrap();
+            hashes.push(proof.original_hash());
+            proof.verify(circuit.circuit).unwrap();
+        }
+        assert_eq!(hashes[0], hashes[1]);
+    }
+
+    #[test]
+    fn test_shared_original_has_to_match_circuit() {
+        let original = random_bytes((WIDTH * HEIGHT * 4) as usize);
+        let (transformation, edited) = threshold(&original);
+        let mut circuit =
+            TransformationCircuitBuilder::<L>::new(original.len(), Box::new(transformation))
+                .build_curcuit();
+
+        let merkle = CommitmentScheme::MerkleTiles {
+            width: WIDTH,
+            height: HEIGHT,
+        };
+        let layout = PixelFormat::Rgba8.into();
+        let witness = OriginalWitness::<L>::new(&original, layout, merkle, None);
+        assert!(circuit
+            .prove_with_original(&witness, &edited, None)
+            .is_err());
+
+        let dense = PixelLayout {
+            format: PixelFormat::Rgba8,
+            packing: Packing::Dense,
+        };
+        let witness = OriginalWitness::<L>::new(&original, dense, CommitmentScheme::Sponge, None);
+        assert!(circuit
+            .prove_with_original(&witness, &edited, None)
+            .is_err());
+
+        let witness = OriginalWitness::<L>::new(&original, layout, CommitmentScheme::Sponge, None);
+        assert!(circuit
+            .prove_with_original(&witness, &edited[..edited.len() - 4], None)
+            .is_err());
+    }
+}
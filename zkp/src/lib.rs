@@ -1,10 +1,22 @@
+mod aggregation;
 pub mod builder;
 pub mod circuit;
+pub mod commitment;
+pub mod file;
+pub mod halo;
 pub mod hash;
+pub mod pixel;
 pub mod proof;
+pub mod reveal;
+pub mod salt;
+pub mod sha256;
+pub mod signature;
 pub mod transformations;
 pub mod util;
 
+#[cfg(test)]
+mod testing;
+
 use plonky2::plonk::config::{GenericConfig, PoseidonGoldilocksConfig};
 
 const D: usize = 2;
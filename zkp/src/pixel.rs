@@ -0,0 +1,344 @@
+use anyhow::{anyhow, Result};
+use plonky2::hash::hashing::SPONGE_WIDTH;
+use plonky2_field::types::PrimeField64;
+use serde::{Deserialize, Serialize};
+use zkedit_transformations::Transformation;
+
+use crate::util::pack_le_bytes;
+
+/// Layout of the pixel stream an image is committed as. Samples are
+/// little-endian and, unless the stream is packed densely, as many whole
+/// pixels as fit into 7 bytes share a field element, the chunk circuits split
+/// them apart again.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PixelFormat {
+    Luma8,
+    Luma16,
+    Rgb8,
+    Rgb16,
+    #[default]
+    Rgba8,
+    /// Doesn't fit into one element, its pixels take two: red and green,
+    /// then blue and alpha.
+    Rgba16,
+}
+
+impl PixelFormat {
+    pub fn channels(&self) -> usize {
+        match self {
+            PixelFormat::Luma8 | PixelFormat::Luma16 => 1,
+            PixelFormat::Rgb8 | PixelFormat::Rgb16 => 3,
+            PixelFormat::Rgba8 | PixelFormat::Rgba16 => 4,
+        }
+    }
+
+    pub fn bytes_per_sample(&self) -> usize {
+        match self {
+            PixelFormat::Luma8 | PixelFormat::Rgb8 | PixelFormat::Rgba8 => 1,
+            PixelFormat::Luma16 | PixelFormat::Rgb16 | PixelFormat::Rgba16 => 2,
+        }
+    }
+
+    pub fn bytes_per_pixel(&self) -> usize {
+        self.channels() * self.bytes_per_sample()
+    }
+
+    /// RGBA8 keeps one pixel per element, which the commitments made before
+    /// formats existed hash.
+    pub fn pixels_per_element(&self) -> usize {
+        match self {
+            PixelFormat::Luma8 => 7,
+            PixelFormat::Luma16 => 3,
+            PixelFormat::Rgb8 => 2,
+            _ => 1,
+        }
+    }
+
+    pub fn elements_per_pixel(&self) -> usize {
+        match self {
+            PixelFormat::Rgba16 => 2,
+            _ => 1,
+        }
+    }
+
+    /// Bytes of the part of a pixel the circuits take as one target, the
+    /// whole pixel unless it spans several elements.
+    pub fn bytes_per_unit(&self) -> usize {
+        self.bytes_per_pixel() / self.elements_per_pixel()
+    }
+
+    pub fn bytes_per_element(&self) -> usize {
+        self.bytes_per_unit() * self.pixels_per_element()
+    }
+
+    /// Separates the commitments of the formats, so the same bytes can't be
+    /// passed off as another format. RGBA8 is 0, which keeps the commitments
+    /// made before formats existed.
+    pub fn tag(&self) -> u64 {
+        match self {
+            PixelFormat::Rgba8 => 0,
+            PixelFormat::Luma8 => 1,
+            PixelFormat::Luma16 => 2,
+            PixelFormat::Rgb8 => 3,
+            PixelFormat::Rgb16 => 4,
+            PixelFormat::Rgba16 => 5,
+        }
+    }
+
+    /// Checks that the circuits of `transformation` handle originals of this
+    /// format. Geometric edits move whole pixels and handle every format,
+    /// channel edits work on 8-bit samples.
+    pub fn check_transformation(&self, transformation: &Transformation) -> Result<()> {
+        match transformation {
+            Transformation::Crop { .. }
+            | Transformation::HiddenCrop { .. }
+            | Transformation::PreserveOutside { .. } => Ok(()),
+            _ if self.bytes_per_sample() > 1 => Err(anyhow!(
+                "{:?} originals only support crops and preserved regions",
+                self
+            )),
+            Transformation::ColorMatrix { .. } if self.channels() == 1 => Err(anyhow!(
+                "a color matrix can't be applied to a luma original"
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// How a pixel stream is packed into field elements.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Packing {
+    /// Whole pixels, see `PixelFormat::pixels_per_element`.
+    #[default]
+    Pixels,
+    /// 7 bytes of the stream per element whatever the format, pixels straddle
+    /// the elements. RGBA8 takes 4/7 of the permutations, but the chunk
+    /// circuits split every element into bits.
+    Dense,
+}
+
+/// The format of a pixel stream and how it's packed, which together decide
+/// the elements an image is committed as.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PixelLayout {
+    pub format: PixelFormat,
+    pub packing: Packing,
+}
+
+impl From<PixelFormat> for PixelLayout {
+    fn from(format: PixelFormat) -> Self {
+        PixelLayout {
+            format,
+            packing: Packing::Pixels,
+        }
+    }
+}
+
+impl PixelLayout {
+    pub fn bytes_per_element(&self) -> usize {
+        match self.packing {
+            Packing::Pixels => self.format.bytes_per_element(),
+            Packing::Dense => 7,
+        }
+    }
+
+    /// Number of pixels, as the circuits take them, in `elements` whole
+    /// elements. A dense chunk must end on a pixel boundary.
+    pub(crate) fn pixels_in(&self, elements: usize) -> usize {
+        elements * self.bytes_per_element() / self.format.bytes_per_unit()
+    }
+
+    /// `PixelFormat::tag` with the packing in the second byte, pixel packing
+    /// keeps the tag of the format.
+    pub fn tag(&self) -> u64 {
+        let packing = match self.packing {
+            Packing::Pixels => 0,
+            Packing::Dense => 1,
+        };
+        self.format.tag() | packing << 8
+    }
+
+    /// Packs a pixel stream into field elements, the bytes after the last
+    /// pixel are zero.
+    pub fn pack<F: PrimeField64>(&self, bytes: &[u8]) -> Vec<F> {
+        pack_le_bytes(bytes, self.bytes_per_element())
+    }
+
+    /// Native counterpart of `split_pixels`, the values of the first `count`
+    /// units packed into `elements`.
+    pub(crate) fn unpack<F: PrimeField64>(&self, elements: &[F], count: usize) -> Vec<u64> {
+        let per_element = self.bytes_per_element();
+        let bytes: Vec<u8> = elements
+            .iter()
+            .flat_map(|element| {
+                element
+                    .to_canonical_u64()
+                    .to_le_bytes()
+                    .into_iter()
+                    .take(per_element)
+            })
+            .collect();
+        bytes
+            .chunks(self.format.bytes_per_unit())
+            .take(count)
+            .map(|unit| {
+                unit.iter()
+                    .rev()
+                    .fold(0, |value, byte| value << 8 | *byte as u64)
+            })
+            .collect()
+    }
+
+    /// The initial sponge state of a commitment, the tag takes the last
+    /// capacity element.
+    pub(crate) fn sponge_initial_state<F: PrimeField64>(&self) -> [F; SPONGE_WIDTH] {
+        let mut state = [F::ZERO; SPONGE_WIDTH];
+        state[SPONGE_WIDTH - 1] = F::from_canonical_u64(self.tag());
+        state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use plonky2_field::types::Field;
+    use zkedit_transformations::Region;
+
+    use super::*;
+    use crate::builder::TransformationCircuitBuilder;
+    use crate::commitment::CommitmentScheme;
+    use crate::testing::{proves, random_bytes, HEIGHT, L, WIDTH};
+    use crate::util::bytes_to_field64;
+    use crate::F;
+
+    const CROP: Region = Region {
+        x: 2,
+        y: 1,
+        w: 5,
+        h: 2,
+    };
+
+    /// Proves and verifies a crop of a random `layout` original, the proof
+    /// commits to both images as `layout`. Setting the byte `outside` of the
+    /// crop in the edited image has to be rejected.
+    fn check_crop(layout: PixelLayout, outside: usize) {
+        let bytes_per_pixel = layout.format.bytes_per_pixel();
+        let original = random_bytes((WIDTH * HEIGHT) as usize * bytes_per_pixel);
+        let mut edited = vec![0; original.len()];
+        for y in CROP.y..CROP.y + CROP.h {
+            let start = (y * WIDTH + CROP.x) as usize * bytes_per_pixel;
+            let end = start + CROP.w as usize * bytes_per_pixel;
+            edited[start..end].copy_from_slice(&original[start..end]);
+        }
+        let crop = Transformation::Crop {
+            orig_w: WIDTH,
+            orig_h: HEIGHT,
+            x: CROP.x,
+            y: CROP.y,
+            w: CROP.w,
+            h: CROP.h,
+        };
+        let mut builder = TransformationCircuitBuilder::<L>::new(original.len(), Box::new(crop))
+            .with_pixel_format(layout.format);
+        if layout.packing == Packing::Dense {
+            builder = builder.with_dense_packing();
+        }
+        let mut circuit = builder.build_curcuit();
+
+        let proof = circuit.prove(&original, &edited, None, None).unwrap();
+        let sponge = CommitmentScheme::Sponge;
+        assert_eq!(
+            proof.original_hash(),
+            Some(sponge.commit::<L>(&original, layout))
+        );
+        assert_eq!(proof.edited_hash(), sponge.commit::<L>(&edited, layout));
+
+        assert_eq!(edited[outside], 0);
+        let mut tampered = edited.clone();
+        tampered[outside] = 1;
+        assert!(!proves(|| circuit
+            .prove(&original, &tampered, None, None)
+            .map(|_| ())));
+        proof.verify(circuit.circuit).unwrap();
+    }
+
+    #[test]
+    fn test_pack_pixels() {
+        let layout = |format: PixelFormat| PixelLayout::from(format);
+        let bytes: Vec<u8> = (1..=24).collect();
+        assert_eq!(
+            layout(PixelFormat::Rgba8).pack::<F>(&bytes),
+            bytes_to_field64(&bytes)
+        );
+
+        let rgb16 = layout(PixelFormat::Rgb16).pack::<F>(&bytes);
+        assert_eq!(rgb16.len(), 4);
+        assert_eq!(rgb16[0], F::from_canonical_u64(0x06_05_04_03_02_01));
+
+        let rgba16 = layout(PixelFormat::Rgba16).pack::<F>(&bytes);
+        assert_eq!(rgba16.len(), 6);
+        assert_eq!(rgba16[1], F::from_canonical_u64(0x08_07_06_05));
+
+        // 8 RGB pixels in 4 elements, the last 5 luma samples in a padded one
+        let rgb8 = layout(PixelFormat::Rgb8).pack::<F>(&bytes);
+        assert_eq!(rgb8.len(), 4);
+        assert_eq!(layout(PixelFormat::Rgb8).unpack(&rgb8, 8)[1], 0x06_05_04);
+        let luma8 = layout(PixelFormat::Luma8).pack::<F>(&bytes);
+        assert_eq!(luma8.len(), 4);
+        assert_eq!(
+            layout(PixelFormat::Luma8).unpack(&luma8, 24),
+            (1..=24).collect::<Vec<u64>>()
+        );
+    }
+
+    #[test]
+    fn test_dense_packing() {
+        let dense = PixelLayout {
+            format: PixelFormat::Rgba8,
+            packing: Packing::Dense,
+        };
+        let bytes: Vec<u8> = (1..=24).collect();
+        let elements = dense.pack::<F>(&bytes);
+        assert_eq!(elements.len(), 4);
+        assert_eq!(elements[1], F::from_canonical_u64(0x0e_0d_0c_0b_0a_09_08));
+        // The second pixel straddles the first two elements
+        let pixels = dense.unpack(&elements, 6);
+        assert_eq!(pixels[1], 0x08_07_06_05);
+        assert_eq!(
+            pixels,
+            PixelLayout::from(PixelFormat::Rgba8).unpack(&bytes_to_field64::<F>(&bytes), 6)
+        );
+        assert_eq!(dense.pixels_in(4), 7);
+        assert_ne!(dense.tag(), PixelFormat::Rgba8.tag());
+    }
+
+    #[test]
+    fn test_16_bit_crops_prove() {
+        // The high byte of the second sample
+        check_crop(PixelFormat::Luma16.into(), 3);
+        // The alpha of the first pixel, in its second element
+        check_crop(PixelFormat::Rgba16.into(), 7);
+
+        let bytes: Vec<u8> = (1..=24).collect();
+        assert_ne!(
+            CommitmentScheme::Sponge.commit::<L>(&bytes, PixelFormat::Rgb16.into()),
+            CommitmentScheme::Sponge.commit::<L>(&bytes, PixelFormat::Rgba8.into())
+        );
+    }
+
+    #[test]
+    fn test_packed_8_bit_crops_prove() {
+        // Both in the middle of their element
+        check_crop(PixelFormat::Luma8.into(), 3);
+        check_crop(PixelFormat::Rgb8.into(), 4);
+    }
+
+    #[test]
+    fn test_dense_crop_proves() {
+        let dense = PixelLayout {
+            format: PixelFormat::Rgba8,
+            packing: Packing::Dense,
+        };
+        // The last byte of the second pixel, which straddles two elements
+        check_crop(dense, 7);
+    }
+}
@@ -1,16 +1,29 @@
 use plonky2::{
-    hash::hashing::{SPONGE_CAPACITY, SPONGE_WIDTH},
+    hash::{
+        hash_types::NUM_HASH_OUT_ELTS,
+        hashing::{SPONGE_CAPACITY, SPONGE_WIDTH},
+    },
     plonk::{
         circuit_data::{CircuitData, CommonCircuitData},
         proof::{CompressedProofWithPublicInputs, ProofWithPublicInputs},
     },
     util::serialization::Write,
 };
-use plonky2_field::types::Field;
+use plonky2_field::types::{Field, PrimeField64};
 use serde::{Deserialize, Serialize};
 
+use crate::commitment::CommitmentScheme;
+use crate::signature::SignatureScheme;
 use crate::{C, D, F};
 use anyhow::Result;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+
+/// How the bytes handed to `TransformationProof::from_bytes` are encoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProofEncoding {
+    Bytes,
+    Base64,
+}
 
 pub struct ChunkProof {
     pub proof: ProofWithPublicInputs<F, C, D>,
@@ -22,6 +35,20 @@ impl ChunkProof {
     pub const EDITED_INIT_STATE_PI_INDEXES: (usize, usize) = (SPONGE_WIDTH * 2, SPONGE_WIDTH * 3);
     pub const EDITED_FINAL_STATE_PI_INDEXES: (usize, usize) = (SPONGE_WIDTH * 3, SPONGE_WIDTH * 4);
 
+    // With a Merkle tile commitment the chunk only publishes its two leaves
+    pub const ORIGINAL_LEAF_PI_INDEXES: (usize, usize) = (0, NUM_HASH_OUT_ELTS);
+    pub const EDITED_LEAF_PI_INDEXES: (usize, usize) = (NUM_HASH_OUT_ELTS, NUM_HASH_OUT_ELTS * 2);
+
+    // Only present for transformations with a halo, relative to the end of
+    // the commitment public inputs
+    pub const HALO_HEAD_HASH_PI_INDEXES: (usize, usize) = (0, NUM_HASH_OUT_ELTS);
+    pub const HALO_TAIL_HASH_PI_INDEXES: (usize, usize) =
+        (NUM_HASH_OUT_ELTS, NUM_HASH_OUT_ELTS * 2);
+    pub const HALO_BEFORE_HASH_PI_INDEXES: (usize, usize) =
+        (NUM_HASH_OUT_ELTS * 2, NUM_HASH_OUT_ELTS * 3);
+    pub const HALO_AFTER_HASH_PI_INDEXES: (usize, usize) =
+        (NUM_HASH_OUT_ELTS * 3, NUM_HASH_OUT_ELTS * 4);
+
     pub fn init_state_public_inputs(&self) -> [F; SPONGE_WIDTH] {
         let mut init_state_public_inputs = [F::ZERO; SPONGE_WIDTH];
         init_state_public_inputs.clone_from_slice(&self.proof.public_inputs[..SPONGE_WIDTH]);
@@ -39,25 +66,73 @@ impl ChunkProof {
 #[derive(Serialize, Deserialize)]
 pub struct TransformationProof {
     pub(crate) proof: CompressedProofWithPublicInputs<F, C, D>,
+    /// Set when the original hash is replaced by the public key of its signer
+    pub(crate) signature: Option<SignatureScheme>,
+    /// Set when the original hash is a salted commitment
+    pub(crate) salted: bool,
+    pub(crate) commitment: CommitmentScheme,
 }
 
 impl TransformationProof {
-    pub fn original_hash(&self) -> Vec<u8> {
+    /// Length of the public inputs that either hold the original hash or the
+    /// public key of its signer.
+    fn original_pis_len(&self) -> usize {
+        self.signature
+            .map_or(SPONGE_CAPACITY, |scheme| scheme.public_key_len())
+    }
+
+    /// `None` if the original is only attested by a signature.
+    pub fn original_hash(&self) -> Option<Vec<u8>> {
+        if self.signature.is_some() {
+            return None;
+        }
         let mut bytes = Vec::new();
         bytes
             .write_field_vec(&self.proof.public_inputs[..SPONGE_CAPACITY])
             .unwrap();
-        bytes
+        Some(bytes)
+    }
+
+    /// Public key of the device that signed the original, if the proof was
+    /// built with a signed original.
+    pub fn signer_public_key(&self) -> Option<Vec<u8>> {
+        self.signature.map(|scheme| {
+            scheme.encode_public_key(&self.proof.public_inputs[..scheme.public_key_len()])
+        })
+    }
+
+    pub fn signature_scheme(&self) -> Option<SignatureScheme> {
+        self.signature
+    }
+
+    /// How the original and edited hashes commit to the images.
+    pub fn commitment_scheme(&self) -> CommitmentScheme {
+        self.commitment
+    }
+
+    /// Whether the original hash only opens together with a secret salt.
+    pub fn is_salted(&self) -> bool {
+        self.salted
     }
 
     pub fn edited_hash(&self) -> Vec<u8> {
+        let start = self.original_pis_len();
         let mut bytes = Vec::new();
         bytes
-            .write_field_vec(&self.proof.public_inputs[SPONGE_CAPACITY..SPONGE_CAPACITY * 2])
+            .write_field_vec(&self.proof.public_inputs[start..start + SPONGE_CAPACITY])
             .unwrap();
         bytes
     }
 
+    /// Public inputs the transformation registers after the original and the
+    /// edited section, e.g. the lookup tables of an equalization.
+    pub fn transformation_public_inputs(&self) -> Vec<u64> {
+        self.proof.public_inputs[self.original_pis_len() + SPONGE_CAPACITY..]
+            .iter()
+            .map(|input| input.to_canonical_u64())
+            .collect()
+    }
+
     pub fn verify(&self, circuit: CircuitData<F, C, D>) -> Result<()> {
         circuit.verify_compressed(self.proof.clone())
     }
@@ -66,9 +141,29 @@ impl TransformationProof {
         self.proof.to_bytes()
     }
 
-    pub fn from_bytes(bytes: Vec<u8>, common_data: &CommonCircuitData<F, D>) -> Result<Self> {
+    /// `to_bytes` as base64, the way JSON exports carry the proof.
+    pub fn to_base64(&self) -> String {
+        BASE64.encode(self.to_bytes())
+    }
+
+    /// Reads the bytes of `to_bytes` or their base64, as `encoding` says.
+    pub fn from_bytes(
+        bytes: Vec<u8>,
+        encoding: ProofEncoding,
+        common_data: &CommonCircuitData<F, D>,
+        signature: Option<SignatureScheme>,
+        salted: bool,
+        commitment: CommitmentScheme,
+    ) -> Result<Self> {
+        let bytes = match encoding {
+            ProofEncoding::Bytes => bytes,
+            ProofEncoding::Base64 => BASE64.decode(bytes)?,
+        };
         Ok(TransformationProof {
             proof: CompressedProofWithPublicInputs::from_bytes(bytes, common_data)?,
+            signature,
+            salted,
+            commitment,
         })
     }
 }
@@ -0,0 +1,330 @@
+use anyhow::{anyhow, Result};
+use plonky2::{
+    hash::hashing::SPONGE_CAPACITY,
+    iop::witness::{PartialWitness, WitnessWrite},
+    plonk::{
+        circuit_builder::CircuitBuilder,
+        circuit_data::{CircuitConfig, CircuitData, CommonCircuitData},
+        proof::{CompressedProofWithPublicInputs, ProofWithPublicInputsTarget},
+    },
+    util::serialization::Write,
+};
+use plonky2_field::types::PrimeField64;
+use serde::{Deserialize, Serialize};
+use zkedit_transformations::Region;
+
+use crate::{
+    aggregation::{
+        chain_state, prove_chunk, prove_compressed, verify_chunk_proof, zk_recursion_config,
+        Chunking,
+    },
+    hash::{build_hash_chunk_circuit, ChunkHashTargets, ChunkHasher},
+    pixel::{PixelFormat, PixelLayout},
+    proof::ChunkProof,
+    salt::Salt,
+    transformations::util::{pixel_channels, pixel_number_to_coords, split_pixels},
+    C, D, F,
+};
+
+// Reveals the pixels of one region of a committed original. The chunk circuits
+// hash the original exactly like the ones of a transformation proof, so the
+// resulting hash is the original hash of those proofs, and additionally
+// publish the pixels inside the region. Nothing else about the original is
+// revealed.
+
+struct RevealChunkCircuit {
+    circuit: CircuitData<F, C, D>,
+    original_chunk: ChunkHashTargets,
+}
+
+pub struct RegionRevealBuilder<const L: usize> {
+    original: Chunking<L>,
+    orig_w: u32,
+    region: Region,
+    salted: bool,
+}
+
+impl<const L: usize> RegionRevealBuilder<L> {
+    /// `original_len` is in bytes, like the one of
+    /// `TransformationCircuitBuilder`.
+    pub fn new(original_len: usize, orig_w: u32, region: Region) -> Self {
+        Self {
+            original: Chunking::new(original_len, PixelFormat::Rgba8.into()),
+            orig_w,
+            region,
+            salted: false,
+        }
+    }
+
+    /// Reveals from a salted original commitment.
+    pub fn with_salted_original(mut self) -> Self {
+        self.salted = true;
+        self
+    }
+
+    /// Reveals from an original committed with `layout` instead of RGBA8
+    /// pixels.
+    pub fn with_layout(mut self, layout: PixelLayout) -> Self {
+        self.original.layout = layout;
+        self
+    }
+
+    fn build_chunk_circuit(&self, config: &CircuitConfig, chunk: usize) -> RevealChunkCircuit {
+        let mut builder = CircuitBuilder::<F, D>::new(config.clone());
+
+        let chunk_len = self.original.chunk_len(chunk);
+        let original_chunk =
+            build_hash_chunk_circuit::<F, D, L>(&mut builder, chunk_len, self.salted && chunk == 0);
+        let pixels = self.original.chunk_pixels(chunk);
+        let pixel_targets = split_pixels(
+            &mut builder,
+            &original_chunk.input[..chunk_len],
+            self.original.layout,
+            pixels.len(),
+        );
+        for (number, pixel) in pixels.zip(pixel_targets) {
+            let (x, y) = pixel_number_to_coords(number, self.orig_w);
+            if self.region.contains(x, y) {
+                builder.register_public_input(pixel);
+            }
+        }
+
+        RevealChunkCircuit {
+            circuit: builder.build::<C>(),
+            original_chunk,
+        }
+    }
+
+    pub fn build_circuit(self) -> RegionRevealCircuit<L> {
+        let layout = self.original.layout;
+        assert!(
+            layout.format.bytes_per_sample() == 1,
+            "Only 8-bit pixel formats can be revealed"
+        );
+        assert!(
+            self.original.has_whole_pixels(),
+            "A chunk must hold whole pixels"
+        );
+        assert!(
+            self.region
+                .fits(self.orig_w, (self.original.pixels() as u32) / self.orig_w),
+            "the revealed region must lie inside the image"
+        );
+        let config = zk_recursion_config();
+        let mut chunk_circuits = Vec::new();
+        let mut builder = CircuitBuilder::<F, D>::new(config.clone());
+
+        let mut last_final_state_target = builder.constants(&layout.sponge_initial_state::<F>());
+        let mut revealed_pixels = Vec::new();
+
+        let mut pts = Vec::new();
+        for chunk in 0..self.original.total_chunks() {
+            let chunk_circuit = self.build_chunk_circuit(&config, chunk);
+            let pt = verify_chunk_proof(&mut builder, &chunk_circuit.circuit);
+
+            chain_state(
+                &mut builder,
+                &mut last_final_state_target,
+                &pt.public_inputs,
+                ChunkProof::ORIGINAL_INIT_STATE_PI_INDEXES,
+                ChunkProof::ORIGINAL_FINAL_STATE_PI_INDEXES,
+            );
+            revealed_pixels.extend_from_slice(
+                &pt.public_inputs[ChunkProof::ORIGINAL_FINAL_STATE_PI_INDEXES.1..],
+            );
+
+            chunk_circuits.push(chunk_circuit);
+            pts.push(pt);
+        }
+
+        builder.register_public_inputs(&last_final_state_target[..SPONGE_CAPACITY]);
+        builder.register_public_inputs(&revealed_pixels);
+
+        RegionRevealCircuit {
+            circuit: builder.build::<C>(),
+            salted: self.salted,
+            layout,
+            chunk_circuits,
+            pts,
+        }
+    }
+}
+
+pub struct RegionRevealCircuit<const L: usize> {
+    pub circuit: CircuitData<F, C, D>,
+    salted: bool,
+    layout: PixelLayout,
+    chunk_circuits: Vec<RevealChunkCircuit>,
+    pts: Vec<ProofWithPublicInputsTarget<D>>,
+}
+
+impl<const L: usize> RegionRevealCircuit<L> {
+    /// `salt` is required exactly when the circuit was built for a salted
+    /// original.
+    pub fn prove(&self, original: &[u8], salt: Option<&Salt>) -> Result<RegionRevealProof> {
+        if self.salted != salt.is_some() {
+            return Err(anyhow!("the salt doesn't match the circuit"));
+        }
+        let original_elements = self.layout.pack::<F>(original);
+        let mut hasher = match salt {
+            Some(salt) => ChunkHasher::<F, D, L>::new_salted(&original_elements, salt.0),
+            None => ChunkHasher::<F, D, L>::new(&original_elements),
+        }
+        .with_layout(self.layout);
+
+        let mut pw = PartialWitness::new();
+        for (chunk_circuit, pt) in self.chunk_circuits.iter().zip(&self.pts) {
+            let mut inputs = PartialWitness::<F>::new();
+            hasher.populate_chunk_inputs(&chunk_circuit.original_chunk, &mut inputs);
+            let proof = prove_chunk(&chunk_circuit.circuit, inputs)?;
+            pw.set_proof_with_pis_target(pt, &proof);
+        }
+
+        Ok(RegionRevealProof {
+            proof: prove_compressed(&self.circuit, pw)?,
+            salted: self.salted,
+            format: self.layout.format,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RegionRevealProof {
+    pub(crate) proof: CompressedProofWithPublicInputs<F, C, D>,
+    pub(crate) salted: bool,
+    pub(crate) format: PixelFormat,
+}
+
+impl RegionRevealProof {
+    /// The hash the region was revealed from, comparable with
+    /// `TransformationProof::original_hash`.
+    pub fn original_hash(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes
+            .write_field_vec(&self.proof.public_inputs[..SPONGE_CAPACITY])
+            .unwrap();
+        bytes
+    }
+
+    pub fn is_salted(&self) -> bool {
+        self.salted
+    }
+
+    /// RGBA pixels of the region in raster order, see `pixel_channels` for
+    /// the formats with fewer channels.
+    pub fn revealed_pixels(&self) -> Vec<[u8; 4]> {
+        self.proof.public_inputs[SPONGE_CAPACITY..]
+            .iter()
+            .map(|element| pixel_channels(element.to_canonical_u64(), self.format))
+            .collect()
+    }
+
+    pub fn verify(&self, circuit: CircuitData<F, C, D>) -> Result<()> {
+        circuit.verify_compressed(self.proof.clone())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.proof.to_bytes()
+    }
+
+    pub fn from_bytes(
+        bytes: Vec<u8>,
+        common_data: &CommonCircuitData<F, D>,
+        salted: bool,
+        format: PixelFormat,
+    ) -> Result<Self> {
+        Ok(RegionRevealProof {
+            proof: CompressedProofWithPublicInputs::from_bytes(bytes, common_data)?,
+            salted,
+            format,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::TransformationCircuitBuilder;
+    use crate::testing::{random_bytes, threshold, HEIGHT, L, WIDTH};
+
+    const REGION: Region = Region {
+        x: 2,
+        y: 1,
+        w: 3,
+        h: 2,
+    };
+
+    fn region_bytes(original: &[u8], bytes_per_pixel: usize) -> Vec<&[u8]> {
+        (REGION.y..REGION.y + REGION.h)
+            .flat_map(|y| (REGION.x..REGION.x + REGION.w).map(move |x| (y * WIDTH + x) as usize))
+            .map(|number| &original[number * bytes_per_pixel..(number + 1) * bytes_per_pixel])
+            .collect()
+    }
+
+    #[test]
+    fn test_reveal_proves_region_of_original() {
+        // Two full chunks, the last one doesn't have a shorter rest
+        let original = random_bytes((WIDTH * HEIGHT * 4) as usize);
+        let circuit = RegionRevealBuilder::<L>::new(original.len(), WIDTH, REGION).build_circuit();
+        let proof = circuit.prove(&original, None).unwrap();
+
+        let expected: Vec<[u8; 4]> = region_bytes(&original, 4)
+            .into_iter()
+            .map(|pixel| pixel.try_into().unwrap())
+            .collect();
+        assert_eq!(proof.revealed_pixels(), expected);
+
+        let (transformation, edited) = threshold(&original);
+        let mut transformation_circuit =
+            TransformationCircuitBuilder::<L>::new(original.len(), Box::new(transformation))
+                .build_curcuit();
+        let transformation_proof = transformation_circuit
+            .prove(&original, &edited, None, None)
+            .unwrap();
+        assert_eq!(
+            Some(proof.original_hash()),
+            transformation_proof.original_hash()
+        );
+        proof.verify(circuit.circuit).unwrap();
+    }
+
+    #[test]
+    fn test_reveal_luma8() {
+        let original = random_bytes((WIDTH * HEIGHT) as usize);
+        let circuit = RegionRevealBuilder::<L>::new(original.len(), WIDTH, REGION)
+            .with_layout(PixelFormat::Luma8.into())
+            .build_circuit();
+        let proof = circuit.prove(&original, None).unwrap();
+
+        let expected: Vec<[u8; 4]> = region_bytes(&original, 1)
+            .into_iter()
+            .map(|pixel| [pixel[0], pixel[0], pixel[0], u8::MAX])
+            .collect();
+        assert_eq!(proof.revealed_pixels(), expected);
+        proof.verify(circuit.circuit).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "Only 8-bit pixel formats can be revealed")]
+    fn test_reveal_rejects_16_bit_formats() {
+        RegionRevealBuilder::<L>::new((WIDTH * HEIGHT * 2) as usize, WIDTH, REGION)
+            .with_layout(PixelFormat::Luma16.into())
+            .build_circuit();
+    }
+
+    #[test]
+    fn test_salted_reveal() {
+        let original = random_bytes((WIDTH * HEIGHT * 4) as usize);
+        let circuit = RegionRevealBuilder::<L>::new(original.len(), WIDTH, REGION)
+            .with_salted_original()
+            .build_circuit();
+        assert!(circuit.prove(&original, None).is_err());
+
+        let salt = Salt::random();
+        let proof = circuit.prove(&original, Some(&salt)).unwrap();
+        let other = circuit.prove(&original, Some(&Salt::random())).unwrap();
+        assert!(proof.is_salted());
+        assert_ne!(proof.original_hash(), other.original_hash());
+        proof.verify(circuit.circuit).unwrap();
+    }
+}
@@ -0,0 +1,116 @@
+use anyhow::{anyhow, Result};
+use plonky2::util::serialization::Write;
+use plonky2_field::types::{Field, Field64, PrimeField64, Sample};
+use serde::{Deserialize, Serialize};
+
+use crate::hash::{ChunkHasher, SALT_LEN};
+use crate::pixel::PixelLayout;
+use crate::proof::TransformationProof;
+use crate::{D, F};
+
+// A salted commitment absorbs a random salt before the pixels of the original.
+// The owner keeps the salt and the original and opens the commitment to a
+// chosen party by handing both over.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Salt(pub [F; SALT_LEN]);
+
+impl Salt {
+    pub fn random() -> Self {
+        Self(F::rand_array())
+    }
+
+    pub fn to_hex(&self) -> String {
+        self.0
+            .iter()
+            .map(|element| format!("{:016x}", element.to_canonical_u64()))
+            .collect()
+    }
+
+    pub fn from_hex(hex: &str) -> Result<Self> {
+        let hex = hex.trim();
+        if hex.len() != SALT_LEN * 16 {
+            return Err(anyhow!("a salt is {} hex digits", SALT_LEN * 16));
+        }
+        let mut elements = [F::ZERO; SALT_LEN];
+        for (element, digits) in elements.iter_mut().zip(hex.as_bytes().chunks(16)) {
+            let value = u64::from_str_radix(std::str::from_utf8(digits)?, 16)?;
+            if value >= F::ORDER {
+                return Err(anyhow!("salt element {:x} is out of the field", value));
+            }
+            *element = F::from_canonical_u64(value);
+        }
+        Ok(Self(elements))
+    }
+}
+
+/// The salted original hash `original`, a pixel stream packed as `layout`, and
+/// `salt` open, comparable with `TransformationProof::original_hash`.
+pub fn salted_original_hash<const L: usize>(
+    original: &[u8],
+    layout: PixelLayout,
+    salt: &Salt,
+) -> Vec<u8> {
+    let hash = ChunkHasher::<F, D, L>::new_salted(&layout.pack::<F>(original), salt.0)
+        .with_layout(layout)
+        .absorbed()
+        .output();
+    let mut bytes = Vec::new();
+    bytes.write_field_vec(&hash.elements).unwrap();
+    bytes
+}
+
+/// Checks that `original` with `salt` opens the original commitment of the
+/// proof.
+pub fn opens_commitment<const L: usize>(
+    proof: &TransformationProof,
+    original: &[u8],
+    layout: PixelLayout,
+    salt: &Salt,
+) -> bool {
+    proof.is_salted()
+        && proof.original_hash() == Some(salted_original_hash::<L>(original, layout, salt))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::TransformationCircuitBuilder;
+    use crate::pixel::PixelFormat;
+    use crate::testing::{random_bytes, threshold, HEIGHT, L, WIDTH};
+
+    #[test]
+    fn test_salted_proof_opens_with_its_salt() {
+        let original = random_bytes((WIDTH * HEIGHT * 4) as usize);
+        let (transformation, edited) = threshold(&original);
+        let mut circuit =
+            TransformationCircuitBuilder::<L>::new(original.len(), Box::new(transformation))
+                .with_salted_original()
+                .build_curcuit();
+        let salt = Salt::random();
+        let proof = circuit
+            .prove(&original, &edited, None, Some(&salt))
+            .unwrap();
+
+        let layout = PixelFormat::Rgba8.into();
+        assert!(opens_commitment::<L>(&proof, &original, layout, &salt));
+        assert!(!opens_commitment::<L>(
+            &proof,
+            &original,
+            layout,
+            &Salt::random()
+        ));
+        let other = random_bytes(original.len());
+        assert!(!opens_commitment::<L>(&proof, &other, layout, &salt));
+
+        // The circuit only proves salted originals
+        assert!(circuit.prove(&original, &edited, None, None).is_err());
+        proof.verify(circuit.circuit).unwrap();
+    }
+
+    #[test]
+    fn test_salt_hex_roundtrip() {
+        let salt = Salt::random();
+        assert_eq!(Salt::from_hex(&salt.to_hex()).unwrap(), salt);
+    }
+}
@@ -0,0 +1,537 @@
+use anyhow::{anyhow, Result};
+use itertools::izip;
+use plonky2::{
+    hash::hashing::{SPONGE_CAPACITY, SPONGE_WIDTH},
+    iop::{
+        target::{BoolTarget, Target},
+        witness::{PartialWitness, WitnessWrite},
+    },
+    plonk::{
+        circuit_builder::CircuitBuilder,
+        circuit_data::{CircuitConfig, CircuitData, CommonCircuitData},
+        proof::{CompressedProofWithPublicInputs, ProofWithPublicInputsTarget},
+    },
+    util::serialization::Write,
+};
+use plonky2_field::types::{Field, PrimeField64};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    aggregation::{
+        chain_state, prove_chunk, prove_compressed, verify_chunk_proof, zk_recursion_config,
+        Chunking,
+    },
+    hash::{build_hash_chunk_circuit, ChunkHashTargets, ChunkHasher},
+    pixel::PixelFormat,
+    proof::ChunkProof,
+    util::bytes_to_field64,
+    C, D, F,
+};
+
+// Links the Poseidon hash of an original to the SHA-256 digest of the same
+// pixel stream, the one C2PA manifests and signing cameras refer to. Each
+// chunk circuit absorbs its pixels into both the chained sponge of `hash.rs`
+// and a chained SHA-256 state, the aggregation circuit publishes the Poseidon
+// hash and the digest. SHA-256 is bit oriented and costs far more than the
+// sponge, so chunks are kept to a small multiple of the 16 pixel block.
+
+const SHA256_IV: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// Message words per SHA-256 block, a pixel is one word.
+const BLOCK_WORDS: usize = 16;
+
+/// Public inputs a chunk registers after the two sponge states: the SHA-256
+/// state before and after the chunk.
+const SHA256_INIT_STATE_PI_INDEXES: (usize, usize) = (SPONGE_WIDTH * 2, SPONGE_WIDTH * 2 + 8);
+const SHA256_FINAL_STATE_PI_INDEXES: (usize, usize) = (SPONGE_WIDTH * 2 + 8, SPONGE_WIDTH * 2 + 16);
+
+/// The RGBA bytes of a pixel read as a big-endian message word.
+fn pixel_word(pixel: F) -> u32 {
+    (pixel.to_canonical_u64() as u32).swap_bytes()
+}
+
+/// Padding words of a message of `message_words` words.
+fn padding_words(message_words: usize) -> Vec<u32> {
+    let bit_len = message_words as u64 * 32;
+    let mut padding = vec![0x80000000];
+    while (message_words + padding.len() + 2) % BLOCK_WORDS != 0 {
+        padding.push(0);
+    }
+    padding.extend([(bit_len >> 32) as u32, bit_len as u32]);
+    padding
+}
+
+fn compress(state: &mut [u32; 8], block: &[u32]) {
+    let mut w = block.to_vec();
+    for t in 16..64 {
+        let s0 = w[t - 15].rotate_right(7) ^ w[t - 15].rotate_right(18) ^ (w[t - 15] >> 3);
+        let s1 = w[t - 2].rotate_right(17) ^ w[t - 2].rotate_right(19) ^ (w[t - 2] >> 10);
+        w.push(
+            w[t - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[t - 7])
+                .wrapping_add(s1),
+        );
+    }
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *state;
+    for t in 0..64 {
+        let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+        let ch = (e & f) ^ (!e & g);
+        let t1 = h
+            .wrapping_add(s1)
+            .wrapping_add(ch)
+            .wrapping_add(SHA256_K[t])
+            .wrapping_add(w[t]);
+        let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+        let maj = (a & b) ^ (a & c) ^ (b & c);
+        h = g;
+        g = f;
+        f = e;
+        e = d.wrapping_add(t1);
+        d = c;
+        c = b;
+        b = a;
+        a = t1.wrapping_add(s0.wrapping_add(maj));
+    }
+    for (word, value) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+        *word = word.wrapping_add(value);
+    }
+}
+
+/// Natively computes the SHA-256 states after each chunk of `L` pixels, the
+/// last one is the digest.
+fn chunk_states<const L: usize>(pixels: &[F]) -> Vec<[u32; 8]> {
+    let mut words: Vec<u32> = pixels.iter().map(|pixel| pixel_word(*pixel)).collect();
+    words.extend(padding_words(pixels.len()));
+    let total_chunks = (pixels.len() + L - 1) / L;
+    let mut state = SHA256_IV;
+    let mut states = vec![state];
+    for chunk in 0..total_chunks {
+        let end = if chunk == total_chunks - 1 {
+            words.len()
+        } else {
+            (chunk + 1) * L
+        };
+        for block in words[chunk * L..end].chunks(BLOCK_WORDS) {
+            compress(&mut state, block);
+        }
+        states.push(state);
+    }
+    states
+}
+
+/// The message is taken a pixel word at a time and its padding counts words,
+/// so the pixel stream has to hold whole RGBA8 pixels.
+fn check_whole_pixels(len: usize) -> Result<()> {
+    if len % PixelFormat::Rgba8.bytes_per_pixel() != 0 {
+        return Err(anyhow!(
+            "the pixel stream of {} bytes doesn't hold whole RGBA8 pixels",
+            len
+        ));
+    }
+    Ok(())
+}
+
+/// SHA-256 digest of the pixel stream, what `Sha256LinkProof::sha256_digest`
+/// publishes.
+pub fn sha256_digest(original: &[u8]) -> Result<[u8; 32]> {
+    check_whole_pixels(original.len())?;
+    let pixels = bytes_to_field64::<F>(original);
+    let state = *chunk_states::<BLOCK_WORDS>(&pixels).last().unwrap();
+    let mut digest = [0u8; 32];
+    for (bytes, word) in digest.chunks_mut(4).zip(state) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    Ok(digest)
+}
+
+/// Little-endian bits of a 32-bit word.
+type WordTarget = [BoolTarget; 32];
+
+fn constant_word(builder: &mut CircuitBuilder<F, D>, value: u32) -> WordTarget {
+    std::array::from_fn(|i| builder.constant_bool(value >> i & 1 == 1))
+}
+
+fn word_value(builder: &mut CircuitBuilder<F, D>, word: &WordTarget) -> Target {
+    builder.le_sum(word.iter())
+}
+
+fn rotr(word: &WordTarget, n: usize) -> WordTarget {
+    std::array::from_fn(|i| word[(i + n) % 32])
+}
+
+fn shr(builder: &mut CircuitBuilder<F, D>, word: &WordTarget, n: usize) -> WordTarget {
+    let zero = builder._false();
+    std::array::from_fn(|i| if i + n < 32 { word[i + n] } else { zero })
+}
+
+pub(crate) fn xor(builder: &mut CircuitBuilder<F, D>, a: BoolTarget, b: BoolTarget) -> BoolTarget {
+    // a + b - 2ab
+    let sum = builder.add(a.target, b.target);
+    BoolTarget::new_unsafe(builder.arithmetic(-F::TWO, F::ONE, a.target, b.target, sum))
+}
+
+fn xor3(
+    builder: &mut CircuitBuilder<F, D>,
+    a: &WordTarget,
+    b: &WordTarget,
+    c: &WordTarget,
+) -> WordTarget {
+    std::array::from_fn(|i| {
+        let ab = xor(builder, a[i], b[i]);
+        xor(builder, ab, c[i])
+    })
+}
+
+fn ch(
+    builder: &mut CircuitBuilder<F, D>,
+    e: &WordTarget,
+    f: &WordTarget,
+    g: &WordTarget,
+) -> WordTarget {
+    // g + e * (f - g)
+    std::array::from_fn(|i| {
+        let diff = builder.sub(f[i].target, g[i].target);
+        BoolTarget::new_unsafe(builder.arithmetic(F::ONE, F::ONE, e[i].target, diff, g[i].target))
+    })
+}
+
+fn maj(
+    builder: &mut CircuitBuilder<F, D>,
+    a: &WordTarget,
+    b: &WordTarget,
+    c: &WordTarget,
+) -> WordTarget {
+    // ab + c * (a xor b)
+    std::array::from_fn(|i| {
+        let ab = builder.mul(a[i].target, b[i].target);
+        let a_xor_b = xor(builder, a[i], b[i]);
+        BoolTarget::new_unsafe(builder.arithmetic(F::ONE, F::ONE, c[i].target, a_xor_b.target, ab))
+    })
+}
+
+/// Sum of at most seven words and a constant modulo 2^32.
+fn add_words(
+    builder: &mut CircuitBuilder<F, D>,
+    words: &[&WordTarget],
+    constant: u32,
+) -> WordTarget {
+    assert!(words.len() <= 7);
+    let mut values: Vec<Target> = words.iter().map(|word| word_value(builder, word)).collect();
+    values.push(builder.constant(F::from_canonical_u32(constant)));
+    let sum = builder.add_many(values);
+    let bits = builder.split_le(sum, 35);
+    std::array::from_fn(|i| bits[i])
+}
+
+fn compress_circuit(
+    builder: &mut CircuitBuilder<F, D>,
+    state: &[WordTarget; 8],
+    block: &[WordTarget],
+) -> [WordTarget; 8] {
+    let mut w = block.to_vec();
+    for t in 16..64 {
+        let shifted = shr(builder, &w[t - 15], 3);
+        let s0 = xor3(
+            builder,
+            &rotr(&w[t - 15], 7),
+            &rotr(&w[t - 15], 18),
+            &shifted,
+        );
+        let shifted = shr(builder, &w[t - 2], 10);
+        let s1 = xor3(
+            builder,
+            &rotr(&w[t - 2], 17),
+            &rotr(&w[t - 2], 19),
+            &shifted,
+        );
+        let next = add_words(builder, &[&w[t - 16], &s0, &w[t - 7], &s1], 0);
+        w.push(next);
+    }
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *state;
+    for t in 0..64 {
+        let s1 = xor3(builder, &rotr(&e, 6), &rotr(&e, 11), &rotr(&e, 25));
+        let choice = ch(builder, &e, &f, &g);
+        let s0 = xor3(builder, &rotr(&a, 2), &rotr(&a, 13), &rotr(&a, 22));
+        let majority = maj(builder, &a, &b, &c);
+        // t1 is never split on its own, both new words add its terms directly
+        let new_e = add_words(builder, &[&d, &h, &s1, &choice, &w[t]], SHA256_K[t]);
+        let new_a = add_words(
+            builder,
+            &[&h, &s1, &choice, &w[t], &s0, &majority],
+            SHA256_K[t],
+        );
+        h = g;
+        g = f;
+        f = e;
+        e = new_e;
+        d = c;
+        c = b;
+        b = a;
+        a = new_a;
+    }
+    let mut next = *state;
+    for (word, value) in next.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+        *word = add_words(builder, &[word, &value], 0);
+    }
+    next
+}
+
+struct Sha256ChunkCircuit {
+    circuit: CircuitData<F, C, D>,
+    original_chunk: ChunkHashTargets,
+    init_state: [WordTarget; 8],
+}
+
+pub struct Sha256LinkBuilder<const L: usize> {
+    original: Chunking<L>,
+}
+
+impl<const L: usize> Sha256LinkBuilder<L> {
+    /// `L` has to be a multiple of the 16 pixels of a SHA-256 block.
+    pub fn new(original_len: usize) -> Self {
+        assert_eq!(L % BLOCK_WORDS, 0, "chunks must consist of whole blocks");
+        assert!(
+            check_whole_pixels(original_len).is_ok(),
+            "the pixel stream must hold whole RGBA8 pixels"
+        );
+        Self {
+            original: Chunking::new(original_len, PixelFormat::Rgba8.into()),
+        }
+    }
+
+    fn build_chunk_circuit(&self, config: &CircuitConfig, chunk: usize) -> Sha256ChunkCircuit {
+        let mut builder = CircuitBuilder::<F, D>::new(config.clone());
+
+        let chunk_len = self.original.chunk_len(chunk);
+        let original_chunk = build_hash_chunk_circuit::<F, D, L>(&mut builder, chunk_len, false);
+
+        let init_state: [WordTarget; 8] = std::array::from_fn(|_| {
+            std::array::from_fn(|_| builder.add_virtual_bool_target_safe())
+        });
+        let mut words: Vec<WordTarget> = original_chunk.input[..chunk_len]
+            .iter()
+            .map(|pixel| {
+                // byte k of the pixel is byte 3 - k of the big-endian word
+                let bits = builder.split_le(*pixel, 32);
+                std::array::from_fn(|i| bits[8 * (3 - i / 8) + i % 8])
+            })
+            .collect();
+        if chunk == self.original.total_chunks() - 1 {
+            for word in padding_words(self.original.pixels()) {
+                words.push(constant_word(&mut builder, word));
+            }
+        }
+        let mut state = init_state;
+        for block in words.chunks(BLOCK_WORDS) {
+            state = compress_circuit(&mut builder, &state, block);
+        }
+
+        for word in init_state.iter().chain(state.iter()) {
+            let value = word_value(&mut builder, word);
+            builder.register_public_input(value);
+        }
+
+        Sha256ChunkCircuit {
+            circuit: builder.build::<C>(),
+            original_chunk,
+            init_state,
+        }
+    }
+
+    pub fn build_circuit(self) -> Sha256LinkCircuit<L> {
+        let config = zk_recursion_config();
+        let mut chunk_circuits = Vec::new();
+        let mut builder = CircuitBuilder::<F, D>::new(config.clone());
+
+        let mut last_final_state_target =
+            builder.constants(&self.original.layout.sponge_initial_state::<F>());
+        let mut last_sha256_state_target =
+            builder.constants(&SHA256_IV.map(|word| F::from_canonical_u32(word)));
+
+        let mut pts = Vec::new();
+        for chunk in 0..self.original.total_chunks() {
+            let chunk_circuit = self.build_chunk_circuit(&config, chunk);
+            let pt = verify_chunk_proof(&mut builder, &chunk_circuit.circuit);
+
+            for (last, init, fin) in [
+                (
+                    &mut last_final_state_target,
+                    ChunkProof::ORIGINAL_INIT_STATE_PI_INDEXES,
+                    ChunkProof::ORIGINAL_FINAL_STATE_PI_INDEXES,
+                ),
+                (
+                    &mut last_sha256_state_target,
+                    SHA256_INIT_STATE_PI_INDEXES,
+                    SHA256_FINAL_STATE_PI_INDEXES,
+                ),
+            ] {
+                chain_state(&mut builder, last, &pt.public_inputs, init, fin);
+            }
+
+            chunk_circuits.push(chunk_circuit);
+            pts.push(pt);
+        }
+
+        builder.register_public_inputs(&last_final_state_target[..SPONGE_CAPACITY]);
+        builder.register_public_inputs(&last_sha256_state_target);
+
+        Sha256LinkCircuit {
+            circuit: builder.build::<C>(),
+            chunk_circuits,
+            pts,
+        }
+    }
+}
+
+pub struct Sha256LinkCircuit<const L: usize> {
+    pub circuit: CircuitData<F, C, D>,
+    chunk_circuits: Vec<Sha256ChunkCircuit>,
+    pts: Vec<ProofWithPublicInputsTarget<D>>,
+}
+
+impl<const L: usize> Sha256LinkCircuit<L> {
+    pub fn prove(&self, original: &[u8]) -> Result<Sha256LinkProof> {
+        check_whole_pixels(original.len())?;
+        let original_elements = bytes_to_field64::<F>(original);
+        let sha256_states = chunk_states::<L>(&original_elements);
+        self.prove_with_states(&original_elements, &sha256_states)
+    }
+
+    /// Proves with the SHA-256 state before each chunk taken from
+    /// `sha256_states`.
+    fn prove_with_states(
+        &self,
+        original_elements: &[F],
+        sha256_states: &[[u32; 8]],
+    ) -> Result<Sha256LinkProof> {
+        let mut hasher = ChunkHasher::<F, D, L>::new(original_elements);
+
+        let mut pw = PartialWitness::new();
+        for (chunk_circuit, pt, sha256_state) in
+            izip!(&self.chunk_circuits, &self.pts, sha256_states)
+        {
+            let mut inputs = PartialWitness::<F>::new();
+            hasher.populate_chunk_inputs(&chunk_circuit.original_chunk, &mut inputs);
+            for (word, value) in chunk_circuit.init_state.iter().zip(sha256_state) {
+                for (i, bit) in word.iter().enumerate() {
+                    inputs.set_bool_target(*bit, value >> i & 1 == 1);
+                }
+            }
+            let proof = prove_chunk(&chunk_circuit.circuit, inputs)?;
+            pw.set_proof_with_pis_target(pt, &proof);
+        }
+
+        Ok(Sha256LinkProof {
+            proof: prove_compressed(&self.circuit, pw)?,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Sha256LinkProof {
+    pub(crate) proof: CompressedProofWithPublicInputs<F, C, D>,
+}
+
+impl Sha256LinkProof {
+    /// Comparable with `TransformationProof::original_hash`.
+    pub fn poseidon_hash(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes
+            .write_field_vec(&self.proof.public_inputs[..SPONGE_CAPACITY])
+            .unwrap();
+        bytes
+    }
+
+    pub fn sha256_digest(&self) -> [u8; 32] {
+        let mut digest = [0u8; 32];
+        for (bytes, word) in digest
+            .chunks_mut(4)
+            .zip(&self.proof.public_inputs[SPONGE_CAPACITY..])
+        {
+            bytes.copy_from_slice(&(word.to_canonical_u64() as u32).to_be_bytes());
+        }
+        digest
+    }
+
+    pub fn verify(&self, circuit: CircuitData<F, C, D>) -> Result<()> {
+        circuit.verify_compressed(self.proof.clone())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.proof.to_bytes()
+    }
+
+    pub fn from_bytes(bytes: Vec<u8>, common_data: &CommonCircuitData<F, D>) -> Result<Self> {
+        Ok(Sha256LinkProof {
+            proof: CompressedProofWithPublicInputs::from_bytes(bytes, common_data)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sha2::{Digest, Sha256};
+
+    use super::*;
+    use crate::commitment::CommitmentScheme;
+    use crate::testing::{proves, random_bytes, HEIGHT, L, WIDTH};
+
+    #[test]
+    fn test_chunked_digest_matches_sha256() {
+        let original: Vec<u8> = (0..4 * 100).map(|i| (i * 7) as u8).collect();
+        let pixels = bytes_to_field64::<F>(&original);
+        let digest = sha256_digest(&original).unwrap();
+        assert_eq!(digest[..], Sha256::digest(&original)[..]);
+
+        let state = chunk_states::<32>(&pixels).pop().unwrap();
+        let mut chunked = [0u8; 32];
+        for (bytes, word) in chunked.chunks_mut(4).zip(state) {
+            bytes.copy_from_slice(&word.to_be_bytes());
+        }
+        assert_eq!(chunked, digest);
+
+        // A partial pixel would be padded into the word as zero bytes
+        assert!(sha256_digest(&original[..original.len() - 2]).is_err());
+    }
+
+    #[test]
+    fn test_link_proves_sha256_digest() {
+        // Two full chunks, the padding takes a block of its own
+        let original = random_bytes((WIDTH * HEIGHT * 4) as usize);
+        let circuit = Sha256LinkBuilder::<L>::new(original.len()).build_circuit();
+        let proof = circuit.prove(&original).unwrap();
+        assert_eq!(proof.sha256_digest()[..], Sha256::digest(&original)[..]);
+        assert_eq!(
+            proof.poseidon_hash(),
+            CommitmentScheme::Sponge.commit::<L>(&original, PixelFormat::Rgba8.into())
+        );
+        proof.verify(circuit.circuit).unwrap();
+    }
+
+    #[test]
+    fn test_link_chains_sha256_states() {
+        let original = random_bytes((WIDTH * HEIGHT * 4) as usize);
+        let circuit = Sha256LinkBuilder::<L>::new(original.len()).build_circuit();
+        let elements = bytes_to_field64::<F>(&original);
+        // The state after the first chunk of another image
+        let other = bytes_to_field64::<F>(&random_bytes(original.len()));
+        let states = chunk_states::<L>(&other);
+        assert!(!proves(|| circuit
+            .prove_with_states(&elements, &states)
+            .map(|_| ())));
+    }
+}
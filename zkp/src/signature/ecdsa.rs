@@ -0,0 +1,242 @@
+use anyhow::{anyhow, Result};
+use num::BigUint;
+use plonky2::hash::hash_types::{HashOut, NUM_HASH_OUT_ELTS};
+use plonky2::iop::target::Target;
+use plonky2::iop::witness::PartialWitness;
+use plonky2::plonk::circuit_builder::CircuitBuilder;
+use plonky2_ecdsa::curve::curve_types::{AffinePoint, Curve};
+use plonky2_ecdsa::curve::ecdsa::{
+    sign_message, verify_message, ECDSAPublicKey, ECDSASecretKey, ECDSASignature,
+};
+use plonky2_ecdsa::gadgets::biguint::{BigUintTarget, WitnessBigUint};
+use plonky2_ecdsa::gadgets::curve::CircuitBuilderCurve;
+use plonky2_ecdsa::gadgets::ecdsa::{
+    verify_message_circuit, ECDSAPublicKeyTarget, ECDSASignatureTarget,
+};
+use plonky2_ecdsa::gadgets::nonnative::{CircuitBuilderNonNative, NonNativeTarget};
+use plonky2_field::types::{Field, PrimeField, PrimeField64};
+use plonky2_u32::gadgets::arithmetic_u32::U32Target;
+
+use crate::{D, F};
+
+// ECDSA over any of the 256-bit curves. The signed message is the hash of the
+// original read as a little-endian 256-bit integer and reduced into the scalar
+// field, devices sign it as an already hashed digest.
+
+const LIMB_BITS: usize = 32;
+pub(crate) const COORDINATE_LIMBS: usize = 8;
+
+pub(crate) fn hash_to_scalar<C: Curve>(hash: &HashOut<F>) -> C::ScalarField {
+    let limbs: Vec<u32> = hash
+        .elements
+        .iter()
+        .flat_map(|element| {
+            let value = element.to_canonical_u64();
+            [value as u32, (value >> LIMB_BITS) as u32]
+        })
+        .collect();
+    C::ScalarField::from_noncanonical_biguint(BigUint::new(limbs))
+}
+
+fn hash_to_scalar_circuit<C: Curve>(
+    builder: &mut CircuitBuilder<F, D>,
+    hash: &[Target],
+) -> NonNativeTarget<C::ScalarField> {
+    assert_eq!(hash.len(), NUM_HASH_OUT_ELTS);
+    let mut limbs = Vec::new();
+    for element in hash {
+        let (low, high) = builder.split_low_high(*element, LIMB_BITS, 64);
+        limbs.push(U32Target(low));
+        limbs.push(U32Target(high));
+    }
+    builder.reduce(&BigUintTarget { limbs })
+}
+
+pub fn secret_key_from_hex<C: Curve>(hex: &str) -> Result<ECDSASecretKey<C>> {
+    let value = BigUint::parse_bytes(hex.trim().as_bytes(), 16)
+        .ok_or_else(|| anyhow!("the secret key is not a hex number"))?;
+    Ok(ECDSASecretKey(C::ScalarField::from_noncanonical_biguint(
+        value,
+    )))
+}
+
+pub(crate) fn sign_hash<C: Curve>(
+    hash: &HashOut<F>,
+    secret_key: &ECDSASecretKey<C>,
+) -> (ECDSAPublicKey<C>, ECDSASignature<C>) {
+    (
+        secret_key.to_public(),
+        sign_message(hash_to_scalar::<C>(hash), *secret_key),
+    )
+}
+
+fn scalar_from_be_bytes<C: Curve>(bytes: &[u8]) -> C::ScalarField {
+    C::ScalarField::from_noncanonical_biguint(BigUint::from_bytes_be(bytes))
+}
+
+fn to_be_bytes(value: BigUint) -> [u8; 32] {
+    let bytes = value.to_bytes_be();
+    let mut padded = [0u8; 32];
+    padded[32 - bytes.len()..].copy_from_slice(&bytes);
+    padded
+}
+
+/// The public key of `secret_key` as an uncompressed SEC1 point.
+pub fn public_key_bytes<C: Curve>(secret_key: &ECDSASecretKey<C>) -> Vec<u8> {
+    let point = secret_key.to_public().0;
+    let mut bytes = vec![0x04];
+    bytes.extend(to_be_bytes(point.x.to_canonical_biguint()));
+    bytes.extend(to_be_bytes(point.y.to_canonical_biguint()));
+    bytes
+}
+
+/// Signs a SHA-256 digest the standard way, read as a big-endian integer,
+/// for formats outside the proofs such as COSE. The signature is `r || s`,
+/// both big-endian.
+pub fn sign_digest<C: Curve>(digest: &[u8; 32], secret_key: &ECDSASecretKey<C>) -> [u8; 64] {
+    let signature = sign_message(scalar_from_be_bytes::<C>(digest), *secret_key);
+    let mut bytes = [0u8; 64];
+    bytes[..32].copy_from_slice(&to_be_bytes(signature.r.to_canonical_biguint()));
+    bytes[32..].copy_from_slice(&to_be_bytes(signature.s.to_canonical_biguint()));
+    bytes
+}
+
+/// Checks a signature of `sign_digest` against an uncompressed SEC1 point.
+pub fn verify_digest<C: Curve>(digest: &[u8; 32], signature: &[u8], public_key: &[u8]) -> bool {
+    let (x, y) = match public_key {
+        [0x04, point @ ..] if point.len() == 64 => point.split_at(32),
+        _ => return false,
+    };
+    let (x, y) = (BigUint::from_bytes_be(x), BigUint::from_bytes_be(y));
+    if x >= C::BaseField::order() || y >= C::BaseField::order() || signature.len() != 64 {
+        return false;
+    }
+    let point = AffinePoint::<C>::nonzero(
+        C::BaseField::from_noncanonical_biguint(x),
+        C::BaseField::from_noncanonical_biguint(y),
+    );
+    let (r, s) = signature.split_at(32);
+    let in_range = |value: &[u8]| {
+        let value = BigUint::from_bytes_be(value);
+        value > BigUint::default() && value < C::ScalarField::order()
+    };
+    point.is_valid()
+        && in_range(r)
+        && in_range(s)
+        && verify_message(
+            scalar_from_be_bytes::<C>(digest),
+            ECDSASignature {
+                r: scalar_from_be_bytes::<C>(r),
+                s: scalar_from_be_bytes::<C>(s),
+            },
+            ECDSAPublicKey(point),
+        )
+}
+
+/// Encodes public key public inputs as an uncompressed SEC1 point.
+pub(crate) fn encode_public_key(public_inputs: &[F]) -> Vec<u8> {
+    let mut bytes = vec![0x04];
+    for coordinate in public_inputs.chunks(COORDINATE_LIMBS) {
+        let mut coordinate_bytes: Vec<u8> = coordinate
+            .iter()
+            .flat_map(|limb| (limb.to_canonical_u64() as u32).to_le_bytes())
+            .collect();
+        coordinate_bytes.reverse();
+        bytes.extend(coordinate_bytes);
+    }
+    bytes
+}
+
+pub(crate) struct EcdsaTargets<C: Curve> {
+    public_key: ECDSAPublicKeyTarget<C>,
+    signature: ECDSASignatureTarget<C>,
+}
+
+fn range_check_limbs(builder: &mut CircuitBuilder<F, D>, value: &BigUintTarget) {
+    for limb in &value.limbs {
+        builder.range_check(limb.0, LIMB_BITS);
+    }
+}
+
+/// Verifies a signature over `hash` and registers the public key as public
+/// inputs.
+pub(crate) fn build_ecdsa_circuit<C: Curve>(
+    builder: &mut CircuitBuilder<F, D>,
+    hash: &[Target],
+) -> EcdsaTargets<C> {
+    let message = hash_to_scalar_circuit::<C>(builder, hash);
+    let point = builder.add_virtual_affine_point_target::<C>();
+    range_check_limbs(builder, &point.x.value);
+    range_check_limbs(builder, &point.y.value);
+    builder.curve_assert_valid(&point);
+    let public_key = ECDSAPublicKeyTarget(point);
+
+    let signature = ECDSASignatureTarget {
+        r: builder.add_virtual_nonnative_target(),
+        s: builder.add_virtual_nonnative_target(),
+    };
+    range_check_limbs(builder, &signature.r.value);
+    range_check_limbs(builder, &signature.s.value);
+
+    for coordinate in [&public_key.0.x.value, &public_key.0.y.value] {
+        for limb in &coordinate.limbs {
+            builder.register_public_input(limb.0);
+        }
+    }
+    verify_message_circuit(builder, message, signature.clone(), public_key.clone());
+    EcdsaTargets {
+        public_key,
+        signature,
+    }
+}
+
+impl<C: Curve> EcdsaTargets<C> {
+    pub(crate) fn set_witness(
+        &self,
+        pw: &mut PartialWitness<F>,
+        public_key: &ECDSAPublicKey<C>,
+        signature: &ECDSASignature<C>,
+    ) {
+        pw.set_biguint_target(
+            &self.public_key.0.x.value,
+            &public_key.0.x.to_canonical_biguint(),
+        );
+        pw.set_biguint_target(
+            &self.public_key.0.y.value,
+            &public_key.0.y.to_canonical_biguint(),
+        );
+        pw.set_biguint_target(&self.signature.r.value, &signature.r.to_canonical_biguint());
+        pw.set_biguint_target(&self.signature.s.value, &signature.s.to_canonical_biguint());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use plonky2_ecdsa::curve::secp256k1::Secp256K1;
+    use plonky2_field::secp256k1_scalar::Secp256K1Scalar;
+
+    use super::super::p256::P256;
+    use super::*;
+
+    #[test]
+    fn test_hash_to_scalar_is_little_endian() {
+        let hash = HashOut {
+            elements: [F::ONE, F::ZERO, F::ZERO, F::TWO],
+        };
+        let expected = (BigUint::from(2u32) << 192) + BigUint::from(1u32);
+        assert_eq!(
+            hash_to_scalar::<Secp256K1>(&hash),
+            Secp256K1Scalar::from_noncanonical_biguint(expected)
+        );
+    }
+
+    #[test]
+    fn test_sign_digest_verifies() {
+        let secret_key = secret_key_from_hex::<P256>("c0ffee").unwrap();
+        let public_key = public_key_bytes(&secret_key);
+        let digest = [7u8; 32];
+        let signature = sign_digest(&digest, &secret_key);
+        assert!(verify_digest::<P256>(&digest, &signature, &public_key));
+        assert!(!verify_digest::<P256>(&[8u8; 32], &signature, &public_key));
+    }
+}
@@ -0,0 +1,486 @@
+use num::BigUint;
+use plonky2::iop::generator::{GeneratedValues, SimpleGenerator};
+use plonky2::iop::target::{BoolTarget, Target};
+use plonky2::iop::witness::{PartitionWitness, Witness, WitnessWrite};
+use plonky2::plonk::circuit_builder::CircuitBuilder;
+use plonky2_field::extension::quintic::QuinticExtension;
+use plonky2_field::goldilocks_field::GoldilocksField;
+use plonky2_field::types::{Field, Field64};
+
+use crate::{D, F};
+
+// EcGFp5 is the curve `y^2 = x (x^2 + 2 x + 263 z)` over GF(p^5), where p is
+// the Goldilocks prime and z^5 = 3, so its arithmetic stays in the native
+// field of the proof system. The curve has order 2n for the prime n below,
+// points are kept in affine coordinates of its order n subgroup. Its only
+// point of order 2 is N = (0, 0), and as b is not a square, a point other
+// than N lies in the subgroup exactly when its x is a square.
+
+pub type GFp5 = QuinticExtension<GoldilocksField>;
+
+/// Order of the prime subgroup.
+const SCALAR_ORDER: &[u8] = b"1067993516717146951041484916571792702745057740581727230159139685185762082554198619328292418486241";
+pub const SCALAR_BITS: usize = 319;
+
+/// Twice the point with `x = 1` whose `y` has an even constant coefficient.
+const GENERATOR: [[u64; 5]; 2] = [
+    [
+        0xbed746dd8eb062a0,
+        0x7af21089a2ee39f6,
+        0x25c5ab5e49c0e81b,
+        0x10a450a9139b2ece,
+        0x4d1060be4930f8d0,
+    ],
+    [
+        0x0429ee252ef6d274,
+        0x66d8b00f46df6065,
+        0x54ef97a44c784d26,
+        0x065bb0851093c7d9,
+        0x72ee74f1a4035cf7,
+    ],
+];
+
+/// Derived like the generator from `x = 5`, nobody knows its discrete
+/// logarithm. Scalar multiplications in the circuit start from it so that the
+/// incomplete addition formulas never meet the identity.
+const OFFSET: [[u64; 5]; 2] = [
+    [
+        0xf8a4664e8ee10689,
+        0x5f846cebd9567714,
+        0x190582ead3e3fdf4,
+        0x09778004b92a3b45,
+        0xb8dd20c5909e16c0,
+    ],
+    [
+        0x7c734d3e61edd5a2,
+        0x594d763d81ec6381,
+        0xd73588b1e290794e,
+        0xed81507fa91f169f,
+        0xe810387b782a507e,
+    ],
+];
+
+pub fn scalar_order() -> BigUint {
+    BigUint::parse_bytes(SCALAR_ORDER, 10).unwrap()
+}
+
+fn curve_a() -> GFp5 {
+    GFp5::from_canonical_u64(2)
+}
+
+fn curve_b() -> GFp5 {
+    let mut b = GFp5::ZERO;
+    b.0[1] = F::from_canonical_u64(263);
+    b
+}
+
+/// `x^((q - 1) / 2)` for `q = p^5` is one exactly for the nonzero squares.
+fn euler_exponent() -> BigUint {
+    (BigUint::from(GoldilocksField::ORDER).pow(5) - 1u32) >> 1
+}
+
+fn gfp5_from_u64s(limbs: [u64; 5]) -> GFp5 {
+    QuinticExtension(limbs.map(F::from_canonical_u64))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EcGFp5Point {
+    pub x: GFp5,
+    pub y: GFp5,
+    pub is_zero: bool,
+}
+
+impl EcGFp5Point {
+    pub const ZERO: Self = Self {
+        x: GFp5::ZERO,
+        y: GFp5::ZERO,
+        is_zero: true,
+    };
+
+    fn from_limbs(limbs: [[u64; 5]; 2]) -> Self {
+        Self {
+            x: gfp5_from_u64s(limbs[0]),
+            y: gfp5_from_u64s(limbs[1]),
+            is_zero: false,
+        }
+    }
+
+    pub fn generator() -> Self {
+        Self::from_limbs(GENERATOR)
+    }
+
+    pub(crate) fn offset() -> Self {
+        Self::from_limbs(OFFSET)
+    }
+
+    pub fn is_on_curve(&self) -> bool {
+        self.is_zero
+            || self.y.square() == self.x * (self.x.square() + curve_a() * self.x + curve_b())
+    }
+
+    /// Whether the point lies in the subgroup of order n and isn't its
+    /// identity, which the circuit can't represent.
+    pub fn is_in_subgroup(&self) -> bool {
+        !self.is_zero && self.is_on_curve() && self.mul(&scalar_order()).is_zero
+    }
+
+    pub fn neg(&self) -> Self {
+        Self {
+            y: -self.y,
+            ..*self
+        }
+    }
+
+    pub fn double(&self) -> Self {
+        if self.is_zero || self.y.is_zero() {
+            return Self::ZERO;
+        }
+        let numerator =
+            GFp5::from_canonical_u64(3) * self.x.square() + curve_a().double() * self.x + curve_b();
+        let lambda = numerator / self.y.double();
+        let x = lambda.square() - curve_a() - self.x.double();
+        let y = lambda * (self.x - x) - self.y;
+        Self {
+            x,
+            y,
+            is_zero: false,
+        }
+    }
+
+    pub fn add(&self, rhs: &Self) -> Self {
+        if self.is_zero {
+            return *rhs;
+        }
+        if rhs.is_zero {
+            return *self;
+        }
+        if self.x == rhs.x {
+            return if self.y == rhs.y {
+                self.double()
+            } else {
+                Self::ZERO
+            };
+        }
+        let lambda = (rhs.y - self.y) / (rhs.x - self.x);
+        let x = lambda.square() - curve_a() - self.x - rhs.x;
+        let y = lambda * (self.x - x) - self.y;
+        Self {
+            x,
+            y,
+            is_zero: false,
+        }
+    }
+
+    pub fn mul(&self, scalar: &BigUint) -> Self {
+        let mut result = Self::ZERO;
+        for i in (0..scalar.bits()).rev() {
+            result = result.double();
+            if scalar.bit(i) {
+                result = result.add(self);
+            }
+        }
+        result
+    }
+
+    pub(crate) fn coordinates(&self) -> Vec<F> {
+        self.x.0.iter().chain(&self.y.0).copied().collect()
+    }
+}
+
+/// An element of GF(p^5) in the circuit.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct QuinticTarget(pub [Target; 5]);
+
+/// An affine point that is never the identity.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct PointTarget {
+    pub x: QuinticTarget,
+    pub y: QuinticTarget,
+}
+
+impl PointTarget {
+    pub(crate) fn targets(&self) -> Vec<Target> {
+        self.x.0.iter().chain(&self.y.0).copied().collect()
+    }
+}
+
+#[derive(Debug)]
+struct QuinticInverseGenerator {
+    x: [Target; 5],
+    inverse: [Target; 5],
+}
+
+impl SimpleGenerator<F> for QuinticInverseGenerator {
+    fn dependencies(&self) -> Vec<Target> {
+        self.x.to_vec()
+    }
+
+    fn run_once(&self, witness: &PartitionWitness<F>, out_buffer: &mut GeneratedValues<F>) {
+        let x = QuinticExtension(self.x.map(|t| witness.get_target(t)));
+        // A zero has no inverse, the constraint then fails instead
+        let inverse = x.try_inverse().unwrap_or(GFp5::ZERO);
+        for (target, value) in self.inverse.iter().zip(inverse.0) {
+            out_buffer.set_target(*target, value);
+        }
+    }
+}
+
+pub(crate) trait CircuitBuilderEcGFp5 {
+    fn add_virtual_quintic_target(&mut self) -> QuinticTarget;
+    fn constant_quintic(&mut self, value: GFp5) -> QuinticTarget;
+    fn connect_quintic(&mut self, a: QuinticTarget, b: QuinticTarget);
+    fn add_quintic(&mut self, a: QuinticTarget, b: QuinticTarget) -> QuinticTarget;
+    fn sub_quintic(&mut self, a: QuinticTarget, b: QuinticTarget) -> QuinticTarget;
+    fn mul_quintic(&mut self, a: QuinticTarget, b: QuinticTarget) -> QuinticTarget;
+    fn scalar_mul_quintic(&mut self, scalar: F, a: QuinticTarget) -> QuinticTarget;
+    fn exp_quintic(&mut self, a: QuinticTarget, exponent: &BigUint) -> QuinticTarget;
+    /// Fails to prove for zero.
+    fn inverse_quintic(&mut self, a: QuinticTarget) -> QuinticTarget;
+    fn select_quintic(
+        &mut self,
+        b: BoolTarget,
+        x: QuinticTarget,
+        y: QuinticTarget,
+    ) -> QuinticTarget;
+
+    fn add_virtual_point_target(&mut self) -> PointTarget;
+    fn constant_point(&mut self, point: &EcGFp5Point) -> PointTarget;
+    fn connect_points(&mut self, a: &PointTarget, b: &PointTarget);
+    fn assert_on_curve(&mut self, p: &PointTarget);
+    /// Checks that `p` is on the curve and in the subgroup of order n.
+    fn assert_in_subgroup(&mut self, p: &PointTarget);
+    /// Fails to prove if the points share their `x`.
+    fn add_points(&mut self, a: &PointTarget, b: &PointTarget) -> PointTarget;
+    fn double_point(&mut self, p: &PointTarget) -> PointTarget;
+    fn select_point(&mut self, b: BoolTarget, x: &PointTarget, y: &PointTarget) -> PointTarget;
+    /// `scalar * G` for little-endian scalar bits.
+    fn generator_mul(&mut self, scalar_bits: &[BoolTarget]) -> PointTarget;
+    /// `scalar * p` for little-endian scalar bits.
+    fn point_mul(&mut self, p: &PointTarget, scalar_bits: &[BoolTarget]) -> PointTarget;
+}
+
+impl CircuitBuilderEcGFp5 for CircuitBuilder<F, D> {
+    fn add_virtual_quintic_target(&mut self) -> QuinticTarget {
+        QuinticTarget(self.add_virtual_target_arr())
+    }
+
+    fn constant_quintic(&mut self, value: GFp5) -> QuinticTarget {
+        QuinticTarget(value.0.map(|c| self.constant(c)))
+    }
+
+    fn connect_quintic(&mut self, a: QuinticTarget, b: QuinticTarget) {
+        for (a, b) in a.0.into_iter().zip(b.0) {
+            self.connect(a, b);
+        }
+    }
+
+    fn add_quintic(&mut self, a: QuinticTarget, b: QuinticTarget) -> QuinticTarget {
+        QuinticTarget([0, 1, 2, 3, 4].map(|i| self.add(a.0[i], b.0[i])))
+    }
+
+    fn sub_quintic(&mut self, a: QuinticTarget, b: QuinticTarget) -> QuinticTarget {
+        QuinticTarget([0, 1, 2, 3, 4].map(|i| self.sub(a.0[i], b.0[i])))
+    }
+
+    fn mul_quintic(&mut self, a: QuinticTarget, b: QuinticTarget) -> QuinticTarget {
+        // z^5 = 3 folds the upper half of the product back
+        QuinticTarget([0, 1, 2, 3, 4].map(|k| {
+            let mut low = self.zero();
+            let mut high = self.zero();
+            for i in 0..5 {
+                for j in 0..5 {
+                    if i + j == k {
+                        low = self.mul_add(a.0[i], b.0[j], low);
+                    } else if i + j == k + 5 {
+                        high = self.mul_add(a.0[i], b.0[j], high);
+                    }
+                }
+            }
+            self.mul_const_add(F::from_canonical_u64(3), high, low)
+        }))
+    }
+
+    fn scalar_mul_quintic(&mut self, scalar: F, a: QuinticTarget) -> QuinticTarget {
+        QuinticTarget(a.0.map(|c| self.mul_const(scalar, c)))
+    }
+
+    fn exp_quintic(&mut self, a: QuinticTarget, exponent: &BigUint) -> QuinticTarget {
+        let mut result = self.constant_quintic(GFp5::ONE);
+        for i in (0..exponent.bits()).rev() {
+            result = self.mul_quintic(result, result);
+            if exponent.bit(i) {
+                result = self.mul_quintic(result, a);
+            }
+        }
+        result
+    }
+
+    fn inverse_quintic(&mut self, a: QuinticTarget) -> QuinticTarget {
+        let inverse = self.add_virtual_quintic_target();
+        self.add_simple_generator(QuinticInverseGenerator {
+            x: a.0,
+            inverse: inverse.0,
+        });
+        let product = self.mul_quintic(a, inverse);
+        let one = self.constant_quintic(GFp5::ONE);
+        self.connect_quintic(product, one);
+        inverse
+    }
+
+    fn select_quintic(
+        &mut self,
+        b: BoolTarget,
+        x: QuinticTarget,
+        y: QuinticTarget,
+    ) -> QuinticTarget {
+        QuinticTarget([0, 1, 2, 3, 4].map(|i| self.select(b, x.0[i], y.0[i])))
+    }
+
+    fn add_virtual_point_target(&mut self) -> PointTarget {
+        PointTarget {
+            x: self.add_virtual_quintic_target(),
+            y: self.add_virtual_quintic_target(),
+        }
+    }
+
+    fn constant_point(&mut self, point: &EcGFp5Point) -> PointTarget {
+        assert!(!point.is_zero);
+        PointTarget {
+            x: self.constant_quintic(point.x),
+            y: self.constant_quintic(point.y),
+        }
+    }
+
+    fn connect_points(&mut self, a: &PointTarget, b: &PointTarget) {
+        self.connect_quintic(a.x, b.x);
+        self.connect_quintic(a.y, b.y);
+    }
+
+    fn assert_on_curve(&mut self, p: &PointTarget) {
+        let a = self.constant_quintic(curve_a());
+        let b = self.constant_quintic(curve_b());
+        let x_squared = self.mul_quintic(p.x, p.x);
+        let ax = self.mul_quintic(a, p.x);
+        let sum = self.add_quintic(x_squared, ax);
+        let sum = self.add_quintic(sum, b);
+        let rhs = self.mul_quintic(p.x, sum);
+        let lhs = self.mul_quintic(p.y, p.y);
+        self.connect_quintic(lhs, rhs);
+    }
+
+    fn assert_in_subgroup(&mut self, p: &PointTarget) {
+        self.assert_on_curve(p);
+        // Euler's criterion, which also rules out N with its zero x
+        let character = self.exp_quintic(p.x, &euler_exponent());
+        let one = self.constant_quintic(GFp5::ONE);
+        self.connect_quintic(character, one);
+    }
+
+    fn add_points(&mut self, a: &PointTarget, b: &PointTarget) -> PointTarget {
+        let dy = self.sub_quintic(b.y, a.y);
+        let dx = self.sub_quintic(b.x, a.x);
+        let dx_inverse = self.inverse_quintic(dx);
+        let lambda = self.mul_quintic(dy, dx_inverse);
+        line_through(self, lambda, a, b.x)
+    }
+
+    fn double_point(&mut self, p: &PointTarget) -> PointTarget {
+        let x_squared = self.mul_quintic(p.x, p.x);
+        let three_x_squared = self.scalar_mul_quintic(F::from_canonical_u64(3), x_squared);
+        let a = self.constant_quintic(curve_a().double());
+        let two_ax = self.mul_quintic(a, p.x);
+        let b = self.constant_quintic(curve_b());
+        let numerator = self.add_quintic(three_x_squared, two_ax);
+        let numerator = self.add_quintic(numerator, b);
+        let two_y = self.scalar_mul_quintic(F::TWO, p.y);
+        let two_y_inverse = self.inverse_quintic(two_y);
+        let lambda = self.mul_quintic(numerator, two_y_inverse);
+        line_through(self, lambda, p, p.x)
+    }
+
+    fn select_point(&mut self, b: BoolTarget, x: &PointTarget, y: &PointTarget) -> PointTarget {
+        PointTarget {
+            x: self.select_quintic(b, x.x, y.x),
+            y: self.select_quintic(b, x.y, y.y),
+        }
+    }
+
+    fn generator_mul(&mut self, scalar_bits: &[BoolTarget]) -> PointTarget {
+        let offset = EcGFp5Point::offset();
+        let mut acc = self.constant_point(&offset);
+        let mut power = EcGFp5Point::generator();
+        for bit in scalar_bits {
+            let power_target = self.constant_point(&power);
+            let sum = self.add_points(&acc, &power_target);
+            acc = self.select_point(*bit, &sum, &acc);
+            power = power.double();
+        }
+        let correction = self.constant_point(&offset.neg());
+        self.add_points(&acc, &correction)
+    }
+
+    fn point_mul(&mut self, p: &PointTarget, scalar_bits: &[BoolTarget]) -> PointTarget {
+        let mut offset = EcGFp5Point::offset();
+        let mut acc = self.constant_point(&offset);
+        for bit in scalar_bits.iter().rev() {
+            acc = self.double_point(&acc);
+            offset = offset.double();
+            let sum = self.add_points(&acc, p);
+            acc = self.select_point(*bit, &sum, &acc);
+        }
+        let correction = self.constant_point(&offset.neg());
+        self.add_points(&acc, &correction)
+    }
+}
+
+/// Third intersection of the line with slope `lambda` through `p`, negated.
+fn line_through(
+    builder: &mut CircuitBuilder<F, D>,
+    lambda: QuinticTarget,
+    p: &PointTarget,
+    other_x: QuinticTarget,
+) -> PointTarget {
+    let a = builder.constant_quintic(curve_a());
+    let lambda_squared = builder.mul_quintic(lambda, lambda);
+    let x = builder.sub_quintic(lambda_squared, a);
+    let x = builder.sub_quintic(x, p.x);
+    let x = builder.sub_quintic(x, other_x);
+    let dx = builder.sub_quintic(p.x, x);
+    let y = builder.mul_quintic(lambda, dx);
+    let y = builder.sub_quintic(y, p.y);
+    PointTarget { x, y }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generator_has_prime_order() {
+        let generator = EcGFp5Point::generator();
+        assert!(generator.is_on_curve());
+        assert!(EcGFp5Point::offset().is_on_curve());
+        assert_eq!(generator.mul(&scalar_order()), EcGFp5Point::ZERO);
+    }
+
+    #[test]
+    fn test_subgroup_points_have_square_x() {
+        let torsion = EcGFp5Point {
+            x: GFp5::ZERO,
+            y: GFp5::ZERO,
+            is_zero: false,
+        };
+        assert!(torsion.is_on_curve());
+        assert!(!curve_b().exp_biguint(&euler_exponent()).is_one());
+
+        let point = EcGFp5Point::generator().mul(&BigUint::from(12345u32));
+        assert!(point.is_in_subgroup());
+        assert!(point.x.exp_biguint(&euler_exponent()).is_one());
+
+        let outside = point.add(&torsion);
+        assert!(outside.is_on_curve());
+        assert!(!outside.is_in_subgroup());
+        assert!(!outside.x.exp_biguint(&euler_exponent()).is_one());
+        assert!(!torsion.is_in_subgroup());
+    }
+}
@@ -0,0 +1,325 @@
+use std::str::FromStr;
+
+use anyhow::Result;
+use plonky2::hash::hash_types::HashOut;
+use plonky2::iop::target::Target;
+use plonky2::iop::witness::PartialWitness;
+use plonky2::plonk::circuit_builder::CircuitBuilder;
+use plonky2_ecdsa::curve::ecdsa::{ECDSAPublicKey, ECDSASignature};
+use plonky2_ecdsa::curve::secp256k1::Secp256K1;
+use serde::{Deserialize, Serialize};
+
+use crate::commitment::CommitmentScheme;
+use crate::file::file_hash;
+use crate::pixel::PixelLayout;
+use crate::{D, F};
+
+use self::ecdsa::{
+    build_ecdsa_circuit, encode_public_key, secret_key_from_hex, sign_hash, EcdsaTargets,
+    COORDINATE_LIMBS,
+};
+use self::p256::P256;
+use self::schnorr::{
+    build_schnorr_circuit, SchnorrPublicKey, SchnorrSecretKey, SchnorrSignature, SchnorrTargets,
+    PUBLIC_KEY_LEN,
+};
+
+pub mod ecdsa;
+pub mod ecgfp5;
+pub mod p256;
+pub mod schnorr;
+
+// In the signed original mode the hash of the original stays private: the
+// aggregation circuit verifies a signature over it and publishes the public
+// key of the signer instead.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SignatureScheme {
+    EcdsaSecp256k1,
+    /// The curve of attested cameras and phone secure enclaves.
+    EcdsaP256,
+    /// Cheap to verify in-circuit, for devices we control.
+    SchnorrEcGFp5,
+}
+
+impl SignatureScheme {
+    /// Number of public inputs the public key takes in place of the original
+    /// hash.
+    pub fn public_key_len(&self) -> usize {
+        match self {
+            SignatureScheme::EcdsaSecp256k1 | SignatureScheme::EcdsaP256 => 2 * COORDINATE_LIMBS,
+            SignatureScheme::SchnorrEcGFp5 => PUBLIC_KEY_LEN,
+        }
+    }
+
+    /// Encodes the public key public inputs the way devices publish their key.
+    pub(crate) fn encode_public_key(&self, public_inputs: &[F]) -> Vec<u8> {
+        match self {
+            SignatureScheme::EcdsaSecp256k1 | SignatureScheme::EcdsaP256 => {
+                encode_public_key(public_inputs)
+            }
+            SignatureScheme::SchnorrEcGFp5 => schnorr::encode_public_key(public_inputs),
+        }
+    }
+}
+
+/// Parses `secp256k1`, `p256` or `schnorr`.
+impl FromStr for SignatureScheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "secp256k1" => Ok(SignatureScheme::EcdsaSecp256k1),
+            "p256" => Ok(SignatureScheme::EcdsaP256),
+            "schnorr" => Ok(SignatureScheme::SchnorrEcGFp5),
+            _ => Err(format!("unknown signature scheme {}", s)),
+        }
+    }
+}
+
+/// Signature of a device over the hash of the original.
+#[derive(Clone, Debug)]
+pub enum OriginalSignature {
+    EcdsaSecp256k1 {
+        public_key: ECDSAPublicKey<Secp256K1>,
+        signature: ECDSASignature<Secp256K1>,
+    },
+    EcdsaP256 {
+        public_key: ECDSAPublicKey<P256>,
+        signature: ECDSASignature<P256>,
+    },
+    SchnorrEcGFp5 {
+        public_key: SchnorrPublicKey,
+        signature: SchnorrSignature,
+    },
+}
+
+impl OriginalSignature {
+    pub fn scheme(&self) -> SignatureScheme {
+        match self {
+            OriginalSignature::EcdsaSecp256k1 { .. } => SignatureScheme::EcdsaSecp256k1,
+            OriginalSignature::EcdsaP256 { .. } => SignatureScheme::EcdsaP256,
+            OriginalSignature::SchnorrEcGFp5 { .. } => SignatureScheme::SchnorrEcGFp5,
+        }
+    }
+}
+
+/// The hash of the original a device signs, the commitment the proof
+/// computes.
+pub fn original_hash<const L: usize>(
+    original: &[u8],
+    layout: PixelLayout,
+    commitment: CommitmentScheme,
+) -> HashOut<F> {
+    commitment.commit_elements::<L>(&layout.pack::<F>(original), layout)
+}
+
+/// Natively signs the original the way an attested device would, the secret
+/// key is a hex scalar of the scheme's curve.
+pub fn sign_original<const L: usize>(
+    original: &[u8],
+    layout: PixelLayout,
+    commitment: CommitmentScheme,
+    scheme: SignatureScheme,
+    secret_key_hex: &str,
+) -> Result<OriginalSignature> {
+    sign(
+        &original_hash::<L>(original, layout, commitment),
+        scheme,
+        secret_key_hex,
+    )
+}
+
+/// Natively signs the bytes of an image file, see `file::file_hash`.
+pub fn sign_file(
+    file: &[u8],
+    scheme: SignatureScheme,
+    secret_key_hex: &str,
+) -> Result<OriginalSignature> {
+    sign(&file_hash(file), scheme, secret_key_hex)
+}
+
+fn sign(
+    hash: &HashOut<F>,
+    scheme: SignatureScheme,
+    secret_key_hex: &str,
+) -> Result<OriginalSignature> {
+    Ok(match scheme {
+        SignatureScheme::EcdsaSecp256k1 => {
+            let (public_key, signature) =
+                sign_hash::<Secp256K1>(hash, &secret_key_from_hex(secret_key_hex)?);
+            OriginalSignature::EcdsaSecp256k1 {
+                public_key,
+                signature,
+            }
+        }
+        SignatureScheme::EcdsaP256 => {
+            let (public_key, signature) =
+                sign_hash::<P256>(hash, &secret_key_from_hex(secret_key_hex)?);
+            OriginalSignature::EcdsaP256 {
+                public_key,
+                signature,
+            }
+        }
+        SignatureScheme::SchnorrEcGFp5 => {
+            let secret_key = SchnorrSecretKey::from_hex(secret_key_hex)?;
+            OriginalSignature::SchnorrEcGFp5 {
+                public_key: secret_key.public_key(),
+                signature: secret_key.sign(hash),
+            }
+        }
+    })
+}
+
+pub(crate) enum SignatureTargets {
+    EcdsaSecp256k1(EcdsaTargets<Secp256K1>),
+    EcdsaP256(EcdsaTargets<P256>),
+    SchnorrEcGFp5(SchnorrTargets),
+}
+
+/// Verifies the signature over `original_hash` and registers the public key
+/// as public inputs.
+pub(crate) fn build_signature_circuit(
+    builder: &mut CircuitBuilder<F, D>,
+    scheme: SignatureScheme,
+    original_hash: &[Target],
+) -> SignatureTargets {
+    match scheme {
+        SignatureScheme::EcdsaSecp256k1 => {
+            SignatureTargets::EcdsaSecp256k1(build_ecdsa_circuit(builder, original_hash))
+        }
+        SignatureScheme::EcdsaP256 => {
+            SignatureTargets::EcdsaP256(build_ecdsa_circuit(builder, original_hash))
+        }
+        SignatureScheme::SchnorrEcGFp5 => {
+            SignatureTargets::SchnorrEcGFp5(build_schnorr_circuit(builder, original_hash))
+        }
+    }
+}
+
+impl SignatureTargets {
+    pub(crate) fn scheme(&self) -> SignatureScheme {
+        match self {
+            SignatureTargets::EcdsaSecp256k1(_) => SignatureScheme::EcdsaSecp256k1,
+            SignatureTargets::EcdsaP256(_) => SignatureScheme::EcdsaP256,
+            SignatureTargets::SchnorrEcGFp5(_) => SignatureScheme::SchnorrEcGFp5,
+        }
+    }
+
+    /// Panics if the signature was made with another scheme.
+    pub(crate) fn set_witness(&self, pw: &mut PartialWitness<F>, original: &OriginalSignature) {
+        match (self, original) {
+            (
+                SignatureTargets::EcdsaSecp256k1(targets),
+                OriginalSignature::EcdsaSecp256k1 {
+                    public_key,
+                    signature,
+                },
+            ) => targets.set_witness(pw, public_key, signature),
+            (
+                SignatureTargets::EcdsaP256(targets),
+                OriginalSignature::EcdsaP256 {
+                    public_key,
+                    signature,
+                },
+            ) => targets.set_witness(pw, public_key, signature),
+            (
+                SignatureTargets::SchnorrEcGFp5(targets),
+                OriginalSignature::SchnorrEcGFp5 {
+                    public_key,
+                    signature,
+                },
+            ) => targets.set_witness(pw, public_key, signature),
+            _ => panic!(
+                "the circuit expects a {:?} signature, got {:?}",
+                self.scheme(),
+                original.scheme()
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::TransformationCircuitBuilder;
+    use crate::pixel::PixelFormat;
+    use crate::testing::{prove_and_verify, proves, random_bytes, threshold, HEIGHT, L, WIDTH};
+
+    /// Whether a threshold of `original` proves with its signature verified
+    /// in-circuit.
+    fn signed_proves(signature: &OriginalSignature, original: &[u8]) -> bool {
+        let (transformation, edited) = threshold(original);
+        let builder =
+            TransformationCircuitBuilder::<L>::new(original.len(), Box::new(transformation))
+                .with_signed_original(signature.scheme());
+        proves(|| prove_and_verify(builder, original, &edited, Some(signature), None))
+    }
+
+    /// `signature` claimed to be by the signer of `other`.
+    fn with_key_of(signature: OriginalSignature, other: OriginalSignature) -> OriginalSignature {
+        match (signature, other) {
+            (
+                OriginalSignature::EcdsaSecp256k1 { signature, .. },
+                OriginalSignature::EcdsaSecp256k1 { public_key, .. },
+            ) => OriginalSignature::EcdsaSecp256k1 {
+                public_key,
+                signature,
+            },
+            (
+                OriginalSignature::EcdsaP256 { signature, .. },
+                OriginalSignature::EcdsaP256 { public_key, .. },
+            ) => OriginalSignature::EcdsaP256 {
+                public_key,
+                signature,
+            },
+            (
+                OriginalSignature::SchnorrEcGFp5 { signature, .. },
+                OriginalSignature::SchnorrEcGFp5 { public_key, .. },
+            ) => OriginalSignature::SchnorrEcGFp5 {
+                public_key,
+                signature,
+            },
+            _ => panic!("the signatures have different schemes"),
+        }
+    }
+
+    fn check_signed_original(scheme: SignatureScheme) {
+        let original = random_bytes((WIDTH * HEIGHT * 4) as usize);
+        let sign = |image: &[u8], secret_key_hex| {
+            sign_original::<L>(
+                image,
+                PixelFormat::Rgba8.into(),
+                CommitmentScheme::Sponge,
+                scheme,
+                secret_key_hex,
+            )
+            .unwrap()
+        };
+        let signature = sign(&original, "c0ffee");
+        assert!(signed_proves(&signature, &original));
+
+        // A signature over another original
+        let other = random_bytes(original.len());
+        assert!(!signed_proves(&sign(&other, "c0ffee"), &original));
+
+        // A signature claimed to be by another device
+        let forged = with_key_of(signature, sign(&original, "beef"));
+        assert!(!signed_proves(&forged, &original));
+    }
+
+    #[test]
+    fn test_secp256k1_signed_original_proves() {
+        check_signed_original(SignatureScheme::EcdsaSecp256k1);
+    }
+
+    #[test]
+    fn test_p256_signed_original_proves() {
+        check_signed_original(SignatureScheme::EcdsaP256);
+    }
+
+    #[test]
+    fn test_schnorr_signed_original_proves() {
+        check_signed_original(SignatureScheme::SchnorrEcGFp5);
+    }
+}
@@ -0,0 +1,334 @@
+use core::fmt::{self, Debug, Display, Formatter};
+use core::hash::{Hash, Hasher};
+use core::iter::{Product, Sum};
+use core::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign};
+
+use itertools::Itertools;
+use num::bigint::{BigUint, RandBigInt};
+use num::{Integer, One};
+use plonky2_ecdsa::curve::curve_types::{AffinePoint, Curve};
+use plonky2_field::types::{Field, PrimeField, Sample};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+// NIST P-256 (secp256r1), the curve attested cameras and phone secure enclaves
+// sign with. plonky2_field only ships the secp256k1 fields, these follow their
+// representation: four little-endian u64 limbs, arithmetic over BigUint.
+
+fn biguint_from_array(arr: [u64; 4]) -> BigUint {
+    BigUint::from_slice(&[
+        arr[0] as u32,
+        (arr[0] >> 32) as u32,
+        arr[1] as u32,
+        (arr[1] >> 32) as u32,
+        arr[2] as u32,
+        (arr[2] >> 32) as u32,
+        arr[3] as u32,
+        (arr[3] >> 32) as u32,
+    ])
+}
+
+macro_rules! p256_field {
+    (
+        $(#[$attr:meta])*
+        $name:ident,
+        order: $order:expr,
+        neg_one: $neg_one:expr,
+        two_adicity: $two_adicity:expr,
+        generator: $generator:expr,
+        power_of_two_generator: $power_of_two_generator:expr $(,)?
+    ) => {
+        $(#[$attr])*
+        #[derive(Copy, Clone, Serialize, Deserialize)]
+        pub struct $name(pub [u64; 4]);
+
+        impl Default for $name {
+            fn default() -> Self {
+                Self::ZERO
+            }
+        }
+
+        impl PartialEq for $name {
+            fn eq(&self, other: &Self) -> bool {
+                self.to_canonical_biguint() == other.to_canonical_biguint()
+            }
+        }
+
+        impl Eq for $name {}
+
+        impl Hash for $name {
+            fn hash<H: Hasher>(&self, state: &mut H) {
+                self.to_canonical_biguint().hash(state)
+            }
+        }
+
+        impl Display for $name {
+            fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+                Display::fmt(&self.to_canonical_biguint(), f)
+            }
+        }
+
+        impl Debug for $name {
+            fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+                Debug::fmt(&self.to_canonical_biguint(), f)
+            }
+        }
+
+        impl Sample for $name {
+            fn sample<R: Rng + ?Sized>(rng: &mut R) -> Self {
+                Self::from_noncanonical_biguint(rng.gen_biguint_below(&Self::order()))
+            }
+        }
+
+        impl Field for $name {
+            const ZERO: Self = Self([0; 4]);
+            const ONE: Self = Self([1, 0, 0, 0]);
+            const TWO: Self = Self([2, 0, 0, 0]);
+            const NEG_ONE: Self = Self($neg_one);
+
+            const TWO_ADICITY: usize = $two_adicity;
+            const CHARACTERISTIC_TWO_ADICITY: usize = Self::TWO_ADICITY;
+
+            const MULTIPLICATIVE_GROUP_GENERATOR: Self = Self([$generator, 0, 0, 0]);
+            const POWER_OF_TWO_GENERATOR: Self = Self($power_of_two_generator);
+
+            const BITS: usize = 256;
+
+            fn order() -> BigUint {
+                biguint_from_array($order)
+            }
+
+            fn characteristic() -> BigUint {
+                Self::order()
+            }
+
+            fn try_inverse(&self) -> Option<Self> {
+                if self.is_zero() {
+                    return None;
+                }
+                // Fermat's little theorem
+                Some(self.exp_biguint(&(Self::order() - BigUint::one() - BigUint::one())))
+            }
+
+            fn from_noncanonical_biguint(val: BigUint) -> Self {
+                Self(
+                    (val % Self::order())
+                        .to_u64_digits()
+                        .into_iter()
+                        .pad_using(4, |_| 0)
+                        .collect::<Vec<_>>()[..]
+                        .try_into()
+                        .expect("error converting to u64 array"),
+                )
+            }
+
+            fn from_canonical_u64(n: u64) -> Self {
+                Self([n, 0, 0, 0])
+            }
+
+            fn from_noncanonical_u128(n: u128) -> Self {
+                Self([n as u64, (n >> 64) as u64, 0, 0])
+            }
+
+            fn from_noncanonical_u96(n: (u64, u32)) -> Self {
+                Self([n.0, n.1 as u64, 0, 0])
+            }
+        }
+
+        impl PrimeField for $name {
+            fn to_canonical_biguint(&self) -> BigUint {
+                biguint_from_array(self.0).mod_floor(&Self::order())
+            }
+        }
+
+        impl Neg for $name {
+            type Output = Self;
+
+            fn neg(self) -> Self {
+                if self.is_zero() {
+                    Self::ZERO
+                } else {
+                    Self::from_noncanonical_biguint(Self::order() - self.to_canonical_biguint())
+                }
+            }
+        }
+
+        impl Add for $name {
+            type Output = Self;
+
+            fn add(self, rhs: Self) -> Self {
+                Self::from_noncanonical_biguint(
+                    self.to_canonical_biguint() + rhs.to_canonical_biguint(),
+                )
+            }
+        }
+
+        impl AddAssign for $name {
+            fn add_assign(&mut self, rhs: Self) {
+                *self = *self + rhs;
+            }
+        }
+
+        impl Sum for $name {
+            fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+                iter.fold(Self::ZERO, |acc, x| acc + x)
+            }
+        }
+
+        impl Sub for $name {
+            type Output = Self;
+
+            #[allow(clippy::suspicious_arithmetic_impl)]
+            fn sub(self, rhs: Self) -> Self {
+                self + -rhs
+            }
+        }
+
+        impl SubAssign for $name {
+            fn sub_assign(&mut self, rhs: Self) {
+                *self = *self - rhs;
+            }
+        }
+
+        impl Mul for $name {
+            type Output = Self;
+
+            fn mul(self, rhs: Self) -> Self {
+                Self::from_noncanonical_biguint(
+                    self.to_canonical_biguint() * rhs.to_canonical_biguint(),
+                )
+            }
+        }
+
+        impl MulAssign for $name {
+            fn mul_assign(&mut self, rhs: Self) {
+                *self = *self * rhs;
+            }
+        }
+
+        impl Product for $name {
+            fn product<I: Iterator<Item = Self>>(iter: I) -> Self {
+                iter.reduce(|acc, x| acc * x).unwrap_or(Self::ONE)
+            }
+        }
+
+        impl Div for $name {
+            type Output = Self;
+
+            #[allow(clippy::suspicious_arithmetic_impl)]
+            fn div(self, rhs: Self) -> Self {
+                self * rhs.inverse()
+            }
+        }
+
+        impl DivAssign for $name {
+            fn div_assign(&mut self, rhs: Self) {
+                *self = *self / rhs;
+            }
+        }
+    };
+}
+
+p256_field!(
+    /// The base field of P-256.
+    P256Base,
+    order: [
+        0xffffffffffffffff,
+        0x00000000ffffffff,
+        0x0000000000000000,
+        0xffffffff00000001,
+    ],
+    neg_one: [
+        0xfffffffffffffffe,
+        0x00000000ffffffff,
+        0x0000000000000000,
+        0xffffffff00000001,
+    ],
+    two_adicity: 1,
+    generator: 6,
+    // 6 is a quadratic non-residue, so its power of order two is -1
+    power_of_two_generator: [
+        0xfffffffffffffffe,
+        0x00000000ffffffff,
+        0x0000000000000000,
+        0xffffffff00000001,
+    ],
+);
+
+p256_field!(
+    /// The scalar field of P-256.
+    P256Scalar,
+    order: [
+        0xf3b9cac2fc632551,
+        0xbce6faada7179e84,
+        0xffffffffffffffff,
+        0xffffffff00000000,
+    ],
+    neg_one: [
+        0xf3b9cac2fc632550,
+        0xbce6faada7179e84,
+        0xffffffffffffffff,
+        0xffffffff00000000,
+    ],
+    two_adicity: 4,
+    generator: 7,
+    power_of_two_generator: [
+        0x0592d7fbb41e6602,
+        0x1546cad004378daf,
+        0xba807ace842a3dfc,
+        0xffc97f062a770992,
+    ],
+);
+
+#[derive(Debug, Copy, Clone, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct P256;
+
+impl Curve for P256 {
+    type BaseField = P256Base;
+    type ScalarField = P256Scalar;
+
+    const A: P256Base = P256Base([
+        0xfffffffffffffffc,
+        0x00000000ffffffff,
+        0x0000000000000000,
+        0xffffffff00000001,
+    ]);
+    const B: P256Base = P256Base([
+        0x3bce3c3e27d2604b,
+        0x651d06b0cc53b0f6,
+        0xb3ebbd55769886bc,
+        0x5ac635d8aa3a93e7,
+    ]);
+    const GENERATOR_AFFINE: AffinePoint<Self> = AffinePoint {
+        x: P256Base([
+            0xf4a13945d898c296,
+            0x77037d812deb33a0,
+            0xf8bce6e563a440f2,
+            0x6b17d1f2e12c4247,
+        ]),
+        y: P256Base([
+            0xcbb6406837bf51f5,
+            0x2bce33576b315ece,
+            0x8ee7eb4a7c0f9e16,
+            0x4fe342e2fe1a7f9b,
+        ]),
+        zero: false,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use plonky2_ecdsa::curve::curve_types::CurveScalar;
+
+    use super::*;
+
+    #[test]
+    fn test_generator_has_curve_order() {
+        assert!(P256::GENERATOR_AFFINE.is_valid());
+        let order_minus_one = CurveScalar::<P256>(P256Scalar::NEG_ONE) * P256::GENERATOR_PROJECTIVE;
+        assert_eq!(
+            (order_minus_one + P256::GENERATOR_PROJECTIVE).to_affine(),
+            AffinePoint::ZERO
+        );
+    }
+}
@@ -0,0 +1,313 @@
+use anyhow::{anyhow, Result};
+use num::bigint::RandBigInt;
+use num::BigUint;
+use plonky2::hash::hash_types::{HashOut, NUM_HASH_OUT_ELTS};
+use plonky2::hash::poseidon::PoseidonHash;
+use plonky2::iop::target::{BoolTarget, Target};
+use plonky2::iop::witness::{PartialWitness, WitnessWrite};
+use plonky2::plonk::circuit_builder::CircuitBuilder;
+use plonky2::plonk::config::Hasher;
+use plonky2::util::serialization::Write;
+use plonky2_field::types::{Field, PrimeField64};
+use rand::Rng;
+
+use crate::{D, F};
+
+use super::ecgfp5::{scalar_order, CircuitBuilderEcGFp5, EcGFp5Point, PointTarget, SCALAR_BITS};
+
+// Schnorr signatures over EcGFp5 with a Poseidon challenge: a signature is
+// `(R, s)` with `s * G = R + e * PK` for `e = Poseidon(R, PK, message)` read as
+// a 256-bit integer. Everything is native field arithmetic, so verifying one
+// costs a fraction of a foreign-field ECDSA verification. The curve has points
+// of order 2n, so the key and `R` are checked to lie in the subgroup of order
+// n, and `s` to be below n, which leaves exactly one valid signature per nonce.
+
+const CHALLENGE_BITS: usize = NUM_HASH_OUT_ELTS * 64;
+
+pub const PUBLIC_KEY_LEN: usize = 10;
+
+#[derive(Clone, Debug)]
+pub struct SchnorrSecretKey(pub BigUint);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SchnorrPublicKey(pub EcGFp5Point);
+
+#[derive(Clone, Debug)]
+pub struct SchnorrSignature {
+    pub r: EcGFp5Point,
+    pub s: BigUint,
+}
+
+fn challenge(r: &EcGFp5Point, public_key: &SchnorrPublicKey, message: &HashOut<F>) -> BigUint {
+    let mut inputs = r.coordinates();
+    inputs.extend(public_key.0.coordinates());
+    inputs.extend(message.elements);
+    let hash = PoseidonHash::hash_no_pad(&inputs);
+    let limbs: Vec<u32> = hash
+        .elements
+        .iter()
+        .flat_map(|element| {
+            let value = element.to_canonical_u64();
+            [value as u32, (value >> 32) as u32]
+        })
+        .collect();
+    BigUint::new(limbs)
+}
+
+impl SchnorrSecretKey {
+    pub fn generate<R: Rng>(rng: &mut R) -> Self {
+        Self(rng.gen_biguint_below(&scalar_order()))
+    }
+
+    pub fn from_hex(hex: &str) -> Result<Self> {
+        let value = BigUint::parse_bytes(hex.trim().as_bytes(), 16)
+            .ok_or_else(|| anyhow!("the secret key is not a hex number"))?;
+        Ok(Self(value % scalar_order()))
+    }
+
+    pub fn public_key(&self) -> SchnorrPublicKey {
+        SchnorrPublicKey(EcGFp5Point::generator().mul(&self.0))
+    }
+
+    pub fn sign(&self, message: &HashOut<F>) -> SchnorrSignature {
+        let order = scalar_order();
+        let nonce = rand::thread_rng().gen_biguint_below(&order);
+        let r = EcGFp5Point::generator().mul(&nonce);
+        let e = challenge(&r, &self.public_key(), message);
+        SchnorrSignature {
+            r,
+            s: (nonce + e * &self.0) % order,
+        }
+    }
+}
+
+impl SchnorrPublicKey {
+    pub fn verify(&self, message: &HashOut<F>, signature: &SchnorrSignature) -> bool {
+        let e = challenge(&signature.r, self, message);
+        self.0.is_in_subgroup()
+            && signature.r.is_in_subgroup()
+            && signature.s < scalar_order()
+            && EcGFp5Point::generator().mul(&signature.s) == signature.r.add(&self.0.mul(&e))
+    }
+
+    /// Encodes the key the way `TransformationProof::signer_public_key` does.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encode_public_key(&self.0.coordinates())
+    }
+}
+
+pub(crate) fn encode_public_key(public_inputs: &[F]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.write_field_vec(public_inputs).unwrap();
+    bytes
+}
+
+pub(crate) struct SchnorrTargets {
+    public_key: PointTarget,
+    r: PointTarget,
+    s: Vec<BoolTarget>,
+}
+
+/// Verifies a signature over `message` and registers the public key as public
+/// inputs.
+pub(crate) fn build_schnorr_circuit(
+    builder: &mut CircuitBuilder<F, D>,
+    message: &[Target],
+) -> SchnorrTargets {
+    let public_key = builder.add_virtual_point_target();
+    builder.assert_in_subgroup(&public_key);
+    builder.register_public_inputs(&public_key.targets());
+    let r = builder.add_virtual_point_target();
+    builder.assert_in_subgroup(&r);
+    let s: Vec<BoolTarget> = (0..SCALAR_BITS)
+        .map(|_| builder.add_virtual_bool_target_safe())
+        .collect();
+    assert_less_than(builder, &s, &scalar_order());
+
+    let inputs: Vec<Target> = r
+        .targets()
+        .into_iter()
+        .chain(public_key.targets())
+        .chain(message.iter().copied())
+        .collect();
+    let hash = builder.hash_n_to_hash_no_pad::<PoseidonHash>(inputs);
+    let e: Vec<BoolTarget> = hash
+        .elements
+        .iter()
+        .flat_map(|element| split_le_canonical(builder, *element))
+        .collect();
+    assert_eq!(e.len(), CHALLENGE_BITS);
+
+    let lhs = builder.generator_mul(&s);
+    let e_public_key = builder.point_mul(&public_key, &e);
+    let rhs = builder.add_points(&r, &e_public_key);
+    builder.connect_points(&lhs, &rhs);
+
+    SchnorrTargets { public_key, r, s }
+}
+
+/// Checks that the little-endian `bits` are below the constant `bound`.
+fn assert_less_than(builder: &mut CircuitBuilder<F, D>, bits: &[BoolTarget], bound: &BigUint) {
+    assert_eq!(bits.len() as u64, bound.bits());
+    // Whether the bits read so far are below the same bits of the bound
+    let mut less = builder._false();
+    for (i, bit) in bits.iter().enumerate() {
+        less = if bound.bit(i as u64) {
+            let not_less = builder.not(less);
+            let not_below = builder.and(*bit, not_less);
+            builder.not(not_below)
+        } else {
+            let not_bit = builder.not(*bit);
+            builder.and(not_bit, less)
+        };
+    }
+    builder.assert_one(less.target);
+}
+
+/// The 64 bits of the canonical integer of `x`. Integers up to `2^32 - 2`
+/// also fit 64 bits when the Goldilocks prime `2^64 - 2^32 + 1` is added, so
+/// the upper half may only have all bits set when the lower half is zero.
+fn split_le_canonical(builder: &mut CircuitBuilder<F, D>, x: Target) -> Vec<BoolTarget> {
+    let bits = builder.split_le(x, 64);
+    let low = builder.le_sum(bits[..32].iter());
+    let high = builder.le_sum(bits[32..].iter());
+    let all_set = builder.constant(F::from_canonical_u32(u32::MAX));
+    let high_all_set = builder.is_equal(high, all_set);
+    let low_if_all_set = builder.mul(high_all_set.target, low);
+    builder.assert_zero(low_if_all_set);
+    bits
+}
+
+impl SchnorrTargets {
+    pub(crate) fn set_witness(
+        &self,
+        pw: &mut PartialWitness<F>,
+        public_key: &SchnorrPublicKey,
+        signature: &SchnorrSignature,
+    ) {
+        for (point, value) in [(&self.public_key, &public_key.0), (&self.r, &signature.r)] {
+            for (target, coordinate) in point.targets().into_iter().zip(value.coordinates()) {
+                pw.set_target(target, coordinate);
+            }
+        }
+        for (i, bit) in self.s.iter().enumerate() {
+            pw.set_bool_target(*bit, signature.s.bit(i as u64));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use plonky2::plonk::circuit_data::CircuitConfig;
+
+    use super::*;
+    use crate::signature::ecgfp5::GFp5;
+    use crate::testing::proves;
+    use crate::C;
+
+    fn circuit_verifies(
+        public_key: &SchnorrPublicKey,
+        message: &HashOut<F>,
+        signature: &SchnorrSignature,
+    ) -> bool {
+        let mut builder = CircuitBuilder::<F, D>::new(CircuitConfig::standard_recursion_config());
+        let message_target = builder.add_virtual_hash();
+        let targets = build_schnorr_circuit(&mut builder, &message_target.elements);
+        let circuit = builder.build::<C>();
+
+        let mut pw = PartialWitness::new();
+        pw.set_hash_target(message_target, *message);
+        targets.set_witness(&mut pw, public_key, signature);
+        proves(|| circuit.verify(circuit.prove(pw)?))
+    }
+
+    #[test]
+    fn test_circuit_verifies_signature() {
+        let secret_key = SchnorrSecretKey::generate(&mut rand::thread_rng());
+        let public_key = secret_key.public_key();
+        let message = HashOut {
+            elements: [F::ONE, F::TWO, F::ZERO, F::NEG_ONE],
+        };
+        let mut signature = secret_key.sign(&message);
+        assert!(circuit_verifies(&public_key, &message, &signature));
+
+        signature.s = (signature.s + 1u32) % scalar_order();
+        assert!(!public_key.verify(&message, &signature));
+        assert!(!circuit_verifies(&public_key, &message, &signature));
+    }
+
+    #[test]
+    fn test_rejects_key_outside_subgroup() {
+        let secret_key = SchnorrSecretKey::generate(&mut rand::thread_rng());
+        let message = HashOut {
+            elements: [F::TWO, F::ONE, F::NEG_ONE, F::ZERO],
+        };
+        // Adding the point of order 2 to the key goes unnoticed by the
+        // verification equation whenever the challenge is even
+        let torsion = EcGFp5Point {
+            x: GFp5::ZERO,
+            y: GFp5::ZERO,
+            is_zero: false,
+        };
+        let forged_key = SchnorrPublicKey(secret_key.public_key().0.add(&torsion));
+        let order = scalar_order();
+        let signature = loop {
+            let nonce = rand::thread_rng().gen_biguint_below(&order);
+            let r = EcGFp5Point::generator().mul(&nonce);
+            let e = challenge(&r, &forged_key, &message);
+            if !e.bit(0) {
+                break SchnorrSignature {
+                    r,
+                    s: (nonce + e * &secret_key.0) % &order,
+                };
+            }
+        };
+        let e = challenge(&signature.r, &forged_key, &message);
+        assert_eq!(
+            EcGFp5Point::generator().mul(&signature.s),
+            signature.r.add(&forged_key.0.mul(&e))
+        );
+
+        assert!(!forged_key.verify(&message, &signature));
+        assert!(!circuit_verifies(&forged_key, &message, &signature));
+    }
+
+    #[test]
+    fn test_s_has_to_be_below_order() {
+        let mut builder = CircuitBuilder::<F, D>::new(CircuitConfig::standard_recursion_config());
+        let bits: Vec<BoolTarget> = (0..SCALAR_BITS)
+            .map(|_| builder.add_virtual_bool_target_safe())
+            .collect();
+        assert_less_than(&mut builder, &bits, &scalar_order());
+        let circuit = builder.build::<C>();
+
+        let below = |value: BigUint| {
+            let mut pw = PartialWitness::new();
+            for (i, bit) in bits.iter().enumerate() {
+                pw.set_bool_target(*bit, value.bit(i as u64));
+            }
+            proves(|| circuit.verify(circuit.prove(pw)?))
+        };
+        let order = scalar_order();
+        assert!(below(BigUint::from(0u32)));
+        assert!(below(&order - 1u32));
+        assert!(!below(order.clone()));
+        // s + n for a small s still fits the bits
+        assert!(!below(&order + 5u32));
+    }
+
+    #[test]
+    fn test_sign_verify() {
+        let secret_key = SchnorrSecretKey::generate(&mut rand::thread_rng());
+        let message = HashOut {
+            elements: [F::ONE, F::TWO, F::ZERO, F::NEG_ONE],
+        };
+        let signature = secret_key.sign(&message);
+        assert!(secret_key.public_key().verify(&message, &signature));
+
+        let other = HashOut {
+            elements: [F::TWO, F::TWO, F::ZERO, F::NEG_ONE],
+        };
+        assert!(!secret_key.public_key().verify(&other, &signature));
+    }
+}
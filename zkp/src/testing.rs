@@ -0,0 +1,70 @@
+//! Helpers of the circuit tests. They prove images of a few pixels with short
+//! chunks, which keeps the circuits small enough to prove in a test.
+
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use anyhow::Result;
+use rand::Rng;
+use zkedit_transformations::tone::apply_threshold;
+use zkedit_transformations::Transformation;
+
+use crate::builder::TransformationCircuitBuilder;
+use crate::salt::Salt;
+use crate::signature::OriginalSignature;
+
+/// Elements per chunk, an RGBA8 image of `WIDTH` x `HEIGHT` spans two chunks.
+pub(crate) const L: usize = 16;
+pub(crate) const WIDTH: u32 = 8;
+pub(crate) const HEIGHT: u32 = 4;
+
+pub(crate) fn random_pixels(count: usize) -> Vec<[u8; 4]> {
+    let mut rng = rand::thread_rng();
+    (0..count).map(|_| rng.gen()).collect()
+}
+
+pub(crate) fn random_bytes(count: usize) -> Vec<u8> {
+    let mut rng = rand::thread_rng();
+    (0..count).map(|_| rng.gen()).collect()
+}
+
+/// A threshold and its result on the RGBA8 `original`, for tests about
+/// anything but the transformation.
+pub(crate) fn threshold(original: &[u8]) -> (Transformation, Vec<u8>) {
+    let edited = original
+        .chunks(4)
+        .flat_map(|pixel| apply_threshold(128, pixel.try_into().unwrap()))
+        .collect();
+    (Transformation::Threshold { level: 128 }, edited)
+}
+
+/// Whether `prove` makes a proof that verifies. The prover panics on some
+/// violated constraints instead of returning an error, that counts as a
+/// rejection as well.
+pub(crate) fn proves(prove: impl FnOnce() -> Result<()>) -> bool {
+    catch_unwind(AssertUnwindSafe(prove)).map_or(false, |result| result.is_ok())
+}
+
+/// Proves that `edited` is the transformation of `original` the builder was
+/// made with and verifies the proof.
+pub(crate) fn prove_and_verify(
+    builder: TransformationCircuitBuilder<L>,
+    original: &[u8],
+    edited: &[u8],
+    signature: Option<&OriginalSignature>,
+    salt: Option<&Salt>,
+) -> Result<()> {
+    let mut circuit = builder.build_curcuit();
+    let proof = circuit.prove(original, edited, signature, salt)?;
+    proof.verify(circuit.circuit)
+}
+
+/// Whether an RGBA8 `edited` proves to be `transformation` of `original`.
+pub(crate) fn transformation_proves(
+    transformation: Transformation,
+    original: &[[u8; 4]],
+    edited: &[[u8; 4]],
+) -> bool {
+    let (original, edited) = (original.concat(), edited.concat());
+    let builder = TransformationCircuitBuilder::new(original.len(), Box::new(transformation));
+    proves(|| prove_and_verify(builder, &original, &edited, None, None))
+}
@@ -0,0 +1,61 @@
+use plonky2::iop::target::Target;
+use plonky2::plonk::circuit_builder::CircuitBuilder;
+use plonky2_field::goldilocks_field::GoldilocksField;
+use zkedit_transformations::color::color_matrix_params_valid;
+
+use crate::pixel::PixelFormat;
+
+use super::util::{pack_pixel, round_shift_clamp, signed_constant, unpack_pixel};
+
+pub(crate) fn build_color_matrix_circuit(
+    builder: &mut CircuitBuilder<GoldilocksField, 2>,
+    original_chunk: &[Target],
+    edited_chunk: &[Target],
+    format: PixelFormat,
+    m: &[[i32; 3]; 3],
+    offset: &[i32; 3],
+    shift: u32,
+) {
+    assert!(
+        color_matrix_params_valid(m, offset, shift),
+        "color matrix parameters are out of bounds"
+    );
+    for (orig_pixel, edit_pixel) in original_chunk.iter().zip(edited_chunk) {
+        let channels = unpack_pixel(builder, *orig_pixel, format);
+        let mut edited = channels;
+        for c in 0..3 {
+            let mut acc = builder.constant(signed_constant(offset[c] as i64));
+            for j in 0..3 {
+                acc = builder.mul_const_add(signed_constant(m[c][j] as i64), channels[j], acc);
+            }
+            edited[c] = round_shift_clamp(builder, acc, shift as usize);
+        }
+        let edited_pixel = pack_pixel(builder, edited, format);
+        builder.connect(edited_pixel, *edit_pixel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zkedit_transformations::{color::apply_color_matrix, Transformation};
+
+    use crate::testing::{random_pixels, transformation_proves, HEIGHT, WIDTH};
+
+    #[test]
+    fn test_color_matrix_proves() {
+        let original = random_pixels((WIDTH * HEIGHT) as usize);
+        let sepia = Transformation::sepia();
+        let Transformation::ColorMatrix { m, offset, shift } = &sepia else {
+            unreachable!()
+        };
+        let mut edited: Vec<[u8; 4]> = original
+            .iter()
+            .map(|pixel| apply_color_matrix(m, offset, *shift, *pixel))
+            .collect();
+        assert!(transformation_proves(sepia.clone(), &original, &edited));
+
+        // A channel off by one in the second chunk
+        edited[20][1] ^= 1;
+        assert!(!transformation_proves(sepia, &original, &edited));
+    }
+}
@@ -1,14 +1,24 @@
-use plonky2::iop::target::Target;
+use std::ops::Range;
+
+use plonky2::iop::ext_target::ExtensionTarget;
+use plonky2::iop::target::{BoolTarget, Target};
 use plonky2::plonk::circuit_builder::CircuitBuilder;
 use plonky2_field::goldilocks_field::GoldilocksField;
+use plonky2_field::types::Field;
+use zkedit_transformations::CropPosition;
 
-use super::util::pixel_number_to_coords;
+use super::multiset::{
+    accumulate_term, challenges_targets, challenges_witness, extension_at, multiset_challenges,
+    multiset_challenges_circuit, tuple_key, Ext,
+};
+use super::util::{is_less_than, pixel_number_to_coords};
+use super::ImageWitness;
 
-pub(crate) fn build_crop_circuit<const L: usize>(
+pub(crate) fn build_crop_circuit(
     builder: &mut CircuitBuilder<GoldilocksField, 2>,
     original_chunk: &[Target],
     edited_chunk: &[Target],
-    chunk_number: usize,
+    chunk_start: usize,
     orig_w: u32,
     x: u32,
     y: u32,
@@ -20,11 +30,374 @@ pub(crate) fn build_crop_circuit<const L: usize>(
     let uy_bound = y;
     let dy_bound = y + h;
     for (i, (orig_pixel, edit_pixel)) in original_chunk.iter().zip(edited_chunk).enumerate() {
-        let (x, y) = pixel_number_to_coords(chunk_number * L + i, orig_w);
+        let (x, y) = pixel_number_to_coords(chunk_start + i, orig_w);
         if x >= lx_bound && x < rx_bound && y >= uy_bound && y < dy_bound {
-            builder.is_equal(*orig_pixel, *edit_pixel);
+            builder.connect(*orig_pixel, *edit_pixel);
         } else {
             builder.assert_zero(*edit_pixel);
         }
     }
 }
+
+// A hidden crop can't place its output at compile-time positions, so the
+// edited stream is the cropped raster followed by zeros and the two are matched
+// with a multiset argument: every original pixel inside the private rectangle
+// contributes `(index in the crop, pixel)`, every pixel of the crop contributes
+// `(its index, pixel)`. Chunks track the coordinates of their pixels from a
+// start position the aggregation circuit chains from `(0, 0)`, so the original
+// width never becomes a circuit constant. The commitment to the original
+// absorbs its private dimensions, which pins the rows the chunks walk to the
+// ones of the committed image.
+
+/// Crop position and original width in elements are checked to fit into this
+/// many bits.
+const COORD_BITS: usize = 32;
+
+// The challenges and products are extension elements taking two public inputs
+// from the index on.
+const HIDDEN_CROP_GAMMA_PI: usize = 0;
+const HIDDEN_CROP_BETA_PI: usize = 2;
+const HIDDEN_CROP_POSITION_PIS: Range<usize> = 4..7;
+const HIDDEN_CROP_START_PIS: Range<usize> = 7..9;
+const HIDDEN_CROP_END_PIS: Range<usize> = 9..11;
+const HIDDEN_CROP_ORIGINAL_PRODUCT_PI: usize = 11;
+const HIDDEN_CROP_EDITED_PRODUCT_PI: usize = 13;
+
+fn crop_key(
+    builder: &mut CircuitBuilder<GoldilocksField, 2>,
+    beta: ExtensionTarget<2>,
+    index: Target,
+    pixel: Target,
+) -> ExtensionTarget<2> {
+    tuple_key(builder, beta, pixel, index)
+}
+
+/// Whether `start <= coord < start + len`.
+fn is_inside(
+    builder: &mut CircuitBuilder<GoldilocksField, 2>,
+    coord: Target,
+    start: Target,
+    len: u32,
+) -> BoolTarget {
+    let end = builder.add_const(start, GoldilocksField::from_canonical_u32(len));
+    let before_start = is_less_than(builder, coord, start, COORD_BITS + 1);
+    let after_start = builder.not(before_start);
+    let before_end = is_less_than(builder, coord, end, COORD_BITS + 1);
+    builder.and(after_start, before_end)
+}
+
+pub(crate) fn build_hidden_crop_chunk_circuit(
+    builder: &mut CircuitBuilder<GoldilocksField, 2>,
+    original_chunk: &[Target],
+    edited_chunk: &[Target],
+    chunk_start: usize,
+    w: u32,
+    h: u32,
+) -> Vec<Target> {
+    let (gamma, beta) = challenges_targets(builder);
+    let [orig_w, x, y] = [(); 3].map(|_| builder.add_virtual_target());
+    let [start_x, start_y] = [(); 2].map(|_| builder.add_virtual_target());
+
+    let zero = builder.zero();
+    let one = builder.one_extension();
+    let mut original_product = one;
+    let (mut px, mut py) = (start_x, start_y);
+    for orig_pixel in original_chunk {
+        let inside_x = is_inside(builder, px, x, w);
+        let inside_y = is_inside(builder, py, y, h);
+        let inside = builder.and(inside_x, inside_y);
+
+        let rel_x = builder.sub(px, x);
+        let rel_y = builder.sub(py, y);
+        let index = builder.mul_const_add(GoldilocksField::from_canonical_u32(w), rel_y, rel_x);
+        let key = crop_key(builder, beta, index, *orig_pixel);
+        let term = builder.sub_extension(gamma, key);
+        let term = builder.select_ext(inside, term, one);
+        original_product = builder.mul_extension(original_product, term);
+
+        let next_x = builder.add_const(px, GoldilocksField::ONE);
+        let wraps = builder.is_equal(next_x, orig_w);
+        px = builder.select(wraps, zero, next_x);
+        py = builder.add(py, wraps.target);
+    }
+
+    let crop_len = w as usize * h as usize;
+    let mut edited_product = one;
+    for (i, edit_pixel) in edited_chunk.iter().enumerate() {
+        let index = chunk_start + i;
+        if index < crop_len {
+            let index = builder.constant(GoldilocksField::from_canonical_usize(index));
+            let key = crop_key(builder, beta, index, *edit_pixel);
+            edited_product = accumulate_term(builder, edited_product, gamma, key);
+        } else {
+            builder.assert_zero(*edit_pixel);
+        }
+    }
+
+    let mut inputs = gamma.0.to_vec();
+    inputs.extend(beta.0);
+    inputs.extend([orig_w, x, y, start_x, start_y]);
+    builder.register_public_inputs(&inputs);
+    builder.register_public_inputs(&[px, py]);
+    builder.register_public_inputs(&original_product.0);
+    builder.register_public_inputs(&edited_product.0);
+    inputs
+}
+
+pub(crate) fn build_hidden_crop_aggregation_circuit(
+    builder: &mut CircuitBuilder<GoldilocksField, 2>,
+    original_hash: &[Target],
+    edited_hash: &[Target],
+    original_dimensions: &[Target],
+    chunk_public_inputs: &[Vec<Target>],
+    elements_per_pixel: usize,
+) -> Vec<Target> {
+    // The chunks walk targets and a pixel spans at most two of them, so the
+    // width in targets fits into `COORD_BITS` as well
+    let (width, height) = (original_dimensions[0], original_dimensions[1]);
+    builder.range_check(width, COORD_BITS - 1);
+    let orig_w = builder.mul_const(
+        GoldilocksField::from_canonical_usize(elements_per_pixel),
+        width,
+    );
+    // A rectangle that doesn't fit into the original has fewer than `w * h`
+    // pixels inside it, so the multisets can't match and no bound check on the
+    // position is needed beyond keeping the comparisons sound.
+    let offset = builder.add_virtual_targets(2);
+    for coord in &offset {
+        builder.range_check(*coord, COORD_BITS);
+    }
+    let position = vec![orig_w, offset[0], offset[1]];
+    let (gamma, beta) = multiset_challenges_circuit(builder, original_hash, edited_hash, &position);
+
+    let zero = builder.zero();
+    let mut last_end = vec![zero, zero];
+    let mut original_product = builder.one_extension();
+    let mut edited_product = builder.one_extension();
+    for public_inputs in chunk_public_inputs {
+        builder.connect_extension(extension_at(public_inputs, HIDDEN_CROP_GAMMA_PI), gamma);
+        builder.connect_extension(extension_at(public_inputs, HIDDEN_CROP_BETA_PI), beta);
+        for (chunk_coord, coord) in public_inputs[HIDDEN_CROP_POSITION_PIS]
+            .iter()
+            .zip(&position)
+        {
+            builder.connect(*chunk_coord, *coord);
+        }
+        for (start, end) in public_inputs[HIDDEN_CROP_START_PIS].iter().zip(&last_end) {
+            builder.connect(*start, *end);
+        }
+        last_end = public_inputs[HIDDEN_CROP_END_PIS].to_vec();
+        let chunk_original = extension_at(public_inputs, HIDDEN_CROP_ORIGINAL_PRODUCT_PI);
+        original_product = builder.mul_extension(original_product, chunk_original);
+        let chunk_edited = extension_at(public_inputs, HIDDEN_CROP_EDITED_PRODUCT_PI);
+        edited_product = builder.mul_extension(edited_product, chunk_edited);
+    }
+    // the original is made of `height` whole rows of the private width
+    builder.assert_zero(last_end[0]);
+    builder.connect(last_end[1], height);
+    builder.connect_extension(original_product, edited_product);
+    offset
+}
+
+fn hidden_crop_challenges(images: &ImageWitness, position: &CropPosition) -> (Ext, Ext) {
+    multiset_challenges(
+        &images.original_hash,
+        &images.edited_hash,
+        &position_witness(position),
+    )
+}
+
+fn position_witness(position: &CropPosition) -> Vec<GoldilocksField> {
+    [position.orig_w, position.x, position.y]
+        .map(GoldilocksField::from_canonical_u32)
+        .to_vec()
+}
+
+pub(crate) fn hidden_crop_chunk_witness(
+    images: &ImageWitness,
+    chunk_start: usize,
+    position: &CropPosition,
+) -> Vec<GoldilocksField> {
+    let (gamma, beta) = hidden_crop_challenges(images, position);
+    let (start_x, start_y) = pixel_number_to_coords(chunk_start, position.orig_w);
+    let mut witness = challenges_witness(gamma, beta);
+    witness.extend(position_witness(position));
+    witness.extend([start_x, start_y].map(GoldilocksField::from_canonical_u32));
+    witness
+}
+
+/// The original dimensions in pixels and the crop offset.
+pub(crate) fn hidden_crop_aggregation_witness(
+    images: &ImageWitness,
+    position: &CropPosition,
+) -> Vec<GoldilocksField> {
+    let width = position.orig_w as usize / images.layout.format.elements_per_pixel();
+    let height = images.pixels / position.orig_w as usize;
+    [width, height, position.x as usize, position.y as usize]
+        .map(GoldilocksField::from_canonical_usize)
+        .to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use zkedit_transformations::{CropPosition, Region, Transformation};
+
+    use crate::builder::TransformationCircuitBuilder;
+    use crate::commitment::{bind_dimensions, CommitmentScheme};
+    use crate::pixel::PixelFormat;
+    use crate::testing::{random_pixels, transformation_proves, HEIGHT, L, WIDTH};
+
+    const CROP: Region = Region {
+        x: 2,
+        y: 1,
+        w: 5,
+        h: 2,
+    };
+
+    /// The crop in place, zero outside of it.
+    fn crop_in_place(original: &[[u8; 4]]) -> Vec<[u8; 4]> {
+        let mut edited = vec![[0; 4]; original.len()];
+        for (i, pixel) in original.iter().enumerate() {
+            if CROP.contains(i as u32 % WIDTH, i as u32 / WIDTH) {
+                edited[i] = *pixel;
+            }
+        }
+        edited
+    }
+
+    /// The `crop` of `original` taken as `orig_w` pixels wide, followed by
+    /// zeros.
+    fn crop_packed(original: &[[u8; 4]], orig_w: u32, crop: Region) -> Vec<[u8; 4]> {
+        let mut edited: Vec<[u8; 4]> = original
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| crop.contains(*i as u32 % orig_w, *i as u32 / orig_w))
+            .map(|(_, pixel)| *pixel)
+            .collect();
+        edited.resize(original.len(), [0; 4]);
+        edited
+    }
+
+    #[test]
+    fn test_crop_proves() {
+        let original = random_pixels((WIDTH * HEIGHT) as usize);
+        let crop = Transformation::Crop {
+            orig_w: WIDTH,
+            orig_h: HEIGHT,
+            x: CROP.x,
+            y: CROP.y,
+            w: CROP.w,
+            h: CROP.h,
+        };
+        let edited = crop_in_place(&original);
+        assert!(transformation_proves(crop.clone(), &original, &edited));
+
+        // A pixel inside the crop that isn't the original one
+        let mut changed_inside = edited.clone();
+        changed_inside[WIDTH as usize + 3][0] ^= 1;
+        assert!(!transformation_proves(
+            crop.clone(),
+            &original,
+            &changed_inside
+        ));
+
+        // A pixel outside of it that isn't cleared
+        let mut kept_outside = edited;
+        kept_outside[0] = [1; 4];
+        assert!(!transformation_proves(crop, &original, &kept_outside));
+    }
+
+    fn hidden_crop(x: u32, y: u32) -> Transformation {
+        Transformation::HiddenCrop {
+            w: CROP.w,
+            h: CROP.h,
+            position: Some(CropPosition {
+                orig_w: WIDTH,
+                x,
+                y,
+            }),
+        }
+    }
+
+    #[test]
+    fn test_hidden_crop_proves() {
+        let original = random_pixels((WIDTH * HEIGHT) as usize);
+        let edited = crop_packed(&original, WIDTH, CROP);
+        assert!(transformation_proves(
+            hidden_crop(CROP.x, CROP.y),
+            &original,
+            &edited
+        ));
+
+        // The crop is taken from another position than the one proven
+        assert!(!transformation_proves(
+            hidden_crop(CROP.x + 1, CROP.y),
+            &original,
+            &edited
+        ));
+
+        // A rectangle reaching out of the original
+        assert!(!transformation_proves(
+            hidden_crop(WIDTH - 2, CROP.y),
+            &original,
+            &edited
+        ));
+
+        // Anything after the crop
+        let mut trailing = edited;
+        trailing[(CROP.w * CROP.h) as usize] = [1; 4];
+        assert!(!transformation_proves(
+            hidden_crop(CROP.x, CROP.y),
+            &original,
+            &trailing
+        ));
+    }
+
+    /// The original hash of a proven hidden crop of `original` taken as
+    /// `orig_w` pixels wide.
+    fn hidden_crop_original_hash(original: &[[u8; 4]], orig_w: u32, crop: Region) -> Vec<u8> {
+        let transformation = Transformation::HiddenCrop {
+            w: crop.w,
+            h: crop.h,
+            position: Some(CropPosition {
+                orig_w,
+                x: crop.x,
+                y: crop.y,
+            }),
+        };
+        let edited = crop_packed(original, orig_w, crop).concat();
+        let original = original.concat();
+        let mut circuit =
+            TransformationCircuitBuilder::<L>::new(original.len(), Box::new(transformation))
+                .build_curcuit();
+        let proof = circuit.prove(&original, &edited, None, None).unwrap();
+        proof.verify(circuit.circuit).unwrap();
+        proof.original_hash().unwrap()
+    }
+
+    #[test]
+    fn test_hidden_crop_binds_original_dimensions() {
+        let original = random_pixels((WIDTH * HEIGHT) as usize);
+        let layout = PixelFormat::Rgba8.into();
+        let hash = CommitmentScheme::Sponge.commit::<L>(&original.concat(), layout);
+        assert_eq!(
+            hidden_crop_original_hash(&original, WIDTH, CROP),
+            bind_dimensions(&hash, WIDTH, HEIGHT, layout)
+        );
+
+        // Taken as twice as wide, the rectangle spans the end of one row and
+        // the start of the next, the proof holds for another original
+        let wide = Region {
+            x: 6,
+            y: 0,
+            w: 5,
+            h: 2,
+        };
+        let wide_hash = hidden_crop_original_hash(&original, 2 * WIDTH, wide);
+        assert_ne!(wide_hash, bind_dimensions(&hash, WIDTH, HEIGHT, layout));
+        assert_eq!(
+            wide_hash,
+            bind_dimensions(&hash, 2 * WIDTH, HEIGHT / 2, layout)
+        );
+    }
+}
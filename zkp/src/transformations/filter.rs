@@ -0,0 +1,187 @@
+use plonky2::iop::target::Target;
+use plonky2::plonk::circuit_builder::CircuitBuilder;
+use plonky2_field::goldilocks_field::GoldilocksField;
+use plonky2_field::types::Field;
+use zkedit_transformations::filter::{
+    clamped_window, unsharp_coefficient, unsharp_params_valid, window_size, MEDIAN9_NETWORK,
+    UNSHARP_SHIFT,
+};
+use zkedit_transformations::Region;
+
+use crate::halo::HaloTargets;
+use crate::pixel::PixelFormat;
+
+use super::util::{
+    is_less_than, pack_pixel, pixel_number_to_coords, round_shift_clamp, UnpackedWindow,
+};
+
+pub(crate) fn build_unsharp_circuit(
+    builder: &mut CircuitBuilder<GoldilocksField, 2>,
+    original_chunk: &[Target],
+    edited_chunk: &[Target],
+    format: PixelFormat,
+    halo: &HaloTargets,
+    chunk_start: usize,
+    orig_w: u32,
+    orig_h: u32,
+    amount: u32,
+    radius: u32,
+    threshold: u8,
+) {
+    assert!(
+        unsharp_params_valid(amount, radius),
+        "unsharp mask parameters are out of bounds"
+    );
+    let n = window_size(radius) as u64;
+    let coefficient =
+        GoldilocksField::from_canonical_u64(unsharp_coefficient(amount, radius) as u64);
+    // `diff = n * channel - window_sum` lies in (-255n, 255n), shifting it by
+    // 255n makes it comparable against the threshold bounds
+    let diff_offset = u8::MAX as u64 * n;
+    let diff_bits = 64 - (2 * diff_offset).leading_zeros() as usize;
+    let threshold_n = threshold as u64 * n;
+    let upper_bound = builder.constant(GoldilocksField::from_canonical_u64(
+        diff_offset + threshold_n,
+    ));
+    let lower_bound = builder.constant(GoldilocksField::from_canonical_u64(
+        diff_offset + 1 - threshold_n,
+    ));
+
+    let mut window = UnpackedWindow::new(original_chunk, halo, chunk_start, format);
+    for (i, edit_pixel) in edited_chunk.iter().enumerate() {
+        let (x, y) = pixel_number_to_coords(chunk_start + i, orig_w);
+        let channels = window.pixel(builder, chunk_start + i);
+        let neighbours: Vec<[Target; 4]> = clamped_window(x, y, orig_w, orig_h, radius)
+            .map(|(nx, ny)| window.pixel(builder, (ny * orig_w + nx) as usize))
+            .collect();
+        let mut edited = channels;
+        for c in 0..3 {
+            let window_sum = builder.add_many(neighbours.iter().map(|pixel| pixel[c]));
+            let scaled = builder.mul_const(GoldilocksField::from_canonical_u64(n), channels[c]);
+            let diff = builder.sub(scaled, window_sum);
+
+            let shifted_channel = builder.mul_const(
+                GoldilocksField::from_canonical_u64(1 << UNSHARP_SHIFT),
+                channels[c],
+            );
+            let acc = builder.mul_const_add(coefficient, diff, shifted_channel);
+            let sharpened = round_shift_clamp(builder, acc, UNSHARP_SHIFT as usize);
+
+            edited[c] = if threshold == 0 {
+                sharpened
+            } else {
+                let shifted_diff =
+                    builder.add_const(diff, GoldilocksField::from_canonical_u64(diff_offset));
+                let below_upper = is_less_than(builder, shifted_diff, upper_bound, diff_bits);
+                let above_lower = is_less_than(builder, shifted_diff, lower_bound, diff_bits);
+                let above_lower = builder.not(above_lower);
+                // |diff| < threshold * n
+                let keep = builder.and(below_upper, above_lower);
+                builder.select(keep, channels[c], sharpened)
+            };
+        }
+        let edited_pixel = pack_pixel(builder, edited, format);
+        builder.connect(edited_pixel, *edit_pixel);
+    }
+}
+
+pub(crate) fn build_median3x3_circuit(
+    builder: &mut CircuitBuilder<GoldilocksField, 2>,
+    original_chunk: &[Target],
+    edited_chunk: &[Target],
+    format: PixelFormat,
+    halo: &HaloTargets,
+    chunk_start: usize,
+    orig_w: u32,
+    orig_h: u32,
+    regions: &[Region],
+) {
+    assert!(
+        regions.iter().all(|region| region.fits(orig_w, orig_h)),
+        "median regions must lie inside the image"
+    );
+    let mut window = UnpackedWindow::new(original_chunk, halo, chunk_start, format);
+    for (i, (orig_pixel, edit_pixel)) in original_chunk.iter().zip(edited_chunk).enumerate() {
+        let (x, y) = pixel_number_to_coords(chunk_start + i, orig_w);
+        if !regions.iter().any(|region| region.contains(x, y)) {
+            builder.connect(*orig_pixel, *edit_pixel);
+            continue;
+        }
+
+        let neighbours: Vec<[Target; 4]> = clamped_window(x, y, orig_w, orig_h, 1)
+            .map(|(nx, ny)| window.pixel(builder, (ny * orig_w + nx) as usize))
+            .collect();
+        let mut edited = window.pixel(builder, chunk_start + i);
+        for c in 0..3 {
+            let mut values: Vec<Target> = neighbours.iter().map(|pixel| pixel[c]).collect();
+            for (a, b) in MEDIAN9_NETWORK {
+                let a_is_less = is_less_than(builder, values[a], values[b], 8);
+                let min = builder.select(a_is_less, values[a], values[b]);
+                let sum = builder.add(values[a], values[b]);
+                values[b] = builder.sub(sum, min);
+                values[a] = min;
+            }
+            edited[c] = values[4];
+        }
+        let edited_pixel = pack_pixel(builder, edited, format);
+        builder.connect(edited_pixel, *edit_pixel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zkedit_transformations::filter::{apply_median3x3, apply_unsharp};
+    use zkedit_transformations::{Region, Transformation};
+
+    use crate::testing::{random_pixels, transformation_proves, HEIGHT, WIDTH};
+
+    #[test]
+    fn test_unsharp_proves() {
+        let original = random_pixels((WIDTH * HEIGHT) as usize);
+        let unsharp = Transformation::Unsharp {
+            orig_w: WIDTH,
+            orig_h: HEIGHT,
+            amount: 150,
+            radius: 1,
+            threshold: 4,
+        };
+        let mut edited = apply_unsharp(&original, WIDTH, HEIGHT, 150, 1, 4);
+        assert!(transformation_proves(unsharp.clone(), &original, &edited));
+
+        // The first pixel of the second chunk, its window reaches into the halo
+        edited[16][0] ^= 1;
+        assert!(!transformation_proves(unsharp, &original, &edited));
+    }
+
+    #[test]
+    fn test_median3x3_proves() {
+        let original = random_pixels((WIDTH * HEIGHT) as usize);
+        let regions = vec![Region {
+            x: 1,
+            y: 1,
+            w: 4,
+            h: 2,
+        }];
+        let median = Transformation::Median3x3 {
+            orig_w: WIDTH,
+            orig_h: HEIGHT,
+            regions: regions.clone(),
+        };
+        let edited = apply_median3x3(&original, WIDTH, HEIGHT, &regions);
+        assert!(transformation_proves(median.clone(), &original, &edited));
+
+        // Inside the region the median is enforced
+        let mut wrong_median = edited.clone();
+        wrong_median[WIDTH as usize * 2 + 2][1] ^= 1;
+        assert!(!transformation_proves(
+            median.clone(),
+            &original,
+            &wrong_median
+        ));
+
+        // Outside of it the pixels are kept
+        let mut changed_outside = edited;
+        changed_outside[WIDTH as usize * 3 + 7][0] ^= 1;
+        assert!(!transformation_proves(median, &original, &changed_outside));
+    }
+}
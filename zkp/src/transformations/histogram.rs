@@ -0,0 +1,252 @@
+use plonky2::iop::ext_target::ExtensionTarget;
+use plonky2::iop::target::Target;
+use plonky2::plonk::circuit_builder::CircuitBuilder;
+use plonky2_field::goldilocks_field::GoldilocksField;
+use plonky2_field::types::Field;
+use zkedit_transformations::histogram::{channel_histograms, equalization_lut, HISTOGRAM_BINS};
+
+use crate::pixel::PixelFormat;
+
+use super::multiset::{
+    accumulate_term, challenges_targets, challenges_witness, exp_extension, extension_at,
+    multiset_challenges, multiset_challenges_circuit, tuple_key,
+};
+use super::util::{is_less_than, pixel_channels, unpack_pixel};
+use super::ImageWitness;
+
+// Equalization is proven with a lookup-style multiset argument: the tuples
+// `(channel, original value, edited value)` of all pixels must equal the
+// table `(channel, k, lut[channel][k])` taken `histogram[channel][k]` times.
+// This proves both the histogram and that every edited value is the table
+// entry of its original value, while each chunk only does one multiplication
+// per channel.
+
+// Starts of the extension elements among the public inputs of a chunk.
+const CHUNK_GAMMA_PI: usize = 0;
+const CHUNK_BETA_PI: usize = 2;
+const CHUNK_PRODUCT_PI: usize = 4;
+
+/// Maximal number of pixels with the same channel value.
+const COUNT_BITS: usize = 32;
+
+fn lut_key(
+    builder: &mut CircuitBuilder<GoldilocksField, 2>,
+    beta: ExtensionTarget<2>,
+    channel: usize,
+    value: Target,
+    mapped: Target,
+) -> ExtensionTarget<2> {
+    let offset = builder.add_const(
+        value,
+        GoldilocksField::from_canonical_usize(channel * HISTOGRAM_BINS),
+    );
+    tuple_key(builder, beta, mapped, offset)
+}
+
+pub(crate) fn build_equalize_chunk_circuit(
+    builder: &mut CircuitBuilder<GoldilocksField, 2>,
+    original_chunk: &[Target],
+    edited_chunk: &[Target],
+    format: PixelFormat,
+) -> Vec<Target> {
+    let (gamma, beta) = challenges_targets(builder);
+
+    let mut product = builder.one_extension();
+    for (orig_pixel, edit_pixel) in original_chunk.iter().zip(edited_chunk) {
+        let original = unpack_pixel(builder, *orig_pixel, format);
+        let edited = unpack_pixel(builder, *edit_pixel, format);
+        builder.connect(original[3], edited[3]);
+        for c in 0..3 {
+            let key = lut_key(builder, beta, c, original[c], edited[c]);
+            product = accumulate_term(builder, product, gamma, key);
+        }
+    }
+
+    builder.register_public_inputs(&gamma.0);
+    builder.register_public_inputs(&beta.0);
+    builder.register_public_inputs(&product.0);
+    gamma.0.into_iter().chain(beta.0).collect()
+}
+
+pub(crate) fn build_equalize_aggregation_circuit(
+    builder: &mut CircuitBuilder<GoldilocksField, 2>,
+    original_hash: &[Target],
+    edited_hash: &[Target],
+    chunk_public_inputs: &[Vec<Target>],
+    total_pixels: u64,
+) -> Vec<Target> {
+    let histograms = builder.add_virtual_targets(3 * HISTOGRAM_BINS);
+    let luts = builder.add_virtual_targets(3 * HISTOGRAM_BINS);
+    let (gamma, beta) =
+        multiset_challenges_circuit(builder, original_hash, edited_hash, &histograms);
+
+    let mut chunks_product = builder.one_extension();
+    for public_inputs in chunk_public_inputs {
+        builder.connect_extension(extension_at(public_inputs, CHUNK_GAMMA_PI), gamma);
+        builder.connect_extension(extension_at(public_inputs, CHUNK_BETA_PI), beta);
+        let product = extension_at(public_inputs, CHUNK_PRODUCT_PI);
+        chunks_product = builder.mul_extension(chunks_product, product);
+    }
+
+    let total = builder.constant(GoldilocksField::from_canonical_u64(total_pixels));
+    let remainder_bits = 64 - (total_pixels * u8::MAX as u64).leading_zeros() as usize;
+    let mut table_product = builder.one_extension();
+    for c in 0..3 {
+        let mut cdf = builder.zero();
+        for k in 0..HISTOGRAM_BINS {
+            let count = histograms[c * HISTOGRAM_BINS + k];
+            let entry = luts[c * HISTOGRAM_BINS + k];
+            cdf = builder.add(cdf, count);
+
+            // entry = floor(cdf * 255 / total) <=> 0 <= cdf * 255 - entry * total < total
+            builder.range_check(entry, 8);
+            let scaled_cdf =
+                builder.mul_const(GoldilocksField::from_canonical_u64(u8::MAX as u64), cdf);
+            let scaled_entry = builder.mul(entry, total);
+            let remainder = builder.sub(scaled_cdf, scaled_entry);
+            // A too large entry wraps the remainder around the field, the
+            // comparison alone only holds for remainders of `remainder_bits`
+            builder.range_check(remainder, remainder_bits);
+            let remainder_fits = is_less_than(builder, remainder, total, remainder_bits);
+            builder.assert_one(remainder_fits.target);
+
+            let value = builder.constant(GoldilocksField::from_canonical_usize(k));
+            let key = lut_key(builder, beta, c, value, entry);
+            let base = builder.sub_extension(gamma, key);
+            let power = exp_extension(builder, base, count, COUNT_BITS);
+            table_product = builder.mul_extension(table_product, power);
+        }
+        builder.connect(cdf, total);
+    }
+    builder.connect_extension(chunks_product, table_product);
+
+    builder.register_public_inputs(&luts);
+    histograms.into_iter().chain(luts).collect()
+}
+
+fn original_histograms(images: &ImageWitness) -> [[u64; HISTOGRAM_BINS]; 3] {
+    let pixels: Vec<[u8; 4]> = images
+        .original_pixels()
+        .into_iter()
+        .map(|pixel| pixel_channels(pixel, images.layout.format))
+        .collect();
+    channel_histograms(&pixels)
+}
+
+fn flatten(values: &[[u64; HISTOGRAM_BINS]]) -> Vec<GoldilocksField> {
+    values
+        .iter()
+        .flatten()
+        .map(|v| GoldilocksField::from_canonical_u64(*v))
+        .collect()
+}
+
+pub(crate) fn equalize_chunk_witness(images: &ImageWitness) -> Vec<GoldilocksField> {
+    let histograms = flatten(&original_histograms(images));
+    let (gamma, beta) =
+        multiset_challenges(&images.original_hash, &images.edited_hash, &histograms);
+    challenges_witness(gamma, beta)
+}
+
+pub(crate) fn equalize_aggregation_witness(images: &ImageWitness) -> Vec<GoldilocksField> {
+    let histograms = original_histograms(images);
+    let luts = histograms.map(|histogram| {
+        equalization_lut(&histogram, images.pixels as u64).map(|entry| entry as u64)
+    });
+    let mut witness = flatten(&histograms);
+    witness.extend(flatten(&luts));
+    witness
+}
+
+#[cfg(test)]
+mod tests {
+    use plonky2::hash::hash_types::HashOut;
+    use plonky2::iop::witness::{PartialWitness, WitnessWrite};
+    use plonky2::plonk::circuit_data::CircuitConfig;
+    use plonky2_field::extension::FieldExtension;
+    use plonky2_field::types::Sample;
+    use zkedit_transformations::histogram::apply_equalize;
+    use zkedit_transformations::Transformation;
+
+    use super::*;
+    use crate::testing::{proves, random_pixels, transformation_proves, HEIGHT, WIDTH};
+    use crate::transformations::multiset::Ext;
+    use crate::util::set_multiple_targets;
+    use crate::C;
+
+    #[test]
+    fn test_equalize_proves() {
+        let original = random_pixels((WIDTH * HEIGHT) as usize);
+        let equalize = Transformation::Equalize {
+            orig_w: WIDTH,
+            orig_h: HEIGHT,
+        };
+        let mut edited = apply_equalize(&original);
+        assert!(transformation_proves(equalize.clone(), &original, &edited));
+
+        edited[9][2] ^= 1;
+        assert!(!transformation_proves(equalize, &original, &edited));
+    }
+
+    /// Whether the aggregation circuit accepts `luts` for a single chunk of
+    /// `pixels` edited with them.
+    fn aggregation_proves(pixels: &[[u8; 4]], luts: &[[u64; HISTOGRAM_BINS]; 3]) -> bool {
+        let mut builder =
+            CircuitBuilder::<GoldilocksField, 2>::new(CircuitConfig::standard_recursion_config());
+        let original_hash_target = builder.add_virtual_hash();
+        let edited_hash_target = builder.add_virtual_hash();
+        let chunk_inputs = builder.add_virtual_targets(6);
+        let aggregation_inputs = build_equalize_aggregation_circuit(
+            &mut builder,
+            &original_hash_target.elements,
+            &edited_hash_target.elements,
+            &[chunk_inputs.clone()],
+            pixels.len() as u64,
+        );
+        let circuit = builder.build::<C>();
+
+        let original_hash = HashOut {
+            elements: GoldilocksField::rand_array(),
+        };
+        let edited_hash = HashOut {
+            elements: GoldilocksField::rand_array(),
+        };
+        let histograms = flatten(&channel_histograms(pixels));
+        let (gamma, beta) = multiset_challenges(&original_hash, &edited_hash, &histograms);
+        let mut product = Ext::ONE;
+        for pixel in pixels {
+            for (c, lut) in luts.iter().enumerate() {
+                let value = pixel[c] as usize;
+                let key = beta * Ext::from_canonical_u64(lut[value])
+                    + Ext::from_canonical_usize(c * HISTOGRAM_BINS + value);
+                product *= gamma - key;
+            }
+        }
+
+        let mut pw = PartialWitness::new();
+        pw.set_hash_target(original_hash_target, original_hash);
+        pw.set_hash_target(edited_hash_target, edited_hash);
+        let mut chunk_witness = challenges_witness(gamma, beta);
+        chunk_witness.extend(product.to_basefield_array());
+        set_multiple_targets(&mut pw, &chunk_inputs, &chunk_witness);
+        let mut witness = histograms;
+        witness.extend(flatten(luts));
+        set_multiple_targets(&mut pw, &aggregation_inputs, &witness);
+        proves(|| circuit.verify(circuit.prove(pw)?))
+    }
+
+    #[test]
+    fn test_equalize_rejects_too_large_entry() {
+        let pixels = [[0, 10, 20, 255], [60, 70, 80, 255], [120, 130, 140, 255]];
+        let histograms = channel_histograms(&pixels);
+        let total = pixels.len() as u64;
+        let mut luts =
+            histograms.map(|histogram| equalization_lut(&histogram, total).map(|e| e as u64));
+        assert!(aggregation_proves(&pixels, &luts));
+
+        // floor(255 / 3) + 1 is off by less than the comparison window
+        assert_eq!(luts[0][0], 85);
+        luts[0][0] += 1;
+        assert!(!aggregation_proves(&pixels, &luts));
+    }
+}
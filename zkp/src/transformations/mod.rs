@@ -1,31 +1,209 @@
-use plonky2::{iop::target::Target, plonk::circuit_builder::CircuitBuilder};
+use std::borrow::Cow;
+use std::ops::Range;
+
+use plonky2::{
+    hash::hash_types::HashOut, iop::target::Target, plonk::circuit_builder::CircuitBuilder,
+};
 use plonky2_field::goldilocks_field::GoldilocksField;
-use zkedit_transformations::Transformation;
+use zkedit_transformations::{filter::window_reach, CropPosition, Region, Transformation};
+
+use crate::halo::HaloTargets;
+use crate::pixel::{PixelFormat, PixelLayout};
 
-use self::crop::build_crop_circuit;
+use self::color::build_color_matrix_circuit;
+use self::crop::{
+    build_crop_circuit, build_hidden_crop_aggregation_circuit, build_hidden_crop_chunk_circuit,
+    hidden_crop_aggregation_witness, hidden_crop_chunk_witness,
+};
+use self::filter::{build_median3x3_circuit, build_unsharp_circuit};
+use self::histogram::{
+    build_equalize_aggregation_circuit, build_equalize_chunk_circuit, equalize_aggregation_witness,
+    equalize_chunk_witness,
+};
+use self::region::{build_preserve_outside_aggregation_circuit, build_preserve_outside_circuit};
+use self::tone::{build_posterize_circuit, build_threshold_circuit};
 
+pub mod color;
 pub mod crop;
+pub mod filter;
+pub mod histogram;
+pub mod multiset;
+pub mod region;
+pub mod tone;
 pub mod util;
 
+/// Native data the prover derives the transformation specific witness from.
+pub struct ImageWitness<'a> {
+    pub original: &'a [GoldilocksField],
+    pub edited: &'a [GoldilocksField],
+    pub original_hash: HashOut<GoldilocksField>,
+    pub edited_hash: HashOut<GoldilocksField>,
+    pub layout: PixelLayout,
+    /// Number of original pixels, the elements may hold padding after them
+    pub pixels: usize,
+}
+
+impl ImageWitness<'_> {
+    /// Values of the original pixels, as the chunk circuits split them.
+    pub(crate) fn original_pixels(&self) -> Vec<u64> {
+        self.layout.unpack(self.original, self.pixels)
+    }
+}
+
 pub trait TransformationLogic<const L: usize> {
+    /// Whether the circuits handle pixel streams of `format`.
+    fn supports_format(&self, format: PixelFormat) -> bool {
+        format == PixelFormat::Rgba8
+    }
+
+    /// Number of original pixels before and after a chunk the chunk circuit
+    /// needs to see.
+    fn halo(&self) -> usize {
+        0
+    }
+
+    /// Whether the edit may change pixels of the chunk, given by the range of
+    /// its pixel numbers. Chunks it doesn't touch need no chunk proof under a
+    /// Merkle tile commitment.
+    fn touches_chunk(&self, _pixels: Range<usize>, _format: PixelFormat) -> bool {
+        true
+    }
+
+    /// Whether the original dimensions are private inputs of the proof. The
+    /// commitment to the original absorbs them, see `bind_dimensions`, and
+    /// they are passed to `build_aggregation_circuit`.
+    fn hides_dimensions(&self) -> bool {
+        false
+    }
+
+    /// Constrains the edited chunk against the original one. The chunks and
+    /// the halo hold one target per pixel, the first chunk pixel is number
+    /// `chunk_start`. Public inputs registered here are passed to
+    /// `build_aggregation_circuit`, the returned private inputs are filled
+    /// from `chunk_witness`.
     fn build_chunk_circuit(
         &self,
         builder: &mut CircuitBuilder<GoldilocksField, 2>,
         original_chunk: &[Target],
         edited_chunk: &[Target],
-        chunk_number: usize,
-    );
+        halo: &HaloTargets,
+        chunk_start: usize,
+        format: PixelFormat,
+    ) -> Vec<Target>;
+
+    /// Combines the transformation specific public inputs of all proven chunks
+    /// for statistics that span the whole image. The returned private inputs
+    /// are filled from `aggregation_witness`, after the private original
+    /// `(width, height)` if the transformation hides them.
+    fn build_aggregation_circuit(
+        &self,
+        _builder: &mut CircuitBuilder<GoldilocksField, 2>,
+        _original_hash: &[Target],
+        _edited_hash: &[Target],
+        _original_dimensions: &[Target],
+        _chunk_public_inputs: &[Vec<Target>],
+        _format: PixelFormat,
+    ) -> Vec<Target> {
+        Vec::new()
+    }
+
+    fn chunk_witness(&self, _images: &ImageWitness, _chunk_start: usize) -> Vec<GoldilocksField> {
+        Vec::new()
+    }
+
+    fn aggregation_witness(&self, _images: &ImageWitness) -> Vec<GoldilocksField> {
+        Vec::new()
+    }
+}
+
+/// The transformation over the targets the chunk circuits see. A pixel spanning
+/// several elements is taken as several narrower pixels, the geometric
+/// transformations widen their columns accordingly.
+fn element_geometry(transformation: &Transformation, format: PixelFormat) -> Cow<Transformation> {
+    let n = format.elements_per_pixel() as u32;
+    if n == 1 {
+        return Cow::Borrowed(transformation);
+    }
+    let widen = |region: &Region| Region {
+        x: region.x * n,
+        w: region.w * n,
+        ..*region
+    };
+    Cow::Owned(match transformation {
+        Transformation::Crop {
+            orig_w,
+            orig_h,
+            x,
+            y,
+            w,
+            h,
+        } => Transformation::Crop {
+            orig_w: orig_w * n,
+            orig_h: *orig_h,
+            x: x * n,
+            y: *y,
+            w: w * n,
+            h: *h,
+        },
+        Transformation::HiddenCrop { w, h, position } => Transformation::HiddenCrop {
+            w: w * n,
+            h: *h,
+            position: position.map(|position| CropPosition {
+                orig_w: position.orig_w * n,
+                x: position.x * n,
+                y: position.y,
+            }),
+        },
+        Transformation::PreserveOutside {
+            orig_w,
+            orig_h,
+            regions,
+        } => Transformation::PreserveOutside {
+            orig_w: orig_w * n,
+            orig_h: *orig_h,
+            regions: regions.iter().map(widen).collect(),
+        },
+        other => other.clone(),
+    })
 }
 
 impl<const L: usize> TransformationLogic<L> for Transformation {
+    fn supports_format(&self, format: PixelFormat) -> bool {
+        format.check_transformation(self).is_ok()
+    }
+
+    fn halo(&self) -> usize {
+        match self {
+            Transformation::Unsharp { orig_w, radius, .. } => window_reach(*orig_w, *radius),
+            Transformation::Median3x3 { orig_w, .. } => window_reach(*orig_w, 1),
+            _ => 0,
+        }
+    }
+
+    fn touches_chunk(&self, pixels: Range<usize>, format: PixelFormat) -> bool {
+        match &*element_geometry(self, format) {
+            Transformation::Median3x3 {
+                orig_w, regions, ..
+            }
+            | Transformation::PreserveOutside {
+                orig_w, regions, ..
+            } => regions
+                .iter()
+                .any(|region| region.overlaps_pixels(*orig_w, pixels.clone())),
+            _ => true,
+        }
+    }
+
     fn build_chunk_circuit(
         &self,
         builder: &mut CircuitBuilder<GoldilocksField, 2>,
         original_chunk: &[Target],
         edited_chunk: &[Target],
-        chunk_number: usize,
-    ) {
-        match self {
+        halo: &HaloTargets,
+        chunk_start: usize,
+        format: PixelFormat,
+    ) -> Vec<Target> {
+        match &*element_geometry(self, format) {
             Transformation::Crop {
                 orig_w,
                 x,
@@ -34,11 +212,11 @@ impl<const L: usize> TransformationLogic<L> for Transformation {
                 h,
                 orig_h: _,
             } => {
-                build_crop_circuit::<L>(
+                build_crop_circuit(
                     builder,
                     original_chunk,
                     edited_chunk,
-                    chunk_number,
+                    chunk_start,
                     *orig_w,
                     *x,
                     *y,
@@ -46,6 +224,154 @@ impl<const L: usize> TransformationLogic<L> for Transformation {
                     *h,
                 );
             }
+            Transformation::ColorMatrix { m, offset, shift } => {
+                build_color_matrix_circuit(
+                    builder,
+                    original_chunk,
+                    edited_chunk,
+                    format,
+                    m,
+                    offset,
+                    *shift,
+                );
+            }
+            Transformation::Threshold { level } => {
+                build_threshold_circuit(builder, original_chunk, edited_chunk, format, *level);
+            }
+            Transformation::Posterize { levels } => {
+                build_posterize_circuit(builder, original_chunk, edited_chunk, format, *levels);
+            }
+            Transformation::Unsharp {
+                orig_w,
+                orig_h,
+                amount,
+                radius,
+                threshold,
+            } => {
+                build_unsharp_circuit(
+                    builder,
+                    original_chunk,
+                    edited_chunk,
+                    format,
+                    halo,
+                    chunk_start,
+                    *orig_w,
+                    *orig_h,
+                    *amount,
+                    *radius,
+                    *threshold,
+                );
+            }
+            Transformation::Median3x3 {
+                orig_w,
+                orig_h,
+                regions,
+            } => {
+                build_median3x3_circuit(
+                    builder,
+                    original_chunk,
+                    edited_chunk,
+                    format,
+                    halo,
+                    chunk_start,
+                    *orig_w,
+                    *orig_h,
+                    regions,
+                );
+            }
+            Transformation::Equalize { .. } => {
+                return build_equalize_chunk_circuit(builder, original_chunk, edited_chunk, format);
+            }
+            Transformation::HiddenCrop { w, h, .. } => {
+                return build_hidden_crop_chunk_circuit(
+                    builder,
+                    original_chunk,
+                    edited_chunk,
+                    chunk_start,
+                    *w,
+                    *h,
+                );
+            }
+            Transformation::PreserveOutside {
+                orig_w,
+                orig_h,
+                regions,
+            } => {
+                build_preserve_outside_circuit(
+                    builder,
+                    original_chunk,
+                    edited_chunk,
+                    chunk_start,
+                    *orig_w,
+                    *orig_h,
+                    regions,
+                );
+            }
+        }
+        Vec::new()
+    }
+
+    fn hides_dimensions(&self) -> bool {
+        matches!(self, Transformation::HiddenCrop { .. })
+    }
+
+    fn build_aggregation_circuit(
+        &self,
+        builder: &mut CircuitBuilder<GoldilocksField, 2>,
+        original_hash: &[Target],
+        edited_hash: &[Target],
+        original_dimensions: &[Target],
+        chunk_public_inputs: &[Vec<Target>],
+        format: PixelFormat,
+    ) -> Vec<Target> {
+        match self {
+            Transformation::Equalize { orig_w, orig_h } => build_equalize_aggregation_circuit(
+                builder,
+                original_hash,
+                edited_hash,
+                chunk_public_inputs,
+                *orig_w as u64 * *orig_h as u64,
+            ),
+            Transformation::HiddenCrop { .. } => build_hidden_crop_aggregation_circuit(
+                builder,
+                original_hash,
+                edited_hash,
+                original_dimensions,
+                chunk_public_inputs,
+                format.elements_per_pixel(),
+            ),
+            Transformation::PreserveOutside { regions, .. } => {
+                build_preserve_outside_aggregation_circuit(builder, regions);
+                Vec::new()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn chunk_witness(&self, images: &ImageWitness, chunk_start: usize) -> Vec<GoldilocksField> {
+        match &*element_geometry(self, images.layout.format) {
+            Transformation::Equalize { .. } => equalize_chunk_witness(images),
+            Transformation::HiddenCrop { position, .. } => hidden_crop_chunk_witness(
+                images,
+                chunk_start,
+                position
+                    .as_ref()
+                    .expect("hidden crop position is needed to prove"),
+            ),
+            _ => Vec::new(),
+        }
+    }
+
+    fn aggregation_witness(&self, images: &ImageWitness) -> Vec<GoldilocksField> {
+        match &*element_geometry(self, images.layout.format) {
+            Transformation::Equalize { .. } => equalize_aggregation_witness(images),
+            Transformation::HiddenCrop { position, .. } => hidden_crop_aggregation_witness(
+                images,
+                position
+                    .as_ref()
+                    .expect("hidden crop position is needed to prove"),
+            ),
+            _ => Vec::new(),
         }
     }
 }
@@ -0,0 +1,123 @@
+use plonky2::hash::hash_types::HashOut;
+use plonky2::hash::poseidon::PoseidonHash;
+use plonky2::iop::ext_target::ExtensionTarget;
+use plonky2::iop::target::Target;
+use plonky2::plonk::circuit_builder::CircuitBuilder;
+use plonky2::plonk::config::Hasher;
+use plonky2_field::extension::{Extendable, FieldExtension};
+use plonky2_field::goldilocks_field::GoldilocksField;
+
+// Multiset arguments prove that the chunks together produce the claimed
+// multiset of values: every chunk multiplies `gamma - value` over its pixels
+// and the aggregation circuit compares the product of all chunks with the
+// product over the claimed multiset. The challenges have to be derived from
+// the hashes of both images and every prover-chosen value the claim depends
+// on, otherwise the prover could adapt the claim to them. They are drawn from
+// the quadratic extension and the products are taken there, the 64-bit base
+// field alone leaves too little room against a prover grinding the hashes.
+
+/// The field of the challenges and products.
+pub(crate) type Ext = <GoldilocksField as Extendable<2>>::Extension;
+
+/// Fiat-Shamir challenges `(gamma, beta)`, `beta` combines tuples into a
+/// single value.
+pub(crate) fn multiset_challenges(
+    original_hash: &HashOut<GoldilocksField>,
+    edited_hash: &HashOut<GoldilocksField>,
+    claim: &[GoldilocksField],
+) -> (Ext, Ext) {
+    let inputs: Vec<GoldilocksField> = original_hash
+        .elements
+        .iter()
+        .chain(&edited_hash.elements)
+        .chain(claim)
+        .copied()
+        .collect();
+    let [g0, g1, b0, b1] = PoseidonHash::hash_no_pad(&inputs).elements;
+    (
+        Ext::from_basefield_array([g0, g1]),
+        Ext::from_basefield_array([b0, b1]),
+    )
+}
+
+pub(crate) fn multiset_challenges_circuit(
+    builder: &mut CircuitBuilder<GoldilocksField, 2>,
+    original_hash: &[Target],
+    edited_hash: &[Target],
+    claim: &[Target],
+) -> (ExtensionTarget<2>, ExtensionTarget<2>) {
+    let inputs: Vec<Target> = original_hash
+        .iter()
+        .chain(edited_hash)
+        .chain(claim)
+        .copied()
+        .collect();
+    let [g0, g1, b0, b1] = builder
+        .hash_n_to_hash_no_pad::<PoseidonHash>(inputs)
+        .elements;
+    (ExtensionTarget([g0, g1]), ExtensionTarget([b0, b1]))
+}
+
+/// Witness of the targets of `challenges_targets`.
+pub(crate) fn challenges_witness(gamma: Ext, beta: Ext) -> Vec<GoldilocksField> {
+    gamma
+        .to_basefield_array()
+        .into_iter()
+        .chain(beta.to_basefield_array())
+        .collect()
+}
+
+/// Virtual targets a chunk receives `(gamma, beta)` in.
+pub(crate) fn challenges_targets(
+    builder: &mut CircuitBuilder<GoldilocksField, 2>,
+) -> (ExtensionTarget<2>, ExtensionTarget<2>) {
+    (
+        builder.add_virtual_extension_target(),
+        builder.add_virtual_extension_target(),
+    )
+}
+
+/// The extension element in the two targets at `start`, such as a challenge or
+/// product among the public inputs of a chunk.
+pub(crate) fn extension_at(targets: &[Target], start: usize) -> ExtensionTarget<2> {
+    ExtensionTarget([targets[start], targets[start + 1]])
+}
+
+/// `beta * value + offset`, the key of the tuple `(offset, value)`.
+pub(crate) fn tuple_key(
+    builder: &mut CircuitBuilder<GoldilocksField, 2>,
+    beta: ExtensionTarget<2>,
+    value: Target,
+    offset: Target,
+) -> ExtensionTarget<2> {
+    let offset = builder.convert_to_ext(offset);
+    builder.scalar_mul_add_extension(value, beta, offset)
+}
+
+/// Multiplies `acc` by `gamma - key`.
+pub(crate) fn accumulate_term(
+    builder: &mut CircuitBuilder<GoldilocksField, 2>,
+    acc: ExtensionTarget<2>,
+    gamma: ExtensionTarget<2>,
+    key: ExtensionTarget<2>,
+) -> ExtensionTarget<2> {
+    let term = builder.sub_extension(gamma, key);
+    builder.mul_extension(acc, term)
+}
+
+/// `base` to the power of `exponent`, which is checked to fit into `bits`.
+pub(crate) fn exp_extension(
+    builder: &mut CircuitBuilder<GoldilocksField, 2>,
+    base: ExtensionTarget<2>,
+    exponent: Target,
+    bits: usize,
+) -> ExtensionTarget<2> {
+    let mut result = builder.one_extension();
+    let mut power = base;
+    for bit in builder.split_le(exponent, bits) {
+        let multiplied = builder.mul_extension(result, power);
+        result = builder.select_ext(bit, multiplied, result);
+        power = builder.square_extension(power);
+    }
+    result
+}
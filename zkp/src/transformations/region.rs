@@ -0,0 +1,111 @@
+use plonky2::iop::target::Target;
+use plonky2::plonk::circuit_builder::CircuitBuilder;
+use plonky2_field::goldilocks_field::GoldilocksField;
+use plonky2_field::types::Field;
+use zkedit_transformations::Region;
+
+use super::util::pixel_number_to_coords;
+
+/// Number of public inputs a region takes: x, y, w and h.
+const REGION_PIS_LEN: usize = 4;
+
+pub(crate) fn build_preserve_outside_circuit(
+    builder: &mut CircuitBuilder<GoldilocksField, 2>,
+    original_chunk: &[Target],
+    edited_chunk: &[Target],
+    chunk_start: usize,
+    orig_w: u32,
+    orig_h: u32,
+    regions: &[Region],
+) {
+    assert!(
+        regions.iter().all(|region| region.fits(orig_w, orig_h)),
+        "preserved regions must lie inside the image"
+    );
+    for (i, (orig_pixel, edit_pixel)) in original_chunk.iter().zip(edited_chunk).enumerate() {
+        let (x, y) = pixel_number_to_coords(chunk_start + i, orig_w);
+        if !regions.iter().any(|region| region.contains(x, y)) {
+            builder.connect(*orig_pixel, *edit_pixel);
+        }
+    }
+}
+
+/// Registers the regions as public inputs, so a viewer can highlight them
+/// straight from the proof. They are constants of the circuit and therefore
+/// bound by its digest.
+pub(crate) fn build_preserve_outside_aggregation_circuit(
+    builder: &mut CircuitBuilder<GoldilocksField, 2>,
+    regions: &[Region],
+) {
+    for region in regions {
+        for value in [region.x, region.y, region.w, region.h] {
+            let target = builder.constant(GoldilocksField::from_canonical_u32(value));
+            builder.register_public_input(target);
+        }
+    }
+}
+
+/// Reads the regions back from `TransformationProof::transformation_public_inputs`
+/// of a `PreserveOutside` proof.
+pub fn unauthenticated_regions(public_inputs: &[u64]) -> Vec<Region> {
+    public_inputs
+        .chunks_exact(REGION_PIS_LEN)
+        .map(|region| Region {
+            x: region[0] as u32,
+            y: region[1] as u32,
+            w: region[2] as u32,
+            h: region[3] as u32,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use zkedit_transformations::Transformation;
+
+    use super::*;
+    use crate::builder::TransformationCircuitBuilder;
+    use crate::testing::{random_pixels, transformation_proves, HEIGHT, L, WIDTH};
+
+    #[test]
+    fn test_preserve_outside_proves() {
+        let original = random_pixels((WIDTH * HEIGHT) as usize);
+        let regions = vec![Region {
+            x: 3,
+            y: 1,
+            w: 2,
+            h: 3,
+        }];
+        let preserve_outside = Transformation::PreserveOutside {
+            orig_w: WIDTH,
+            orig_h: HEIGHT,
+            regions: regions.clone(),
+        };
+        // A sticker inside the region
+        let mut edited = original.clone();
+        for (i, pixel) in edited.iter_mut().enumerate() {
+            if regions[0].contains(i as u32 % WIDTH, i as u32 / WIDTH) {
+                *pixel = [255, 0, 255, 255];
+            }
+        }
+
+        let (original_bytes, edited_bytes) = (original.concat(), edited.concat());
+        let mut circuit = TransformationCircuitBuilder::<L>::new(
+            original_bytes.len(),
+            Box::new(preserve_outside.clone()),
+        )
+        .build_curcuit();
+        let proof = circuit
+            .prove(&original_bytes, &edited_bytes, None, None)
+            .unwrap();
+        assert_eq!(
+            unauthenticated_regions(&proof.transformation_public_inputs()),
+            regions
+        );
+        proof.verify(circuit.circuit).unwrap();
+
+        // A pixel outside of the region
+        edited[WIDTH as usize * 2][0] ^= 1;
+        assert!(!transformation_proves(preserve_outside, &original, &edited));
+    }
+}
@@ -0,0 +1,98 @@
+use plonky2::iop::target::Target;
+use plonky2::plonk::circuit_builder::CircuitBuilder;
+use plonky2_field::goldilocks_field::GoldilocksField;
+use plonky2_field::types::Field;
+use zkedit_transformations::tone::{posterize_levels_valid, posterize_multiplier, POSTERIZE_SHIFT};
+
+use crate::pixel::PixelFormat;
+
+use super::util::{is_less_than, pack_pixel, round_shift_clamp, unpack_pixel};
+
+pub(crate) fn build_threshold_circuit(
+    builder: &mut CircuitBuilder<GoldilocksField, 2>,
+    original_chunk: &[Target],
+    edited_chunk: &[Target],
+    format: PixelFormat,
+    level: u8,
+) {
+    let level = builder.constant(GoldilocksField::from_canonical_u64(level as u64));
+    let max = GoldilocksField::from_canonical_u64(u8::MAX as u64);
+    for (orig_pixel, edit_pixel) in original_chunk.iter().zip(edited_chunk) {
+        let channels = unpack_pixel(builder, *orig_pixel, format);
+        let mut edited = channels;
+        for c in 0..3 {
+            let below = is_less_than(builder, channels[c], level, 8);
+            let above = builder.not(below);
+            edited[c] = builder.mul_const(max, above.target);
+        }
+        let edited_pixel = pack_pixel(builder, edited, format);
+        builder.connect(edited_pixel, *edit_pixel);
+    }
+}
+
+pub(crate) fn build_posterize_circuit(
+    builder: &mut CircuitBuilder<GoldilocksField, 2>,
+    original_chunk: &[Target],
+    edited_chunk: &[Target],
+    format: PixelFormat,
+    levels: u8,
+) {
+    assert!(
+        posterize_levels_valid(levels),
+        "posterize needs at least two levels"
+    );
+    let levels_constant = GoldilocksField::from_canonical_u64(levels as u64);
+    let multiplier = GoldilocksField::from_canonical_u64(posterize_multiplier(levels) as u64);
+    for (orig_pixel, edit_pixel) in original_chunk.iter().zip(edited_chunk) {
+        let channels = unpack_pixel(builder, *orig_pixel, format);
+        let mut edited = channels;
+        for c in 0..3 {
+            // bucket = (channel * levels) >> 8
+            let scaled = builder.mul_const(levels_constant, channels[c]);
+            let bits = builder.split_le(scaled, 16);
+            let bucket = builder.le_sum(bits[8..].iter());
+            let acc = builder.mul_const(multiplier, bucket);
+            edited[c] = round_shift_clamp(builder, acc, POSTERIZE_SHIFT as usize);
+        }
+        let edited_pixel = pack_pixel(builder, edited, format);
+        builder.connect(edited_pixel, *edit_pixel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zkedit_transformations::tone::{apply_posterize, apply_threshold};
+    use zkedit_transformations::Transformation;
+
+    use crate::testing::{random_pixels, transformation_proves, HEIGHT, WIDTH};
+
+    #[test]
+    fn test_threshold_proves() {
+        let mut original = random_pixels((WIDTH * HEIGHT) as usize);
+        // Channels on both sides of the level
+        original[3] = [99, 100, 101, 255];
+        let mut edited: Vec<[u8; 4]> = original
+            .iter()
+            .map(|pixel| apply_threshold(100, *pixel))
+            .collect();
+        let threshold = Transformation::Threshold { level: 100 };
+        assert!(transformation_proves(threshold.clone(), &original, &edited));
+
+        edited[3][0] = 255;
+        assert!(!transformation_proves(threshold, &original, &edited));
+    }
+
+    #[test]
+    fn test_posterize_proves() {
+        let original = random_pixels((WIDTH * HEIGHT) as usize);
+        let mut edited: Vec<[u8; 4]> = original
+            .iter()
+            .map(|pixel| apply_posterize(4, *pixel))
+            .collect();
+        let posterize = Transformation::Posterize { levels: 4 };
+        assert!(transformation_proves(posterize.clone(), &original, &edited));
+
+        edited[25][2] = edited[25][2].wrapping_add(85);
+        assert!(!transformation_proves(posterize, &original, &edited));
+    }
+}
@@ -1,3 +1,244 @@
+use std::cmp::min;
+
+use plonky2::iop::target::{BoolTarget, Target};
+use plonky2::plonk::circuit_builder::CircuitBuilder;
+use plonky2_field::goldilocks_field::GoldilocksField;
+use plonky2_field::types::Field;
+
+use crate::halo::HaloTargets;
+use crate::pixel::{PixelFormat, PixelLayout};
+
+/// Width of the window signed fixed-point accumulators are decomposed in.
+pub(crate) const ACCUMULATOR_BITS: usize = 32;
+
 pub(crate) fn pixel_number_to_coords(num: usize, width: u32) -> (u32, u32) {
     (num as u32 % width, num as u32 / width)
 }
+
+pub(crate) fn signed_constant(value: i64) -> GoldilocksField {
+    let magnitude = GoldilocksField::from_canonical_u64(value.unsigned_abs());
+    if value < 0 {
+        -magnitude
+    } else {
+        magnitude
+    }
+}
+
+/// Native counterpart of `unpack_pixel`.
+pub(crate) fn pixel_channels(pixel: u64, format: PixelFormat) -> [u8; 4] {
+    let bytes = pixel.to_le_bytes();
+    match format.channels() {
+        1 => [bytes[0], bytes[0], bytes[0], u8::MAX],
+        3 => [bytes[0], bytes[1], bytes[2], u8::MAX],
+        _ => [bytes[0], bytes[1], bytes[2], bytes[3]],
+    }
+}
+
+/// Bits of packed elements, little-endian across the elements.
+fn element_bits(
+    builder: &mut CircuitBuilder<GoldilocksField, 2>,
+    elements: &[Target],
+    layout: PixelLayout,
+) -> Vec<BoolTarget> {
+    let mut bits = Vec::new();
+    for element in elements {
+        bits.extend(builder.split_le(*element, 8 * layout.bytes_per_element()));
+    }
+    bits
+}
+
+/// Whether an element holds exactly one pixel and needs no splitting.
+fn one_pixel_per_element(layout: PixelLayout) -> bool {
+    layout.bytes_per_element() == layout.format.bytes_per_unit()
+}
+
+/// Splits packed elements into one target per pixel and keeps the first
+/// `count`. The bytes after the last pixel must be zero, which keeps the
+/// packing injective.
+pub(crate) fn split_pixels(
+    builder: &mut CircuitBuilder<GoldilocksField, 2>,
+    elements: &[Target],
+    layout: PixelLayout,
+    count: usize,
+) -> Vec<Target> {
+    if one_pixel_per_element(layout) {
+        return elements[..count].to_vec();
+    }
+    let unit_bits = 8 * layout.format.bytes_per_unit();
+    let mut pixels = Vec::new();
+    for unit in element_bits(builder, elements, layout).chunks(unit_bits) {
+        let pixel = builder.le_sum(unit.iter());
+        if pixels.len() < count {
+            pixels.push(pixel);
+        } else {
+            builder.assert_zero(pixel);
+        }
+    }
+    pixels
+}
+
+/// Splits halo elements into the whole pixels next to the chunk, at most
+/// `after` of them after it. Densely packed, the first element before the
+/// chunk may start with the end of a pixel, which is left out. The chunks
+/// owning the elements check their padding.
+pub(crate) fn split_halo(
+    builder: &mut CircuitBuilder<GoldilocksField, 2>,
+    halo: &HaloTargets,
+    layout: PixelLayout,
+    after: usize,
+) -> HaloTargets {
+    if one_pixel_per_element(layout) {
+        return HaloTargets {
+            before: halo.before.clone(),
+            after: halo.after[..min(after, halo.after.len())].to_vec(),
+        };
+    }
+    let unit_bits = 8 * layout.format.bytes_per_unit();
+    let before_bits = element_bits(builder, &halo.before, layout);
+    let before_bits = &before_bits[before_bits.len() % unit_bits..];
+    let before = before_bits
+        .chunks(unit_bits)
+        .map(|unit| builder.le_sum(unit.iter()))
+        .collect();
+    let after_bits = element_bits(builder, &halo.after, layout);
+    let after = after_bits
+        .chunks_exact(unit_bits)
+        .take(after)
+        .map(|unit| builder.le_sum(unit.iter()))
+        .collect();
+    HaloTargets { before, after }
+}
+
+/// Splits a packed little-endian pixel of an 8-bit `format` into RGBA byte
+/// channels. Luma is repeated in the color channels and a pixel without alpha
+/// is opaque.
+pub(crate) fn unpack_pixel(
+    builder: &mut CircuitBuilder<GoldilocksField, 2>,
+    pixel: Target,
+    format: PixelFormat,
+) -> [Target; 4] {
+    assert_eq!(format.bytes_per_sample(), 1, "channels are 8-bit");
+    let bits = builder.split_le(pixel, 8 * format.channels());
+    let samples: Vec<Target> = bits
+        .chunks(8)
+        .map(|sample| builder.le_sum(sample.iter()))
+        .collect();
+    let opaque = builder.constant(GoldilocksField::from_canonical_u64(u8::MAX as u64));
+    match format.channels() {
+        1 => [samples[0], samples[0], samples[0], opaque],
+        3 => [samples[0], samples[1], samples[2], opaque],
+        _ => [samples[0], samples[1], samples[2], samples[3]],
+    }
+}
+
+/// Packs RGBA byte channels back into a little-endian pixel of `format`, the
+/// channels it doesn't store are dropped. The channels are expected to be
+/// already range checked.
+pub(crate) fn pack_pixel(
+    builder: &mut CircuitBuilder<GoldilocksField, 2>,
+    channels: [Target; 4],
+    format: PixelFormat,
+) -> Target {
+    let mut pixel = builder.zero();
+    for (i, channel) in channels[..format.channels()].iter().enumerate() {
+        pixel = builder.mul_const_add(
+            GoldilocksField::from_canonical_u64(1 << (8 * i)),
+            *channel,
+            pixel,
+        );
+    }
+    pixel
+}
+
+/// In-circuit counterpart of `zkedit_transformations::util::round_shift_clamp`.
+/// `acc` is a signed accumulator with `|acc| < 2^(ACCUMULATOR_BITS - 2)`.
+pub(crate) fn round_shift_clamp(
+    builder: &mut CircuitBuilder<GoldilocksField, 2>,
+    acc: Target,
+    shift: usize,
+) -> Target {
+    assert!(shift + 8 < ACCUMULATOR_BITS);
+    let rounding = if shift > 0 { 1u64 << (shift - 1) } else { 0 };
+    // Biasing by 2^(ACCUMULATOR_BITS-1) keeps the value positive, so its top bit
+    // is the sign and the bits above `shift` are `floor(acc / 2^shift)` plus a
+    // constant that the sign bit absorbs.
+    let biased = builder.add_const(
+        acc,
+        GoldilocksField::from_canonical_u64((1 << (ACCUMULATOR_BITS - 1)) + rounding),
+    );
+    let bits = builder.split_le(biased, ACCUMULATOR_BITS);
+    let non_negative = bits[ACCUMULATOR_BITS - 1];
+
+    let low_byte = builder.le_sum(bits[shift..shift + 8].iter());
+    let overflow_bits = builder.add_many(
+        bits[shift + 8..ACCUMULATOR_BITS - 1]
+            .iter()
+            .map(|b| b.target),
+    );
+    let zero = builder.zero();
+    let fits = builder.is_equal(overflow_bits, zero);
+    let max = builder.constant(GoldilocksField::from_canonical_u64(u8::MAX as u64));
+    let clamped = builder.select(fits, low_byte, max);
+
+    builder.mul(non_negative.target, clamped)
+}
+
+/// Returns whether `a < b` for values that fit into `num_bits` bits.
+pub(crate) fn is_less_than(
+    builder: &mut CircuitBuilder<GoldilocksField, 2>,
+    a: Target,
+    b: Target,
+    num_bits: usize,
+) -> BoolTarget {
+    // a - b + 2^num_bits has its top bit set exactly when a >= b
+    let diff = builder.sub(a, b);
+    let shifted = builder.add_const(diff, GoldilocksField::from_canonical_u64(1 << num_bits));
+    let bits = builder.split_le(shifted, num_bits + 1);
+    builder.not(bits[num_bits])
+}
+
+/// Channels of a chunk together with its halo, addressed by the pixel number
+/// in the whole image. Pixels are unpacked on first access.
+pub(crate) struct UnpackedWindow {
+    start: usize,
+    format: PixelFormat,
+    pixels: Vec<Target>,
+    channels: Vec<Option<[Target; 4]>>,
+}
+
+impl UnpackedWindow {
+    pub(crate) fn new(
+        original_chunk: &[Target],
+        halo: &HaloTargets,
+        chunk_start: usize,
+        format: PixelFormat,
+    ) -> Self {
+        let pixels: Vec<Target> = halo
+            .before
+            .iter()
+            .chain(original_chunk)
+            .chain(&halo.after)
+            .copied()
+            .collect();
+        Self {
+            start: chunk_start - halo.before.len(),
+            format,
+            channels: vec![None; pixels.len()],
+            pixels,
+        }
+    }
+
+    pub(crate) fn pixel(
+        &mut self,
+        builder: &mut CircuitBuilder<GoldilocksField, 2>,
+        pixel_number: usize,
+    ) -> [Target; 4] {
+        let index = pixel_number - self.start;
+        if let Some(channels) = self.channels[index] {
+            return channels;
+        }
+        let channels = unpack_pixel(builder, self.pixels[index], self.format);
+        self.channels[index] = Some(channels);
+        channels
+    }
+}
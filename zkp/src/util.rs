@@ -22,16 +22,23 @@ pub(crate) fn set_multiple_targets<F: Field>(
     }
 }
 
-pub(crate) fn bytes_to_field64<F: PrimeField64>(bytes: &[u8]) -> Vec<F> {
+/// Packs `width` little-endian bytes into each element, `width` has to stay
+/// below 8 for the packing to be injective.
+pub(crate) fn pack_le_bytes<F: PrimeField64>(bytes: &[u8], width: usize) -> Vec<F> {
+    assert!(width < 8, "elements hold at most 7 bytes");
     let mut field_elements = Vec::new();
-    for chunk in bytes.chunks(4) {
+    for chunk in bytes.chunks(width) {
         let mut elem_bytes = [0u8; 8];
-        elem_bytes[..4].copy_from_slice(chunk);
+        elem_bytes[..chunk.len()].copy_from_slice(chunk);
         field_elements.push(F::from_canonical_u64(u64::from_le_bytes(elem_bytes)));
     }
     field_elements
 }
 
+pub(crate) fn bytes_to_field64<F: PrimeField64>(bytes: &[u8]) -> Vec<F> {
+    pack_le_bytes(bytes, 4)
+}
+
 #[allow(dead_code)]
 pub(crate) fn field64_to_bytes<F: PrimeField64>(field_elements: &[F]) -> Vec<u8> {
     let mut bytes = Vec::new();